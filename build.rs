@@ -1,25 +1,25 @@
-use std::{
-    env,
-    path::PathBuf,
-};
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    use std::env;
 
-fn main() {
-    pkg_config::probe_library("libfreeipmi").unwrap();
-    pkg_config::probe_library("libipmimonitoring").unwrap();
+    println!("cargo:rerun-if-env-changed=PROTOC");
+
+    // Use the bundled protoc unless the user provided their own
+    if env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path()
+            .unwrap_or_else(|e| panic!("Failed to find bundled protoc: {}", e));
+        env::set_var("PROTOC", protoc);
+    }
 
-    println!("cargo:rerun-if-changed=wrapper.h");
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/ipmi_fan_control.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile protos: {}", e));
+}
 
-    let bindings = bindgen::Builder::default()
-        .header("wrapper.h")
-        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
-        .allowlist_function("^ipmi_(cmd|completion_code|ctx|monitoring)_.*")
-        .allowlist_type("^ipmi_monitoring_.*")
-        .allowlist_var("^IPMI_(CMD|COMP_CODE|FLAGS|NET_FN|PRIVILEGE_LEVEL)_.*")
-        .generate()
-        .expect("Failed to generate bindings");
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings
-        .write_to_file(out_path.join("bindings.rs"))
-        .expect("Failed to write bindings");
+    #[cfg(feature = "grpc")]
+    compile_protos();
 }
@@ -0,0 +1,171 @@
+use std::{
+    env,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Run a build command and panic if it fails.
+fn run(command: &mut Command) {
+    println!("Running: {:?}", command);
+
+    let status = command.status()
+        .unwrap_or_else(|e| panic!("Failed to run {:?}: {}", command, e));
+    if !status.success() {
+        panic!("{:?} failed: {}", command, status);
+    }
+}
+
+/// Recursively copy `source` to `target`, preserving symlinks on unix. Git
+/// metadata is skipped.
+fn copy_tree(source: &Path, target: &Path) {
+    fs::create_dir_all(target)
+        .unwrap_or_else(|e| panic!("Failed to create {:?}: {}", target, e));
+
+    let entries = fs::read_dir(source)
+        .unwrap_or_else(|e| panic!("Failed to read {:?}: {}", source, e));
+
+    for entry in entries {
+        let entry = entry.unwrap_or_else(|e| panic!("Failed to read {:?}: {}", source, e));
+        if entry.file_name() == ".git" {
+            continue;
+        }
+
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+        let file_type = entry.file_type()
+            .unwrap_or_else(|e| panic!("Failed to stat {:?}: {}", source_path, e));
+
+        if file_type.is_dir() {
+            copy_tree(&source_path, &target_path);
+        } else if file_type.is_symlink() {
+            copy_symlink(&source_path, &target_path);
+        } else {
+            fs::copy(&source_path, &target_path)
+                .unwrap_or_else(|e| panic!("Failed to copy {:?}: {}", source_path, e));
+        }
+    }
+}
+
+/// Create a symlink at `target` with the same contents as `source`.
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) {
+    let link = fs::read_link(source)
+        .unwrap_or_else(|e| panic!("Failed to read link {:?}: {}", source, e));
+    std::os::unix::fs::symlink(&link, target)
+        .unwrap_or_else(|e| panic!("Failed to create link {:?}: {}", target, e));
+}
+
+/// Symlinks can't be created without extra privileges on Windows, so the file
+/// that the link points to is copied instead.
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, target: &Path) {
+    fs::copy(source, target)
+        .unwrap_or_else(|e| panic!("Failed to copy {:?}: {}", source, e));
+}
+
+/// Build static libfreeipmi and libipmimonitoring from the vendored source tree
+/// and install them to `prefix`. Only the libraries are built, not the tools.
+fn build_vendored(prefix: &Path) {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let source_dir = env::var_os("FREEIPMI_SOURCE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest_dir.join("..").join("vendor").join("freeipmi"));
+
+    println!("cargo:rerun-if-env-changed=FREEIPMI_SOURCE_DIR");
+    println!("cargo:rerun-if-changed={}", source_dir.display());
+
+    if !source_dir.join("configure.ac").exists() {
+        panic!("freeipmi source tree not found at {:?}; set FREEIPMI_SOURCE_DIR", source_dir);
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    // Git checkouts don't include the generated configure script. autogen.sh
+    // writes into the tree it runs in, so run it on a copy in OUT_DIR to keep
+    // the source tree untouched.
+    let source_dir = if source_dir.join("configure").exists() {
+        source_dir
+    } else {
+        let copy_dir = out_dir.join("freeipmi-src");
+        if copy_dir.exists() {
+            fs::remove_dir_all(&copy_dir)
+                .unwrap_or_else(|e| panic!("Failed to remove {:?}: {}", copy_dir, e));
+        }
+        copy_tree(&source_dir, &copy_dir);
+
+        run(Command::new("sh").arg("autogen.sh").current_dir(&copy_dir));
+        copy_dir
+    };
+
+    let build_dir = out_dir.join("freeipmi-build");
+    fs::create_dir_all(&build_dir).expect("Failed to create freeipmi build directory");
+
+    run(Command::new(source_dir.join("configure"))
+        .arg(format!("--prefix={}", prefix.display()))
+        .args(["--enable-static", "--disable-shared", "--with-pic"])
+        .current_dir(&build_dir));
+
+    let jobs = env::var("NUM_JOBS").unwrap_or_else(|_| "1".to_owned());
+
+    for dir in ["common", "libfreeipmi", "libipmimonitoring"] {
+        run(Command::new("make")
+            .arg(format!("-j{}", jobs))
+            .arg("-C")
+            .arg(dir)
+            .current_dir(&build_dir));
+    }
+    for dir in ["libfreeipmi", "libipmimonitoring"] {
+        run(Command::new("make")
+            .args(["-C", dir, "install"])
+            .current_dir(&build_dir));
+    }
+}
+
+fn main() {
+    // Only the freeipmi backend needs the C libraries
+    if env::var_os("CARGO_FEATURE_FREEIPMI").is_none() {
+        return;
+    }
+
+    let mut pkg_config = pkg_config::Config::new();
+
+    if env::var_os("CARGO_FEATURE_VENDORED").is_some() {
+        let prefix = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("freeipmi");
+        build_vendored(&prefix);
+
+        // Find the installed .pc files. Static linking also pulls in the
+        // private dependencies, like libgcrypt, which are linked statically if
+        // they are not in a system directory.
+        let pc_dir = prefix.join("lib").join("pkgconfig");
+        let mut paths = vec![pc_dir];
+        paths.extend(env::var_os("PKG_CONFIG_PATH").iter().flat_map(env::split_paths));
+        env::set_var("PKG_CONFIG_PATH", env::join_paths(paths).unwrap());
+
+        pkg_config.statik(true);
+    }
+
+    let libraries = ["libfreeipmi", "libipmimonitoring"].map(|name| {
+        pkg_config.probe(name)
+            .unwrap_or_else(|e| panic!("Failed to find {}: {}", name, e))
+    });
+
+    println!("cargo:rerun-if-changed=wrapper.h");
+
+    let bindings = bindgen::Builder::default()
+        .header("wrapper.h")
+        .clang_args(libraries.iter()
+            .flat_map(|l| &l.include_paths)
+            .map(|p| format!("-I{}", p.display())))
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
+        .allowlist_function("^ipmi_(cmd|completion_code|ctx|monitoring)_.*")
+        .allowlist_type("^ipmi_(driver_type|errnum|monitoring_.*)$")
+        .allowlist_var("^IPMI_(AUTHENTICATION_TYPE|CMD|COMP_CODE|FLAGS|MONITORING_WORKAROUND_FLAGS|NET_FN|PRIVILEGE_LEVEL|WORKAROUND_FLAGS)_.*")
+        .generate()
+        .expect("Failed to generate bindings");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    bindings
+        .write_to_file(out_path.join("bindings.rs"))
+        .expect("Failed to write bindings");
+}
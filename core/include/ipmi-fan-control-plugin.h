@@ -0,0 +1,111 @@
+/*
+ * Plugin interface for ipmi-fan-control.
+ *
+ * A plugin is a shared library that exports an `ipmi_fan_control_plugin`
+ * function returning a pointer to a static `struct ifc_plugin`. It can provide
+ * new temperature source types, new session backends, or both. Plugins are
+ * listed by path in the config file's `plugins` option and are loaded once
+ * for the lifetime of the process.
+ *
+ * All functions may be called from multiple threads, though never with the
+ * same handle at the same time. Strings are NUL-terminated UTF-8. Functions
+ * that can fail return one of the IFC_* status codes and, on failure, write a
+ * NUL-terminated message of at most `error_len` bytes (including the NUL) to
+ * `error`.
+ */
+
+#ifndef IPMI_FAN_CONTROL_PLUGIN_H
+#define IPMI_FAN_CONTROL_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define IFC_ABI_VERSION 1
+
+/* Status codes */
+#define IFC_OK 0
+#define IFC_ERROR 1
+/* The drive is in standby (sources only). Like with `smart` sources, the
+ * source's `standby` key, if set, decides what reading is used instead. */
+#define IFC_STANDBY 2
+/* The BMC could not be reached (backends only). The session is reconnected,
+ * possibly to a failover hostname. */
+#define IFC_DISCONNECTED 3
+
+/* Sensor types passed to `backend_sensors` */
+#define IFC_SENSOR_TEMPERATURE 0
+#define IFC_SENSOR_FAN 1
+#define IFC_SENSOR_VOLTAGE 2
+#define IFC_SENSOR_CURRENT 3
+#define IFC_SENSOR_POWER 4
+
+/* Units reported by `backend_sensors`. Other values are shown as unknown
+ * units. */
+#define IFC_UNITS_CELSIUS 1
+#define IFC_UNITS_FAHRENHEIT 2
+#define IFC_UNITS_RPM 3
+#define IFC_UNITS_VOLTS 4
+#define IFC_UNITS_AMPS 5
+#define IFC_UNITS_WATTS 6
+
+/* Called by `backend_sensors` once per sensor. `has_value` is 0 for sensors
+ * without a reading, like empty fan headers. */
+typedef void (*ifc_sensor_fn)(void *ctx, const char *name, int has_value,
+                              double value, uint32_t units);
+
+struct ifc_plugin {
+    /* Must be IFC_ABI_VERSION */
+    uint32_t abi_version;
+
+    /* NULL-terminated list of source `type` names, or NULL */
+    const char *const *source_types;
+
+    /* Open a source of one of `source_types`. `params` contains the source's
+     * keys, other than `type` and the common options like `weight`, as a
+     * TOML document. This is also called when the config is loaded to
+     * validate the keys. The handle is kept open until the source is removed
+     * from the config or the program exits. */
+    int (*source_open)(const char *type, const char *params, void **handle,
+                       char *error, size_t error_len);
+    /* Read the temperature in degrees Celsius */
+    int (*source_read)(void *handle, double *temp, char *error,
+                       size_t error_len);
+    void (*source_close)(void *handle);
+
+    /* NULL-terminated list of backend names for `plugin` in a session, or
+     * NULL */
+    const char *const *backends;
+
+    /* Connect to the BMC. `hostname`, `username`, and `password` are NULL
+     * for local sessions. */
+    int (*backend_open)(const char *name, const char *hostname,
+                        const char *username, const char *password,
+                        void **handle, char *error, size_t error_len);
+    /* Execute a raw IPMI command. On entry, `*response_len` is the size of
+     * `response`. On success, it must be set to the length of the response
+     * data, excluding the completion code. A non-zero completion code must
+     * be reported as an error. */
+    int (*backend_raw)(void *handle, uint8_t net_fn, uint8_t command,
+                       const uint8_t *data, size_t data_len,
+                       uint8_t *response, size_t *response_len,
+                       char *error, size_t error_len);
+    /* Report the readings of all sensors of an IFC_SENSOR_* type by calling
+     * `callback` with `ctx` */
+    int (*backend_sensors)(void *handle, uint32_t sensor_type,
+                           ifc_sensor_fn callback, void *ctx,
+                           char *error, size_t error_len);
+    void (*backend_close)(void *handle);
+};
+
+/* Entry point that every plugin must export */
+const struct ifc_plugin *ipmi_fan_control_plugin(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,3850 @@
+use {
+    std::{
+        borrow::Cow,
+        collections::{HashMap, HashSet},
+        convert::TryFrom,
+        env,
+        fmt,
+        fs,
+        mem,
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    },
+    clap::{Parser, ValueEnum},
+    retry::delay::{Exponential, jitter},
+    schemars::{json_schema, JsonSchema, Schema, SchemaGenerator},
+    serde::{
+        de::{
+            self,
+            value::{MapAccessDeserializer, SeqAccessDeserializer},
+            MapAccess,
+            SeqAccess,
+            Visitor,
+        },
+        Deserialize,
+        Deserializer,
+        Serialize,
+    },
+    toml::{value::Table, Value},
+    crate::{
+        error::{Error, Result},
+        plugin,
+        profile,
+        script::Script,
+        sensor::{SensorType, SensorUnits},
+        source,
+        tools,
+    },
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        Self::Info
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => f.write_str("error"),
+            Self::Warn => f.write_str("warn"),
+            Self::Info => f.write_str("info"),
+            Self::Debug => f.write_str("debug"),
+            Self::Trace => f.write_str("trace"),
+        }
+    }
+}
+
+/// What to do with config keys that this version doesn't know about.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownFields {
+    /// Fail to load the config
+    #[default]
+    Error,
+    /// Log a warning and ignore the key
+    Warn,
+}
+
+/// Duration as written in the config. Integers are in the unit of the field
+/// (for backwards compatibility), while strings must have a unit suffix, like
+/// `"90s"` or `"250ms"`.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum DurationValue {
+    Integer(u64),
+    String(String),
+}
+
+impl DurationValue {
+    fn to_duration(&self, unit: Duration) -> Result<Duration, String> {
+        match self {
+            Self::Integer(n) => u32::try_from(*n).ok()
+                .and_then(|n| unit.checked_mul(n))
+                .ok_or_else(|| format!("duration is too large: {}", n)),
+            Self::String(s) => parse_duration(s),
+        }
+    }
+}
+
+/// Parse a duration string consisting of a (possibly fractional) number and a
+/// unit: `ms`, `s`, `m`, `h`, or `d`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number.parse::<f64>()
+        .map_err(|e| format!("invalid duration {:?}: {}", value, e))?;
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        u => return Err(format!("invalid duration {:?}: unknown unit {:?}", value, u)),
+    };
+
+    Duration::try_from_secs_f64(secs)
+        .map_err(|e| format!("invalid duration {:?}: {}", value, e))
+}
+
+/// Interval in seconds or a duration string.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "DurationValue")]
+#[schemars(with = "DurationValue")]
+pub struct Interval(pub Duration);
+
+impl Interval {
+    pub fn to_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl Default for Interval {
+    fn default() -> Self {
+        Self(Duration::from_secs(1))
+    }
+}
+
+impl TryFrom<DurationValue> for Interval {
+    type Error = String;
+
+    fn try_from(value: DurationValue) -> Result<Self, Self::Error> {
+        value.to_duration(Duration::from_secs(1)).map(Self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct Retries(pub usize);
+
+impl Default for Retries {
+    fn default() -> Self {
+        Self(2)
+    }
+}
+
+/// Retry delay in milliseconds or a duration string.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "DurationValue")]
+#[schemars(with = "DurationValue")]
+pub struct RetryDelay(pub Duration);
+
+impl Default for RetryDelay {
+    fn default() -> Self {
+        Self(Duration::from_millis(500))
+    }
+}
+
+impl TryFrom<DurationValue> for RetryDelay {
+    type Error = String;
+
+    fn try_from(value: DurationValue) -> Result<Self, Self::Error> {
+        value.to_duration(Duration::from_millis(1)).map(Self)
+    }
+}
+
+/// How the delay between retries changes after each attempt.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    /// Always wait `retry_delay`
+    #[default]
+    Fixed,
+    /// Double the delay after each attempt
+    Exponential,
+}
+
+/// Retry settings for a query, combined from the zone's and the source's
+/// settings.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RetryPolicy {
+    pub retries: usize,
+    pub delay: Duration,
+    pub backoff: Backoff,
+    /// Upper limit for the delay between attempts
+    pub max_delay: Option<Duration>,
+    /// Whether to randomize each delay between 0 and its full value
+    pub jitter: bool,
+    /// Stop retrying if the next attempt would start after this much time
+    pub max_elapsed: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Get the delays to wait before each retry. The time limit is measured
+    /// from when this function is called, so it must be called right before
+    /// the first attempt.
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let factor = match self.backoff {
+            Backoff::Fixed => 1.0,
+            Backoff::Exponential => 2.0,
+        };
+        let max_delay = self.max_delay;
+        let use_jitter = self.jitter;
+        let deadline = self.max_elapsed.map(|d| Instant::now() + d);
+
+        Exponential::from_millis_with_factor(self.delay.as_millis() as u64, factor)
+            .map(move |d| max_delay.map_or(d, |m| d.min(m)))
+            .map(move |d| if use_jitter { jitter(d) } else { d })
+            .take(self.retries)
+            .take_while(move |d| deadline.is_none_or(|t| Instant::now() + *d <= t))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct FailsafeDcycle(pub u8);
+
+impl Default for FailsafeDcycle {
+    fn default() -> Self {
+        Self(100)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct MinDcycle(pub u8);
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct MaxDcycle(pub u8);
+
+impl Default for MaxDcycle {
+    fn default() -> Self {
+        Self(100)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct FailsafeCycles(pub u32);
+
+impl Default for FailsafeCycles {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct RestoreDcycle(pub u8);
+
+impl Default for RestoreDcycle {
+    fn default() -> Self {
+        Self(100)
+    }
+}
+
+/// Duty cycle that is briefly applied when fans start from a stop.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Spinup {
+    pub dcycle: u8,
+    /// How long the spin-up duty cycle is applied
+    pub duration: Interval,
+}
+
+/// Time window over which the rate of temperature change is measured.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct TrendWindow(pub Interval);
+
+impl Default for TrendWindow {
+    fn default() -> Self {
+        Self(Interval(Duration::from_secs(60)))
+    }
+}
+
+/// Upper limit for the trend boost in percentage points.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct TrendMaxBoost(pub u8);
+
+impl Default for TrendMaxBoost {
+    fn default() -> Self {
+        Self(30)
+    }
+}
+
+/// Duty cycle boost that is added to the steps' duty cycle while the
+/// temperature is rising.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TrendBoost {
+    /// Percentage points added per degree Celsius per minute of temperature
+    /// rise
+    pub gain: u8,
+    #[serde(default)]
+    pub window: TrendWindow,
+    #[serde(default)]
+    pub max_boost: TrendMaxBoost,
+}
+
+/// Detection of duty cycles that repeatedly rise and fall. Once detected,
+/// decreases smaller than the observed swing are ignored.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct OscillationDamping {
+    pub enabled: bool,
+    /// Number of changes in direction within `window` that count as
+    /// oscillation
+    pub flips: u32,
+    pub window: Interval,
+}
+
+impl Default for OscillationDamping {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            flips: 4,
+            window: Interval(Duration::from_secs(300)),
+        }
+    }
+}
+
+/// Command to run when a zone stays above a critical temperature.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Critical {
+    pub temp: i8,
+    /// How long the temperature must stay above `temp`
+    pub duration: Option<Interval>,
+    /// Program and arguments to execute
+    pub command: Vec<String>,
+}
+
+/// What duty cycle a zone's fans are set to when the program exits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Restore {
+    /// The zone's `restore_dcycle`
+    #[default]
+    Dcycle,
+    /// The duty cycle that was read when the zone was first started
+    Snapshot,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub temp: i8,
+    pub dcycle: u8,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpmStep {
+    pub temp: i8,
+    pub rpm: u32,
+}
+
+/// Time of day in `HH:MM` format, stored as minutes since midnight.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "String")]
+#[schemars(with = "String")]
+pub struct TimeOfDay(pub u16);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (hours, minutes) = value.split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u16>().ok()?, m.parse::<u16>().ok()?)))
+            .filter(|(h, m)| *h < 24 && *m < 60)
+            .ok_or_else(|| format!("invalid time of day (expected HH:MM): {:?}", value))?;
+
+        Ok(Self(hours * 60 + minutes))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Sun,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+}
+
+/// Time range during which a zone uses a different step table or a lower
+/// maximum duty cycle.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// Start of the range (inclusive)
+    pub start: TimeOfDay,
+    /// End of the range (exclusive). If it is not after `start`, the range
+    /// ends on the next day.
+    pub end: TimeOfDay,
+    /// Days on which the range starts. If empty, the range starts every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Name of the step table in `step_tables` used instead of `steps`
+    pub steps: Option<String>,
+    /// Cap for the duty cycle computed from the steps
+    pub max_dcycle: Option<u8>,
+}
+
+impl Schedule {
+    /// Check if the schedule is active at `minute` (since midnight) on `day`.
+    pub fn is_active(&self, day: Weekday, minute: u16) -> bool {
+        let starts_on = |d: Weekday| self.days.is_empty() || self.days.contains(&d);
+
+        if self.start.0 < self.end.0 {
+            starts_on(day) && (self.start.0..self.end.0).contains(&minute)
+        } else if minute >= self.start.0 {
+            starts_on(day)
+        } else {
+            // Ranges that wrap around midnight started on the previous day
+            let previous = match day {
+                Weekday::Sun => Weekday::Sat,
+                Weekday::Mon => Weekday::Sun,
+                Weekday::Tue => Weekday::Mon,
+                Weekday::Wed => Weekday::Tue,
+                Weekday::Thu => Weekday::Wed,
+                Weekday::Fri => Weekday::Thu,
+                Weekday::Sat => Weekday::Fri,
+            };
+            minute < self.end.0 && starts_on(previous)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct SessionName(pub String);
+
+impl Default for SessionName {
+    fn default() -> Self {
+        Self("default".to_owned())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum SourceType {
+    Ipmi {
+        #[serde(default)]
+        sensor: String,
+        /// Wildcard pattern used instead of `sensor`. The source reports the
+        /// highest reading of the matching sensors.
+        sensor_pattern: Option<String>,
+        /// Units of the sensor, which don't have to be a temperature
+        #[serde(default)]
+        units: IpmiUnits,
+        /// The reading is divided by this so that it fits in the range of the
+        /// steps' `temp`
+        #[serde(default)]
+        divisor: Divisor,
+    },
+    File {
+        // TOML can't encode OsString
+        path: String,
+        /// Units of the thousandths of a degree in the file
+        #[serde(default)]
+        units: TempUnits,
+    },
+    Smart {
+        // TOML can't encode OsString
+        block_dev: String,
+        /// smartctl device type (`-d`), like `sat` or `megaraid,N`
+        device_type: Option<String>,
+        /// Extra arguments passed to smartctl before the block device
+        #[serde(default)]
+        args: Vec<String>,
+        standby: Option<StandbyPolicy>,
+        #[serde(default)]
+        backend: SmartBackend,
+    },
+    Hdparm {
+        // TOML can't encode OsString
+        block_dev: String,
+        standby: Option<StandbyPolicy>,
+    },
+    /// A `smart` source for every whole-disk block device matching the
+    /// patterns. This is expanded when the config is loaded.
+    Disks {
+        /// Paths where the last component may contain `*` and `?` wildcards
+        patterns: Vec<String>,
+        /// Wildcard patterns for matched paths to leave out
+        #[serde(default)]
+        exclude: Vec<String>,
+        standby: Option<StandbyPolicy>,
+        #[serde(default)]
+        backend: SmartBackend,
+    },
+    /// A `smart` source for every disk in a ZFS pool, including spares and
+    /// cache and log devices. This is expanded when the config is loaded.
+    Zpool {
+        pool: String,
+        standby: Option<StandbyPolicy>,
+        #[serde(default)]
+        backend: SmartBackend,
+    },
+    /// CPU usage as a percentage (Linux only)
+    Cpu {
+        #[serde(default)]
+        metric: CpuMetric,
+        /// Temperatures that 0% and 100% are mapped to so that the source can
+        /// be used alongside temperature sources
+        range: Option<[i8; 2]>,
+    },
+    /// Difference between two sources, like exhaust minus inlet temperature
+    Delta {
+        #[serde(deserialize_with = "deserialize_boxed_source_type")]
+        source: Box<SourceType>,
+        #[serde(deserialize_with = "deserialize_boxed_source_type")]
+        reference: Box<SourceType>,
+    },
+    Wmi {
+        /// ACPI thermal zone instance name or hardware monitor sensor
+        /// identifier, depending on the provider
+        sensor: String,
+        #[serde(default)]
+        provider: WmiProvider,
+    },
+    /// A source type that was added with [`crate::source::register_source`].
+    /// This is not deserialized directly, but from any source table whose
+    /// `type` is registered and is not one of the built-in types.
+    #[serde(skip)]
+    #[schemars(skip)]
+    Custom {
+        type_name: String,
+        /// The source's keys, except for `type` and the source options
+        params: CustomParams,
+    },
+}
+
+/// Keys of a [`SourceType::Custom`] source.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CustomParams(pub Table);
+
+// TOML floats can't be NaN, so the comparison is reflexive
+impl Eq for CustomParams {}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipmi { sensor_pattern: Some(p), .. } => write!(f, "ipmi:{}", p),
+            Self::Ipmi { sensor, .. } => write!(f, "ipmi:{}", sensor),
+            Self::File { path, .. } => write!(f, "file:{}", path),
+            Self::Smart { block_dev, device_type, args, .. } => {
+                // Disks behind a RAID controller share the controller's block
+                // device, so the device type is needed to tell them apart
+                f.write_str("smart:")?;
+                if let Some(t) = device_type {
+                    write!(f, "{}:", t)?;
+                }
+                f.write_str(block_dev)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                Ok(())
+            }
+            Self::Hdparm { block_dev, .. } => write!(f, "hdparm:{}", block_dev),
+            Self::Disks { patterns, .. } => write!(f, "disks:{}", patterns.join(",")),
+            Self::Zpool { pool, .. } => write!(f, "zpool:{}", pool),
+            Self::Cpu { metric, .. } => write!(f, "cpu:{}", metric),
+            Self::Delta { source, reference } => write!(f, "delta:({})-({})", source, reference),
+            Self::Wmi { sensor, provider } => write!(f, "wmi:{}:{}", provider, sensor),
+            Self::Custom { type_name, params } => {
+                write!(f, "{}:", type_name)?;
+                for (i, (key, value)) in params.0.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    match value {
+                        // toml writes tables on multiple lines
+                        Value::Table(_) => write!(f, "{}={}", key,
+                                                  serde_json::to_string(value).map_err(|_| fmt::Error)?)?,
+                        _ => write!(f, "{}={}", key, value)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl SourceType {
+    /// Values of `type` that are handled by [`SourceType`]'s own variants. This
+    /// must be kept in sync with the enum variants.
+    pub const BUILTIN_TYPES: &'static [&'static str] = &[
+        "ipmi", "file", "smart", "hdparm", "disks", "zpool", "cpu", "delta", "wmi",
+    ];
+
+    /// Get the value of the source's `type` key.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Self::Ipmi { .. } => "ipmi",
+            Self::File { .. } => "file",
+            Self::Smart { .. } => "smart",
+            Self::Hdparm { .. } => "hdparm",
+            Self::Disks { .. } => "disks",
+            Self::Zpool { .. } => "zpool",
+            Self::Cpu { .. } => "cpu",
+            Self::Delta { .. } => "delta",
+            Self::Wmi { .. } => "wmi",
+            Self::Custom { type_name, .. } => type_name,
+        }
+    }
+
+    /// Get the sources that a reading is computed from, which is the source
+    /// itself unless it is a `delta` source.
+    pub fn leaves(&self) -> Vec<&SourceType> {
+        match self {
+            Self::Delta { source, reference } => {
+                let mut leaves = source.leaves();
+                leaves.extend(reference.leaves());
+                leaves
+            }
+            _ => vec![self],
+        }
+    }
+
+    /// Get the IPMI sensors that the source reads from. Each item is the
+    /// sensor name, the sensor pattern if there is one, and the units.
+    pub fn ipmi_sensors(&self) -> Vec<(&str, Option<&str>, IpmiUnits)> {
+        self.leaves()
+            .into_iter()
+            .filter_map(|t| match t {
+                Self::Ipmi { sensor, sensor_pattern, units, .. } => {
+                    Some((sensor.as_str(), sensor_pattern.as_deref(), *units))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get what to do if the source's drive is spun down or [`None`] if the
+    /// source should fail.
+    pub fn standby(&self) -> Option<StandbyPolicy> {
+        match self {
+            Self::Smart { standby, .. }
+            | Self::Hdparm { standby, .. }
+            | Self::Disks { standby, .. }
+            | Self::Zpool { standby, .. } => *standby,
+            // Custom sources can report standby too, like plugin sources
+            Self::Custom { params, .. } => custom_standby(&params.0).and_then(Result::ok),
+            _ => None,
+        }
+    }
+}
+
+/// What a drive source reports while the drive is spun down. Drives are never
+/// woken up to read their temperature.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StandbyPolicy {
+    /// Skip the source, like a failed optional source
+    Skip,
+    /// Reuse the last reading from before the drive was spun down
+    LastValue,
+    /// Report a fixed temperature
+    TreatAs(i8),
+}
+
+/// WMI class that a `wmi` source reads from (Windows only).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WmiProvider {
+    /// `MSAcpi_ThermalZoneTemperature` in `root/WMI`
+    #[default]
+    Acpi,
+    /// `Sensor` in `root/LibreHardwareMonitor`
+    LibreHardwareMonitor,
+    /// `Sensor` in `root/OpenHardwareMonitor`
+    OpenHardwareMonitor,
+}
+
+impl fmt::Display for WmiProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Acpi => f.write_str("acpi"),
+            Self::LibreHardwareMonitor => f.write_str("librehardwaremonitor"),
+            Self::OpenHardwareMonitor => f.write_str("openhardwaremonitor"),
+        }
+    }
+}
+
+/// Units of an `ipmi` source. Anything other than a temperature makes the
+/// steps' `temp` refer to those units instead.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpmiUnits {
+    #[default]
+    Celsius,
+    Volts,
+    Amps,
+    Watts,
+}
+
+impl IpmiUnits {
+    /// Get the type of sensor that reports these units.
+    pub fn sensor_type(self) -> SensorType {
+        match self {
+            Self::Celsius => SensorType::Temperature,
+            Self::Volts => SensorType::Voltage,
+            Self::Amps => SensorType::Current,
+            Self::Watts => SensorType::Power,
+        }
+    }
+
+    pub fn sensor_units(self) -> SensorUnits {
+        match self {
+            Self::Celsius => SensorUnits::Celsius,
+            Self::Volts => SensorUnits::Volts,
+            Self::Amps => SensorUnits::Amps,
+            Self::Watts => SensorUnits::Watts,
+        }
+    }
+}
+
+/// Units of temperatures in the config or in a `file` source.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TempUnits {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TempUnits {
+    /// Convert a temperature in these units to degrees Celsius.
+    pub fn to_celsius(self, temp: f64) -> f64 {
+        match self {
+            Self::Celsius => temp,
+            Self::Fahrenheit => (temp - 32.0) / 1.8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct Divisor(pub u32);
+
+impl Default for Divisor {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// What a `cpu` source measures.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuMetric {
+    /// Busy time across all CPUs since the previous reading, from /proc/stat
+    #[default]
+    Utilization,
+    /// 1-minute load average divided by the number of CPUs
+    Load1,
+    /// 5-minute load average divided by the number of CPUs
+    Load5,
+    /// 15-minute load average divided by the number of CPUs
+    Load15,
+}
+
+impl fmt::Display for CpuMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Utilization => f.write_str("utilization"),
+            Self::Load1 => f.write_str("load1"),
+            Self::Load5 => f.write_str("load5"),
+            Self::Load15 => f.write_str("load15"),
+        }
+    }
+}
+
+/// How a `smart` source reads the drive's temperature.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmartBackend {
+    /// Run `smartctl`
+    #[default]
+    Smartctl,
+    /// Send ATA or NVMe commands to the drive directly (Linux only)
+    Native,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct Weight(pub u32);
+
+impl Default for Weight {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// Options that apply to all source types.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SourceOptions {
+    /// Minimum time between queries of the source
+    pub poll_interval: Option<Interval>,
+    /// Weight of the source when using weighted average aggregation
+    #[serde(default)]
+    pub weight: Weight,
+    /// Whether the source is skipped with a warning if it fails
+    #[serde(default)]
+    pub optional: bool,
+    /// Retry settings that override the zone's
+    pub retries: Option<Retries>,
+    pub retry_delay: Option<RetryDelay>,
+    pub retry_backoff: Option<Backoff>,
+    pub retry_max_delay: Option<RetryDelay>,
+    pub retry_jitter: Option<bool>,
+    pub retry_max_elapsed: Option<RetryDelay>,
+}
+
+impl SourceOptions {
+    /// Keys in a source's config that belong to [`SourceOptions`] instead of
+    /// [`SourceType`]. This must be kept in sync with the struct fields.
+    const FIELDS: &'static [&'static str] = &[
+        "poll_interval", "weight", "optional", "retries", "retry_delay", "retry_backoff",
+        "retry_max_delay", "retry_jitter", "retry_max_elapsed",
+    ];
+
+    /// Whether any of the zone's retry settings are overridden.
+    fn has_retry_options(&self) -> bool {
+        self.retries.is_some()
+            || self.retry_delay.is_some()
+            || self.retry_backoff.is_some()
+            || self.retry_max_delay.is_some()
+            || self.retry_jitter.is_some()
+            || self.retry_max_elapsed.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Source {
+    /// Where the temperature is read from
+    pub source_type: SourceType,
+    pub options: SourceOptions,
+}
+
+impl Source {
+    /// Minimum time between queries of this source or [`None`] if the source
+    /// should be queried on every fan update interval.
+    pub fn poll_interval(&self) -> Option<Duration> {
+        self.options.poll_interval.map(Interval::to_duration)
+    }
+
+    /// Get the retry settings for this source, falling back to the zone's
+    /// settings for anything that is not overridden.
+    pub fn retry_policy(&self, zone: &RetryPolicy) -> RetryPolicy {
+        let o = &self.options;
+
+        RetryPolicy {
+            retries: o.retries.map_or(zone.retries, |r| r.0),
+            delay: o.retry_delay.map_or(zone.delay, |d| d.0),
+            backoff: o.retry_backoff.unwrap_or(zone.backoff),
+            max_delay: o.retry_max_delay.map(|d| d.0).or(zone.max_delay),
+            jitter: o.retry_jitter.unwrap_or(zone.jitter),
+            max_elapsed: o.retry_max_elapsed.map(|d| d.0).or(zone.max_elapsed),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.source_type.fmt(f)
+    }
+}
+
+/// Convert a source's table, without the source options, into a
+/// [`SourceType`]. If the `type` is not built-in, but is registered, the source
+/// is a [`SourceType::Custom`] and its keys are checked by creating the source.
+fn source_type_from_table(mut table: Table) -> Result<SourceType, String> {
+    let custom_type = match table.get("type") {
+        Some(Value::String(t)) if !SourceType::BUILTIN_TYPES.contains(&t.as_str())
+            && source::is_registered(t) => Some(t.clone()),
+        _ => None,
+    };
+
+    match custom_type {
+        Some(type_name) => {
+            table.remove("type");
+
+            if let Some(Err(e)) = custom_standby(&table) {
+                return Err(format!("standby: {}", e));
+            }
+
+            let source_type = SourceType::Custom {
+                type_name,
+                params: CustomParams(table),
+            };
+            // Catch invalid keys when loading instead of on every query
+            source::create_source(&source_type).map_err(|e| e.to_string())?;
+
+            Ok(source_type)
+        }
+        None => Value::Table(table).try_into().map_err(|e: toml::de::Error| e.to_string()),
+    }
+}
+
+/// Parse a custom source's `standby` key, if it has one. toml can't deserialize
+/// `{ treat_as = <temp> }` from a [`Value`], so the key goes through JSON.
+fn custom_standby(params: &Table) -> Option<Result<StandbyPolicy, String>> {
+    let value = params.get("standby")?;
+
+    Some(serde_json::to_value(value)
+        .and_then(StandbyPolicy::deserialize)
+        .map_err(|e| e.to_string()))
+}
+
+/// Deserialize a `delta` source's part, which may also be a custom source.
+fn deserialize_boxed_source_type<'de, D>(deserializer: D) -> Result<Box<SourceType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let table = Table::deserialize(deserializer)?;
+
+    source_type_from_table(table).map(Box::new).map_err(de::Error::custom)
+}
+
+/// Deserialize a map into [`SourceType`] and [`SourceOptions`]. The keys are
+/// split manually because serde's flatten does not support deny_unknown_fields.
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut type_table = Table::deserialize(deserializer)?;
+        let mut options_table = Table::new();
+
+        for field in SourceOptions::FIELDS {
+            if let Some(value) = type_table.remove(*field) {
+                options_table.insert((*field).to_owned(), value);
+            }
+        }
+
+        Ok(Source {
+            source_type: source_type_from_table(type_table).map_err(de::Error::custom)?,
+            options: Value::Table(options_table).try_into()
+                .map_err(de::Error::custom)?,
+        })
+    }
+}
+
+/// Schema for [`Source`], which has the keys of both [`SourceType`] and
+/// [`SourceOptions`] in the same table.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct SourceSchema {
+    #[schemars(flatten)]
+    source_type: SourceType,
+    #[schemars(flatten)]
+    options: SourceOptions,
+}
+
+impl JsonSchema for Source {
+    fn schema_name() -> Cow<'static, str> {
+        "Source".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let mut schema = SourceSchema::json_schema(generator);
+        schema.insert("description".to_owned(), "Temperature source".into());
+        // Flattening drops the types' deny_unknown_fields
+        schema.insert("unevaluatedProperties".to_owned(), false.into());
+        schema
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum Aggregation {
+    Maximum,
+    Average {
+        top: Option<usize>,
+    },
+    /// Average where each source counts according to its `weight`
+    Weighted,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Self::Maximum
+    }
+}
+
+/// Where a zone's duty cycle is written to.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum Output {
+    /// The zone's `ipmi_zones` via the zone's IPMI session
+    #[default]
+    Ipmi,
+    /// Linux hwmon PWM outputs (eg. `/sys/class/hwmon/hwmon0/pwm1`)
+    Hwmon {
+        // TOML can't encode OsString
+        pwm: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Zone {
+    /// Name used in log messages and status output
+    pub name: Option<String>,
+    #[serde(default)]
+    pub session: SessionName,
+    #[serde(default)]
+    pub interval: Interval,
+    /// Upper limit for the random delay added to each interval
+    pub interval_jitter: Option<Interval>,
+    #[serde(default)]
+    pub retries: Retries,
+    #[serde(default, alias = "retry_delay_ms")]
+    pub retry_delay: RetryDelay,
+    #[serde(default)]
+    pub retry_backoff: Backoff,
+    pub retry_max_delay: Option<RetryDelay>,
+    #[serde(default)]
+    pub retry_jitter: bool,
+    pub retry_max_elapsed: Option<RetryDelay>,
+    #[serde(default)]
+    pub output: Output,
+    #[serde(default)]
+    pub ipmi_zones: Vec<u8>,
+    /// IPMI fan tachometer sensors to monitor for stalls
+    #[serde(default)]
+    pub fan_sensors: Vec<String>,
+    pub sources: Vec<Source>,
+    /// `sources` as written in the config if it contains `disks` or `zpool`
+    /// sources, which are replaced in `sources` by the disks they matched
+    #[serde(skip)]
+    pub unexpanded_sources: Vec<Source>,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// Units of the temperatures in the zone's settings. They are converted
+    /// to Celsius when the config is loaded.
+    #[serde(default)]
+    pub units: TempUnits,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// Steps used instead of `steps` while the duty cycle is decreasing
+    #[serde(default)]
+    pub cooldown_steps: Vec<Step>,
+    /// Named alternatives to `steps` that can be selected by `schedules`
+    #[serde(default)]
+    pub step_tables: HashMap<String, Vec<Step>>,
+    /// Time ranges that change the steps or the maximum duty cycle. The first
+    /// active schedule is used.
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    /// Target fan speeds, which are reached by adjusting the duty cycle based
+    /// on the `fan_sensors` readings
+    #[serde(default)]
+    pub rpm_steps: Vec<RpmStep>,
+    /// Limits for the duty cycle computed from the steps
+    #[serde(default)]
+    pub min_dcycle: MinDcycle,
+    #[serde(default)]
+    pub max_dcycle: MaxDcycle,
+    pub emergency_temp: Option<i8>,
+    pub critical: Option<Critical>,
+    /// Temperature at or below which the fans are stopped
+    pub stop_temp: Option<i8>,
+    pub spinup: Option<Spinup>,
+    /// Duty cycle applied as soon as the zone takes control of the fans
+    pub initial_dcycle: Option<u8>,
+    /// How long `initial_dcycle` is kept before the duty cycle follows the
+    /// steps
+    pub startup_grace: Option<Interval>,
+    /// Raise the duty cycle while the temperature is rising quickly
+    pub trend_boost: Option<TrendBoost>,
+    #[serde(default)]
+    pub oscillation_damping: OscillationDamping,
+    #[serde(default)]
+    pub failsafe_dcycle: FailsafeDcycle,
+    #[serde(default)]
+    pub failsafe_cycles: FailsafeCycles,
+    #[serde(default)]
+    pub restore: Restore,
+    #[serde(default)]
+    pub restore_dcycle: RestoreDcycle,
+    /// File containing a duty cycle override and an optional duration
+    // TOML can't encode OsString
+    pub override_file: Option<String>,
+    /// Rhai script that computes the duty cycle instead of the steps
+    // TOML can't encode OsString
+    pub script: Option<String>,
+    /// Log how the duty cycle was computed on every iteration
+    #[serde(default)]
+    pub log_decisions: bool,
+    /// Only log the status message when the temperature or duty cycle changes
+    #[serde(default)]
+    pub log_on_change_only: bool,
+    /// Log the status message at least this often with `log_on_change_only`
+    pub log_heartbeat: Option<Interval>,
+}
+
+impl Zone {
+    /// Get the zone's name for log messages. If the zone has no name, then
+    /// the session and the IPMI zones or PWM outputs are used instead.
+    pub fn label(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        match &self.output {
+            Output::Ipmi => {
+                let zones = self.ipmi_zones.iter()
+                    .map(|z| z.to_string())
+                    .collect::<Vec<_>>();
+                format!("{}:{}", self.session.0, zones.join(","))
+            }
+            Output::Hwmon { pwm } => format!("hwmon:{}", pwm.join(",")),
+        }
+    }
+
+    /// Get the time to wait before the next iteration, including a random
+    /// delay of up to `interval_jitter`.
+    pub fn next_interval(&self) -> Duration {
+        match self.interval_jitter {
+            Some(j) => self.interval.to_duration() + jitter(j.to_duration()),
+            None => self.interval.to_duration(),
+        }
+    }
+
+    /// Get the zone's retry settings for querying sources and writing duty
+    /// cycles.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries.0,
+            delay: self.retry_delay.0,
+            backoff: self.retry_backoff,
+            max_delay: self.retry_max_delay.map(|d| d.0),
+            jitter: self.retry_jitter,
+            max_elapsed: self.retry_max_elapsed.map(|d| d.0),
+        }
+    }
+
+    /// Get the duty cycle to restore on exit or [`None`] if the duty cycle
+    /// read at startup should be restored.
+    pub fn restore_dcycle(&self) -> Option<u8> {
+        match self.restore {
+            Restore::Dcycle => Some(self.restore_dcycle.0),
+            Restore::Snapshot => None,
+        }
+    }
+
+    /// Whether the zone needs an IPMI session, either for its output, its fan
+    /// sensors, or its sources.
+    pub fn uses_ipmi(&self) -> bool {
+        self.output == Output::Ipmi
+            || !self.fan_sensors.is_empty()
+            || self.sources.iter().any(|s| !s.source_type.ipmi_sensors().is_empty())
+    }
+}
+
+/// Simple wrapper around a password string with a redacted Debug implementation
+#[derive(Clone, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct Password(pub String);
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// BMC key (K_g) for IPMI 2.0 authentication. This is either a plain string or
+/// a hex string prefixed with `0x`.
+#[derive(Clone, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "String")]
+#[schemars(with = "String")]
+pub struct KG(pub Vec<u8>);
+
+impl KG {
+    /// Maximum key length supported by IPMI 2.0
+    const MAX_LEN: usize = 20;
+}
+
+impl fmt::Debug for KG {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl TryFrom<String> for KG {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let bytes = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(hex) => {
+                if hex.len() % 2 != 0 {
+                    return Err("hex k_g must have an even number of digits".to_owned());
+                }
+
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        hex.get(i..i + 2)
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                            .ok_or_else(|| "invalid hex digit in k_g".to_owned())
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            None => value.into_bytes(),
+        };
+
+        if bytes.len() > Self::MAX_LEN {
+            return Err(format!("k_g must not exceed {} bytes", Self::MAX_LEN));
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+/// IPMI privilege level to request for out-of-band sessions.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegeLevel {
+    User,
+    Operator,
+    #[default]
+    Admin,
+}
+
+/// In-band IPMI driver. If unspecified, freeipmi probes for one.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InBandDriver {
+    Kcs,
+    Ssif,
+    OpenIpmi,
+    SunBmc,
+}
+
+/// freeipmi workarounds for in-band sessions. The names are the same as in
+/// freeipmi's `--workaround-flags` option.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub enum InBandWorkaround {
+    #[serde(rename = "assumeio")]
+    AssumeIoBaseAddress,
+    #[serde(rename = "spinpoll")]
+    SpinPoll,
+}
+
+/// IPMI version of an out-of-band session.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+pub enum IpmiVersion {
+    /// RMCP (`lan` interface), for BMCs that don't support IPMI 2.0
+    #[serde(rename = "1.5")]
+    V15,
+    /// RMCP+ (`lanplus` interface)
+    #[default]
+    #[serde(rename = "2.0")]
+    V20,
+}
+
+impl fmt::Display for IpmiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V15 => f.write_str("1.5"),
+            Self::V20 => f.write_str("2.0"),
+        }
+    }
+}
+
+/// Authentication type for IPMI 1.5 sessions.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthenticationType {
+    None,
+    StraightPasswordKey,
+    Md2,
+    Md5,
+}
+
+/// freeipmi workarounds for out-of-band sessions. The names are the same as
+/// in freeipmi's `--workaround-flags` option.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub enum OutOfBandWorkaround {
+    #[serde(rename = "authcap")]
+    AuthenticationCapabilities,
+    #[serde(rename = "idzero")]
+    AcceptSessionIdZero,
+    #[serde(rename = "forcepermsg")]
+    ForcePermsgAuthentication,
+    #[serde(rename = "unexpectedauth")]
+    CheckUnexpectedAuthcode,
+    #[serde(rename = "endianseq")]
+    BigEndianSequenceNumber,
+    #[serde(rename = "noauthcodecheck")]
+    NoAuthCodeCheck,
+    #[serde(rename = "intel20")]
+    Intel20Session,
+    #[serde(rename = "supermicro20")]
+    Supermicro20Session,
+    #[serde(rename = "sun20")]
+    Sun20Session,
+    #[serde(rename = "opensesspriv")]
+    OpenSessionPrivilege,
+    #[serde(rename = "integritycheckvalue")]
+    NonEmptyIntegrityCheckValue,
+    #[serde(rename = "nochecksumcheck")]
+    NoChecksumCheck,
+}
+
+impl OutOfBandWorkaround {
+    /// Whether the workaround applies to sessions of the given IPMI version.
+    pub fn supports(self, version: IpmiVersion) -> bool {
+        match self {
+            Self::AuthenticationCapabilities => true,
+            Self::AcceptSessionIdZero
+            | Self::ForcePermsgAuthentication
+            | Self::CheckUnexpectedAuthcode
+            | Self::BigEndianSequenceNumber
+            | Self::NoAuthCodeCheck => version == IpmiVersion::V15,
+            Self::Intel20Session
+            | Self::Supermicro20Session
+            | Self::Sun20Session
+            | Self::OpenSessionPrivilege
+            | Self::NonEmptyIntegrityCheckValue
+            | Self::NoChecksumCheck => version == IpmiVersion::V20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum SessionType {
+    Local {
+        driver: Option<InBandDriver>,
+        /// Device path, like `/dev/i2c-0` for SSIF
+        // TOML can't encode OsString
+        driver_device: Option<String>,
+        /// I/O or SMBus address of the BMC
+        driver_address: Option<u16>,
+        register_spacing: Option<u8>,
+        #[serde(default)]
+        workaround_flags: Vec<InBandWorkaround>,
+    },
+    Remote {
+        hostname: String,
+        /// Other addresses of the same BMC to fail over to, in order, if
+        /// `hostname` cannot be reached
+        #[serde(default)]
+        failover_hostnames: Vec<String>,
+        username: String,
+        /// Always [`Some`] after the config is loaded since `password_file`
+        /// and `password_env` are resolved into this field
+        password: Option<Password>,
+        // TOML can't encode OsString
+        password_file: Option<String>,
+        /// Name of the environment variable containing the password
+        password_env: Option<String>,
+        #[serde(default)]
+        privilege_level: PrivilegeLevel,
+        #[serde(default)]
+        ipmi_version: IpmiVersion,
+        /// IPMI 1.5 authentication type or [`None`] for the default (MD5)
+        authentication_type: Option<AuthenticationType>,
+        /// IPMI 2.0 cipher suite ID or [`None`] for the default (3)
+        cipher_suite_id: Option<u8>,
+        k_g: Option<KG>,
+        /// Session timeout or [`None`] for the freeipmi default
+        session_timeout_ms: Option<u32>,
+        /// Retransmission timeout or [`None`] for the freeipmi default
+        retransmission_timeout_ms: Option<u32>,
+        #[serde(default)]
+        workaround_flags: Vec<OutOfBandWorkaround>,
+    },
+}
+
+impl SessionType {
+    /// Whether any freeipmi-specific driver or workaround options are set.
+    pub fn has_freeipmi_options(&self) -> bool {
+        match self {
+            Self::Local { driver, driver_device, driver_address, register_spacing, workaround_flags } => {
+                driver.is_some()
+                    || driver_device.is_some()
+                    || driver_address.is_some()
+                    || register_spacing.is_some()
+                    || !workaround_flags.is_empty()
+            }
+            Self::Remote { workaround_flags, .. } => !workaround_flags.is_empty(),
+        }
+    }
+
+    /// All addresses of a remote BMC, starting with the primary hostname.
+    /// Local sessions have no addresses.
+    pub fn hostnames(&self) -> Vec<&str> {
+        match self {
+            Self::Local { .. } => vec![],
+            Self::Remote { hostname, failover_hostnames, .. } => {
+                std::iter::once(hostname)
+                    .chain(failover_hostnames)
+                    .map(String::as_str)
+                    .collect()
+            }
+        }
+    }
+
+    /// Get a copy of this session type that connects to the address at
+    /// `index` in [`Self::hostnames`].
+    pub fn with_host(&self, index: usize) -> Self {
+        let mut result = self.clone();
+
+        if let Self::Remote { hostname, failover_hostnames, .. } = &mut result {
+            if index > 0 {
+                *hostname = failover_hostnames[index - 1].clone();
+            }
+        }
+
+        result
+    }
+}
+
+impl Default for SessionType {
+    fn default() -> Self {
+        Self::Local {
+            driver: None,
+            driver_device: None,
+            driver_address: None,
+            register_spacing: None,
+            workaround_flags: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Parser, ValueEnum)]
+#[clap(rename_all = "lower")]
+pub enum IpmitoolInterfaceOpt {
+    Lan,
+    LanPlus,
+}
+
+/// Basic compatibility layer for ipmitool's command line arguments
+#[derive(Debug, Parser)]
+pub struct IpmitoolOpt {
+    #[clap(short = 'I', value_enum)]
+    pub interface: IpmitoolInterfaceOpt,
+    #[clap(short = 'H')]
+    pub hostname: String,
+    #[clap(short = 'U')]
+    pub username: String,
+    #[clap(short = 'P')]
+    pub password: String,
+}
+
+/// Byte in a raw command template.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TemplateByte {
+    Literal(u8),
+    /// Replaced by the IPMI zone
+    Zone,
+    /// Replaced by the duty cycle or fan mode being written
+    Value,
+}
+
+/// Raw IPMI command template in the same format as `ipmitool raw`: a
+/// whitespace-separated list of bytes, starting with the network function and
+/// command. Bytes can be written in hex (`0x30`) or decimal (`48`). The
+/// placeholders `{zone}` and `{value}` are substituted when the command is run.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "String")]
+#[schemars(with = "String")]
+pub struct CommandTemplate(pub Vec<TemplateByte>);
+
+impl CommandTemplate {
+    fn has_placeholder(&self, placeholder: TemplateByte) -> bool {
+        self.0.contains(&placeholder)
+    }
+
+    /// Substitute the placeholders and return the full command, including the
+    /// network function and command bytes.
+    pub fn expand(&self, zone: u8, value: u8) -> Vec<u8> {
+        self.0.iter()
+            .map(|b| match b {
+                TemplateByte::Literal(n) => *n,
+                TemplateByte::Zone => zone,
+                TemplateByte::Value => value,
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for CommandTemplate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let bytes = value.split_whitespace()
+            .map(|token| match token {
+                "{zone}" => Ok(TemplateByte::Zone),
+                "{value}" => Ok(TemplateByte::Value),
+                t => {
+                    let n = match t.strip_prefix("0x").or_else(|| t.strip_prefix("0X")) {
+                        Some(hex) => u8::from_str_radix(hex, 16),
+                        None => t.parse(),
+                    };
+
+                    n.map(TemplateByte::Literal)
+                        .map_err(|e| format!("invalid byte in command {:?}: {:?}: {}", value, t, e))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if bytes.len() < 2 {
+            return Err(format!("command {:?} must contain at least the network function and command bytes", value));
+        } else if bytes[..2].iter().any(|b| !matches!(b, TemplateByte::Literal(_))) {
+            return Err(format!("command {:?} cannot have placeholders for the network function or command", value));
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+/// Raw command template for reading a value.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReadCommand {
+    pub command: CommandTemplate,
+    /// Index of the value within the response data (excluding the completion
+    /// code)
+    #[serde(default)]
+    pub response_index: usize,
+}
+
+/// User-defined fan control protocol built from raw IPMI commands.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RawProtocol {
+    /// If [`None`], the last written duty cycle is assumed
+    pub get_duty: Option<ReadCommand>,
+    pub set_duty: CommandTemplate,
+    pub get_mode: Option<ReadCommand>,
+    pub set_mode: Option<CommandTemplate>,
+    /// Mode value that allows the duty cycle to be controlled manually
+    pub manual_mode: Option<u8>,
+}
+
+/// Supermicro board generation, which determines how fans are addressed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SupermicroGeneration {
+    X9,
+    X10,
+    X11,
+    X12,
+    X13,
+    H11,
+    H12,
+    H13,
+}
+
+impl SupermicroGeneration {
+    /// Whether the BMC addresses individual fan headers instead of zones.
+    pub fn per_fan(self) -> bool {
+        matches!(self, Self::X12 | Self::X13 | Self::H12 | Self::H13)
+    }
+}
+
+/// Command set used for fan control.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum Protocol {
+    Supermicro {
+        /// If [`None`], zones are addressed like on every generation up to X11
+        generation: Option<SupermicroGeneration>,
+    },
+    /// Lenovo/IBM IMM2 OEM commands, as found on System x M4 and M5 servers
+    Imm,
+    /// Tyan OEM commands for boards with an AMI MegaRAC BMC
+    Tyan,
+    Raw(RawProtocol),
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self::Supermicro { generation: None }
+    }
+}
+
+/// Fan mode to set on the BMC while the program is running.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionFanMode {
+    #[default]
+    Full,
+    HeavyIo,
+    /// Leave the fan mode untouched and only write duty cycles
+    None,
+}
+
+/// How often to check that the BMC is still in the session's fan mode. Zero
+/// disables the check.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct FanModeCheckInterval(pub Interval);
+
+impl Default for FanModeCheckInterval {
+    fn default() -> Self {
+        Self(Interval(Duration::from_secs(60)))
+    }
+}
+
+/// Number of consecutive failed IPMI commands before control is handed back to
+/// the BMC. Zero makes IPMI failures fatal instead.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct IpmiFailureLimit(pub u32);
+
+impl Default for IpmiFailureLimit {
+    fn default() -> Self {
+        Self(3)
+    }
+}
+
+/// Library or tool used to communicate with the BMC.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Freeipmi,
+    /// Run `ipmitool` for every command
+    Ipmitool,
+    /// Built-in RMCP+ client for remote sessions
+    Native,
+    /// Backend provided by a plugin, named by `plugin`
+    Plugin,
+    /// Redfish API over HTTPS via `curl`, like on HPE iLO. Only sensors can be
+    /// read.
+    Redfish,
+}
+
+/// Lower thresholds of one of the BMC's fan sensors in RPM. Thresholds that are
+/// not specified are left unchanged.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FanThreshold {
+    /// Sensor name, as listed by the `sensors` subcommand
+    pub sensor: String,
+    pub lower_non_recoverable: Option<u32>,
+    pub lower_critical: Option<u32>,
+    pub lower_non_critical: Option<u32>,
+}
+
+/// IPMB target that raw commands are bridged to, like a node's BMC in a
+/// multi-node chassis or a satellite management controller.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Bridge {
+    /// 8-bit IPMB slave address
+    pub address: u8,
+    /// Channel number, where 0 is the primary IPMB
+    #[serde(default)]
+    pub channel: u8,
+}
+
+/// Options that apply to all session types.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SessionOptions {
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub fan_mode: SessionFanMode,
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default)]
+    pub fan_mode_check_interval: FanModeCheckInterval,
+    #[serde(default)]
+    pub ipmi_failure_limit: IpmiFailureLimit,
+    /// Directory for the freeipmi backend's SDR cache. Defaults to the
+    /// system's temporary directory.
+    // TOML can't encode OsString
+    pub sdr_cache_dir: Option<String>,
+    /// Bridge raw commands to another controller. Sensors are still read
+    /// from the BMC.
+    pub bridge: Option<Bridge>,
+    /// Lower thresholds to set on the BMC's fan sensors when the session is
+    /// opened
+    #[serde(default)]
+    pub fan_thresholds: Vec<FanThreshold>,
+    /// How long the last duty cycle written to each IPMI zone is trusted
+    /// instead of reading it back. [`None`] reads it every interval.
+    pub duty_cycle_cache: Option<Interval>,
+    /// Send a lightweight command to a remote BMC after the session has been
+    /// idle for this long so that the session doesn't time out
+    pub keepalive_interval: Option<Interval>,
+    /// Name of the plugin backend when `backend` is `plugin`
+    pub plugin: Option<String>,
+    /// CA certificate that the BMC's HTTPS certificate is verified against
+    /// when `backend` is `redfish`. Defaults to curl's CA store.
+    // TOML can't encode OsString
+    pub ca_file: Option<String>,
+}
+
+impl SessionOptions {
+    /// Keys in a session's config that belong to [`SessionOptions`] instead of
+    /// [`SessionType`]. This must be kept in sync with the struct fields.
+    const FIELDS: &'static [&'static str] = &[
+        "protocol", "fan_mode", "backend", "fan_mode_check_interval", "ipmi_failure_limit",
+        "sdr_cache_dir", "bridge", "fan_thresholds", "duty_cycle_cache", "keepalive_interval",
+        "plugin", "ca_file",
+    ];
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Session {
+    /// How to connect to the BMC
+    pub session_type: SessionType,
+    pub options: SessionOptions,
+}
+
+/// Deserialize either a map as a native [`SessionType`] instance and
+/// [`SessionOptions`] or an array of strings as ipmitool arguments.
+impl<'de> Deserialize<'de> for Session {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SessionVisitor;
+
+        impl<'de> Visitor<'de> for SessionVisitor {
+            type Value = Session;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("native session configuration or ipmitool compatibility layer arguments")
+            }
+
+            // Deserialize an array and parse it as ipmitool arguments
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let args = Vec::<String>::deserialize(SeqAccessDeserializer::new(seq))?;
+
+                // ipmitool defaults to a local connection with run without arguments
+                if args.is_empty() {
+                    return Ok(Session::default());
+                }
+
+                let argv0 = ["ipmitool_compat".to_owned()];
+                let argv = argv0.iter().chain(args.iter());
+
+                let opt = IpmitoolOpt::try_parse_from(argv)
+                    .map_err(|e| de::Error::custom(format!("ipmitool compatibility layer: {}", e)))?;
+
+                Ok(Session {
+                    session_type: SessionType::Remote {
+                        hostname: opt.hostname,
+                        failover_hostnames: vec![],
+                        username: opt.username,
+                        password: Some(Password(opt.password)),
+                        password_file: None,
+                        password_env: None,
+                        privilege_level: PrivilegeLevel::default(),
+                        ipmi_version: match opt.interface {
+                            IpmitoolInterfaceOpt::Lan => IpmiVersion::V15,
+                            IpmitoolInterfaceOpt::LanPlus => IpmiVersion::V20,
+                        },
+                        authentication_type: None,
+                        cipher_suite_id: None,
+                        k_g: None,
+                        session_timeout_ms: None,
+                        retransmission_timeout_ms: None,
+                        workaround_flags: vec![],
+                    },
+                    options: SessionOptions::default(),
+                })
+            }
+
+            // Deserialize a map into SessionType and SessionOptions. The keys
+            // are split manually because serde's flatten does not support
+            // deny_unknown_fields.
+            fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+            where
+                M: MapAccess<'de>,
+            {
+                let mut type_table = Table::deserialize(MapAccessDeserializer::new(map))?;
+                let mut options_table = Table::new();
+
+                for field in SessionOptions::FIELDS {
+                    if let Some(value) = type_table.remove(*field) {
+                        options_table.insert((*field).to_owned(), value);
+                    }
+                }
+
+                Ok(Session {
+                    session_type: Value::Table(type_table).try_into()
+                        .map_err(de::Error::custom)?,
+                    options: Value::Table(options_table).try_into()
+                        .map_err(de::Error::custom)?,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(SessionVisitor)
+    }
+}
+
+/// Schema for [`Session`] when it is specified as a table, which has the keys
+/// of both [`SessionType`] and [`SessionOptions`].
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct SessionSchema {
+    #[schemars(flatten)]
+    session_type: SessionType,
+    #[schemars(flatten)]
+    options: SessionOptions,
+}
+
+impl JsonSchema for Session {
+    fn schema_name() -> Cow<'static, str> {
+        "Session".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let mut table_schema = SessionSchema::json_schema(generator);
+        table_schema.remove("description");
+        // Flattening drops the types' deny_unknown_fields
+        table_schema.insert("unevaluatedProperties".to_owned(), false.into());
+
+        json_schema!({
+            "description": "Connection to a BMC",
+            "anyOf": [
+                table_schema,
+                {
+                    "description": "ipmitool arguments",
+                    "type": "array",
+                    "items": { "type": "string" },
+                },
+            ],
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Sessions(pub HashMap<String, Session>);
+
+/// Plain `http://` URL. HTTPS is not supported.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "String")]
+#[schemars(with = "String")]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    /// Path and query string
+    pub path: String,
+}
+
+impl TryFrom<String> for HttpUrl {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let rest = value.strip_prefix("http://")
+            .ok_or_else(|| format!("unsupported URL (only http:// is supported): {:?}", value))?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') { format!("/{}", path) } else { path.to_owned() };
+
+        // IPv6 addresses are enclosed in brackets
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((h, p)) if !p.contains(']') => {
+                let port = p.parse::<u16>()
+                    .map_err(|e| format!("invalid port in URL {:?}: {}", value, e))?;
+                (h, port)
+            }
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(format!("missing host in URL: {:?}", value));
+        }
+
+        Ok(Self { host: host.to_owned(), port, path })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct Measurement(pub String);
+
+impl Default for Measurement {
+    fn default() -> Self {
+        Self("ipmi_fan_control".to_owned())
+    }
+}
+
+/// Signal that can pause or resume every zone (Unix only).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub enum ControlSignal {
+    #[serde(rename = "SIGUSR1")]
+    Sigusr1,
+    #[serde(rename = "SIGUSR2")]
+    Sigusr2,
+    /// Don't handle any signal
+    #[serde(rename = "none")]
+    None,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct PauseSignal(pub ControlSignal);
+
+impl Default for PauseSignal {
+    fn default() -> Self {
+        Self(ControlSignal::Sigusr1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct ResumeSignal(pub ControlSignal);
+
+impl Default for ResumeSignal {
+    fn default() -> Self {
+        Self(ControlSignal::Sigusr2)
+    }
+}
+
+/// File locked by the daemon to prevent multiple instances from running.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct LockFile(pub String);
+
+impl Default for LockFile {
+    fn default() -> Self {
+        Self("/run/ipmi-fan-control.lock".to_owned())
+    }
+}
+
+/// Event that can trigger an alert.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertEvent {
+    /// A source exceeded the emergency temperature
+    Emergency,
+    /// All sources dropped to or below the emergency temperature again
+    EmergencyCleared,
+    /// A source exceeded the critical temperature
+    Critical,
+    /// All sources dropped to or below the critical temperature again
+    CriticalCleared,
+    /// Fans report 0 RPM while the duty cycle is non-zero
+    FanStall,
+    /// No fans are stalled anymore
+    FanStallCleared,
+    /// Querying the sources failed after previously succeeding
+    SourceFailure,
+    /// The failsafe duty cycle was applied
+    Failsafe,
+    /// An IPMI command failed and the zone loop exited
+    IpmiError,
+    /// IPMI commands failed repeatedly and fan control was handed back to the
+    /// BMC
+    IpmiFallback,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Emergency => f.write_str("emergency"),
+            Self::EmergencyCleared => f.write_str("emergency_cleared"),
+            Self::Critical => f.write_str("critical"),
+            Self::CriticalCleared => f.write_str("critical_cleared"),
+            Self::FanStall => f.write_str("fan_stall"),
+            Self::FanStallCleared => f.write_str("fan_stall_cleared"),
+            Self::SourceFailure => f.write_str("source_failure"),
+            Self::Failsafe => f.write_str("failsafe"),
+            Self::IpmiError => f.write_str("ipmi_error"),
+            Self::IpmiFallback => f.write_str("ipmi_fallback"),
+        }
+    }
+}
+
+/// Hooks that are run when events occur.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Alerts {
+    /// Events that trigger alerts. If empty, all events trigger alerts.
+    #[serde(default)]
+    pub events: Vec<AlertEvent>,
+    /// URL that the event is POSTed to as JSON
+    pub webhook: Option<HttpUrl>,
+    /// Command that is run with the event details in environment variables
+    #[serde(default)]
+    pub command: Vec<String>,
+}
+
+impl Alerts {
+    pub fn enabled(&self, event: AlertEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// InfluxDB line protocol exporter.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDb {
+    /// Write endpoint, including the database or bucket in the query string
+    pub url: HttpUrl,
+    /// API token sent in the `Authorization` header
+    pub token: Option<String>,
+    /// Measurement name for zone data. Source readings use the same name with
+    /// a `_source` suffix.
+    #[serde(default)]
+    pub measurement: Measurement,
+}
+
+/// SNMP object identifier in dotted notation (eg. `1.3.6.1.4.1`).
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(try_from = "String")]
+#[schemars(with = "String")]
+pub struct Oid(pub Vec<u32>);
+
+impl Default for Oid {
+    fn default() -> Self {
+        // netSnmpPlaypen, which is reserved for local experimentation
+        Self(vec![1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 1])
+    }
+}
+
+impl TryFrom<String> for Oid {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let subids = value.trim_start_matches('.')
+            .split('.')
+            .map(|s| s.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid OID {:?}: {}", value, e))?;
+
+        // AgentX limits OIDs to 128 sub-identifiers and the zone table adds 4
+        if subids.len() < 2 || subids.len() > 124 {
+            return Err(format!("invalid OID {:?}: must have 2 to 124 sub-identifiers", value));
+        }
+
+        Ok(Self(subids))
+    }
+}
+
+/// Address of the AgentX master agent.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct AgentxAddress(pub String);
+
+impl Default for AgentxAddress {
+    fn default() -> Self {
+        Self("/var/agentx/master".to_owned())
+    }
+}
+
+/// SNMP subagent that exposes the status of every zone via AgentX.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Snmp {
+    /// Unix socket path or `tcp:host:port` of the master agent
+    #[serde(default)]
+    pub agentx: AgentxAddress,
+    /// Subtree that the zone table is registered under
+    #[serde(default)]
+    pub oid: Oid,
+}
+
+/// How log messages are sent to syslog.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    /// Local syslog daemon's Unix datagram socket
+    #[default]
+    Local,
+    Udp,
+    Tcp,
+}
+
+/// Syslog facility that log messages are sent with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    Kern,
+    User,
+    Mail,
+    #[default]
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Get the facility's numeric code.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Kern => 0,
+            Self::User => 1,
+            Self::Mail => 2,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::Syslog => 5,
+            Self::Lpr => 6,
+            Self::News => 7,
+            Self::Uucp => 8,
+            Self::Cron => 9,
+            Self::Authpriv => 10,
+            Self::Ftp => 11,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// Syslog output, which receives log messages in addition to stderr or the
+/// journal.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Syslog {
+    #[serde(default)]
+    pub transport: SyslogTransport,
+    /// Socket path for the local transport (default: `/dev/log`) or
+    /// `host:port` of the server for the udp and tcp transports
+    pub address: Option<String>,
+    #[serde(default)]
+    pub facility: SyslogFacility,
+}
+
+/// File format of the history recorder.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    /// CSV file that is rotated when it reaches `max_size`
+    #[default]
+    Csv,
+    /// SQLite database written with the `sqlite3` program
+    Sqlite,
+}
+
+/// Size in MiB at which the history CSV file is rotated.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct HistoryMaxSize(pub u64);
+
+impl Default for HistoryMaxSize {
+    fn default() -> Self {
+        Self(16)
+    }
+}
+
+/// Number of rotated history CSV files to keep.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct HistoryMaxFiles(pub usize);
+
+impl Default for HistoryMaxFiles {
+    fn default() -> Self {
+        Self(7)
+    }
+}
+
+/// Recorder that appends the data from every zone loop iteration to a local
+/// file for offline analysis.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct History {
+    #[serde(default)]
+    pub format: HistoryFormat,
+    // TOML can't encode OsString
+    pub path: String,
+    /// Age after which recorded data is deleted
+    pub retention: Option<Interval>,
+    #[serde(default)]
+    pub max_size: HistoryMaxSize,
+    #[serde(default)]
+    pub max_files: HistoryMaxFiles,
+}
+
+/// How often the textfile collector file is rewritten.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub struct TextfileInterval(pub Interval);
+
+impl Default for TextfileInterval {
+    fn default() -> Self {
+        Self(Interval(Duration::from_secs(15)))
+    }
+}
+
+/// Metrics file for node_exporter's textfile collector.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Textfile {
+    // TOML can't encode OsString
+    pub path: String,
+    #[serde(default)]
+    pub interval: TextfileInterval,
+}
+
+/// User and group to switch to once the sessions are open and the servers are
+/// listening.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DropPrivileges {
+    /// If `group` is not set, the user's primary group is used
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+/// Paths of the external programs that are run. Names without a directory
+/// are searched for in `PATH`.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct Tools {
+    pub smartctl: String,
+    pub hdparm: String,
+    pub zpool: String,
+    pub ipmitool: String,
+    pub curl: String,
+    pub powershell: String,
+    pub sqlite3: String,
+}
+
+impl Tools {
+    /// Get the names of the config options and the paths of the programs that
+    /// a source runs.
+    pub fn for_source(&self, source_type: &SourceType) -> Vec<(&'static str, &str)> {
+        let mut tools = vec![];
+
+        if let SourceType::Zpool { .. } = source_type {
+            tools.push(("zpool", self.zpool.as_str()));
+        }
+
+        match source_type {
+            SourceType::Smart { backend: SmartBackend::Smartctl, .. }
+            | SourceType::Disks { backend: SmartBackend::Smartctl, .. }
+            | SourceType::Zpool { backend: SmartBackend::Smartctl, .. } => {
+                tools.push(("smartctl", &self.smartctl));
+            }
+            SourceType::Hdparm { .. } => tools.push(("hdparm", &self.hdparm)),
+            SourceType::Wmi { .. } => tools.push(("powershell", &self.powershell)),
+            _ => {}
+        }
+
+        tools
+    }
+}
+
+impl Default for Tools {
+    fn default() -> Self {
+        Self {
+            smartctl: "smartctl".to_owned(),
+            hdparm: "hdparm".to_owned(),
+            zpool: "zpool".to_owned(),
+            ipmitool: "ipmitool".to_owned(),
+            curl: "curl".to_owned(),
+            powershell: "powershell".to_owned(),
+            sqlite3: "sqlite3".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Built-in board profile that was merged into the config
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub log_level: LogLevel,
+    /// Whether unknown keys are errors or warnings. This is handled before the
+    /// config is deserialized.
+    #[serde(default)]
+    pub unknown_fields: UnknownFields,
+    pub syslog: Option<Syslog>,
+    // TOML can't encode OsString
+    pub control_socket: Option<String>,
+    pub influxdb: Option<InfluxDb>,
+    pub history: Option<History>,
+    pub textfile: Option<Textfile>,
+    pub alerts: Option<Alerts>,
+    /// Address for the HTTP server that serves the status API and the web UI
+    pub http_listen: Option<String>,
+    /// Bearer token required by every HTTP API endpoint
+    pub http_token: Option<String>,
+    /// Address for the gRPC server
+    pub grpc_listen: Option<String>,
+    /// Bearer token required by every gRPC call
+    pub grpc_token: Option<String>,
+    pub snmp: Option<Snmp>,
+    pub drop_privileges: Option<DropPrivileges>,
+    // TOML can't encode OsString
+    #[serde(default)]
+    pub lock_file: LockFile,
+    /// Signal that pauses every zone
+    #[serde(default)]
+    pub pause_signal: PauseSignal,
+    /// Signal that resumes every zone
+    #[serde(default)]
+    pub resume_signal: ResumeSignal,
+    /// Keep the other zones running when a zone loop fails
+    #[serde(default)]
+    pub isolate_zone_failures: bool,
+    /// Check that every source's IPMI sensor, file, or block device exists
+    /// before starting the zones
+    #[serde(default)]
+    pub strict_sources: bool,
+    /// How often `disks` and `zpool` sources are checked for added or removed
+    /// disks
+    pub disk_rescan_interval: Option<Interval>,
+    /// Delay between the start of each zone loop
+    pub zone_start_stagger: Option<Interval>,
+    #[serde(default)]
+    pub tools: Tools,
+    /// Shared libraries providing source types and session backends. These
+    /// are loaded before the rest of the config is deserialized.
+    // TOML can't encode OsString
+    #[serde(default)]
+    pub plugins: Vec<String>,
+    #[serde(default)]
+    pub sessions: Sessions,
+    pub zones: Vec<Zone>,
+    /// Warnings about the config, like ignored unknown keys, to log once
+    /// logging is set up
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub warnings: Vec<String>,
+}
+
+/// Match a string against a pattern where `*` matches any number of characters
+/// and `?` matches exactly one character.
+pub fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    let (mut p, mut i) = (0, 0);
+    // Position of the last `*` and the position in `s` that it matched up to
+    let mut star = None;
+
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            // Let the last `*` match one more character
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, i));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Whether a block device is a partition instead of a whole disk.
+fn is_partition(dev: &Path) -> bool {
+    dev.file_name()
+        .is_some_and(|n| Path::new("/sys/class/block").join(n).join("partition").exists())
+}
+
+/// Find the whole-disk block devices matching a `disks` source's patterns.
+/// Paths that resolve to the same device, like a disk's WWN and model/serial
+/// links in /dev/disk/by-id, are only included once.
+pub fn find_disks(patterns: &[String], exclude: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    for pattern in patterns {
+        let pattern = Path::new(pattern);
+        let (dir, name) = match (pattern.parent(), pattern.file_name()) {
+            (Some(d), Some(n)) => (d, n.to_string_lossy()),
+            _ => continue,
+        };
+
+        let mut matched = vec![];
+        for entry in fs::read_dir(dir).map_err(|e| Error::Io { path: dir.to_owned(), source: e })? {
+            let entry = entry.map_err(|e| Error::Io { path: dir.to_owned(), source: e })?;
+            if wildcard_match(&name, &entry.file_name().to_string_lossy()) {
+                matched.push(entry.path());
+            }
+        }
+        matched.sort();
+        paths.extend(matched);
+    }
+
+    let mut devices = HashSet::new();
+    paths.retain(|p| {
+        if exclude.iter().any(|e| wildcard_match(e, &p.to_string_lossy())) {
+            return false;
+        }
+
+        // Links for disks that were just removed may not resolve
+        match fs::canonicalize(p) {
+            Ok(dev) => !is_partition(&dev) && devices.insert(dev),
+            Err(_) => false,
+        }
+    });
+
+    Ok(paths)
+}
+
+/// Get the whole disk that a partition is on. Other devices are returned as is.
+fn whole_disk(dev: &Path) -> PathBuf {
+    let canonical = match fs::canonicalize(dev) {
+        Ok(p) if is_partition(&p) => p,
+        _ => return dev.to_owned(),
+    };
+
+    // A partition's sysfs directory is inside the disk's directory
+    canonical.file_name()
+        .and_then(|n| fs::canonicalize(Path::new("/sys/class/block").join(n)).ok())
+        .and_then(|p| p.parent().and_then(Path::file_name).map(|n| Path::new("/dev").join(n)))
+        .unwrap_or_else(|| dev.to_owned())
+}
+
+/// Recursively collect the paths of every `disk` vdev in `zpool status -j`
+/// output.
+fn collect_vdev_paths(value: &serde_json::Value, paths: &mut Vec<PathBuf>) {
+    if let Some(object) = value.as_object() {
+        if object.get("vdev_type").and_then(|t| t.as_str()) == Some("disk") {
+            if let Some(path) = object.get("path").and_then(|p| p.as_str()) {
+                paths.push(PathBuf::from(path));
+            }
+        }
+
+        for child in object.values() {
+            collect_vdev_paths(child, paths);
+        }
+    }
+}
+
+/// Find the disks in a ZFS pool via `zpool status`, which requires OpenZFS >=
+/// 2.3 for JSON output. ZFS usually partitions the disks that it is given, so
+/// partitions are replaced by the disks that they are on.
+pub fn zpool_disks(pool: &str, tools: &Tools) -> Result<Vec<PathBuf>> {
+    let output = Command::new(&tools.zpool)
+        .args(["status", "-j", "-P", pool])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::Io { path: "(zpool)".into(), source: e })?;
+    if !output.status.success() {
+        return Err(Error::Command { command: "zpool".into(), status: output.status });
+    }
+
+    let root: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Error::ZpoolParse { pool: pool.to_owned(), source: e })?;
+    let pool_status = root.get("pools")
+        .and_then(|p| p.get(pool))
+        .ok_or_else(|| Error::ZpoolNotFound(pool.to_owned()))?;
+
+    let mut paths = vec![];
+    collect_vdev_paths(pool_status, &mut paths);
+
+    let mut disks = HashSet::new();
+
+    Ok(paths.iter()
+        .map(|p| whole_disk(p))
+        .filter(|d| disks.insert(d.clone()))
+        .collect())
+}
+
+/// Replace each `disks` and `zpool` source with a `smart` source for every
+/// disk that it matches. The other sources are kept as is.
+pub fn expand_sources(sources: &[Source], tools: &Tools) -> Result<Vec<Source>> {
+    let mut expanded = vec![];
+
+    for source in sources {
+        let (paths, standby, backend) = match &source.source_type {
+            SourceType::Disks { patterns, exclude, standby, backend } => {
+                (find_disks(patterns, exclude)?, standby, backend)
+            }
+            SourceType::Zpool { pool, standby, backend } => (zpool_disks(pool, tools)?, standby, backend),
+            _ => {
+                expanded.push(source.clone());
+                continue;
+            }
+        };
+
+        for path in paths {
+            expanded.push(Source {
+                source_type: SourceType::Smart {
+                    block_dev: path.to_string_lossy().into_owned(),
+                    device_type: None,
+                    args: vec![],
+                    standby: *standby,
+                    backend: *backend,
+                },
+                options: source.options.clone(),
+            });
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Zone keys that can't be set in `[defaults]` because they identify a zone's
+/// fans or sources.
+const ZONE_ONLY_KEYS: &[&str] = &["name", "output", "ipmi_zones", "fan_sensors", "sources", "override_file"];
+
+/// If the config has a `[defaults]` table, merge it into every zone. Keys set
+/// by a zone override the defaults.
+fn apply_zone_defaults(path: &Path, config: &mut Table) -> Result<()> {
+    let defaults = match config.remove("defaults") {
+        Some(Value::Table(t)) => t,
+        Some(_) => {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "defaults: must be a table".to_owned(),
+            });
+        }
+        None => return Ok(()),
+    };
+
+    if let Some(key) = defaults.keys().find(|k| ZONE_ONLY_KEYS.contains(&k.as_str())) {
+        return Err(Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("defaults.{}: must be set in each zone", key),
+        });
+    }
+
+    if let Some(Value::Array(zones)) = config.get_mut("zones") {
+        for (i, zone) in zones.iter_mut().enumerate() {
+            let table = zone.as_table_mut().ok_or_else(|| Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}]: must be a table", i),
+            })?;
+
+            for (key, value) in &defaults {
+                table.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert an integer temperature in the config from Fahrenheit to Celsius.
+/// Anything else is left as is so that deserialization reports it.
+fn fahrenheit_to_celsius(value: &mut Value) {
+    if let Value::Integer(t) = value {
+        *t = TempUnits::Fahrenheit.to_celsius(*t as f64).round() as i64;
+    }
+}
+
+/// Convert the `temp` of every step in a list of steps.
+fn convert_step_units(steps: &mut Value) {
+    for step in steps.as_array_mut().into_iter().flatten() {
+        if let Some(t) = step.get_mut("temp") {
+            fahrenheit_to_celsius(t);
+        }
+    }
+}
+
+/// Convert the temperatures of a `cpu` source or of the sources that a
+/// `delta` source is computed from.
+fn convert_source_units(source: &mut Value) {
+    match source.get("type").and_then(Value::as_str) {
+        Some("cpu") => {
+            if let Some(Value::Array(range)) = source.get_mut("range") {
+                range.iter_mut().for_each(fahrenheit_to_celsius);
+            }
+        }
+        Some("delta") => {
+            for key in ["source", "reference"] {
+                if let Some(s) = source.get_mut(key) {
+                    convert_source_units(s);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Convert the temperatures of every zone with `units = "fahrenheit"` to
+/// Celsius, which is what the zone's settings are stored in. This is done
+/// before deserializing because temperatures in Fahrenheit may not fit in the
+/// fields' types.
+fn apply_zone_units(path: &Path, config: &mut Table) -> Result<()> {
+    let zones = match config.get_mut("zones") {
+        Some(Value::Array(z)) => z,
+        _ => return Ok(()),
+    };
+
+    for (i, zone) in zones.iter_mut().enumerate() {
+        let units = match zone.get("units").cloned() {
+            Some(u) => TempUnits::deserialize(u).map_err(|e| Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].units: {}", i, e),
+            })?,
+            None => continue,
+        };
+        if units == TempUnits::Celsius {
+            continue;
+        }
+
+        for key in ["steps", "cooldown_steps", "rpm_steps"] {
+            if let Some(steps) = zone.get_mut(key) {
+                convert_step_units(steps);
+            }
+        }
+
+        if let Some(Value::Table(tables)) = zone.get_mut("step_tables") {
+            tables.iter_mut().for_each(|(_, steps)| convert_step_units(steps));
+        }
+
+        for key in ["emergency_temp", "stop_temp"] {
+            if let Some(t) = zone.get_mut(key) {
+                fahrenheit_to_celsius(t);
+            }
+        }
+
+        if let Some(t) = zone.get_mut("critical").and_then(|c| c.get_mut("temp")) {
+            fahrenheit_to_celsius(t);
+        }
+
+        // The gain is per degree, so it scales with the size of a degree
+        if let Some(Value::Integer(g)) = zone.get_mut("trend_boost").and_then(|b| b.get_mut("gain")) {
+            *g = (*g as f64 * 1.8).round() as i64;
+        }
+
+        if let Some(Value::Array(sources)) = zone.get_mut("sources") {
+            sources.iter_mut().for_each(convert_source_units);
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the files matching an include pattern. Relative patterns are resolved
+/// against the directory containing the config file. Wildcards are only
+/// supported in the file name.
+fn include_paths(path: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern = path.parent().unwrap_or(Path::new("")).join(pattern);
+    let (dir, name) = match (pattern.parent(), pattern.file_name()) {
+        (Some(d), Some(n)) => (d, n.to_string_lossy()),
+        _ => return Ok(vec![pattern]),
+    };
+
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern.clone()]);
+    }
+
+    let entries = fs::read_dir(dir)
+        .map_err(|e| Error::Io { path: dir.to_owned(), source: e })?;
+    let mut paths = vec![];
+
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::Io { path: dir.to_owned(), source: e })?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        // Skip editor backups and other hidden files
+        if !file_name.starts_with('.') && wildcard_match(&name, &file_name) {
+            paths.push(entry.path());
+        }
+    }
+
+    // Include files in a predictable order
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Load the plugins in the config's `plugins` list so that their source types
+/// are registered before the sources are deserialized. Relative paths are
+/// resolved against the directory containing the config file.
+fn load_plugins(path: &Path, config: &Table) -> Result<()> {
+    let plugins = match config.get("plugins") {
+        Some(Value::Array(a)) => a,
+        Some(_) => {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "plugins: must be an array".to_owned(),
+            });
+        }
+        None => return Ok(()),
+    };
+
+    for (i, plugin) in plugins.iter().enumerate() {
+        let plugin = plugin.as_str().ok_or_else(|| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("plugins[{}]: must be a string", i),
+        })?;
+        let plugin_path = path.parent().unwrap_or(Path::new("")).join(plugin);
+
+        plugin::load(&plugin_path).map_err(|e| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("plugins[{}]: {}", i, e),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// If the config has an `include` list, append the zones and merge the
+/// sessions from every matching file. Included files can't include other
+/// files or set any other options.
+fn apply_includes(path: &Path, config: &mut Table) -> Result<()> {
+    let patterns = match config.remove("include") {
+        Some(Value::Array(a)) => a,
+        Some(_) => {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "include: must be an array".to_owned(),
+            });
+        }
+        None => return Ok(()),
+    };
+
+    for (i, pattern) in patterns.iter().enumerate() {
+        let pattern = pattern.as_str().ok_or_else(|| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("include[{}]: must be a string", i),
+        })?;
+
+        for include_path in include_paths(path, pattern)? {
+            let contents = fs::read_to_string(&include_path)
+                .map_err(|e| Error::Io { path: include_path.clone(), source: e })?;
+            let include: Table = toml::from_str(&contents)
+                .map_err(|e| Error::ConfigParse { path: include_path.clone(), source: e })?;
+
+            for (key, value) in include {
+                match (key.as_str(), value) {
+                    ("zones", Value::Array(zones)) => {
+                        match config.entry("zones").or_insert_with(|| Value::Array(vec![])) {
+                            Value::Array(a) => a.extend(zones),
+                            _ => {
+                                return Err(Error::ConfigValidation {
+                                    path: path.to_owned(),
+                                    reason: "zones: must be an array".to_owned(),
+                                });
+                            }
+                        }
+                    }
+                    ("sessions", Value::Table(sessions)) => {
+                        let target = match config.entry("sessions").or_insert_with(|| Value::Table(Table::new())) {
+                            Value::Table(t) => t,
+                            _ => {
+                                return Err(Error::ConfigValidation {
+                                    path: path.to_owned(),
+                                    reason: "sessions: must be a table".to_owned(),
+                                });
+                            }
+                        };
+
+                        for (name, session) in sessions {
+                            if target.contains_key(&name) {
+                                return Err(Error::ConfigValidation {
+                                    path: include_path.clone(),
+                                    reason: format!("sessions[{:?}]: session is already defined", name),
+                                });
+                            }
+
+                            target.insert(name, session);
+                        }
+                    }
+                    ("zones", _) | ("sessions", _) => {
+                        return Err(Error::ConfigValidation {
+                            path: include_path.clone(),
+                            reason: format!("{}: must be an array of zones or a table of sessions", key),
+                        });
+                    }
+                    _ => {
+                        return Err(Error::ConfigValidation {
+                            path: include_path.clone(),
+                            reason: format!("{}: only zones and sessions can be included", key),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace `${VAR}` references in a string with the values of the environment
+/// variables. `$${` is replaced with a literal `${`.
+fn expand_env_string(path: &Path, key: &str, s: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = s;
+
+    while let Some(i) = rest.find("${") {
+        if rest[..i].ends_with('$') {
+            result.push_str(&rest[..i - 1]);
+            result.push_str("${");
+            rest = &rest[i + 2..];
+            continue;
+        }
+
+        let end = rest[i..].find('}').ok_or_else(|| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("{}: unterminated ${{ in {:?}", key, s),
+        })?;
+        let name = &rest[i + 2..i + end];
+
+        let value = env::var(name).map_err(|e| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("{}: ${{{}}}: {}", key, name, e),
+        })?;
+
+        result.push_str(&rest[..i]);
+        result.push_str(&value);
+        rest = &rest[i + end + 1..];
+    }
+
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Expand environment variable references in every string in the value.
+fn expand_env_vars(path: &Path, key: &str, value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(s) if s.contains("${") => {
+            *s = expand_env_string(path, key, s)?;
+        }
+        Value::Array(a) => {
+            for (i, v) in a.iter_mut().enumerate() {
+                expand_env_vars(path, &format!("{}[{}]", key, i), v)?;
+            }
+        }
+        Value::Table(t) => {
+            for (k, v) in t.iter_mut() {
+                expand_env_vars(path, &format!("{}.{}", key, k), v)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Generate a JSON Schema for the config file. Options that are handled before
+/// the config is deserialized are added manually.
+pub fn schema() -> Schema {
+    let mut schema = schemars::schema_for!(Config);
+
+    // Zones can come from included files
+    schema.remove("required");
+
+    if let Some(properties) = schema.get_mut("properties").and_then(|p| p.as_object_mut()) {
+        properties.insert("include".to_owned(), serde_json::json!({
+            "description": "Files to merge zones and sessions from",
+            "type": "array",
+            "items": { "type": "string" },
+        }));
+        properties.insert("defaults".to_owned(), serde_json::json!({
+            "description": "Settings that every zone inherits unless the zone sets them itself",
+            "type": "object",
+        }));
+    }
+
+    schema
+}
+
+/// Follow a schema's `$ref`s and return it along with the subschemas it may
+/// match via `anyOf`, `oneOf`, and `allOf`.
+fn schema_candidates<'a>(
+    defs: &'a serde_json::Value,
+    schema: &'a serde_json::Value,
+    candidates: &mut Vec<&'a serde_json::Value>,
+) {
+    let schema = match schema.get("$ref").and_then(|r| r.as_str()) {
+        Some(r) => match r.strip_prefix("#/$defs/").and_then(|n| defs.get(n)) {
+            Some(s) => s,
+            None => return,
+        },
+        None => schema,
+    };
+
+    candidates.push(schema);
+
+    for keyword in ["anyOf", "oneOf", "allOf"] {
+        if let Some(subschemas) = schema.get(keyword).and_then(|s| s.as_array()) {
+            for subschema in subschemas {
+                schema_candidates(defs, subschema, candidates);
+            }
+        }
+    }
+}
+
+/// Remove the keys that aren't in the schema from every table in the value
+/// and add a warning for each one.
+fn remove_unknown_fields(
+    defs: &serde_json::Value,
+    schema: &serde_json::Value,
+    key: &str,
+    value: &mut Value,
+    warnings: &mut Vec<String>,
+) {
+    let mut candidates = vec![];
+    schema_candidates(defs, schema, &mut candidates);
+
+    match value {
+        Value::Table(table) => {
+            let is_tagged = |c: &&serde_json::Value| {
+                c.get("properties").and_then(|p| p.as_object())
+                    .is_some_and(|p| p.values().any(|v| v.get("const").is_some()))
+            };
+            let had_tags = candidates.iter().any(is_tagged);
+
+            // Skip the variants of tagged enums that don't match the tag
+            candidates.retain(|c| {
+                c.get("properties").and_then(|p| p.as_object()).is_none_or(|p| {
+                    p.iter().all(|(k, v)| match (v.get("const"), table.get(k)) {
+                        (Some(c), Some(Value::String(t))) => c.as_str() == Some(t),
+                        _ => true,
+                    })
+                })
+            });
+
+            // The tag isn't in the schema, like for sources registered with
+            // source::register_source(), so the keys are left to the type
+            if had_tags && !candidates.iter().any(is_tagged) {
+                return;
+            }
+
+            let closed = candidates.iter().any(|c| {
+                c.get("additionalProperties") == Some(&false.into())
+                    || c.get("unevaluatedProperties") == Some(&false.into())
+            });
+            let map_values = candidates.iter()
+                .filter_map(|c| c.get("additionalProperties"))
+                .find(|a| a.is_object());
+
+            let mut unknown = vec![];
+
+            for (k, v) in table.iter_mut() {
+                let subschema = candidates.iter()
+                    .filter_map(|c| c.get("properties").and_then(|p| p.get(k)))
+                    .next()
+                    .or(map_values);
+
+                match subschema {
+                    Some(s) => remove_unknown_fields(defs, s, &format!("{}.{}", key, k), v, warnings),
+                    None if closed => unknown.push(k.clone()),
+                    None => {}
+                }
+            }
+
+            for k in unknown {
+                table.remove(&k);
+                warnings.push(format!("{}.{}: unknown field ignored", key, k));
+            }
+        }
+        Value::Array(array) => {
+            if let Some(items) = candidates.iter().find_map(|c| c.get("items")) {
+                for (i, v) in array.iter_mut().enumerate() {
+                    remove_unknown_fields(defs, items, &format!("{}[{}]", key, i), v, warnings);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn load_config(path: &Path) -> Result<Config> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+
+    let mut table: Table = toml::from_str(&contents)
+        .map_err(|e| Error::ConfigParse { path: path.to_owned(), source: e })?;
+
+    // Deserialize from the original string when possible because errors from
+    // deserializing a Value don't include the line number
+    let lenient = match table.get("unknown_fields") {
+        Some(v) => UnknownFields::deserialize(v.clone())
+            .map_err(|e| Error::ConfigParse { path: path.to_owned(), source: e })?
+            == UnknownFields::Warn,
+        None => false,
+    };
+    let mut warnings = vec![];
+
+    // These are resolved before the rest of the config is expanded
+    for key in ["plugins", "include"] {
+        if let Some(value) = table.get_mut(key) {
+            expand_env_vars(path, key, value)?;
+        }
+    }
+
+    load_plugins(path, &table)?;
+
+    let mut config: Config = if ["include", "profile", "defaults"].iter().any(|k| table.contains_key(*k))
+        || contents.contains("${")
+        || lenient
+        || table.get("zones").and_then(Value::as_array)
+            .is_some_and(|z| z.iter().any(|z| z.get("units").is_some()))
+    {
+        // Included zones inherit the defaults too. The defaults are applied
+        // before the profile so that they take precedence over the profile's
+        // zones.
+        apply_includes(path, &mut table)?;
+
+        for (key, value) in table.iter_mut().filter(|(k, _)| *k != "plugins") {
+            expand_env_vars(path, key, value)?;
+        }
+
+        apply_zone_defaults(path, &mut table)?;
+        // Before the profile is applied because its zones are in Celsius
+        apply_zone_units(path, &mut table)?;
+        profile::apply_profile(path, &mut table)?;
+
+        if lenient {
+            let schema = schema();
+            let defs = &schema.as_value()["$defs"];
+            let properties = &schema.as_value()["properties"];
+            let mut unknown = vec![];
+
+            for (key, value) in table.iter_mut() {
+                match properties.get(key) {
+                    Some(s) => remove_unknown_fields(defs, s, key, value, &mut warnings),
+                    None => unknown.push(key.clone()),
+                }
+            }
+
+            for key in unknown {
+                table.remove(&key);
+                warnings.push(format!("{}: unknown field ignored", key));
+            }
+        }
+
+        Value::Table(table).try_into()
+            .map_err(|e| Error::ConfigParse { path: path.to_owned(), source: e })?
+    } else {
+        toml::from_str(&contents)
+            .map_err(|e| Error::ConfigParse { path: path.to_owned(), source: e })?
+    };
+
+    config.warnings = warnings;
+
+    // Validate config
+
+    // Create default session
+    config.sessions.0.entry(SessionName::default().0)
+        .or_insert_with(Session::default);
+
+    for (name, session) in &mut config.sessions.0 {
+        if let SessionType::Remote { password, password_file, password_env, .. } =
+            &mut session.session_type
+        {
+            let resolved = match (password.take(), password_file.as_ref(), password_env.as_ref()) {
+                (Some(p), None, None) => p,
+                (None, Some(f), None) => {
+                    let contents = fs::read_to_string(f)
+                        .map_err(|e| Error::Io { path: f.into(), source: e })?;
+
+                    Password(contents.trim_end_matches(&['\r', '\n'][..]).to_owned())
+                }
+                (None, None, Some(v)) => {
+                    let value = env::var(v).map_err(|e| Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].password_env: {:?}: {}", name, v, e),
+                    })?;
+
+                    Password(value)
+                }
+                _ => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}]: exactly one of password, password_file, and password_env must be specified", name),
+                    });
+                }
+            };
+
+            *password = Some(resolved);
+        }
+    }
+
+    for (name, session) in &config.sessions.0 {
+        if session.options.backend == Backend::Redfish {
+            if let SessionType::Local { .. } = session.session_type {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].backend: redfish is only supported for remote sessions", name),
+                });
+            } else if !session.options.fan_thresholds.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].fan_thresholds: not supported by the redfish backend", name),
+                });
+            }
+        } else if session.options.ca_file.is_some() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}].ca_file: only supported by the redfish backend", name),
+            });
+        }
+
+        if session.options.backend == Backend::Native {
+            match &session.session_type {
+                SessionType::Local { .. } => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].backend: native is only supported for remote sessions", name),
+                    });
+                }
+                SessionType::Remote { cipher_suite_id: Some(id), .. }
+                    if !matches!(id, 1 | 2 | 3 | 17) =>
+                {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].cipher_suite_id: native backend only supports 1, 2, 3, and 17", name),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if let SessionType::Remote {
+            ipmi_version, authentication_type, cipher_suite_id, k_g, workaround_flags, ..
+        } = &session.session_type {
+            match ipmi_version {
+                IpmiVersion::V15 if cipher_suite_id.is_some() || k_g.is_some() => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}]: cipher_suite_id and k_g only apply to IPMI 2.0", name),
+                    });
+                }
+                IpmiVersion::V15 if session.options.backend == Backend::Native => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].ipmi_version: native backend only supports 2.0", name),
+                    });
+                }
+                IpmiVersion::V20 if authentication_type.is_some() => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}]: authentication_type only applies to IPMI 1.5", name),
+                    });
+                }
+                _ => {}
+            }
+
+            if let Some(k) = workaround_flags.iter().position(|f| !f.supports(*ipmi_version)) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].workaround_flags[{}]: not supported by IPMI {}", name, k, ipmi_version),
+                });
+            }
+
+            let hostnames = session.session_type.hostnames();
+            for (k, hostname) in hostnames.iter().enumerate().skip(1) {
+                if hostnames[..k].contains(hostname) {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].failover_hostnames[{}]: duplicate hostname: {:?}", name, k - 1, hostname),
+                    });
+                }
+            }
+        }
+
+        if session.session_type.has_freeipmi_options() && session.options.backend != Backend::Freeipmi {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}]: driver and workaround options are only supported by the freeipmi backend", name),
+            });
+        }
+
+        match (session.options.backend, &session.options.plugin) {
+            (Backend::Plugin, None) => {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].plugin: must be specified for the plugin backend", name),
+                });
+            }
+            (Backend::Plugin, Some(p)) if !plugin::has_backend(p) => {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].plugin: no loaded plugin provides backend {:?}", name, p),
+                });
+            }
+            (Backend::Plugin, _) | (_, None) => {}
+            (_, Some(_)) => {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].plugin: only supported by the plugin backend", name),
+                });
+            }
+        }
+
+        if let Some(bridge) = &session.options.bridge {
+            if session.options.backend == Backend::Native {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].bridge: not supported by the native backend", name),
+                });
+            } else if session.options.backend == Backend::Plugin {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].bridge: not supported by the plugin backend", name),
+                });
+            } else if session.options.backend == Backend::Redfish {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].bridge: not supported by the redfish backend", name),
+                });
+            } else if bridge.address & 1 != 0 {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].bridge.address: must be an 8-bit address, which is even", name),
+                });
+            }
+        }
+
+        if session.options.sdr_cache_dir.is_some() && session.options.backend != Backend::Freeipmi {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}].sdr_cache_dir: only supported by the freeipmi backend", name),
+            });
+        }
+
+        if let Some(interval) = session.options.keepalive_interval {
+            if !matches!(session.session_type, SessionType::Remote { .. }) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].keepalive_interval: only supported for remote sessions", name),
+                });
+            } else if interval.0.is_zero() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].keepalive_interval: must be greater than 0", name),
+                });
+            }
+        }
+
+        for (k, threshold) in session.options.fan_thresholds.iter().enumerate() {
+            let values = [
+                threshold.lower_non_recoverable,
+                threshold.lower_critical,
+                threshold.lower_non_critical,
+            ];
+            let set: Vec<_> = values.iter().flatten().collect();
+
+            if set.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].fan_thresholds[{}]: at least one threshold must be specified", name, k),
+                });
+            } else if set.windows(2).any(|w| w[0] > w[1]) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].fan_thresholds[{}]: thresholds must be in the order lower_non_recoverable <= lower_critical <= lower_non_critical", name, k),
+                });
+            } else if session.options.fan_thresholds[..k].iter().any(|t| t.sensor == threshold.sensor) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].fan_thresholds[{}]: duplicate sensor: {:?}", name, k, threshold.sensor),
+                });
+            }
+        }
+
+        if session.options.backend == Backend::Ipmitool && tools::find(&config.tools.ipmitool).is_none() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}].backend: ipmitool not found at {:?}; set tools.ipmitool to its path", name, config.tools.ipmitool),
+            });
+        }
+
+        if session.options.backend == Backend::Redfish && tools::find(&config.tools.curl).is_none() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}].backend: curl not found at {:?}; set tools.curl to its path", name, config.tools.curl),
+            });
+        }
+
+        if let Protocol::Raw(raw) = &session.options.protocol {
+            if !raw.set_duty.has_placeholder(TemplateByte::Value) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("sessions[{:?}].protocol.set_duty: must contain {{value}}", name),
+                });
+            }
+
+            match (&raw.get_mode, &raw.set_mode, raw.manual_mode) {
+                (None, None, None) => {}
+                (Some(_), Some(set_mode), Some(_)) => {
+                    if !set_mode.has_placeholder(TemplateByte::Value) {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("sessions[{:?}].protocol.set_mode: must contain {{value}}", name),
+                        });
+                    }
+                }
+                _ => {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("sessions[{:?}].protocol: get_mode, set_mode, and manual_mode must be specified together", name),
+                    });
+                }
+            }
+        }
+
+        if !matches!(session.options.protocol, Protocol::Supermicro { .. })
+            && session.options.fan_mode == SessionFanMode::HeavyIo
+        {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("sessions[{:?}].fan_mode: heavy_io is only supported by the supermicro protocol", name),
+            });
+        }
+    }
+
+    if config.pause_signal.0 == config.resume_signal.0 && config.pause_signal.0 != ControlSignal::None {
+        return Err(Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: "pause_signal, resume_signal: must be different signals".to_owned(),
+        });
+    }
+
+    if let Some(DropPrivileges { user: None, group: None }) = config.drop_privileges {
+        return Err(Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: "drop_privileges: at least one of user and group must be specified".to_owned(),
+        });
+    }
+
+    if config.zones.is_empty() {
+        return Err(Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: "zones: must be non-empty".to_owned(),
+        });
+    }
+
+    for (i, zone_config) in config.zones.iter().enumerate() {
+        if let Some(name) = &zone_config.name {
+            if name.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].name: must be non-empty", i),
+                });
+            } else if config.zones[..i].iter().any(|z| z.name.as_ref() == Some(name)) {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].name: {:?} is used by another zone", i, name),
+                });
+            }
+        }
+
+        if zone_config.interval.0.is_zero() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].interval: must be greater than 0", i),
+            });
+        }
+
+        match &zone_config.output {
+            Output::Ipmi => {
+                if zone_config.ipmi_zones.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].ipmi_zones: must be non-empty", i),
+                    });
+                }
+            }
+            Output::Hwmon { pwm } => {
+                if pwm.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].output[type=hwmon].pwm: must be non-empty", i),
+                    });
+                } else if !zone_config.ipmi_zones.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].ipmi_zones: must be empty when output type is hwmon", i),
+                    });
+                }
+            }
+        }
+
+        if zone_config.sources.is_empty() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].sources: must be non-empty", i),
+            });
+        }
+
+        if zone_config.uses_ipmi() && !config.sessions.0.contains_key(&zone_config.session.0) {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].session: {:?} does not exist", i, zone_config.session.0),
+            });
+        }
+
+        if zone_config.output == Output::Ipmi
+            && config.sessions.0.get(&zone_config.session.0).is_some_and(|s| s.options.backend == Backend::Redfish)
+        {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].output: session {:?} uses the redfish backend, which can't set duty cycles because HPE iLO and other Redfish BMCs have no API for it; use it only for sources", i, zone_config.session.0),
+            });
+        }
+
+        if matches!(zone_config.aggregation, Aggregation::Average { top: Some(0) }) {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].aggregation[type=average].top: must be greater than 0", i),
+            });
+        }
+
+        for (j, source) in zone_config.sources.iter().enumerate() {
+            if matches!(source.source_type, SourceType::Ipmi { .. })
+                && source.options.has_retry_options()
+            {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].sources[{}]: IPMI sources are queried together and use the zone's retry settings", i, j),
+                });
+            }
+
+            let leaves = source.source_type.leaves();
+
+            for leaf in &leaves {
+                if let SourceType::Ipmi { sensor, sensor_pattern, .. } = leaf {
+                    if sensor.is_empty() == sensor_pattern.is_none() {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("zones[{}].sources[{}]: exactly one of sensor and sensor_pattern must be specified", i, j),
+                        });
+                    }
+                }
+
+                if let SourceType::Ipmi { divisor: Divisor(0), .. } = leaf {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].sources[{}].divisor: must be greater than 0", i, j),
+                    });
+                } else if let SourceType::Cpu { range: Some([low, high]), .. } = leaf {
+                    if low >= high {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("zones[{}].sources[{}].range: first value must be less than the second", i, j),
+                        });
+                    }
+                }
+            }
+
+            if matches!(source.source_type, SourceType::Delta { .. })
+                && leaves.iter().any(|t| matches!(t, SourceType::Disks { .. } | SourceType::Zpool { .. }))
+            {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].sources[{}]: disks and zpool sources can't be part of a delta source", i, j),
+                });
+            }
+
+            for leaf in &leaves {
+                if let SourceType::Smart { device_type, args, backend, .. } = leaf {
+                    if device_type.as_ref().is_some_and(|t| t.is_empty()) {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("zones[{}].sources[{}].device_type: must be non-empty", i, j),
+                        });
+                    } else if *backend == SmartBackend::Native && (device_type.is_some() || !args.is_empty()) {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("zones[{}].sources[{}]: device_type and args only apply to the smartctl backend", i, j),
+                        });
+                    }
+                }
+            }
+
+            for (name, program) in leaves.iter().flat_map(|t| config.tools.for_source(t)) {
+                if tools::find(program).is_none() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].sources[{}]: {} not found at {:?}; set tools.{} to its path", i, j, name, program, name),
+                    });
+                }
+            }
+
+            if let SourceType::Zpool { pool, .. } = &source.source_type {
+                if pool.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].sources[{}].pool: must be non-empty", i, j),
+                    });
+                }
+            }
+
+            if let SourceType::Disks { patterns, .. } = &source.source_type {
+                if patterns.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].sources[{}].patterns: must be non-empty", i, j),
+                    });
+                }
+
+                for (k, pattern) in patterns.iter().enumerate() {
+                    let pattern = Path::new(pattern);
+                    let valid = pattern.file_name().is_some()
+                        && pattern.parent().is_some_and(|p| {
+                            !p.as_os_str().is_empty() && !p.to_string_lossy().contains(['*', '?'])
+                        });
+                    if !valid {
+                        return Err(Error::ConfigValidation {
+                            path: path.to_owned(),
+                            reason: format!("zones[{}].sources[{}].patterns[{}]: wildcards are only allowed in the last path component", i, j, k),
+                        });
+                    }
+                }
+            }
+        }
+
+        if zone_config.aggregation == Aggregation::Weighted
+            && zone_config.sources.iter().all(|s| s.options.weight.0 == 0)
+        {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].sources[*].weight: at least one weight must be greater than 0", i),
+            });
+        }
+
+        let step_lists = [("steps".to_owned(), &zone_config.steps), ("cooldown_steps".to_owned(), &zone_config.cooldown_steps)]
+            .into_iter()
+            .chain(zone_config.step_tables.iter().map(|(n, s)| (format!("step_tables.{}", n), s)));
+
+        for (name, steps) in step_lists {
+            for window in steps.windows(2) {
+                if window[0].temp >= window[1].temp {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].{}[*].temp: values are not strictly increasing", i, name),
+                    });
+                } else if window[0].dcycle > window[1].dcycle {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].{}[*].dcycle: values are not increasing", i, name),
+                    });
+                }
+            }
+
+            for (j, &step) in steps.iter().enumerate() {
+                if step.dcycle > 100 {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].{}[{}].dcycle: invalid percentage: {}", i, name, j, step.dcycle),
+                    });
+                } else if step.dcycle < zone_config.min_dcycle.0 || step.dcycle > zone_config.max_dcycle.0 {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].{}[{}].dcycle: outside of min_dcycle and max_dcycle: {}", i, name, j, step.dcycle),
+                    });
+                }
+            }
+        }
+
+        if zone_config.max_dcycle.0 > 100 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].max_dcycle: invalid percentage: {}", i, zone_config.max_dcycle.0),
+            });
+        } else if zone_config.min_dcycle.0 > zone_config.max_dcycle.0 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].min_dcycle: greater than max_dcycle: {}", i, zone_config.min_dcycle.0),
+            });
+        }
+
+        for (j, schedule) in zone_config.schedules.iter().enumerate() {
+            if schedule.steps.is_none() && schedule.max_dcycle.is_none() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].schedules[{}]: steps or max_dcycle must be specified", i, j),
+                });
+            }
+
+            if let Some(name) = &schedule.steps {
+                if !zone_config.step_tables.contains_key(name) {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].schedules[{}].steps: step table not found: {:?}", i, j, name),
+                    });
+                } else if !zone_config.rpm_steps.is_empty() {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].schedules[{}].steps: not supported with rpm_steps", i, j),
+                    });
+                }
+            }
+
+            if let Some(max_dcycle) = schedule.max_dcycle {
+                if max_dcycle > 100 {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].schedules[{}].max_dcycle: invalid percentage: {}", i, j, max_dcycle),
+                    });
+                } else if max_dcycle < zone_config.min_dcycle.0 {
+                    return Err(Error::ConfigValidation {
+                        path: path.to_owned(),
+                        reason: format!("zones[{}].schedules[{}].max_dcycle: less than min_dcycle: {}", i, j, max_dcycle),
+                    });
+                }
+            }
+        }
+
+        if let Some(script) = &zone_config.script {
+            if !zone_config.rpm_steps.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].script: not supported with rpm_steps", i),
+                });
+            }
+
+            Script::load(Path::new(script), &zone_config.label()).map_err(|e| Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].script: {}", i, e),
+            })?;
+        }
+
+        if let Some(trend_boost) = &zone_config.trend_boost {
+            if trend_boost.gain == 0 {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].trend_boost.gain: must be greater than 0", i),
+                });
+            } else if trend_boost.window.0.0.is_zero() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].trend_boost.window: must be greater than 0", i),
+                });
+            } else if trend_boost.max_boost.0 > 100 {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].trend_boost.max_boost: invalid percentage: {}", i, trend_boost.max_boost.0),
+                });
+            } else if !zone_config.rpm_steps.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].trend_boost: not supported with rpm_steps", i),
+                });
+            }
+        }
+
+        if zone_config.oscillation_damping.flips < 2 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].oscillation_damping.flips: must be at least 2", i),
+            });
+        } else if zone_config.oscillation_damping.window.0.is_zero() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].oscillation_damping.window: must be greater than 0", i),
+            });
+        }
+
+        if zone_config.initial_dcycle.is_some_and(|d| d > 100) {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].initial_dcycle: invalid percentage: {}", i, zone_config.initial_dcycle.unwrap()),
+            });
+        } else if zone_config.startup_grace.is_some() && zone_config.initial_dcycle.is_none() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].startup_grace: requires initial_dcycle", i),
+            });
+        }
+
+        if zone_config.log_heartbeat.is_some() && !zone_config.log_on_change_only {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].log_heartbeat: requires log_on_change_only", i),
+            });
+        }
+
+        if !zone_config.cooldown_steps.is_empty() && zone_config.steps.is_empty() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].steps: must be non-empty when using cooldown_steps", i),
+            });
+        }
+
+        if !zone_config.rpm_steps.is_empty() {
+            if !zone_config.steps.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}]: steps and rpm_steps are mutually exclusive", i),
+                });
+            } else if zone_config.fan_sensors.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].fan_sensors: must be non-empty when using rpm_steps", i),
+                });
+            }
+        }
+
+        for window in zone_config.rpm_steps.windows(2) {
+            if window[0].temp >= window[1].temp {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].rpm_steps[*].temp: values are not strictly increasing", i),
+                });
+            } else if window[0].rpm > window[1].rpm {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].rpm_steps[*].rpm: values are not increasing", i),
+                });
+            }
+        }
+
+        if let Some(spinup) = &zone_config.spinup {
+            if spinup.dcycle == 0 || spinup.dcycle > 100 {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].spinup.dcycle: invalid percentage: {}", i, spinup.dcycle),
+                });
+            }
+        }
+
+        if let Some(critical) = &zone_config.critical {
+            if critical.command.is_empty() {
+                return Err(Error::ConfigValidation {
+                    path: path.to_owned(),
+                    reason: format!("zones[{}].critical.command: must be non-empty", i),
+                });
+            }
+        }
+
+        if zone_config.failsafe_dcycle.0 > 100 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].failsafe_dcycle: invalid percentage: {}", i, zone_config.failsafe_dcycle.0),
+            });
+        }
+
+        if zone_config.failsafe_cycles.0 == 0 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].failsafe_cycles: must be greater than 0", i),
+            });
+        }
+
+        if zone_config.restore_dcycle.0 > 100 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].restore_dcycle: invalid percentage: {}", i, zone_config.restore_dcycle.0),
+            });
+        }
+    }
+
+    if config.disk_rescan_interval.is_some_and(|i| i.0.is_zero()) {
+        return Err(Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: "disk_rescan_interval: must be greater than 0".to_owned(),
+        });
+    }
+
+    // Disk patterns are expanded after validation so that the errors above
+    // refer to the sources as written
+    for (i, zone_config) in config.zones.iter_mut().enumerate() {
+        if !zone_config.sources.iter()
+            .any(|s| matches!(s.source_type, SourceType::Disks { .. } | SourceType::Zpool { .. }))
+        {
+            continue;
+        }
+
+        let sources = expand_sources(&zone_config.sources, &config.tools)?;
+        if sources.is_empty() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("zones[{}].sources: no disks matched the patterns or pools", i),
+            });
+        }
+
+        zone_config.unexpanded_sources = mem::replace(&mut zone_config.sources, sources);
+    }
+
+    if let Some(syslog) = &config.syslog {
+        if syslog.transport != SyslogTransport::Local && syslog.address.is_none() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "syslog.address: must be specified for the udp and tcp transports".to_owned(),
+            });
+        } else if cfg!(not(unix)) && syslog.transport == SyslogTransport::Local {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "syslog.transport: local is only supported on Unix-like systems".to_owned(),
+            });
+        }
+    }
+
+    if let Some(history) = &config.history {
+        if history.retention.is_some_and(|r| r.0.is_zero()) {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "history.retention: must be greater than 0".to_owned(),
+            });
+        } else if history.max_size.0 == 0 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "history.max_size: must be greater than 0".to_owned(),
+            });
+        } else if history.max_files.0 == 0 {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "history.max_files: must be greater than 0".to_owned(),
+            });
+        } else if history.format == HistoryFormat::Sqlite && tools::find(&config.tools.sqlite3).is_none() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("history.format: sqlite3 not found at {:?}; set tools.sqlite3 to its path", config.tools.sqlite3),
+            });
+        }
+    }
+
+    if let Some(textfile) = &config.textfile {
+        if textfile.interval.0.0.is_zero() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "textfile.interval: must be greater than 0".to_owned(),
+            });
+        } else if !textfile.path.ends_with(".prom") {
+            // The textfile collector ignores other files
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "textfile.path: must end with .prom".to_owned(),
+            });
+        }
+    }
+
+    if let Some(alerts) = &config.alerts {
+        if alerts.webhook.is_none() && alerts.command.is_empty() {
+            return Err(Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: "alerts: webhook or command must be specified".to_owned(),
+            });
+        }
+    }
+
+    Ok(config)
+}
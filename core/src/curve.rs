@@ -0,0 +1,113 @@
+use crate::config::{Aggregation, Step, Zone};
+
+/// Compute the duty cycle for a temperature by linearly interpolating between
+/// the steps. An empty list of steps means full speed.
+pub fn calc_duty_cycle(steps: &[Step], temp: f32) -> u8 {
+    // Index of first step >= the current temperature
+    let i = steps.partition_point(|s| f32::from(s.temp) < temp);
+
+    if steps.is_empty() {
+        // No steps means full speed
+        100
+    } else if i == 0 {
+        steps[0].dcycle
+    } else if i == steps.len() {
+        steps[i - 1].dcycle
+    } else {
+        let (below, above) = (steps[i - 1], steps[i]);
+
+        // Linearly scale the dcycle
+        ((temp - f32::from(below.temp))
+            * f32::from(above.dcycle - below.dcycle)
+            / (f32::from(above.temp) - f32::from(below.temp))
+            + f32::from(below.dcycle)) as u8
+    }
+}
+
+/// Compute the duty cycle from the zone's steps. If the duty cycle would
+/// decrease from `prev_dcycle` and there are cool-down steps, the cool-down
+/// steps are used instead, though the result never drops below the regular
+/// steps' value nor rises above the previous duty cycle.
+pub fn calc_step_duty_cycle(zone_config: &Zone, prev_dcycle: Option<u8>, temp: f32) -> u8 {
+    let dcycle = calc_duty_cycle(&zone_config.steps, temp);
+
+    match prev_dcycle {
+        Some(prev) if dcycle < prev && !zone_config.cooldown_steps.is_empty() => {
+            calc_duty_cycle(&zone_config.cooldown_steps, temp).clamp(dcycle, prev)
+        }
+        _ => dcycle,
+    }
+}
+
+/// Compute the target fan speed for a temperature by linearly interpolating
+/// between the zone's RPM steps. The list of RPM steps must not be empty.
+pub fn calc_target_rpm(zone_config: &Zone, temp: f32) -> u32 {
+    let steps = &zone_config.rpm_steps;
+    // Index of first step >= the current temperature
+    let i = steps.partition_point(|s| f32::from(s.temp) < temp);
+
+    if i == 0 {
+        steps[0].rpm
+    } else if i == steps.len() {
+        steps[i - 1].rpm
+    } else {
+        let (below, above) = (steps[i - 1], steps[i]);
+
+        // Linearly scale the RPM
+        ((f64::from(temp) - f64::from(below.temp))
+            * f64::from(above.rpm - below.rpm)
+            / (f64::from(above.temp) - f64::from(below.temp))
+            + f64::from(below.rpm)) as u32
+    }
+}
+
+/// Get the hottest reading if it exceeds the zone's emergency temperature.
+pub fn emergency_reading(zone_config: &Zone, readings: &[Option<f32>]) -> Option<f32> {
+    let threshold = f32::from(zone_config.emergency_temp?);
+
+    readings.iter().flatten().copied().reduce(f32::max).filter(|t| *t > threshold)
+}
+
+/// Combine the readings from all of the zone's sources into a single
+/// temperature using the zone's data aggregation method. `readings` must be in
+/// the same order as the zone's sources. Skipped optional sources are ignored,
+/// but at least one reading must be present.
+pub fn aggregate_readings(zone_config: &Zone, readings: Vec<Option<f32>>) -> f32 {
+    let weighted: Vec<_> = readings
+        .into_iter()
+        .zip(&zone_config.sources)
+        .filter_map(|(r, s)| r.map(|r| (r, s.options.weight.0 as f32)))
+        .collect();
+    let mut readings: Vec<_> = weighted.iter().map(|(r, _)| *r).collect();
+
+    match zone_config.aggregation {
+        Aggregation::Maximum => {
+            readings.into_iter().reduce(f32::max).unwrap()
+        }
+        Aggregation::Average { top } => {
+            readings.sort_by(|a, b| b.total_cmp(a));
+
+            // Fewer readings than `top` are available if sources were skipped
+            let n = top.unwrap_or(readings.len()).min(readings.len());
+
+            let sum = readings
+                .into_iter()
+                .take(n)
+                .sum::<f32>();
+
+            sum / n as f32
+        }
+        Aggregation::Weighted => {
+            let (sum, total) = weighted
+                .into_iter()
+                .fold((0.0, 0.0), |(sum, total), (r, w)| (sum + r * w, total + w));
+
+            // Only possible if the sources with non-zero weights were skipped
+            if total == 0.0 {
+                return readings.iter().sum::<f32>() / readings.len() as f32;
+            }
+
+            sum / total
+        }
+    }
+}
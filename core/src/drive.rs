@@ -0,0 +1,175 @@
+use {
+    std::{io, path::Path},
+    crate::error::{Error, Result},
+};
+
+#[cfg(target_os = "linux")]
+pub use linux::read_temperature;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use {
+        std::{
+            fs::{self, File, OpenOptions},
+            os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+        },
+        super::*,
+    };
+
+    /// Legacy IDE ioctl for sending an ATA command. libata translates it for
+    /// SATA drives, including ones behind SAS HBAs.
+    const HDIO_DRIVE_CMD: libc::c_ulong = 0x031f;
+    /// `_IOWR('N', 0x41, struct nvme_admin_cmd)`
+    const NVME_IOCTL_ADMIN_CMD: libc::c_ulong = 0xc048_4e41;
+
+    const ATA_CHECK_POWER_MODE: u8 = 0xe5;
+    const ATA_SMART: u8 = 0xb0;
+    const ATA_SMART_READ_DATA: u8 = 0xd0;
+    /// Sector count returned by CHECK POWER MODE when the drive is in standby.
+    const ATA_POWER_MODE_STANDBY: u8 = 0x00;
+    /// Size of a SMART data sector and of the NVMe SMART/health log.
+    const DATA_SIZE: usize = 512;
+    /// Number of 12-byte attribute entries in the SMART data, starting at
+    /// offset 2.
+    const ATA_ATTRIBUTES: usize = 30;
+    const ATA_ATTR_TEMPERATURE: u8 = 194;
+    const ATA_ATTR_AIRFLOW_TEMPERATURE: u8 = 190;
+
+    const NVME_ADMIN_GET_LOG_PAGE: u8 = 0x02;
+    const NVME_LOG_SMART: u32 = 0x02;
+    const NVME_NSID_ALL: u32 = 0xffff_ffff;
+
+    /// `struct nvme_admin_cmd` from `<linux/nvme_ioctl.h>`.
+    #[repr(C)]
+    #[derive(Default)]
+    struct NvmeAdminCmd {
+        opcode: u8,
+        flags: u8,
+        rsvd1: u16,
+        nsid: u32,
+        cdw2: u32,
+        cdw3: u32,
+        metadata: u64,
+        addr: u64,
+        metadata_len: u32,
+        data_len: u32,
+        cdw10: u32,
+        cdw11: u32,
+        cdw12: u32,
+        cdw13: u32,
+        cdw14: u32,
+        cdw15: u32,
+        timeout_ms: u32,
+        result: u32,
+    }
+
+    /// Send an ATA command with `HDIO_DRIVE_CMD`. `args` holds the command,
+    /// sector number, feature, and sector count, followed by the data buffer.
+    /// On return, the first 4 bytes hold the status, error, and sector count
+    /// registers.
+    fn ata_command(file: &File, block_dev: &Path, args: &mut [u8]) -> Result<()> {
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), HDIO_DRIVE_CMD as _, args.as_mut_ptr()) };
+        if ret != 0 {
+            return Err(Error::Io { path: block_dev.to_owned(), source: io::Error::last_os_error() });
+        }
+
+        Ok(())
+    }
+
+    /// Read the temperature from the SMART attributes of an ATA drive.
+    /// Attribute 194 is preferred over 190, like smartctl does.
+    fn read_ata(file: &File, block_dev: &Path) -> Result<f32> {
+        // Unlike SMART commands, this does not spin the drive up
+        let mut args = [ATA_CHECK_POWER_MODE, 0, 0, 0];
+        ata_command(file, block_dev, &mut args)?;
+        if args[2] == ATA_POWER_MODE_STANDBY {
+            return Err(Error::DriveStandby(block_dev.to_owned()));
+        }
+
+        let mut args = [0u8; 4 + DATA_SIZE];
+        args[..4].copy_from_slice(&[ATA_SMART, 0, ATA_SMART_READ_DATA, 1]);
+        ata_command(file, block_dev, &mut args)?;
+
+        let attributes = args[4 + 2..][..ATA_ATTRIBUTES * 12].chunks_exact(12);
+        // The lowest byte of the raw value is the current temperature
+        let find = |id| attributes.clone().find(|a| a[0] == id).map(|a| a[5]);
+        let temperature = find(ATA_ATTR_TEMPERATURE)
+            .or_else(|| find(ATA_ATTR_AIRFLOW_TEMPERATURE))
+            .ok_or_else(|| Error::SmartNoReading(block_dev.to_owned()))?;
+
+        Ok(f32::from(i8::try_from(temperature).map_err(|_| Error::ReadingExceedsBounds)?))
+    }
+
+    /// Read the composite temperature from the SMART/health log of an NVMe
+    /// drive.
+    fn read_nvme(file: &File, block_dev: &Path) -> Result<f32> {
+        let mut log = [0u8; DATA_SIZE];
+        let mut cmd = NvmeAdminCmd {
+            opcode: NVME_ADMIN_GET_LOG_PAGE,
+            nsid: NVME_NSID_ALL,
+            addr: log.as_mut_ptr() as u64,
+            data_len: DATA_SIZE as u32,
+            // Number of dwords minus one in the upper half
+            cdw10: ((DATA_SIZE as u32 / 4 - 1) << 16) | NVME_LOG_SMART,
+            ..Default::default()
+        };
+
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ADMIN_CMD as _, &mut cmd) };
+        if ret < 0 {
+            return Err(Error::Io { path: block_dev.to_owned(), source: io::Error::last_os_error() });
+        } else if ret > 0 {
+            return Err(Error::Io {
+                path: block_dev.to_owned(),
+                source: io::Error::other(format!("NVMe command failed with status {:#x}", ret)),
+            });
+        }
+
+        let kelvin = u16::from_le_bytes([log[1], log[2]]);
+        if kelvin == 0 {
+            return Err(Error::SmartNoReading(block_dev.to_owned()));
+        }
+
+        let temperature = i8::try_from(i32::from(kelvin) - 273)
+            .map_err(|_| Error::ReadingExceedsBounds)?;
+
+        Ok(f32::from(temperature))
+    }
+
+    /// Get the temperature of a SATA or NVMe drive without running smartctl.
+    /// This function fails with [`Error::DriveStandby`] if an ATA drive is in
+    /// standby, which is detected without waking the drive up.
+    pub fn read_temperature(block_dev: &Path) -> Result<f32> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(block_dev)
+            .map_err(|e| Error::Io { path: block_dev.to_owned(), source: e })?;
+
+        // Resolve /dev/disk/by-id symlinks to get the kernel's device name
+        let is_nvme = fs::canonicalize(block_dev)
+            .map_err(|e| Error::Io { path: block_dev.to_owned(), source: e })?
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with("nvme"));
+
+        if is_nvme {
+            read_nvme(&file, block_dev)
+        } else {
+            read_ata(&file, block_dev)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub use other::read_temperature;
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+    use super::*;
+
+    pub fn read_temperature(block_dev: &Path) -> Result<f32> {
+        Err(Error::Io {
+            path: block_dev.to_owned(),
+            source: io::Error::new(io::ErrorKind::Unsupported, "Reading drives natively is only supported on Linux"),
+        })
+    }
+}
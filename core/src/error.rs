@@ -0,0 +1,186 @@
+use {
+    std::{
+        io,
+        num::ParseIntError,
+        path::PathBuf,
+        process::ExitStatus,
+        result,
+    },
+    thiserror::Error,
+    tokio::task::JoinError,
+    crate::{
+        sensor::{format_suggestions, SensorUnits, SensorValue},
+        ipmi,
+        plugin,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to parse config: {path:?}: {source}")]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Failed to validate config: {path:?}: {reason}")]
+    ConfigValidation {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("Failed to parse sensor value: {value:?}: {source}")]
+    SensorValueParse {
+        value: String,
+        source: ParseIntError,
+    },
+    #[error("Session not found in config: {0}")]
+    SessionNotFound(String),
+    #[error("Zone not found in config: {0}")]
+    ZoneNotFound(String),
+    #[error("Invalid simulation input on line {line}: {reason}")]
+    SimulationInput {
+        line: usize,
+        reason: String,
+    },
+    #[error("Source validation failed:\n  {}", .0.join("\n  "))]
+    SourceValidation(Vec<String>),
+    #[error("Source type is already registered: {0}")]
+    SourceTypeRegistered(String),
+    #[error("Source type is not registered: {0}")]
+    SourceTypeUnknown(String),
+    #[error("Invalid source config: {name}: {reason}")]
+    SourceConfig {
+        name: String,
+        reason: String,
+    },
+    #[error("{0}")]
+    Plugin(#[from] plugin::Error),
+    #[error("Script failed: {path:?}: {reason}")]
+    Script {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("Built without script support; enable the script feature: {0:?}")]
+    ScriptDisabled(PathBuf),
+    #[error("Built without gRPC support; enable the grpc feature")]
+    GrpcDisabled,
+    #[error("Sensor not found: {sensor}{}", format_suggestions(.suggestions))]
+    SensorNotFound {
+        sensor: String,
+        /// Similar sensor names that do exist
+        suggestions: Vec<String>,
+    },
+    #[error("No sensors with readings match pattern: {0}")]
+    SensorPatternNoMatch(String),
+    #[error("Unsupported sensor units: {sensor}: {units:?}")]
+    SensorBadUnits {
+        sensor: String,
+        units: SensorUnits,
+    },
+    #[error("Unsupported sensor value: {sensor}: {value:?}")]
+    SensorBadValue {
+        sensor: String,
+        value: SensorValue,
+    },
+    #[error("Sensor reading not available: {0}")]
+    SensorNoReading(String),
+    #[error("Temperature reading out of bounds")]
+    ReadingExceedsBounds,
+    #[error("All sources are optional and none of them have a reading")]
+    NoReadings,
+    #[error("Failed to parse SMART output for block device: {block_dev:?}: {source}")]
+    SmartParse {
+        block_dev: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("Block device has no temperature reading: {0:?}")]
+    SmartNoReading(PathBuf),
+    #[error("Failed to parse zpool status output for pool: {pool:?}: {source}")]
+    ZpoolParse {
+        pool: String,
+        source: serde_json::Error,
+    },
+    #[error("Pool not found in zpool status output: {0:?}")]
+    ZpoolNotFound(String),
+    #[error("Drive is in standby: {0:?}")]
+    DriveStandby(PathBuf),
+    #[error("hdparm reported no data: {0:?}")]
+    HdparmNoData(PathBuf),
+    #[error("hdparm reported bad data: {0:?}")]
+    HdparmBadData(PathBuf),
+    #[error("Unexpected contents: {0:?}")]
+    ProcBadData(PathBuf),
+    #[error("Failed to parse WMI output: {0:?}")]
+    WmiParse(String),
+    #[error("Failed to run: {command:?}: {status}")]
+    Command {
+        command: PathBuf,
+        status: ExitStatus,
+    },
+    #[error("Failed all {attempts} attempt(s); last attempt error: {source}")]
+    RetriesFailed {
+        attempts: u64,
+        source: Box<Self>,
+    },
+    #[error("IPMI error: {0}")]
+    Ipmi(#[from] ipmi::Error),
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("Another instance is already running{}; lock file: {path:?}",
+            .pid.map(|p| format!(" (PID {})", p)).unwrap_or_default())]
+    AlreadyRunning {
+        path: PathBuf,
+        pid: Option<u32>,
+    },
+    #[error("User not found: {0}")]
+    UserNotFound(String),
+    #[error("Group not found: {0}")]
+    GroupNotFound(String),
+    #[error("Invalid override file: {path:?}: {reason}")]
+    OverrideFile {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("Control socket is not enabled in the config")]
+    ControlSocketDisabled,
+    #[error("Invalid control socket message: {0}")]
+    ControlProtocol(String),
+    #[error("HTTP request failed: {0}")]
+    HttpRequest(String),
+    #[error("Config check found {0} problem(s)")]
+    CheckFailed(usize),
+    #[error("{0} configured IPMI zone(s) did not respond")]
+    ZonesMissing(usize),
+    #[error("Zone monitor loop panicked: {0}")]
+    LoopPanicked(#[source] JoinError),
+    #[error("[{zone}] {source}")]
+    Zone {
+        zone: String,
+        source: Box<Self>,
+    },
+}
+
+impl Error {
+    /// Whether the error was caused by an IPMI command, possibly after
+    /// retrying.
+    pub fn is_ipmi(&self) -> bool {
+        match self {
+            Self::Ipmi(_) => true,
+            Self::RetriesFailed { source, .. } => source.is_ipmi(),
+            _ => false,
+        }
+    }
+}
+
+impl From<retry::Error<Self>> for Error {
+    fn from(value: retry::Error<Self>) -> Self {
+        Self::RetriesFailed {
+            attempts: value.tries,
+            source: Box::new(value.error),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = result::Result<T, E>;
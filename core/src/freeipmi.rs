@@ -0,0 +1,978 @@
+use {
+    std::{
+        cmp::Ordering,
+        convert::TryInto,
+        ffi::{CStr, CString},
+        fs,
+        io,
+        os::raw::{c_char, c_int, c_uint},
+        path::{Path, PathBuf},
+        ptr,
+        result,
+        str::Utf8Error,
+        sync::Mutex,
+    },
+    once_cell::sync::Lazy,
+    crate::{
+        bindings,
+        config::{
+            AuthenticationType, Bridge, InBandDriver, InBandWorkaround, IpmiVersion, OutOfBandWorkaround,
+            PrivilegeLevel, SessionType,
+        },
+        sensor::{SensorReading, SensorType, SensorUnits, SensorValue},
+    },
+};
+
+#[derive(Debug, Eq, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("Failed to parse as UTF-8: {0}")]
+    NotUtf8(#[from] Utf8Error),
+    #[cfg(windows)]
+    #[error("Path is not valid UTF-8: {0:?}")]
+    PathNotUtf8(PathBuf),
+    #[error("[libipmimonitoring] Failed to {action}: {message}")]
+    Lim {
+        action: &'static str,
+        message: &'static str,
+    },
+    #[error("[libfreeipmi] Failed to {action}: {message}")]
+    Lfi {
+        action: &'static str,
+        message: &'static str,
+    },
+    #[error("No in-band IPMI devices found")]
+    InBandDeviceNotFound,
+    #[error("Command response is too short")]
+    ResponseTooShort,
+    #[error("Requested command {request:02x}, but responded with command {response:02x}")]
+    BadResponseCommand {
+        request: u8,
+        response: u8,
+    },
+    #[error("Failed to execute command: {0}")]
+    CommandFailed(String),
+    #[error("[libfreeipmi] IPMI session timed out: {0}")]
+    SessionTimeout(&'static str),
+    #[error("Failed to delete SDR cache {path:?}: {message}")]
+    SdrCacheDelete {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+/// Get the libipmimonitoring sensor types to query for a [`SensorType`].
+/// Readings of the electrical types still need to be filtered by their units.
+fn sensor_type_to_raw(sensor_type: SensorType) -> Vec<c_uint> {
+    match sensor_type {
+        SensorType::Temperature => vec![bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_TEMPERATURE],
+        SensorType::Fan => vec![bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_FAN],
+        SensorType::Voltage => vec![bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_VOLTAGE],
+        SensorType::Current => vec![bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_CURRENT],
+        SensorType::Power => vec![
+            bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_CURRENT,
+            bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_POWER_SUPPLY,
+            bindings::ipmi_monitoring_sensor_type_IPMI_MONITORING_SENSOR_TYPE_OTHER_UNITS_BASED_SENSOR,
+        ],
+    }
+}
+
+/// Try to convert a pointer to a statically allocated C string to a UTF-8 Rust
+/// string. Both LIM and LFI return error messages allocated from static
+/// globals. This is documented behavior of the ipmi_*_strerror() and
+/// ipmi_*_errormsg() functions.
+unsafe fn from_static_utf8_cstr(ptr: *const i8) -> Result<&'static str> {
+    Ok(CStr::from_ptr(ptr).to_str()?)
+}
+
+/// Get the libfreeipmi workaround flags for a session.
+fn lfi_workaround_flags(st: &SessionType) -> c_uint {
+    let flags = match st {
+        SessionType::Local { workaround_flags, .. } => workaround_flags.iter()
+            .map(|f| match f {
+                InBandWorkaround::AssumeIoBaseAddress => bindings::IPMI_WORKAROUND_FLAGS_INBAND_ASSUME_IO_BASE_ADDRESS,
+                InBandWorkaround::SpinPoll => bindings::IPMI_WORKAROUND_FLAGS_INBAND_SPIN_POLL,
+            })
+            .collect::<Vec<_>>(),
+        SessionType::Remote { workaround_flags, ipmi_version, .. } => workaround_flags.iter()
+            .map(|f| match f {
+                OutOfBandWorkaround::AuthenticationCapabilities => match ipmi_version {
+                    IpmiVersion::V15 => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_AUTHENTICATION_CAPABILITIES,
+                    IpmiVersion::V20 => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_AUTHENTICATION_CAPABILITIES,
+                },
+                OutOfBandWorkaround::AcceptSessionIdZero => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_ACCEPT_SESSION_ID_ZERO,
+                OutOfBandWorkaround::ForcePermsgAuthentication => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_FORCE_PERMSG_AUTHENTICATION,
+                OutOfBandWorkaround::CheckUnexpectedAuthcode => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_CHECK_UNEXPECTED_AUTHCODE,
+                OutOfBandWorkaround::BigEndianSequenceNumber => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_BIG_ENDIAN_SEQUENCE_NUMBER,
+                OutOfBandWorkaround::NoAuthCodeCheck => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_NO_AUTH_CODE_CHECK,
+                OutOfBandWorkaround::Intel20Session => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_INTEL_2_0_SESSION,
+                OutOfBandWorkaround::Supermicro20Session => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_SUPERMICRO_2_0_SESSION,
+                OutOfBandWorkaround::Sun20Session => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_SUN_2_0_SESSION,
+                OutOfBandWorkaround::OpenSessionPrivilege => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_OPEN_SESSION_PRIVILEGE,
+                OutOfBandWorkaround::NonEmptyIntegrityCheckValue => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_NON_EMPTY_INTEGRITY_CHECK_VALUE,
+                OutOfBandWorkaround::NoChecksumCheck => bindings::IPMI_WORKAROUND_FLAGS_OUTOFBAND_2_0_NO_CHECKSUM_CHECK,
+            })
+            .collect(),
+    };
+
+    flags.into_iter().fold(0, |acc, f| acc | f as c_uint)
+}
+
+/// Get the libipmimonitoring workaround flags for a session.
+fn lim_workaround_flags(st: &SessionType) -> c_uint {
+    let flags = match st {
+        SessionType::Local { workaround_flags, .. } => workaround_flags.iter()
+            .map(|f| match f {
+                InBandWorkaround::AssumeIoBaseAddress => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_INBAND_ASSUME_IO_BASE_ADDRESS,
+                InBandWorkaround::SpinPoll => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_INBAND_SPIN_POLL,
+            })
+            .collect::<Vec<_>>(),
+        SessionType::Remote { workaround_flags, ipmi_version, .. } => workaround_flags.iter()
+            .map(|f| match f {
+                OutOfBandWorkaround::AuthenticationCapabilities => match ipmi_version {
+                    IpmiVersion::V15 => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_AUTHENTICATION_CAPABILITIES,
+                    IpmiVersion::V20 => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_AUTHENTICATION_CAPABILITIES,
+                },
+                OutOfBandWorkaround::AcceptSessionIdZero => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_ACCEPT_SESSION_ID_ZERO,
+                OutOfBandWorkaround::ForcePermsgAuthentication => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_FORCE_PERMSG_AUTHENTICATION,
+                OutOfBandWorkaround::CheckUnexpectedAuthcode => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_CHECK_UNEXPECTED_AUTHCODE,
+                OutOfBandWorkaround::BigEndianSequenceNumber => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_BIG_ENDIAN_SEQUENCE_NUMBER,
+                OutOfBandWorkaround::NoAuthCodeCheck => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_1_5_NO_AUTH_CODE_CHECK,
+                OutOfBandWorkaround::Intel20Session => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_INTEL_2_0_SESSION,
+                OutOfBandWorkaround::Supermicro20Session => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_SUPERMICRO_2_0_SESSION,
+                OutOfBandWorkaround::Sun20Session => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_SUN_2_0_SESSION,
+                OutOfBandWorkaround::OpenSessionPrivilege => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_OPEN_SESSION_PRIVILEGE,
+                OutOfBandWorkaround::NonEmptyIntegrityCheckValue => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_NON_EMPTY_INTEGRITY_CHECK_VALUE,
+                OutOfBandWorkaround::NoChecksumCheck => bindings::IPMI_MONITORING_WORKAROUND_FLAGS_PROTOCOL_VERSION_2_0_NO_CHECKSUM_CHECK,
+            })
+            .collect(),
+    };
+
+    flags.into_iter().fold(0, |acc, f| acc | f as c_uint)
+}
+
+/// Low-level wrapper for libfreeipmi context.
+struct LfiCtx(*mut bindings::ipmi_ctx);
+
+impl LfiCtx {
+    fn new() -> Result<Self> {
+        // [Unsafe] No memory safety concerns
+        let ctx = unsafe { bindings::ipmi_ctx_create() };
+        if ctx.is_null() {
+            return Err(Error::Lim {
+                action: "create context",
+                message: "(unknown)",
+            });
+        }
+
+        Ok(Self(ctx))
+    }
+
+    fn error_msg(&self) -> Result<&'static str> {
+        // [Unsafe] Always returns a valid string from a statically allocated
+        // region
+        unsafe {
+            from_static_utf8_cstr(bindings::ipmi_ctx_errormsg(self.0))
+        }
+    }
+
+    /// Check if the last error was caused by the out-of-band session or
+    /// connection timing out.
+    fn is_timeout(&self) -> bool {
+        // [Unsafe] No memory safety concerns
+        let errnum = unsafe { bindings::ipmi_ctx_errnum(self.0) } as c_uint;
+
+        errnum == bindings::ipmi_errnum_IPMI_ERR_SESSION_TIMEOUT
+            || errnum == bindings::ipmi_errnum_IPMI_ERR_CONNECTION_TIMEOUT
+    }
+
+    /// Open the local in-band IPMI device and use it for further calls with
+    /// this context instance. If no driver is configured, probing is enabled
+    /// for automatically detecting the appropriate driver to use. The session
+    /// type must be [`SessionType::Local`].
+    #[allow(clippy::comparison_chain)]
+    fn open_in_band(&mut self, st: &SessionType) -> Result<()> {
+        let (driver, driver_device, driver_address, register_spacing) = match st {
+            SessionType::Local { driver, driver_device, driver_address, register_spacing, .. } => {
+                (driver, driver_device, driver_address, register_spacing)
+            }
+            SessionType::Remote { .. } => unreachable!("Not an in-band session"),
+        };
+
+        let device_cstr = driver_device.as_ref()
+            .map(|d| path_to_cstring(Path::new(d)))
+            .transpose()?;
+        let device_ptr = device_cstr.as_ref().map_or(ptr::null(), |d| d.as_ptr());
+        let workaround_flags = lfi_workaround_flags(st);
+
+        let driver_type = match driver {
+            Some(InBandDriver::Kcs) => bindings::ipmi_driver_type_IPMI_DEVICE_KCS,
+            Some(InBandDriver::Ssif) => bindings::ipmi_driver_type_IPMI_DEVICE_SSIF,
+            Some(InBandDriver::OpenIpmi) => bindings::ipmi_driver_type_IPMI_DEVICE_OPENIPMI,
+            Some(InBandDriver::SunBmc) => bindings::ipmi_driver_type_IPMI_DEVICE_SUNBMC,
+            None => {
+                // [Unsafe] freeipmi stores its own copy of the device path.
+                // Zero for the address and register spacing selects the
+                // default or probed values.
+                let ret = unsafe {
+                    bindings::ipmi_ctx_find_inband(
+                        self.0,
+                        ptr::null_mut(),
+                        0,
+                        driver_address.unwrap_or(0),
+                        register_spacing.unwrap_or(0),
+                        device_ptr,
+                        workaround_flags,
+                        bindings::IPMI_FLAGS_DEFAULT,
+                    )
+                };
+
+                return match ret.cmp(&0) {
+                    Ordering::Less => Err(Error::Lfi {
+                        action: "find inband IPMI device",
+                        message: self.error_msg()?,
+                    }),
+                    Ordering::Equal => Err(Error::InBandDeviceNotFound),
+                    Ordering::Greater => Ok(()),
+                };
+            }
+        };
+
+        // [Unsafe] Same as above
+        let ret = unsafe {
+            bindings::ipmi_ctx_open_inband(
+                self.0,
+                driver_type,
+                0,
+                driver_address.unwrap_or(0),
+                register_spacing.unwrap_or(0),
+                device_ptr,
+                workaround_flags,
+                bindings::IPMI_FLAGS_DEFAULT,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Lfi {
+                action: "open inband IPMI device",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Connect to the specified out-of-band IPMI 1.5 or 2.0 device and use it
+    /// for further calls with this context instance. Unspecified connection
+    /// parameters use the same defaults as libipmimonitoring. The session type
+    /// must be [`SessionType::Remote`].
+    fn open_out_of_band(&mut self, st: &SessionType) -> Result<()> {
+        let (hostname, username, password, privilege_level, ipmi_version, authentication_type,
+                cipher_suite_id, k_g, session_timeout_ms, retransmission_timeout_ms) = match st {
+            SessionType::Local { .. } => unreachable!("Not an out-of-band session"),
+            SessionType::Remote {
+                hostname,
+                username,
+                password,
+                privilege_level,
+                ipmi_version,
+                authentication_type,
+                cipher_suite_id,
+                k_g,
+                session_timeout_ms,
+                retransmission_timeout_ms,
+                ..
+            } => (hostname, username, password, privilege_level, ipmi_version, authentication_type,
+                  cipher_suite_id, k_g, session_timeout_ms, retransmission_timeout_ms),
+        };
+
+        let hostname_cstr = CString::new(hostname.as_str()).unwrap();
+        let username_cstr = CString::new(username.as_str()).unwrap();
+        // Resolved when the config is loaded
+        let password_cstr = CString::new(password.as_ref().map_or("", |p| p.0.as_str())).unwrap();
+        let k_g = k_g.as_ref().map_or(&[][..], |k| k.0.as_slice());
+        let k_g_ptr = if k_g.is_empty() { ptr::null() } else { k_g.as_ptr() };
+
+        let privilege_level = match privilege_level {
+            PrivilegeLevel::User => bindings::IPMI_PRIVILEGE_LEVEL_USER,
+            PrivilegeLevel::Operator => bindings::IPMI_PRIVILEGE_LEVEL_OPERATOR,
+            PrivilegeLevel::Admin => bindings::IPMI_PRIVILEGE_LEVEL_ADMIN,
+        };
+
+        if *ipmi_version == IpmiVersion::V15 {
+            let authentication_type = match authentication_type.unwrap_or(AuthenticationType::Md5) {
+                AuthenticationType::None => bindings::IPMI_AUTHENTICATION_TYPE_NONE,
+                AuthenticationType::StraightPasswordKey => bindings::IPMI_AUTHENTICATION_TYPE_STRAIGHT_PASSWORD_KEY,
+                AuthenticationType::Md2 => bindings::IPMI_AUTHENTICATION_TYPE_MD2,
+                AuthenticationType::Md5 => bindings::IPMI_AUTHENTICATION_TYPE_MD5,
+            };
+
+            // [Unsafe] freeipmi stores its own copy of these strings within
+            // ctx. It performs its own max length checks.
+            let ret = unsafe {
+                bindings::ipmi_ctx_open_outofband(
+                    self.0,
+                    hostname_cstr.as_ptr(),
+                    username_cstr.as_ptr(),
+                    password_cstr.as_ptr(),
+                    authentication_type.try_into().unwrap(),
+                    privilege_level.try_into().unwrap(),
+                    session_timeout_ms.unwrap_or(0),
+                    retransmission_timeout_ms.unwrap_or(0),
+                    lfi_workaround_flags(st),
+                    bindings::IPMI_FLAGS_DEFAULT,
+                )
+            };
+            if ret < 0 {
+                return Err(Error::Lfi {
+                    action: "open out-of-band IPMI 1.5 device",
+                    message: self.error_msg()?,
+                });
+            }
+
+            return Ok(());
+        }
+
+        // [Unsafe] freeipmi stores its own copy of these strings and the k_g
+        // buffer within ctx. It performs its own max length checks.
+        let ret = unsafe {
+            bindings::ipmi_ctx_open_outofband_2_0(
+                self.0,
+                hostname_cstr.as_ptr(),
+                username_cstr.as_ptr(),
+                password_cstr.as_ptr(),
+                k_g_ptr,
+                k_g.len() as c_uint,
+                privilege_level.try_into().unwrap(),
+                cipher_suite_id.unwrap_or(3),
+                // 0 selects the libfreeipmi defaults
+                session_timeout_ms.unwrap_or(0),
+                retransmission_timeout_ms.unwrap_or(0),
+                lfi_workaround_flags(st),
+                bindings::IPMI_FLAGS_DEFAULT,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Lfi {
+                action: "open out-of-band IPMI device",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Bridge further commands with this context instance to an IPMB target.
+    fn set_target(&mut self, bridge: Bridge) -> Result<()> {
+        let (mut channel, mut address) = (bridge.channel, bridge.address);
+
+        // [Unsafe] freeipmi copies the values
+        let ret = unsafe { bindings::ipmi_ctx_set_target(self.0, &mut channel, &mut address) };
+        if ret < 0 {
+            return Err(Error::Lfi {
+                action: "set bridging target",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Execute a raw IPMI command. The first byte of the request buffer should
+    /// be the command number, followed by any data if needed. The response
+    /// buffer will contain the command number in the first byte and the status
+    /// code in the second byte, followed by any response data. If the status
+    /// code does not report a successful execution, [`Error::CommandFailed`] is
+    /// returned. The size of both the request and response buffers must not
+    /// exceed the bounds of a [`c_int`] or else the function will panic.
+    fn raw_command(
+        &mut self,
+        lun: u8,
+        net_fn: u8,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize> {
+        // [Unsafe] Shouldn't be any memory safety concerns. We're passing in
+        // the size of all buffers.
+        let ret = unsafe {
+            bindings::ipmi_cmd_raw(
+                self.0,
+                lun,
+                net_fn,
+                request.as_ptr().cast(),
+                request.len().try_into().expect("request buffer too large"),
+                response.as_mut_ptr().cast(),
+                response.len().try_into().expect("response buffer too large"),
+            )
+        };
+        if ret < 0 {
+            if self.is_timeout() {
+                return Err(Error::SessionTimeout(self.error_msg()?));
+            }
+
+            return Err(Error::Lfi {
+                action: "execute raw command",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(ret as usize)
+    }
+}
+
+impl Drop for LfiCtx {
+    fn drop(&mut self) {
+        // [Unsafe] Context is always valid (non-NULL). This implicitly calls
+        // ipmi_ctx_close() if a device is open.
+        unsafe { bindings::ipmi_ctx_destroy(self.0) }
+    }
+}
+
+/// The internal pointer is safe to move between threads.
+unsafe impl Send for LfiCtx {}
+
+/// High-level wrapper for libfreeipmi.
+pub struct LfiSession(LfiCtx);
+
+impl LfiSession {
+    /// Open a session. If `bridge` is set, raw commands are bridged to that
+    /// IPMB target.
+    pub fn new(st: &SessionType, bridge: Option<Bridge>) -> Result<Self> {
+        // [Unsafe] No memory safety concerns
+        let mut ctx = LfiCtx::new()?;
+
+        match st {
+            SessionType::Local { .. } => {
+                ctx.open_in_band(st)?;
+            }
+            SessionType::Remote { .. } => {
+                ctx.open_out_of_band(st)?;
+            },
+        };
+
+        if let Some(bridge) = bridge {
+            ctx.set_target(bridge)?;
+        }
+
+        Ok(Self(ctx))
+    }
+
+    /// Execute a raw IPMI command. The return value only includes the response
+    /// data, excluding the first two bytes (command number and status code). If
+    /// the command fails, [`Error::CommandFailed`] is returned. The size of the
+    /// request data must not be greater than or equal to 256 bytes or else the
+    /// function will panic.
+    pub fn raw_command(
+        &mut self,
+        net_fn: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        // Same as in freeipmi's ipmi-oem
+        const IPMI_OEM_MAX_BYTES: usize = 256;
+        const IPMI_OEM_ERR_BUFLEN: usize = 1024;
+
+        assert!(data.len() < IPMI_OEM_MAX_BYTES);
+
+        let mut request_buf = [0u8; IPMI_OEM_MAX_BYTES];
+        let mut response_buf = [0u8; IPMI_OEM_MAX_BYTES];
+
+        request_buf[0] = command;
+        request_buf[1..=data.len()].copy_from_slice(data);
+
+        let size = self.0.raw_command(
+            0,
+            net_fn,
+            &request_buf[0..=data.len()],
+            &mut response_buf,
+        )?;
+
+        if size < 2 {
+            return Err(Error::ResponseTooShort);
+        }
+
+        if response_buf[0] != command {
+            return Err(Error::BadResponseCommand {
+                request: command,
+                response: response_buf[0],
+            });
+        }
+
+        if response_buf[1] != bindings::IPMI_COMP_CODE_COMMAND_SUCCESS as u8 {
+            let mut error_buf = [0 as c_char; IPMI_OEM_ERR_BUFLEN + 1];
+
+            // [Unsafe] Calls snprintf to fill error_buf. The buffer is zero-
+            // initialized and one element larger, so it's guaranteed to have a
+            // NULL terminator.
+            let error_ret = unsafe {
+                bindings::ipmi_completion_code_strerror_r(
+                    command,
+                    net_fn,
+                    response_buf[1],
+                    error_buf.as_mut_ptr(),
+                    (error_buf.len() - 1).try_into().unwrap(),
+                )
+            };
+            if error_ret < 0 {
+                return Err(Error::CommandFailed(
+                    format!("Unknown status: {:02x}", response_buf[1])));
+            }
+
+            // [Unsafe] Guaranteed to be NULL terminated
+            let error_cstr = unsafe { CStr::from_ptr(error_buf.as_ptr()) };
+
+            return Err(Error::CommandFailed(error_cstr.to_str()?.to_owned()));
+        }
+
+        Ok(response_buf[2..size].to_vec())
+    }
+}
+
+/// Convert a libipmimonitoring error code to a string.
+fn lim_strerror(errnum: c_int) -> Result<&'static str> {
+    // [Unsafe] Always returns a valid string from a statically allocated region
+    unsafe {
+        from_static_utf8_cstr(bindings::ipmi_monitoring_ctx_strerror(errnum))
+    }
+}
+
+static LIM_INITIALIZED: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+/// Initialize libipmimonitoring globally. This is thread safe and is a no-op if
+/// LIM is already initialized.
+fn lim_init() -> Result<()> {
+    let mut initialized = LIM_INITIALIZED.lock().unwrap();
+
+    if !*initialized {
+        let mut errnum = 0i32;
+        // [Unsafe] No memory safety concerns
+        let ret = unsafe { bindings::ipmi_monitoring_init(0, &mut errnum) };
+        if ret < 0 {
+            return Err(Error::Lim {
+                action: "initialize library",
+                message: lim_strerror(errnum)?,
+            });
+        }
+
+        *initialized = true;
+    }
+
+    Ok(())
+}
+
+/// Convert a path to a C string. If the path is not valid UTF-8, then an error
+/// will be returned.
+#[cfg(windows)]
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    // Require that the path is valid UTF-8 because there's no reasonable way to
+    // go from a WTF-8 OsStr to CString on Windows
+    let path_str = path.to_str()
+        .ok_or_else(|| Error::PathNotUtf8(path.to_owned()))?;
+
+    // Safe to unwrap since a path never has embedded null bytes
+    Ok(CString::new(path_str.as_bytes()).unwrap())
+}
+
+/// Convert a path to a C string. Never fails on Unix-like systems.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // Safe to unwrap since a path never has embedded null bytes
+    Ok(CString::new(path.as_os_str().as_bytes()).unwrap())
+}
+
+/// Prefix of the SDR cache file names that libipmimonitoring creates. The
+/// host name follows the prefix.
+const SDR_CACHE_PREFIX: &str = "ipmimonitoringsdrcache";
+
+/// High-level wrapper for limipmimonitoring.
+pub struct LimSession {
+    ctx: *mut bindings::ipmi_monitoring_ctx,
+    config: bindings::ipmi_monitoring_ipmi_config,
+    hostname: Option<String>,
+    sdr_cache_dir: Option<PathBuf>,
+    /// Buffer referenced by `config.k_g`. The heap allocation does not move
+    /// when LimSession is moved.
+    #[allow(dead_code)]
+    k_g: Vec<u8>,
+}
+
+impl LimSession {
+    pub fn new(st: &SessionType) -> Result<Self> {
+        lim_init()?;
+
+        // Negative values and 0 select probing and the default values. The
+        // device path is "owned" by the C struct like the strings below.
+        let (driver_type, driver_address, register_spacing, driver_device) = match st {
+            SessionType::Local { driver, driver_device, driver_address, register_spacing, .. } => (
+                driver.map_or(-1, |d| match d {
+                    InBandDriver::Kcs => bindings::ipmi_monitoring_driver_type_IPMI_MONITORING_DRIVER_TYPE_KCS,
+                    InBandDriver::Ssif => bindings::ipmi_monitoring_driver_type_IPMI_MONITORING_DRIVER_TYPE_SSIF,
+                    InBandDriver::OpenIpmi => bindings::ipmi_monitoring_driver_type_IPMI_MONITORING_DRIVER_TYPE_OPENIPMI,
+                    InBandDriver::SunBmc => bindings::ipmi_monitoring_driver_type_IPMI_MONITORING_DRIVER_TYPE_SUNBMC,
+                } as c_int),
+                driver_address.unwrap_or(0),
+                register_spacing.unwrap_or(0),
+                driver_device.as_ref()
+                    .map(|d| path_to_cstring(Path::new(d)))
+                    .transpose()?
+                    .map_or(ptr::null_mut(), CString::into_raw),
+            ),
+            SessionType::Remote { .. } => (-1, 0, 0, ptr::null_mut()),
+        };
+
+        // These strings will be "owned" by the C struct and will be freed in
+        // the Drop implementation. This allows LimSession to remain movable.
+        let (hostname, username, password) = match st {
+            SessionType::Local { .. } => (None, ptr::null_mut(), ptr::null_mut()),
+            SessionType::Remote { hostname, username, password, .. } => (
+                Some(hostname.clone()),
+                CString::new(username.as_str()).unwrap().into_raw(),
+                // Resolved when the config is loaded
+                CString::new(password.as_ref().map_or("", |p| p.0.as_str())).unwrap().into_raw(),
+            ),
+        };
+
+        // Negative values and 0 select the libipmimonitoring defaults
+        let (protocol_version, authentication_type, privilege_level, cipher_suite_id, mut k_g,
+                session_timeout, retransmission_timeout) =
+            match st {
+                SessionType::Local { .. } => (
+                    bindings::ipmi_monitoring_protocol_version_IPMI_MONITORING_PROTOCOL_VERSION_2_0 as c_int,
+                    -1, -1, -1, vec![], 0, 0,
+                ),
+                SessionType::Remote {
+                    ipmi_version,
+                    authentication_type,
+                    privilege_level,
+                    cipher_suite_id,
+                    k_g,
+                    session_timeout_ms,
+                    retransmission_timeout_ms,
+                    ..
+                } => (
+                    match ipmi_version {
+                        IpmiVersion::V15 => bindings::ipmi_monitoring_protocol_version_IPMI_MONITORING_PROTOCOL_VERSION_1_5,
+                        IpmiVersion::V20 => bindings::ipmi_monitoring_protocol_version_IPMI_MONITORING_PROTOCOL_VERSION_2_0,
+                    } as c_int,
+                    authentication_type.map_or(-1, |t| match t {
+                        AuthenticationType::None => bindings::ipmi_monitoring_authentication_type_IPMI_MONITORING_AUTHENTICATION_TYPE_NONE,
+                        AuthenticationType::StraightPasswordKey => bindings::ipmi_monitoring_authentication_type_IPMI_MONITORING_AUTHENTICATION_TYPE_STRAIGHT_PASSWORD_KEY,
+                        AuthenticationType::Md2 => bindings::ipmi_monitoring_authentication_type_IPMI_MONITORING_AUTHENTICATION_TYPE_MD2,
+                        AuthenticationType::Md5 => bindings::ipmi_monitoring_authentication_type_IPMI_MONITORING_AUTHENTICATION_TYPE_MD5,
+                    } as c_int),
+                    match privilege_level {
+                        PrivilegeLevel::User => bindings::ipmi_monitoring_privilege_IPMI_MONITORING_PRIVILEGE_LEVEL_USER,
+                        PrivilegeLevel::Operator => bindings::ipmi_monitoring_privilege_IPMI_MONITORING_PRIVILEGE_LEVEL_OPERATOR,
+                        PrivilegeLevel::Admin => bindings::ipmi_monitoring_privilege_IPMI_MONITORING_PRIVILEGE_LEVEL_ADMIN,
+                    } as c_int,
+                    cipher_suite_id.map_or(-1, c_int::from),
+                    k_g.as_ref().map_or_else(Vec::new, |k| k.0.clone()),
+                    session_timeout_ms.map_or(0, |t| t.min(c_int::MAX as u32) as c_int),
+                    retransmission_timeout_ms.map_or(0, |t| t.min(c_int::MAX as u32) as c_int),
+                ),
+            };
+        let k_g_ptr = if k_g.is_empty() { ptr::null_mut() } else { k_g.as_mut_ptr() };
+
+        // [Unsafe] No memory safety concerns. This will never leak because no
+        // code below this point can panic.
+        let ctx = unsafe { bindings::ipmi_monitoring_ctx_create() };
+        if ctx.is_null() {
+            return Err(Error::Lim {
+                action: "create context",
+                message: "(unknown)",
+            });
+        }
+
+        let config = bindings::ipmi_monitoring_ipmi_config {
+            // In-band options. All options are set to the default because we
+            // use automatic probing. The documentation says that setting the
+            // driver type to < 0 is equivalent to using the default of
+            // IPMI_MONITORING_DRIVER_TYPE_KCS, but this is incorrect and the
+            // implementation actually calls ipmi_ctx_find_inband.
+            driver_type,
+            disable_auto_probe: 0,
+            driver_address,
+            register_spacing,
+            driver_device,
+            // Out-of-band options. The authentication type only applies to
+            // IPMI 1.5.
+            protocol_version,
+            username,
+            password,
+            k_g: k_g_ptr,
+            k_g_len: k_g.len() as c_uint,
+            privilege_level,
+            authentication_type,
+            cipher_suite_id,
+            session_timeout_len: session_timeout,
+            retransmission_timeout_len: retransmission_timeout,
+            // Other options
+            workaround_flags: lim_workaround_flags(st),
+        };
+
+        Ok(Self { ctx, config, hostname, sdr_cache_dir: None, k_g })
+    }
+
+    fn error_msg(&self) -> Result<&'static str> {
+        // [Unsafe] Similar to ipmi_monitoring_cts_strerror, this always returns
+        // a valid string from a statically allocated region
+        unsafe {
+            from_static_utf8_cstr(
+                bindings::ipmi_monitoring_ctx_errormsg(self.ctx))
+        }
+    }
+
+    /// Set the directory for storing the SDR cache for the current host.
+    pub fn set_sdr_cache_directory(&mut self, path: &Path) -> Result<()> {
+        let cstr = path_to_cstring(path)?;
+
+        // [Unsafe] String is valid and a copy of it will be saved in a
+        // statically sized buffer within the context. If the path is too long,
+        // an error is returned.
+        let ret = unsafe {
+            bindings::ipmi_monitoring_ctx_sdr_cache_directory(
+                self.ctx, cstr.as_ptr())
+        };
+        if ret < 0 {
+            return Err(Error::Lim {
+                action: "set SDR cache directory",
+                message: self.error_msg()?,
+            });
+        }
+
+        self.sdr_cache_dir = Some(path.to_owned());
+
+        Ok(())
+    }
+
+    /// Delete the SDR cache for the current host so that it is rebuilt on the
+    /// next sensor query. libipmimonitoring only rebuilds the cache on its own
+    /// if the BMC reports that the SDR changed, which not every BMC does after
+    /// a firmware update.
+    pub fn clear_sdr_cache(&mut self) -> Result<()> {
+        let dir = match &self.sdr_cache_dir {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+        let host = self.hostname.as_deref().unwrap_or("localhost");
+
+        let delete_error = |path: &Path, e: io::Error| Error::SdrCacheDelete {
+            path: path.to_owned(),
+            message: e.to_string(),
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(delete_error(dir, e)),
+        };
+
+        for entry in entries {
+            let path = entry.map_err(|e| delete_error(dir, e))?.path();
+            let is_cache = path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(SDR_CACHE_PREFIX) && n.ends_with(host));
+
+            if is_cache {
+                fs::remove_file(&path).map_err(|e| delete_error(&path, e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the path to the config file containing sensor reading interpretation
+    /// rules. If the path is [`None`], then the default sensor config file is
+    /// used. If this function is never called, then only the interpretations
+    /// built into libipmimonitoring are used.
+    pub fn set_sensor_config_file(&mut self, path: Option<&Path>) -> Result<()> {
+        // LIM does not store this string
+        let path_cstr = match path {
+            Some(p) => Some(path_to_cstring(p)?),
+            None => None,
+        };
+        let path_ptr = path_cstr.as_ref()
+            .map_or(ptr::null(), |s| s.as_ptr());
+
+        // [Unsafe] NULL strings are valid
+        let ret = unsafe {
+            bindings::ipmi_monitoring_ctx_sensor_config_file(self.ctx, path_ptr)
+        };
+        if ret < 0 {
+            return Err(Error::Lim {
+                action: "set sensor config file",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Start iteration of sensor readings of the specified type. Use
+    /// [`iterator_next`] to advance the iterator and
+    /// [`read_sensor_name`]/[`read_sensor`] to get the actual values.
+    pub fn sensor_readings(&mut self, sensor_type: SensorType) -> Result<usize> {
+        // LIM does not store this string
+        let hostname_cstr = self.hostname.as_ref()
+            .map(|s| CString::new(s.as_str()).unwrap());
+        let hostname_ptr = hostname_cstr.as_ref()
+            .map_or(ptr::null(), |s| s.as_ptr());
+        let mut sensor_types = sensor_type_to_raw(sensor_type);
+
+        // [Unsafe] config and sensor_types are passed as mutable pointers to
+        // satisfy the type signature only. They are never modified. The
+        // hostname pointer does not need to remain valid after the function
+        // returns.
+        let ret = unsafe {
+            bindings::ipmi_monitoring_sensor_readings_by_sensor_type(
+                self.ctx,
+                hostname_ptr,
+                ptr::addr_of_mut!(self.config),
+                bindings::ipmi_monitoring_sensor_reading_flags_IPMI_MONITORING_SENSOR_READING_FLAGS_IGNORE_NON_INTERPRETABLE_SENSORS,
+                sensor_types.as_mut_ptr(),
+                sensor_types.len() as c_uint,
+                None,
+                ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Lim {
+                action: "get sensor readings",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(ret as usize)
+    }
+
+    /// Advance to the next item when iterating through sensor readings.
+    pub fn iterator_next(&mut self) -> Result<()> {
+        // [Unsafe] No memory safety concerns
+        let ret = unsafe {
+            bindings::ipmi_monitoring_sensor_iterator_next(self.ctx)
+        };
+        if ret < 0 {
+            return Err(Error::Lim {
+                action: "get next sensor reading",
+                message: self.error_msg()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Get the sensor name for the current item during sensor reading
+    /// iteration.
+    pub fn read_sensor_name(&mut self) -> Result<String> {
+        // [Unsafe] Returns non-owned pointer
+        let ret = unsafe {
+            bindings::ipmi_monitoring_sensor_read_sensor_name(self.ctx)
+        };
+        if ret.is_null() {
+            return Err(Error::Lim {
+                action: "read sensor name",
+                message: self.error_msg()?,
+            });
+        }
+
+        // [Unsafe] String is not NULL at this point
+        let cstr = unsafe { CStr::from_ptr(ret) };
+
+        Ok(cstr.to_str()?.to_owned())
+    }
+
+    /// Get the sensor reading (value and units) for the current item during
+    /// sensor reading iteration.
+    pub fn read_sensor(&mut self) -> Result<Option<SensorReading>> {
+        // [Unsafe] No memory safety concerns
+        let type_ret = unsafe {
+            bindings::ipmi_monitoring_sensor_read_sensor_reading_type(self.ctx)
+        };
+        if type_ret < 0 {
+            return Err(Error::Lim {
+                action: "read sensor value type",
+                message: self.error_msg()?,
+            });
+        }
+
+        // [Unsafe] The return value can have multiple types and is determined
+        // by type_ret
+        let value_ret = unsafe {
+            bindings::ipmi_monitoring_sensor_read_sensor_reading(self.ctx)
+        };
+        if value_ret.is_null() {
+            return Ok(None);
+        }
+
+        // [Unsafe] Casting value_ret (void *) return value based on type_ret
+        let value = unsafe {
+            match type_ret as c_uint {
+                bindings::ipmi_monitoring_sensor_reading_type_IPMI_MONITORING_SENSOR_READING_TYPE_UNSIGNED_INTEGER8_BOOL => {
+                    SensorValue::Bool(*(value_ret as *const u8) != 0)
+                }
+                bindings::ipmi_monitoring_sensor_reading_type_IPMI_MONITORING_SENSOR_READING_TYPE_UNSIGNED_INTEGER32 => {
+                    SensorValue::Uint32(*(value_ret as *const u32))
+                }
+                bindings::ipmi_monitoring_sensor_reading_type_IPMI_MONITORING_SENSOR_READING_TYPE_DOUBLE => {
+                    SensorValue::Double(*(value_ret as *const f64))
+                }
+                _ => SensorValue::Unknown
+            }
+        };
+
+        // [Unsafe] No memory safety concerns
+        let units_ret = unsafe {
+            bindings::ipmi_monitoring_sensor_read_sensor_units(self.ctx)
+        };
+        if units_ret < 0 {
+            return Err(Error::Lim {
+                action: "read sensor units",
+                message: self.error_msg()?,
+            });
+        }
+
+        let units = match units_ret as c_uint {
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_CELSIUS =>
+                SensorUnits::Celsius,
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_FAHRENHEIT =>
+                SensorUnits::Fahrenheit,
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_RPM =>
+                SensorUnits::Rpm,
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_VOLTS =>
+                SensorUnits::Volts,
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_AMPS =>
+                SensorUnits::Amps,
+            bindings::ipmi_monitoring_sensor_units_IPMI_MONITORING_SENSOR_UNITS_WATTS =>
+                SensorUnits::Watts,
+            o => SensorUnits::Unknown(o),
+        };
+
+        Ok(Some(SensorReading { value, units }))
+    }
+}
+
+impl Drop for LimSession {
+    fn drop(&mut self) {
+        // [Unsafe] Context is always valid (non-NULL)
+        unsafe { bindings::ipmi_monitoring_ctx_destroy(self.ctx) }
+
+        if !self.config.username.is_null() {
+            // [Unsafe] Allocated by CString::new() and never changed
+            unsafe { CString::from_raw(self.config.username) };
+        }
+        if !self.config.password.is_null() {
+            // [Unsafe] Allocated by CString::new() and never changed
+            unsafe { CString::from_raw(self.config.password) };
+        }
+        if !self.config.driver_device.is_null() {
+            // [Unsafe] Allocated by CString::new() and never changed
+            drop(unsafe { CString::from_raw(self.config.driver_device) });
+        }
+    }
+}
+
+/// The internal pointers and structs are safe to move between threads.
+unsafe impl Send for LimSession {}
@@ -0,0 +1,113 @@
+use {
+    std::{
+        ffi::OsString,
+        fs,
+        io,
+        path::{Path, PathBuf},
+    },
+    log::{error, info},
+    crate::error::{Error, Result},
+};
+
+/// Value of `pwmN_enable` that allows the PWM value to be set manually.
+const ENABLE_MANUAL: &str = "1";
+/// Maximum value of `pwmN`.
+const PWM_MAX: u32 = 255;
+
+/// Get the path of the `pwmN_enable` file corresponding to a `pwmN` file.
+fn enable_path(path: &Path) -> PathBuf {
+    let mut enable = OsString::from(path.as_os_str());
+    enable.push("_enable");
+    PathBuf::from(enable)
+}
+
+/// Read the current duty cycle of a PWM output as a percentage without taking
+/// control of it.
+pub fn read_duty_cycle(path: &Path) -> Result<u8> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+    let trimmed = contents.trim();
+
+    let pwm = trimmed
+        .parse::<u32>()
+        .map_err(|e| Error::SensorValueParse { value: trimmed.to_owned(), source: e })?
+        .min(PWM_MAX);
+
+    Ok(((pwm * 100 + PWM_MAX / 2) / PWM_MAX) as u8)
+}
+
+/// PWM fan output controlled via the Linux hwmon sysfs interface. Manual control
+/// is enabled when the output is created. When dropped, the output is set to
+/// the restore duty cycle and the original `pwmN_enable` mode is restored.
+pub struct PwmFan {
+    path: PathBuf,
+    /// Original value of `pwmN_enable` or [`None`] if the driver does not
+    /// provide the file
+    orig_enable: Option<String>,
+    /// Duty cycle to set when dropped
+    restore_dcycle: u8,
+}
+
+impl PwmFan {
+    /// Take control of a PWM output. If `restore_dcycle` is [`None`], the
+    /// current duty cycle is restored when dropped.
+    pub fn new<P: AsRef<Path>>(path: P, restore_dcycle: Option<u8>) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let enable_path = enable_path(&path);
+        let restore_dcycle = match restore_dcycle {
+            Some(d) => d,
+            None => read_duty_cycle(&path)?,
+        };
+
+        let orig_enable = match fs::read_to_string(&enable_path) {
+            Ok(s) => Some(s.trim().to_owned()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::Io { path: enable_path, source: e }),
+        };
+
+        if let Some(mode) = &orig_enable {
+            info!("[{}] Original PWM mode: {}", path.display(), mode);
+
+            if mode != ENABLE_MANUAL {
+                info!("[{}] Setting PWM mode to: {}", path.display(), ENABLE_MANUAL);
+                fs::write(&enable_path, ENABLE_MANUAL)
+                    .map_err(|e| Error::Io { path: enable_path, source: e })?;
+            }
+        }
+
+        Ok(Self { path, orig_enable, restore_dcycle })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the current duty cycle as a percentage.
+    pub fn get_duty_cycle(&self) -> Result<u8> {
+        read_duty_cycle(&self.path)
+    }
+
+    /// Set the duty cycle as a percentage. Values above 100 are clamped.
+    pub fn set_duty_cycle(&mut self, dcycle: u8) -> Result<()> {
+        let pwm = (u32::from(dcycle.min(100)) * PWM_MAX + 50) / 100;
+
+        fs::write(&self.path, pwm.to_string())
+            .map_err(|e| Error::Io { path: self.path.clone(), source: e })
+    }
+}
+
+impl Drop for PwmFan {
+    fn drop(&mut self) {
+        info!("[{}] Setting duty cycle to {}%", self.path.display(), self.restore_dcycle);
+        if let Err(e) = self.set_duty_cycle(self.restore_dcycle) {
+            error!("[{}] Failed to set duty cycle: {}", self.path.display(), e);
+        }
+
+        if let Some(mode) = self.orig_enable.as_ref().filter(|m| *m != ENABLE_MANUAL) {
+            info!("[{}] Restoring PWM mode to: {}", self.path.display(), mode);
+            if let Err(e) = fs::write(enable_path(&self.path), mode) {
+                error!("[{}] Failed to restore PWM mode: {}", self.path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,1020 @@
+use {
+    std::{
+        collections::HashMap,
+        fmt,
+        result,
+        path::PathBuf,
+        sync::mpsc,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tokio::sync::oneshot,
+    log::{debug, info, trace, warn},
+    crate::{
+        config::{Backend, CommandTemplate, FanThreshold, Protocol, RawProtocol, ReadCommand, Session, SessionOptions, SessionType},
+        ipmitool::{self, Ipmitool},
+        plugin::{self, PluginBackend},
+        redfish::{self, Redfish},
+        rmcp::{self, RmcpSession},
+        sdr::{self, SensorRecord},
+        sensor::{format_suggestions, suggest_names, SensorReading, SensorType},
+    },
+};
+
+#[cfg(feature = "freeipmi")]
+use crate::freeipmi::{self, LfiSession, LimSession};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[cfg(feature = "freeipmi")]
+    #[error("{0}")]
+    FreeIpmi(#[from] freeipmi::Error),
+    #[error("{0}")]
+    Ipmitool(#[from] ipmitool::Error),
+    #[error("{0}")]
+    Rmcp(#[from] rmcp::Error),
+    #[error("{0}")]
+    Plugin(#[from] plugin::Error),
+    #[error("{0}")]
+    Redfish(#[from] redfish::Error),
+    #[cfg(not(feature = "freeipmi"))]
+    #[error("Built without freeipmi support; use the native or ipmitool backend")]
+    FreeipmiDisabled,
+    #[error("Expected response to be {expected} bytes, but have {actual} bytes")]
+    BadResponseSize {
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Duty cycle of zone {0} cannot be read until it has been set")]
+    DutyCycleUnknown(u8),
+    #[error("{0}")]
+    Sdr(#[from] sdr::Error),
+    #[error("Fan sensor not found in the SDR: {sensor:?}{}", format_suggestions(.suggestions))]
+    FanSensorNotFound {
+        sensor: String,
+        /// Similar fan sensor names that do exist
+        suggestions: Vec<String>,
+    },
+    #[error("Cannot convert {value} RPM to a raw threshold for sensor {sensor:?}")]
+    ThresholdConversion {
+        sensor: String,
+        value: u32,
+    },
+}
+
+impl Error {
+    /// Whether the error is caused by an out-of-band session timing out.
+    fn is_session_timeout(&self) -> bool {
+        match self {
+            #[cfg(feature = "freeipmi")]
+            Self::FreeIpmi(freeipmi::Error::SessionTimeout(_)) => true,
+            Self::Rmcp(rmcp::Error::SessionTimeout(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the error may be caused by the BMC being unreachable.
+    /// libipmimonitoring connects for every query and does not report why it
+    /// failed, so all of its errors are included.
+    fn is_connection_error(&self) -> bool {
+        match self {
+            #[cfg(feature = "freeipmi")]
+            Self::FreeIpmi(freeipmi::Error::Lim { .. }) => true,
+            Self::Ipmitool(ipmitool::Error::Failed { stderr, .. }) => {
+                stderr.contains("Unable to establish")
+            }
+            Self::Rmcp(rmcp::Error::Io(_)) => true,
+            Self::Plugin(plugin::Error::Disconnected { .. }) => true,
+            Self::Redfish(e) => e.is_connection_error(),
+            e => e.is_session_timeout(),
+        }
+    }
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FanMode {
+    Standard,       // 0
+    Full,           // 1
+    Optimal,        // 2
+    HeavyIo,        // 4
+    Unknown(u8),    // Anything else
+}
+
+impl From<u8> for FanMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Standard,
+            1 => Self::Full,
+            2 => Self::Optimal,
+            4 => Self::HeavyIo,
+            n => Self::Unknown(n),
+        }
+    }
+}
+
+impl From<FanMode> for u8 {
+    fn from(mode: FanMode) -> Self {
+        match mode {
+            FanMode::Standard => 0,
+            FanMode::Full => 1,
+            FanMode::Optimal => 2,
+            FanMode::HeavyIo => 4,
+            FanMode::Unknown(n) => n,
+        }
+    }
+}
+
+// IPMI_NET_FN_OEM_SUPERMICRO_GENERIC_RQ
+const NET_FN_GENERIC: u8 = 0x30;
+const CMD_FAN_MODE: u8 = 0x45;
+// IPMI_CMD_OEM_SUPERMICRO_GENERIC_EXTENSION
+const CMD_GENERIC_EXT: u8 = 0x70;
+const DATA_DUTY_CYCLE: u8 = 0x66;
+const DATA_ACTION_READ: u8 = 0x0;
+const DATA_ACTION_WRITE: u8 = 0x1;
+// Per-fan PWM write for boards that don't address zones
+const CMD_FAN_PWM: u8 = 0x91;
+const DATA_FAN_PWM: [u8; 2] = [0x5a, 0x03];
+
+// Lenovo/IBM IMM2 OEM fan speed override
+const NET_FN_IMM: u8 = 0x3a;
+const CMD_IMM_FAN_SPEED: u8 = 0x07;
+const DATA_IMM_AUTO: u8 = 0x0;
+const DATA_IMM_MANUAL: u8 = 0x1;
+
+// IPMI_NET_FN_OEM_GROUP_RQ with Tyan's IANA enterprise number (6653)
+const NET_FN_OEM_GROUP: u8 = 0x2e;
+const IANA_TYAN: [u8; 3] = [0xfd, 0x19, 0x00];
+const CMD_TYAN_FAN_DUTY: u8 = 0x44;
+const DATA_TYAN_AUTO: u8 = 0x0;
+const DATA_TYAN_MANUAL: u8 = 0x1;
+
+/// Number of IPMI zones to probe. Supermicro boards usually only have zones 0
+/// (CPU/system) and 1 (peripheral), but some have more.
+pub const MAX_PROBE_ZONES: u8 = 8;
+
+const NET_FN_SENSOR: u8 = 0x04;
+const CMD_SET_SENSOR_THRESHOLDS: u8 = 0x26;
+const CMD_GET_SENSOR_THRESHOLDS: u8 = 0x27;
+// Threshold mask bits for the lower thresholds
+const THRESHOLD_LNC: u8 = 1 << 0;
+const THRESHOLD_LCR: u8 = 1 << 1;
+const THRESHOLD_LNR: u8 = 1 << 2;
+
+/// Minimum time between SDR cache invalidations caused by missing sensors. A
+/// sensor that really does not exist should not cause the SDR to be read again
+/// every interval.
+const SDR_INVALIDATE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Lower thresholds of a fan sensor. Thresholds that the BMC does not report
+/// are [`None`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LowerThresholds {
+    pub non_recoverable: Option<f64>,
+    pub critical: Option<f64>,
+    pub non_critical: Option<f64>,
+}
+
+impl fmt::Display for LowerThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = |v: Option<f64>| v.map_or_else(|| "n/a".to_owned(), |v| format!("{:.0}", v));
+
+        write!(f, "lnr={} lcr={} lnc={}",
+               format(self.non_recoverable), format(self.critical), format(self.non_critical))
+    }
+}
+
+/// Connection to the BMC for the session's backend.
+enum Transport {
+    // libipmimonitoring doesn't expose its underlying session and there's no
+    // way to give it an existing session, so we're stuck creating two
+    // connections.
+    #[cfg(feature = "freeipmi")]
+    Freeipmi {
+        lfi: LfiSession,
+        lim: LimSession,
+    },
+    Ipmitool(Ipmitool),
+    Native(RmcpSession),
+    Plugin(PluginBackend),
+    Redfish(Redfish),
+}
+
+pub struct Ipmi {
+    transport: Transport,
+    protocol: Protocol,
+    /// Used for reconnecting if an out-of-band session times out
+    session_type: SessionType,
+    options: SessionOptions,
+    /// Index of the currently connected address in
+    /// [`SessionType::hostnames`]
+    host: usize,
+    /// Fan mode to re-apply after reconnecting
+    reconnect_fan_mode: Option<FanMode>,
+    /// Whether a reconnection is in progress
+    reconnecting: bool,
+    /// Last readings of each sensor type, shared by all zones using this
+    /// session
+    sensor_cache: HashMap<SensorType, (Instant, HashMap<String, Option<SensorReading>>)>,
+    /// When the SDR cache was last invalidated because of a missing sensor
+    sdr_invalidated: Option<Instant>,
+    /// Duty cycles written with a protocol that has no command for reading
+    /// them back
+    written_duty: HashMap<u8, u8>,
+    /// Last duty cycle written to each zone and when it was written
+    duty_cache: HashMap<u8, (u8, Instant)>,
+    /// Fan sensors from the SDR for threshold management, read on first use
+    sdr_fans: Option<Vec<SensorRecord>>,
+    /// Whether the IMM or Tyan protocol is overriding the duty cycles. There
+    /// is no global fan mode, so this is tracked locally.
+    override_fan_mode: FanMode,
+}
+
+impl Ipmi {
+    /// Createt an [`Ipmi`] instance for the given session. If a remote session
+    /// has failover hostnames, they are tried in order until one connects.
+    pub fn new(session: &Session) -> Result<Self> {
+        let (host, transport) = Self::connect_any(&session.session_type, &session.options, 0)?;
+
+        Ok(Self {
+            transport,
+            protocol: session.options.protocol.clone(),
+            session_type: session.session_type.clone(),
+            options: session.options.clone(),
+            host,
+            reconnect_fan_mode: None,
+            reconnecting: false,
+            sensor_cache: HashMap::new(),
+            sdr_invalidated: None,
+            written_duty: HashMap::new(),
+            duty_cache: HashMap::new(),
+            sdr_fans: None,
+            override_fan_mode: FanMode::Standard,
+        })
+    }
+
+    /// Connect to a single BMC address.
+    fn connect(st: &SessionType, options: &SessionOptions) -> Result<Transport> {
+        let transport = match options.backend {
+            #[cfg(feature = "freeipmi")]
+            Backend::Freeipmi => {
+                let lfi = LfiSession::new(st, options.bridge)?;
+                let mut lim = LimSession::new(st)?;
+
+                let sdr_cache_dir = options.sdr_cache_dir.as_ref()
+                    .map_or_else(std::env::temp_dir, PathBuf::from);
+                trace!("SDR cache directory: {:?}", sdr_cache_dir);
+
+                lim.set_sdr_cache_directory(&sdr_cache_dir)?;
+                // This call is required, even if we're not loading a file
+                lim.set_sensor_config_file(None)?;
+
+                Transport::Freeipmi { lfi, lim }
+            }
+            #[cfg(not(feature = "freeipmi"))]
+            Backend::Freeipmi => return Err(Error::FreeipmiDisabled),
+            Backend::Ipmitool => Transport::Ipmitool(Ipmitool::new(st, options.bridge)),
+            Backend::Native => Transport::Native(RmcpSession::new(st)?),
+            Backend::Plugin => {
+                // Checked when the config is loaded
+                let name = options.plugin.as_deref().unwrap_or_default();
+                Transport::Plugin(PluginBackend::new(name, st)?)
+            }
+            Backend::Redfish => Transport::Redfish(Redfish::new(st, options.ca_file.as_deref())?),
+        };
+
+        Ok(transport)
+    }
+
+    /// Try each of the session's addresses, starting with the one at index
+    /// `start` and wrapping around, until one connects. Returns the index of
+    /// the connected address. If all fail, the last error is returned.
+    fn connect_any(st: &SessionType, options: &SessionOptions, start: usize)
+        -> Result<(usize, Transport)> {
+        let hostnames = st.hostnames();
+        let count = hostnames.len().max(1);
+        let mut last_error = None;
+
+        for i in 0..count {
+            let host = (start + i) % count;
+
+            match Self::connect(&st.with_host(host), options) {
+                Ok(transport) => {
+                    if count > 1 {
+                        info!("Connected to BMC at {}", hostnames[host]);
+                    }
+
+                    return Ok((host, transport));
+                }
+                Err(e) if count > 1 => {
+                    warn!("Failed to connect to BMC at {}: {}", hostnames[host], e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap())
+    }
+
+    /// Get how long the session can be idle before [`Self::keepalive`] should
+    /// be called or [`None`] if the session doesn't need to be kept alive. The
+    /// ipmitool and redfish backends open a new connection for every command.
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        match self.transport {
+            Transport::Ipmitool(_) | Transport::Redfish(_) => None,
+            _ => self.options.keepalive_interval.map(|i| i.to_duration()),
+        }
+    }
+
+    /// Send a Get Device ID command to keep an idle session from timing out.
+    /// If the session already timed out, it is re-established.
+    pub fn keepalive(&mut self) -> Result<()> {
+        trace!("Sending keepalive");
+
+        self.raw_command(0x06, 0x01, &[]).map(|_| ())
+    }
+
+    /// Set the fan mode that is re-applied if the session has to reconnect.
+    pub fn set_reconnect_fan_mode(&mut self, mode: Option<FanMode>) {
+        self.reconnect_fan_mode = mode;
+    }
+
+    /// Get how many addresses to advance by when reconnecting after the error,
+    /// or [`None`] if the error does not warrant reconnecting. A session that
+    /// timed out retries the current address first. Other connection errors
+    /// fail over to the next address if the session has more than one.
+    fn reconnect_offset(&self, e: &Error) -> Option<usize> {
+        if self.reconnecting {
+            None
+        } else if e.is_session_timeout() && matches!(self.session_type, SessionType::Remote { .. }) {
+            Some(0)
+        } else if e.is_connection_error() && self.session_type.hostnames().len() > 1 {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    fn transport_raw_command(&mut self, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        match &mut self.transport {
+            #[cfg(feature = "freeipmi")]
+            Transport::Freeipmi { lfi, .. } => Ok(lfi.raw_command(net_fn, command, data)?),
+            Transport::Ipmitool(ipmitool) => Ok(ipmitool.raw_command(net_fn, command, data)?),
+            Transport::Native(rmcp) => Ok(rmcp.raw_command(net_fn, command, data)?),
+            Transport::Plugin(plugin) => Ok(plugin.raw_command(net_fn, command, data)?),
+            Transport::Redfish(_) => Err(redfish::Error::RawCommand.into()),
+        }
+    }
+
+    /// Execute a raw command via the session's backend. If an out-of-band
+    /// session timed out or the BMC could not be reached, the session is
+    /// re-established, possibly to a failover address, the fan mode is
+    /// re-applied, and the command is retried once.
+    fn raw_command(&mut self, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        match self.transport_raw_command(net_fn, command, data) {
+            Err(e) => match self.reconnect_offset(&e) {
+                Some(offset) => {
+                    warn!("IPMI command failed ({}); reconnecting", e);
+                    self.reconnect(offset)?;
+                    self.transport_raw_command(net_fn, command, data)
+                }
+                None => Err(e),
+            },
+            r => r,
+        }
+    }
+
+    /// Re-establish the session, starting with the address `offset` entries
+    /// after the current one, and re-apply the fan mode.
+    fn reconnect(&mut self, offset: usize) -> Result<()> {
+        let (host, transport) = Self::connect_any(
+            &self.session_type,
+            &self.options,
+            self.host + offset,
+        )?;
+
+        self.transport = transport;
+        self.host = host;
+        self.sensor_cache.clear();
+        // The BMC might have been reset
+        self.duty_cache.clear();
+
+        if let Some(mode) = self.reconnect_fan_mode {
+            info!("Re-applying fan mode after reconnecting: {:?}", mode);
+
+            self.reconnecting = true;
+            let result = self.set_fan_mode(mode);
+            self.reconnecting = false;
+
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute raw IPMI command and return the output. The output does not
+    /// include the command number nor the status. If the command does not
+    /// return a successful response or if the size of the response does not
+    /// match the specified value, an error is returned.
+    fn execute(
+        &mut self,
+        net_fn: u8,
+        command: u8,
+        data: &[u8],
+        expected_size: usize,
+    ) -> Result<Vec<u8>> {
+        trace!("Running IPMI command: net_fn={:02x}, command={:02x}, data={:02x?}",
+               net_fn, command, data);
+
+        let response = self.raw_command(net_fn, command, data)?;
+
+        if response.len() != expected_size {
+            return Err(Error::BadResponseSize {
+                expected: expected_size,
+                actual: response.len(),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Execute a raw command template from the config. The template is
+    /// expanded with the specified zone and value.
+    fn execute_template(
+        &mut self,
+        template: &CommandTemplate,
+        zone: u8,
+        value: u8,
+    ) -> Result<Vec<u8>> {
+        let command = template.expand(zone, value);
+
+        trace!("Running IPMI command: net_fn={:02x}, command={:02x}, data={:02x?}",
+               command[0], command[1], &command[2..]);
+
+        self.raw_command(command[0], command[1], &command[2..])
+    }
+
+    /// Execute a raw read command template from the config and return the byte
+    /// at the configured response index.
+    fn execute_read_template(&mut self, read: &ReadCommand, zone: u8) -> Result<u8> {
+        let response = self.execute_template(&read.command, zone, 0)?;
+
+        response.get(read.response_index)
+            .copied()
+            .ok_or(Error::BadResponseSize {
+                expected: read.response_index + 1,
+                actual: response.len(),
+            })
+    }
+
+    /// Override a zone's duty cycle with the IMM or Tyan protocol. With
+    /// [`None`], the zone is handed back to the BMC's automatic control.
+    fn set_duty_override(&mut self, zone: u8, dcycle: Option<u8>) -> Result<()> {
+        let (net_fn, command, data) = match (&self.protocol, dcycle) {
+            (Protocol::Imm, Some(d)) => (NET_FN_IMM, CMD_IMM_FAN_SPEED, vec![zone, d, DATA_IMM_MANUAL]),
+            (Protocol::Imm, None) => (NET_FN_IMM, CMD_IMM_FAN_SPEED, vec![zone, 0, DATA_IMM_AUTO]),
+            (Protocol::Tyan, d) => {
+                let control = if d.is_some() { DATA_TYAN_MANUAL } else { DATA_TYAN_AUTO };
+                let mut data = IANA_TYAN.to_vec();
+                data.extend([zone, control, d.unwrap_or(0)]);
+
+                (NET_FN_OEM_GROUP, CMD_TYAN_FAN_DUTY, data)
+            }
+            (p, _) => unreachable!("Protocol has no duty cycle override: {:?}", p),
+        };
+
+        trace!("Running IPMI command: net_fn={:02x}, command={:02x}, data={:02x?}",
+               net_fn, command, data);
+
+        // The response contents are not documented, so they are ignored
+        self.raw_command(net_fn, command, &data)?;
+
+        Ok(())
+    }
+
+    /// Get the current fan mode. For the raw protocol, the manual mode is
+    /// reported as [`FanMode::Full`] and all other modes are reported as
+    /// [`FanMode::Unknown`]. If the raw protocol has no mode commands, the mode
+    /// is always reported as [`FanMode::Full`].
+    pub fn get_fan_mode(&mut self) -> Result<FanMode> {
+        match self.protocol.clone() {
+            Protocol::Supermicro { .. } => {
+                let response = self.execute(
+                    NET_FN_GENERIC,
+                    CMD_FAN_MODE,
+                    &[DATA_ACTION_READ],
+                    1,
+                )?;
+
+                Ok(FanMode::from(response[0]))
+            }
+            Protocol::Imm | Protocol::Tyan => Ok(self.override_fan_mode),
+            Protocol::Raw(raw) => {
+                let (get_mode, manual_mode) = match (&raw.get_mode, raw.manual_mode) {
+                    (Some(c), Some(m)) => (c, m),
+                    _ => return Ok(FanMode::Full),
+                };
+
+                let mode = self.execute_read_template(get_mode, 0)?;
+
+                if mode == manual_mode {
+                    Ok(FanMode::Full)
+                } else {
+                    Ok(FanMode::Unknown(mode))
+                }
+            }
+        }
+    }
+
+    /// Set the fan mode. For the raw protocol, [`FanMode::Full`] selects the
+    /// manual mode. For the IMM and Tyan protocols, any other mode hands every
+    /// zone that was written back to the BMC.
+    pub fn set_fan_mode(&mut self, mode: FanMode) -> Result<()> {
+        // Supermicro BMCs reset the duty cycles when the fan mode changes
+        self.duty_cache.clear();
+
+        match self.protocol.clone() {
+            Protocol::Supermicro { .. } => {
+                self.execute(
+                    NET_FN_GENERIC,
+                    CMD_FAN_MODE,
+                    &[
+                        DATA_ACTION_WRITE,
+                        mode.into(),
+                    ],
+                    0,
+                )?;
+            }
+            Protocol::Imm | Protocol::Tyan => {
+                if mode != FanMode::Full {
+                    let mut zones = self.written_duty.keys().copied().collect::<Vec<_>>();
+                    zones.sort_unstable();
+
+                    for zone in zones {
+                        self.set_duty_override(zone, None)?;
+                        self.written_duty.remove(&zone);
+                    }
+                }
+
+                self.override_fan_mode = mode;
+            }
+            Protocol::Raw(raw) => {
+                let (set_mode, manual_mode) = match (&raw.set_mode, raw.manual_mode) {
+                    (Some(c), Some(m)) => (c, m),
+                    _ => return Ok(()),
+                };
+
+                let value = match mode {
+                    FanMode::Full => manual_mode,
+                    m => m.into(),
+                };
+
+                self.execute_template(set_mode, 0, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the current duty cycle. The valud should be in the range [0, 100],
+    /// but is not guaranteed as this function returns the raw value supplied by
+    /// the BMC. For protocols that can't read the duty cycle, the last written
+    /// value is returned or [`Error::DutyCycleUnknown`] if the zone has not
+    /// been written.
+    pub fn get_duty_cycle(&mut self, zone: u8) -> Result<u8> {
+        match self.protocol.clone() {
+            Protocol::Supermicro { generation: Some(g) } if g.per_fan() => {
+                self.written_duty.get(&zone).copied().ok_or(Error::DutyCycleUnknown(zone))
+            }
+            Protocol::Supermicro { .. } => {
+                let response = self.execute(
+                    NET_FN_GENERIC,
+                    CMD_GENERIC_EXT,
+                    &[
+                        DATA_DUTY_CYCLE,
+                        DATA_ACTION_READ,
+                        zone,
+                    ],
+                    1,
+                )?;
+
+                Ok(response[0])
+            }
+            Protocol::Raw(RawProtocol { get_duty: Some(get_duty), .. }) => {
+                self.execute_read_template(&get_duty, zone)
+            }
+            Protocol::Imm | Protocol::Tyan | Protocol::Raw(_) => {
+                self.written_duty.get(&zone).copied().ok_or(Error::DutyCycleUnknown(zone))
+            }
+        }
+    }
+
+    /// Set the duty cycle. The valud should be in the range [0, 100], but this
+    /// is not validated. The raw `dcycle` value will be sent to the BMC as-is,
+    /// except for Supermicro boards with per-fan control, which use a 0-255
+    /// scale.
+    pub fn set_duty_cycle(&mut self, zone: u8, dcycle: u8) -> Result<()> {
+        match self.protocol.clone() {
+            Protocol::Supermicro { generation: Some(g) } if g.per_fan() => {
+                // The PWM value is out of 255 instead of 100
+                let pwm = (u32::from(dcycle) * 255 / 100).min(255) as u8;
+                let data = [DATA_FAN_PWM[0], DATA_FAN_PWM[1], zone, pwm];
+
+                self.execute(NET_FN_GENERIC, CMD_FAN_PWM, &data, 0)?;
+                self.written_duty.insert(zone, dcycle);
+            }
+            Protocol::Supermicro { .. } => {
+                self.execute(
+                    NET_FN_GENERIC,
+                    CMD_GENERIC_EXT,
+                    &[
+                        DATA_DUTY_CYCLE,
+                        DATA_ACTION_WRITE,
+                        zone,
+                        dcycle,
+                    ],
+                    0,
+                )?;
+            }
+            Protocol::Imm | Protocol::Tyan => {
+                self.set_duty_override(zone, Some(dcycle))?;
+                self.written_duty.insert(zone, dcycle);
+            }
+            Protocol::Raw(raw) => {
+                self.execute_template(&raw.set_duty, zone, dcycle)?;
+
+                if raw.get_duty.is_none() {
+                    self.written_duty.insert(zone, dcycle);
+                }
+            }
+        }
+
+        self.duty_cache.insert(zone, (dcycle, Instant::now()));
+
+        Ok(())
+    }
+
+    /// Read the duty cycle of each of the first `count` IPMI zones and return
+    /// the zones that responded with a valid duty cycle. Zones that fail are
+    /// not an error because reading a zone that doesn't exist is how the
+    /// BMC's zones are discovered. Protocols that can't read the duty cycle
+    /// never report any zones.
+    pub fn probe_zones(&mut self, count: u8) -> Vec<(u8, u8)> {
+        (0..count)
+            .filter_map(|z| match self.get_duty_cycle(z) {
+                Ok(d) if d <= 100 => Some((z, d)),
+                Ok(d) => {
+                    debug!("Zone {} reported invalid duty cycle: {}", z, d);
+                    None
+                }
+                Err(e) => {
+                    debug!("Zone {} did not respond: {}", z, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Set the duty cycle if it is not already `dcycle` and return the previous
+    /// duty cycle, if known. If the session has a duty cycle cache, the last
+    /// written duty cycle is trusted until the cache expires, so nothing is
+    /// sent to the BMC while the duty cycle is unchanged. After the cache
+    /// expires, the duty cycle is written again without reading it first.
+    /// Otherwise, the duty cycle is read every time.
+    pub fn update_duty_cycle(&mut self, zone: u8, dcycle: u8) -> Result<Option<u8>> {
+        let dcycle_cur = match self.options.duty_cycle_cache {
+            Some(ttl) => match self.duty_cache.get(&zone) {
+                Some((d, t)) if *d == dcycle && t.elapsed() < ttl.to_duration() => {
+                    return Ok(Some(*d));
+                }
+                Some((d, _)) => Some(*d),
+                None => None,
+            },
+            None => match self.get_duty_cycle(zone) {
+                Err(Error::DutyCycleUnknown(_)) => None,
+                r => Some(r?),
+            },
+        };
+
+        if self.options.duty_cycle_cache.is_some() || dcycle_cur != Some(dcycle) {
+            self.set_duty_cycle(zone, dcycle)?;
+        }
+
+        Ok(dcycle_cur)
+    }
+
+    /// Get readings for all temperature sensors. If an error occurs, no partial
+    /// results will be returned. If a temperature sensor has no reading, then
+    /// the value in the result will be [`None`].
+    pub fn get_temperature_readings(&mut self)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        self.get_sensor_readings(SensorType::Temperature)
+    }
+
+    /// Get readings for all sensors of the given type, reusing the previous
+    /// readings if they are newer than `max_age`. This allows multiple zones
+    /// sharing a session to avoid querying the sensors multiple times per
+    /// interval.
+    pub fn get_shared_sensor_readings(&mut self, sensor_type: SensorType, max_age: Duration)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        if let Some((time, readings)) = self.sensor_cache.get(&sensor_type) {
+            if time.elapsed() < max_age {
+                trace!("Using shared {:?} readings", sensor_type);
+                return Ok(readings.clone());
+            }
+        }
+
+        let readings = self.get_sensor_readings(sensor_type)?;
+        self.sensor_cache.insert(sensor_type, (Instant::now(), readings.clone()));
+
+        Ok(readings)
+    }
+
+    /// Get readings for all fan tachometer sensors. The same rules as
+    /// [`Self::get_temperature_readings`] apply.
+    pub fn get_fan_readings(&mut self)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        self.get_sensor_readings(SensorType::Fan)
+    }
+
+    /// Get readings for all sensors of the given type. The same rules as
+    /// [`Self::get_temperature_readings`] apply.
+    pub fn get_sensor_readings(&mut self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        match self.transport_sensor_readings(sensor_type) {
+            Err(e) => match self.reconnect_offset(&e) {
+                Some(offset) => {
+                    warn!("Failed to get sensor readings ({}); reconnecting", e);
+                    self.reconnect(offset)?;
+                    self.transport_sensor_readings(sensor_type)
+                }
+                None => Err(e),
+            },
+            r => r,
+        }
+    }
+
+    fn transport_sensor_readings(&mut self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        match &mut self.transport {
+            #[cfg(feature = "freeipmi")]
+            Transport::Freeipmi { lim, .. } => Self::lim_sensor_readings(lim, sensor_type),
+            Transport::Ipmitool(ipmitool) => Ok(ipmitool.sensor_readings(sensor_type)?),
+            Transport::Native(rmcp) => Ok(rmcp.sensor_readings(sensor_type)?),
+            Transport::Plugin(plugin) => Ok(plugin.sensor_readings(sensor_type)?),
+            Transport::Redfish(redfish) => Ok(redfish.sensor_readings(sensor_type)?),
+        }
+    }
+
+    /// Discard the cached SDR so that it is read from the BMC again by the next
+    /// sensor query. ipmitool does not cache the SDR, plugins manage their own
+    /// caches, and Redfish has no SDR.
+    pub fn clear_sdr_cache(&mut self) -> Result<()> {
+        self.sensor_cache.clear();
+        self.sdr_fans = None;
+
+        match &mut self.transport {
+            #[cfg(feature = "freeipmi")]
+            Transport::Freeipmi { lim, .. } => lim.clear_sdr_cache()?,
+            Transport::Ipmitool(_) => {}
+            Transport::Native(rmcp) => rmcp.clear_sdr_cache(),
+            Transport::Plugin(_) | Transport::Redfish(_) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Discard the cached SDR after a sensor was not found, in case the cache
+    /// is stale, like after a BMC firmware update. This is done at most once
+    /// per [`SDR_INVALIDATE_INTERVAL`]. Returns whether the cache was
+    /// discarded.
+    pub fn invalidate_sdr_cache(&mut self) -> Result<bool> {
+        if self.sdr_invalidated.is_some_and(|t| t.elapsed() < SDR_INVALIDATE_INTERVAL) {
+            return Ok(false);
+        }
+
+        self.sdr_invalidated = Some(Instant::now());
+        self.clear_sdr_cache()?;
+
+        Ok(true)
+    }
+
+    /// Get the fan sensors owned by the BMC from the SDR. The SDR is read with
+    /// raw commands the first time this is called, so that this works the same
+    /// way with every backend.
+    fn sdr_fans(&mut self) -> Result<Vec<SensorRecord>> {
+        if let Some(fans) = &self.sdr_fans {
+            return Ok(fans.clone());
+        }
+
+        let fans: Vec<_> = sdr::read_sdr(|n, c, d| self.raw_command(n, c, d))?
+            .into_iter()
+            // Other LUNs can't be addressed with raw commands
+            .filter(|s| s.sensor_type == SensorType::Fan && s.lun == 0)
+            .collect();
+
+        self.sdr_fans = Some(fans.clone());
+
+        Ok(fans)
+    }
+
+    fn read_lower_thresholds(&mut self, sensor: &SensorRecord) -> Result<LowerThresholds> {
+        let response = self.raw_command(NET_FN_SENSOR, CMD_GET_SENSOR_THRESHOLDS, &[sensor.number])?;
+        if response.len() < 7 {
+            return Err(Error::BadResponseSize { expected: 7, actual: response.len() });
+        }
+
+        let readable = response[0];
+        let get = |bit: u8, raw: u8| (readable & bit != 0).then(|| sensor.convert(raw)).flatten();
+
+        Ok(LowerThresholds {
+            non_recoverable: get(THRESHOLD_LNR, response[3]),
+            critical: get(THRESHOLD_LCR, response[2]),
+            non_critical: get(THRESHOLD_LNC, response[1]),
+        })
+    }
+
+    /// Get the lower thresholds of every fan sensor, sorted by name.
+    pub fn get_fan_thresholds(&mut self) -> Result<Vec<(String, LowerThresholds)>> {
+        let mut fans = self.sdr_fans()?;
+        fans.sort_by(|a, b| a.name.cmp(&b.name));
+
+        fans.iter()
+            .map(|s| Ok((s.name.clone(), self.read_lower_thresholds(s)?)))
+            .collect()
+    }
+
+    /// Set the lower thresholds of a fan sensor. Only the thresholds that are
+    /// specified are written. Returns the thresholds before and after the
+    /// change.
+    pub fn set_fan_threshold(&mut self, threshold: &FanThreshold)
+        -> Result<(LowerThresholds, LowerThresholds)> {
+        let fans = self.sdr_fans()?;
+        let sensor = match fans.iter().find(|s| s.name == threshold.sensor) {
+            Some(s) => s.clone(),
+            None => {
+                return Err(Error::FanSensorNotFound {
+                    sensor: threshold.sensor.clone(),
+                    suggestions: suggest_names(&threshold.sensor, fans.iter().map(|s| &s.name)),
+                });
+            }
+        };
+
+        let old = self.read_lower_thresholds(&sensor)?;
+
+        let mut mask = 0;
+        // lnc, lcr, lnr, unc, ucr, unr
+        let mut values = [0u8; 6];
+
+        for (bit, index, value) in [
+            (THRESHOLD_LNC, 0, threshold.lower_non_critical),
+            (THRESHOLD_LCR, 1, threshold.lower_critical),
+            (THRESHOLD_LNR, 2, threshold.lower_non_recoverable),
+        ] {
+            if let Some(value) = value {
+                values[index] = sensor.to_raw(value.into()).ok_or_else(|| Error::ThresholdConversion {
+                    sensor: sensor.name.clone(),
+                    value,
+                })?;
+                mask |= bit;
+            }
+        }
+
+        let mut data = vec![sensor.number, mask];
+        data.extend(values);
+
+        trace!("Running IPMI command: net_fn={:02x}, command={:02x}, data={:02x?}",
+               NET_FN_SENSOR, CMD_SET_SENSOR_THRESHOLDS, data);
+
+        self.raw_command(NET_FN_SENSOR, CMD_SET_SENSOR_THRESHOLDS, &data)?;
+
+        let new = self.read_lower_thresholds(&sensor)?;
+
+        Ok((old, new))
+    }
+
+    #[cfg(feature = "freeipmi")]
+    fn lim_sensor_readings(lim: &mut LimSession, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>> {
+        let num_sensors = lim.sensor_readings(sensor_type)?;
+        trace!("Number of sensors: {}", num_sensors);
+
+        let mut result = HashMap::new();
+
+        for _ in 0..num_sensors {
+            let name = lim.read_sensor_name()?;
+            let reading = lim.read_sensor()?;
+
+            if reading.as_ref().is_none_or(|r| sensor_type.has_units(r.units)) {
+                result.insert(name, reading);
+            }
+
+            lim.iterator_next()?;
+        }
+
+        Ok(result)
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Ipmi) + Send>;
+
+/// Dedicated thread that owns an [`Ipmi`] instance. IPMI operations are sent to
+/// the thread over a channel and executed one at a time, so waiting for a slow
+/// BMC never blocks the async runtime. If the session needs to be kept alive,
+/// the thread sends a keepalive whenever it was idle for the keepalive
+/// interval.
+pub struct IpmiWorker {
+    sender: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl IpmiWorker {
+    /// Spawn a worker thread for the given [`Ipmi`] instance.
+    pub fn new(name: &str, mut ipmi: Ipmi) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+
+        let thread = thread::Builder::new()
+            .name(format!("ipmi:{}", name))
+            .spawn(move || {
+                let keepalive_interval = ipmi.keepalive_interval();
+
+                loop {
+                    let job = match keepalive_interval {
+                        Some(interval) => match receiver.recv_timeout(interval) {
+                            Ok(job) => job,
+                            Err(mpsc::RecvTimeoutError::Timeout) => {
+                                if let Err(e) = ipmi.keepalive() {
+                                    warn!("Failed to send keepalive: {}", e);
+                                }
+                                continue;
+                            }
+                            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                        },
+                        None => match receiver.recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        },
+                    };
+
+                    job(&mut ipmi);
+                }
+            })
+            .expect("Failed to spawn IPMI worker thread");
+
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    fn send(&self, job: Job) {
+        self.sender.as_ref().unwrap()
+            .send(job)
+            .expect("IPMI worker thread stopped");
+    }
+
+    /// Run `f` on the worker thread and wait for its result.
+    pub async fn run<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Ipmi) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.send(Box::new(move |ipmi| {
+            let _ = tx.send(f(ipmi));
+        }));
+
+        rx.await.expect("IPMI worker thread stopped")
+    }
+
+    /// Run `f` on the worker thread and block the current thread until the
+    /// result is available. This is for synchronous code only.
+    pub fn run_blocking<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Ipmi) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+
+        self.send(Box::new(move |ipmi| {
+            let _ = tx.send(f(ipmi));
+        }));
+
+        rx.recv().expect("IPMI worker thread stopped")
+    }
+}
+
+impl Drop for IpmiWorker {
+    fn drop(&mut self) {
+        // Wait for pending operations to finish and for the session to close
+        self.sender.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
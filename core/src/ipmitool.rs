@@ -0,0 +1,201 @@
+use {
+    std::{
+        collections::HashMap,
+        io,
+        process::{Command, ExitStatus, Stdio},
+        result,
+    },
+    log::trace,
+    crate::{
+        config::{AuthenticationType, Bridge, IpmiVersion, PrivilegeLevel, SessionType},
+        sensor::{SensorReading, SensorType, SensorUnits, SensorValue},
+        tools,
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to run ipmitool: {0}")]
+    Spawn(#[source] io::Error),
+    #[error("ipmitool failed ({status}): {stderr}")]
+    Failed {
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error("Failed to parse ipmitool output: {0:?}")]
+    BadOutput(String),
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+/// IPMI session backed by `ipmitool` subprocesses. Every command runs a new
+/// ipmitool process, so there is no long-lived session that can time out.
+pub struct Ipmitool {
+    /// Interface and connection arguments
+    args: Vec<String>,
+    /// Password for remote sessions. This is passed via the environment so
+    /// that it does not show up in the process list.
+    password: Option<String>,
+    /// Arguments for bridging raw commands
+    bridge_args: Vec<String>,
+}
+
+impl Ipmitool {
+    /// Create the ipmitool arguments for a session. If `bridge` is set, raw
+    /// commands are bridged to that IPMB target.
+    pub fn new(st: &SessionType, bridge: Option<Bridge>) -> Self {
+        let bridge_args = bridge.map_or_else(Vec::new, |b| vec![
+            "-b".into(), b.channel.to_string(),
+            "-t".into(), format!("{:#04x}", b.address),
+        ]);
+
+        match st {
+            SessionType::Local { .. } => Self {
+                args: vec!["-I".into(), "open".into()],
+                password: None,
+                bridge_args,
+            },
+            SessionType::Remote {
+                hostname,
+                username,
+                password,
+                privilege_level,
+                ipmi_version,
+                authentication_type,
+                cipher_suite_id,
+                k_g,
+                retransmission_timeout_ms,
+                ..
+            } => {
+                let privilege_level = match privilege_level {
+                    PrivilegeLevel::User => "USER",
+                    PrivilegeLevel::Operator => "OPERATOR",
+                    PrivilegeLevel::Admin => "ADMINISTRATOR",
+                };
+
+                let interface = match ipmi_version {
+                    IpmiVersion::V15 => "lan",
+                    IpmiVersion::V20 => "lanplus",
+                };
+
+                let mut args = vec![
+                    "-I".into(), interface.into(),
+                    "-H".into(), hostname.clone(),
+                    "-U".into(), username.clone(),
+                    "-L".into(), privilege_level.into(),
+                    // Read the password from IPMI_PASSWORD
+                    "-E".into(),
+                ];
+
+                if let Some(t) = authentication_type {
+                    let t = match t {
+                        AuthenticationType::None => "NONE",
+                        AuthenticationType::StraightPasswordKey => "PASSWORD",
+                        AuthenticationType::Md2 => "MD2",
+                        AuthenticationType::Md5 => "MD5",
+                    };
+                    args.extend(["-A".into(), t.into()]);
+                }
+                if let Some(id) = cipher_suite_id {
+                    args.extend(["-C".into(), id.to_string()]);
+                }
+                if let Some(k_g) = k_g {
+                    let hex: String = k_g.0.iter().map(|b| format!("{:02x}", b)).collect();
+                    args.extend(["-y".into(), hex]);
+                }
+                // ipmitool only supports whole seconds
+                if let Some(ms) = retransmission_timeout_ms {
+                    args.extend(["-N".into(), ms.div_ceil(1000).max(1).to_string()]);
+                }
+
+                Self {
+                    args,
+                    password: password.as_ref().map(|p| p.0.clone()),
+                    bridge_args,
+                }
+            }
+        }
+    }
+
+    /// Run ipmitool with the session arguments followed by `args` and return
+    /// stdout.
+    fn run(&self, args: &[String]) -> Result<String> {
+        trace!("Running ipmitool: {:?}", args);
+
+        let mut command = Command::new(&tools::get().ipmitool);
+        command
+            .args(&self.args)
+            .args(args)
+            .stdin(Stdio::null());
+
+        if let Some(password) = &self.password {
+            command.env("IPMI_PASSWORD", password);
+        }
+
+        let output = command.output().map_err(Error::Spawn)?;
+
+        if !output.status.success() {
+            return Err(Error::Failed {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Execute a raw command with `ipmitool raw`. The response does not include
+    /// the completion code. ipmitool fails if the completion code is non-zero.
+    pub fn raw_command(&self, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let args = self.bridge_args.iter().cloned()
+            .chain(["raw".to_owned()])
+            .chain([net_fn, command].iter().chain(data).map(|b| format!("{:#04x}", b)))
+            .collect::<Vec<_>>();
+
+        let stdout = self.run(&args)?;
+
+        stdout.split_whitespace()
+            .map(|t| u8::from_str_radix(t, 16).map_err(|_| Error::BadOutput(stdout.clone())))
+            .collect()
+    }
+
+    /// Get the readings of all sensors of the given type with `ipmitool
+    /// sensor`. Each line of the output has the sensor name, value, units, and
+    /// status separated by `|`. Sensors without a reading have the value `na`.
+    pub fn sensor_readings(&self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>>
+    {
+        let stdout = self.run(&["sensor".to_owned()])?;
+        let mut result = HashMap::new();
+
+        for line in stdout.lines() {
+            let fields: Vec<_> = line.split('|').map(str::trim).collect();
+            if fields.len() < 3 {
+                continue;
+            }
+
+            let units = match (sensor_type, fields[2]) {
+                (SensorType::Temperature, "degrees C") => SensorUnits::Celsius,
+                (SensorType::Temperature, "degrees F") => SensorUnits::Fahrenheit,
+                (SensorType::Fan, "RPM") => SensorUnits::Rpm,
+                (SensorType::Voltage, "Volts") => SensorUnits::Volts,
+                (SensorType::Current, "Amps") => SensorUnits::Amps,
+                (SensorType::Power, "Watts") => SensorUnits::Watts,
+                _ => continue,
+            };
+
+            let reading = match fields[1] {
+                "na" => None,
+                v => {
+                    let value = v.parse::<f64>()
+                        .map_err(|_| Error::BadOutput(line.to_owned()))?;
+                    Some(SensorReading { value: SensorValue::Double(value), units })
+                }
+            };
+
+            result.insert(fields[0].to_owned(), reading);
+        }
+
+        Ok(result)
+    }
+}
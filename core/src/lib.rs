@@ -0,0 +1,57 @@
+//! Core of ipmi-fan-control: config loading, fan curve math, temperature
+//! sources, and the IPMI abstraction. The daemon, its subcommands, and the
+//! exporters live in the `ipmi-fan-control` binary, which only goes through
+//! the public modules below.
+//!
+//! For example, to show the duty cycle that each zone would apply at 50C:
+//!
+//! ```no_run
+//! use {
+//!     std::path::Path,
+//!     ipmi_fan_control_core::{config, curve},
+//! };
+//!
+//! # fn main() -> ipmi_fan_control_core::error::Result<()> {
+//! let config = config::load_config(Path::new("/etc/ipmi-fan-control.toml"))?;
+//!
+//! for zone in &config.zones {
+//!     println!("{}: {}%", zone.label(), curve::calc_duty_cycle(&zone.steps, 50.0));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+/// Config file structures, loading, and validation.
+pub mod config;
+/// Fan curve math: interpolating steps and aggregating readings.
+pub mod curve;
+/// Error type shared by every module.
+pub mod error;
+/// Linux hwmon PWM fans.
+pub mod hwmon;
+/// IPMI abstraction over the freeipmi, ipmitool, built-in RMCP+, and Redfish
+/// backends.
+pub mod ipmi;
+/// Shared library plugins that provide source types and session backends.
+pub mod plugin;
+/// Time-of-day zone schedules.
+pub mod schedule;
+/// Zone scripts that compute the duty cycle.
+pub mod script;
+/// Sensor readings and name matching.
+pub mod sensor;
+/// Temperature sources, reading them, and registering new source types.
+pub mod source;
+/// Paths of the external programs that sources and commands run.
+pub mod tools;
+
+#[cfg(feature = "freeipmi")]
+mod bindings;
+mod drive;
+#[cfg(feature = "freeipmi")]
+mod freeipmi;
+mod ipmitool;
+mod profile;
+mod redfish;
+mod rmcp;
+mod sdr;
@@ -0,0 +1,431 @@
+use {
+    std::{
+        collections::{HashMap, HashSet},
+        ffi::{c_char, c_int, c_void, CStr, CString},
+        path::{Path, PathBuf},
+        ptr,
+        result,
+        sync::{Mutex, RwLock},
+    },
+    log::{debug, info},
+    once_cell::sync::Lazy,
+    crate::{
+        config::{SessionType, SourceType},
+        error,
+        sensor::{SensorReading, SensorType, SensorUnits, SensorValue},
+        source::{self, TempSource},
+    },
+};
+
+/// Version of `struct ifc_plugin` in `include/ipmi-fan-control-plugin.h`.
+pub const ABI_VERSION: u32 = 1;
+
+const STATUS_OK: c_int = 0;
+const STATUS_STANDBY: c_int = 2;
+const STATUS_DISCONNECTED: c_int = 3;
+
+/// Size of the buffer that plugins write error messages to.
+const ERROR_LEN: usize = 1024;
+/// Size of the buffer for raw command responses. IPMI messages are much
+/// smaller than this.
+const RESPONSE_LEN: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to load plugin {path:?}: {reason}")]
+    Load {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("Plugins are only supported on Unix-like systems: {0:?}")]
+    Unsupported(PathBuf),
+    #[error("Plugin backend is not registered: {0}")]
+    BackendUnknown(String),
+    #[error("Plugin {name} failed: {reason}")]
+    Failed {
+        name: String,
+        reason: String,
+    },
+    #[error("Plugin {name} reported that the drive is in standby")]
+    Standby {
+        name: String,
+    },
+    #[error("Plugin {name} could not reach the BMC: {reason}")]
+    Disconnected {
+        name: String,
+        reason: String,
+    },
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+type SensorFn = unsafe extern "C" fn(*mut c_void, *const c_char, c_int, f64, u32);
+
+/// `struct ifc_plugin` from the C header.
+#[repr(C)]
+struct RawPlugin {
+    abi_version: u32,
+    source_types: *const *const c_char,
+    source_open: Option<unsafe extern "C" fn(
+        *const c_char, *const c_char, *mut *mut c_void, *mut c_char, usize) -> c_int>,
+    source_read: Option<unsafe extern "C" fn(
+        *mut c_void, *mut f64, *mut c_char, usize) -> c_int>,
+    source_close: Option<unsafe extern "C" fn(*mut c_void)>,
+    backends: *const *const c_char,
+    backend_open: Option<unsafe extern "C" fn(
+        *const c_char, *const c_char, *const c_char, *const c_char,
+        *mut *mut c_void, *mut c_char, usize) -> c_int>,
+    backend_raw: Option<unsafe extern "C" fn(
+        *mut c_void, u8, u8, *const u8, usize, *mut u8, *mut usize, *mut c_char, usize) -> c_int>,
+    backend_sensors: Option<unsafe extern "C" fn(
+        *mut c_void, u32, SensorFn, *mut c_void, *mut c_char, usize) -> c_int>,
+    backend_close: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+// The plugin struct is static and its functions are required to be callable
+// from any thread.
+unsafe impl Sync for RawPlugin {}
+
+/// Loaded plugins and the source types and backends they provide. Plugins are
+/// never unloaded, so their structs live for the rest of the process.
+#[derive(Default)]
+struct Plugins {
+    paths: HashSet<PathBuf>,
+    sources: HashMap<String, &'static RawPlugin>,
+    backends: HashMap<String, &'static RawPlugin>,
+}
+
+static PLUGINS: Lazy<RwLock<Plugins>> = Lazy::new(|| RwLock::new(Plugins::default()));
+
+/// Load a plugin and register its source types and backends. Loading the same
+/// path again does nothing, so a changed plugin only takes effect after a
+/// restart.
+pub fn load(path: &Path) -> Result<()> {
+    let mut plugins = PLUGINS.write().unwrap();
+    if plugins.paths.contains(path) {
+        return Ok(());
+    }
+
+    let load_error = |reason: String| Error::Load { path: path.to_owned(), reason };
+
+    let raw = open(path)?;
+    if raw.abi_version != ABI_VERSION {
+        return Err(load_error(format!("ABI version {} is not supported; expected {}",
+                                      raw.abi_version, ABI_VERSION)));
+    }
+
+    // SAFETY: The lists are NULL or NULL-terminated arrays of static strings
+    let source_types = unsafe { names(raw.source_types) };
+    let backends = unsafe { names(raw.backends) };
+
+    if !source_types.is_empty()
+        && (raw.source_open.is_none() || raw.source_read.is_none() || raw.source_close.is_none())
+    {
+        return Err(load_error("source_open, source_read, and source_close are required for source types".to_owned()));
+    } else if !backends.is_empty()
+        && (raw.backend_open.is_none() || raw.backend_raw.is_none()
+            || raw.backend_sensors.is_none() || raw.backend_close.is_none())
+    {
+        return Err(load_error("backend_open, backend_raw, backend_sensors, and backend_close are required for backends".to_owned()));
+    }
+
+    // Check everything before registering anything so that a failed plugin
+    // doesn't leave some of its types behind
+    for name in &source_types {
+        if source::is_registered(name) || SourceType::BUILTIN_TYPES.contains(&name.as_str()) {
+            return Err(load_error(format!("source type is already registered: {}", name)));
+        }
+    }
+    for name in &backends {
+        if plugins.backends.contains_key(name) {
+            return Err(load_error(format!("backend is already registered: {}", name)));
+        }
+    }
+
+    for name in source_types {
+        source::register_source::<PluginSource>(&name)
+            .map_err(|e| load_error(e.to_string()))?;
+        debug!("Registered source type {:?} from {:?}", name, path);
+        plugins.sources.insert(name, raw);
+    }
+    for name in backends {
+        debug!("Registered backend {:?} from {:?}", name, path);
+        plugins.backends.insert(name, raw);
+    }
+
+    info!("Loaded plugin {:?}", path);
+    plugins.paths.insert(path.to_owned());
+
+    Ok(())
+}
+
+/// Check if a loaded plugin provides a backend.
+pub fn has_backend(name: &str) -> bool {
+    PLUGINS.read().unwrap().backends.contains_key(name)
+}
+
+/// Load the shared library and get its plugin struct. The library is never
+/// closed.
+#[cfg(unix)]
+fn open(path: &Path) -> Result<&'static RawPlugin> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let load_error = |reason: String| Error::Load { path: path.to_owned(), reason };
+
+    // dlopen() searches the library path for names without a slash
+    let c_path = if path.is_relative() {
+        Path::new(".").join(path)
+    } else {
+        path.to_owned()
+    };
+    let c_path = CString::new(c_path.as_os_str().as_bytes())
+        .map_err(|e| load_error(e.to_string()))?;
+
+    // SAFETY: Running the library's initializers is the point of loading it
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(load_error(dl_error()));
+    }
+
+    let symbol = unsafe { libc::dlsym(handle, c"ipmi_fan_control_plugin".as_ptr()) };
+    if symbol.is_null() {
+        return Err(load_error("ipmi_fan_control_plugin() not found".to_owned()));
+    }
+
+    // SAFETY: The entry point's signature is defined by the C header and the
+    // struct it returns is static
+    let entry: unsafe extern "C" fn() -> *const RawPlugin = unsafe { std::mem::transmute(symbol) };
+    unsafe { entry().as_ref() }
+        .ok_or_else(|| load_error("ipmi_fan_control_plugin() returned NULL".to_owned()))
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> Result<&'static RawPlugin> {
+    Err(Error::Unsupported(path.to_owned()))
+}
+
+#[cfg(unix)]
+fn dl_error() -> String {
+    let message = unsafe { libc::dlerror() };
+    if message.is_null() {
+        "unknown error".to_owned()
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+    }
+}
+
+/// Collect a NULL-terminated list of strings. A NULL list is empty.
+unsafe fn names(mut list: *const *const c_char) -> Vec<String> {
+    let mut result = vec![];
+
+    while !list.is_null() && !(*list).is_null() {
+        result.push(CStr::from_ptr(*list).to_string_lossy().into_owned());
+        list = list.add(1);
+    }
+
+    result
+}
+
+/// Call a plugin function that reports errors with a status code and an error
+/// buffer. `name` identifies the source type or backend in error messages.
+fn call(name: &str, f: impl FnOnce(*mut c_char, usize) -> c_int) -> Result<()> {
+    let mut buf = [0 as c_char; ERROR_LEN];
+    let status = f(buf.as_mut_ptr(), buf.len());
+    if status == STATUS_OK {
+        return Ok(());
+    }
+
+    // In case the plugin didn't NUL-terminate the message
+    buf[ERROR_LEN - 1] = 0;
+    let reason = match unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy() {
+        r if r.is_empty() => "unknown error".to_owned(),
+        r => r.into_owned(),
+    };
+    let name = name.to_owned();
+
+    match status {
+        STATUS_STANDBY => Err(Error::Standby { name }),
+        STATUS_DISCONNECTED => Err(Error::Disconnected { name, reason }),
+        _ => Err(Error::Failed { name, reason }),
+    }
+}
+
+/// Handle returned by a plugin's open function, which is closed when dropped.
+struct Handle {
+    ptr: *mut c_void,
+    close: unsafe extern "C" fn(*mut c_void),
+}
+
+// Plugins must allow handles to be used from any thread, though not from
+// multiple threads at once.
+unsafe impl Send for Handle {}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { (self.close)(self.ptr) };
+    }
+}
+
+/// Source whose type is provided by a plugin. The plugin's source is opened
+/// when the [`TempSource`] is created, which also validates the config, and
+/// closed when it is dropped.
+struct PluginSource {
+    type_name: String,
+    plugin: &'static RawPlugin,
+    handle: Mutex<Handle>,
+}
+
+impl TempSource for PluginSource {
+    fn from_config(source: &SourceType) -> error::Result<Self> {
+        let (type_name, params) = match source {
+            SourceType::Custom { type_name, params } => (type_name, params),
+            _ => unreachable!("Not a plugin source: {}", source),
+        };
+        let plugin = PLUGINS.read().unwrap().sources.get(type_name).copied()
+            .ok_or_else(|| error::Error::SourceTypeUnknown(type_name.clone()))?;
+
+        let config_error = |reason: String| error::Error::SourceConfig { name: source.to_string(), reason };
+        let c_type = CString::new(type_name.as_str()).map_err(|e| config_error(e.to_string()))?;
+        // Serializing a Value writes the nested tables last, as TOML requires
+        let c_params = toml::to_string(&toml::Value::Table(params.0.clone()))
+            .map_err(|e| config_error(e.to_string()))
+            .and_then(|p| CString::new(p).map_err(|e| config_error(e.to_string())))?;
+
+        let mut ptr = ptr::null_mut();
+        call(type_name, |e, n| unsafe {
+            (plugin.source_open.unwrap())(c_type.as_ptr(), c_params.as_ptr(), &mut ptr, e, n)
+        }).map_err(|e| match e {
+            Error::Failed { reason, .. } => config_error(reason),
+            e => e.into(),
+        })?;
+
+        Ok(Self {
+            type_name: type_name.clone(),
+            plugin,
+            handle: Mutex::new(Handle { ptr, close: plugin.source_close.unwrap() }),
+        })
+    }
+
+    fn read(&self) -> error::Result<f32> {
+        let handle = self.handle.lock().unwrap();
+        let mut temp = 0.0;
+
+        call(&self.type_name, |e, n| unsafe {
+            (self.plugin.source_read.unwrap())(handle.ptr, &mut temp, e, n)
+        }).map_err(|e| match e {
+            Error::Standby { name } => error::Error::DriveStandby(name.into()),
+            e => e.into(),
+        })?;
+
+        Ok(temp as f32)
+    }
+}
+
+/// Session backend provided by a plugin.
+pub struct PluginBackend {
+    name: String,
+    plugin: &'static RawPlugin,
+    handle: Handle,
+}
+
+impl PluginBackend {
+    /// Connect to the BMC with the named plugin backend.
+    pub fn new(name: &str, st: &SessionType) -> Result<Self> {
+        let plugin = PLUGINS.read().unwrap().backends.get(name).copied()
+            .ok_or_else(|| Error::BackendUnknown(name.to_owned()))?;
+
+        let c_string = |s: &str| CString::new(s).map_err(|e| Error::Failed {
+            name: name.to_owned(),
+            reason: e.to_string(),
+        });
+        let c_name = c_string(name)?;
+        let (hostname, username, password) = match st {
+            SessionType::Local { .. } => (None, None, None),
+            SessionType::Remote { hostname, username, password, .. } => (
+                Some(c_string(hostname)?),
+                Some(c_string(username)?),
+                password.as_ref().map(|p| c_string(&p.0)).transpose()?,
+            ),
+        };
+        let as_ptr = |s: &Option<CString>| s.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+
+        let mut ptr = ptr::null_mut();
+        call(name, |e, n| unsafe {
+            (plugin.backend_open.unwrap())(c_name.as_ptr(), as_ptr(&hostname), as_ptr(&username),
+                                           as_ptr(&password), &mut ptr, e, n)
+        })?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            plugin,
+            handle: Handle { ptr, close: plugin.backend_close.unwrap() },
+        })
+    }
+
+    /// Execute a raw IPMI command and return the response data, excluding the
+    /// completion code.
+    pub fn raw_command(&mut self, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let mut response = vec![0u8; RESPONSE_LEN];
+        let mut len = response.len();
+
+        call(&self.name, |e, n| unsafe {
+            (self.plugin.backend_raw.unwrap())(self.handle.ptr, net_fn, command, data.as_ptr(),
+                                               data.len(), response.as_mut_ptr(), &mut len, e, n)
+        })?;
+
+        if len > response.len() {
+            return Err(Error::Failed {
+                name: self.name.clone(),
+                reason: format!("response length {} exceeds buffer size {}", len, response.len()),
+            });
+        }
+
+        response.truncate(len);
+        Ok(response)
+    }
+
+    /// Get readings for all sensors of the given type. Sensors without a
+    /// reading have a value of [`None`].
+    pub fn sensor_readings(&mut self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>>
+    {
+        unsafe extern "C" fn add_reading(ctx: *mut c_void, name: *const c_char, has_value: c_int,
+                                         value: f64, units: u32) {
+            if name.is_null() {
+                return;
+            }
+
+            let readings = &mut *(ctx as *mut HashMap<String, Option<SensorReading>>);
+            let reading = (has_value != 0).then_some(SensorReading {
+                value: SensorValue::Double(value),
+                units: match units {
+                    1 => SensorUnits::Celsius,
+                    2 => SensorUnits::Fahrenheit,
+                    3 => SensorUnits::Rpm,
+                    4 => SensorUnits::Volts,
+                    5 => SensorUnits::Amps,
+                    6 => SensorUnits::Watts,
+                    n => SensorUnits::Unknown(n),
+                },
+            });
+
+            readings.insert(CStr::from_ptr(name).to_string_lossy().into_owned(), reading);
+        }
+
+        let raw_type = match sensor_type {
+            SensorType::Temperature => 0,
+            SensorType::Fan => 1,
+            SensorType::Voltage => 2,
+            SensorType::Current => 3,
+            SensorType::Power => 4,
+        };
+        let mut readings = HashMap::new();
+
+        call(&self.name, |e, n| unsafe {
+            (self.plugin.backend_sensors.unwrap())(self.handle.ptr, raw_type, add_reading,
+                                                   &mut readings as *mut _ as *mut c_void, e, n)
+        })?;
+
+        Ok(readings)
+    }
+}
@@ -0,0 +1,97 @@
+use {
+    std::path::Path,
+    toml::{value::Table, Value},
+    crate::error::{Error, Result},
+};
+
+const SUPERMICRO_1ZONE: &str = include_str!("profiles/supermicro-1zone.toml");
+const SUPERMICRO_2ZONE: &str = include_str!("profiles/supermicro-2zone.toml");
+
+/// Built-in board profiles. The fan control commands are the same across every
+/// Supermicro generation, so the generation-specific names are aliases.
+const PROFILES: &[(&str, &str)] = &[
+    ("supermicro-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-2zone", SUPERMICRO_2ZONE),
+    ("supermicro-x9-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-x9-2zone", SUPERMICRO_2ZONE),
+    ("supermicro-x10-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-x10-2zone", SUPERMICRO_2ZONE),
+    ("supermicro-x11-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-x11-2zone", SUPERMICRO_2ZONE),
+    ("supermicro-x12-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-x12-2zone", SUPERMICRO_2ZONE),
+    ("supermicro-x13-1zone", SUPERMICRO_1ZONE),
+    ("supermicro-x13-2zone", SUPERMICRO_2ZONE),
+];
+
+/// Get the name of a zone table.
+fn zone_name(zone: &Value) -> Option<&str> {
+    zone.get("name").and_then(Value::as_str)
+}
+
+/// Layer the user's zones on top of the profile's zones. Each user zone with
+/// the same name as a profile zone inherits every key that it does not set
+/// itself. Profile zones that the user does not mention are not used because
+/// they have no sources.
+fn merge_zones(path: &Path, profile_zones: &[Value], zones: &mut [Value]) -> Result<()> {
+    for (i, zone) in zones.iter_mut().enumerate() {
+        let base = match zone_name(zone)
+            .and_then(|n| profile_zones.iter().find(|z| zone_name(z) == Some(n)))
+            .and_then(Value::as_table)
+        {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let table = zone.as_table_mut().ok_or_else(|| Error::ConfigValidation {
+            path: path.to_owned(),
+            reason: format!("zones[{}]: must be a table", i),
+        })?;
+
+        for (key, value) in base {
+            table.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// If the config sets `profile`, merge the built-in profile into the config.
+/// Top-level keys in the config override the profile's and zones are merged by
+/// name with [`merge_zones`].
+pub fn apply_profile(path: &Path, config: &mut Table) -> Result<()> {
+    let name = match config.get("profile") {
+        Some(Value::String(n)) => n.clone(),
+        // Let deserialization report the type error
+        _ => return Ok(()),
+    };
+
+    let contents = PROFILES.iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, c)| *c)
+        .ok_or_else(|| {
+            let names = PROFILES.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+            Error::ConfigValidation {
+                path: path.to_owned(),
+                reason: format!("profile: unknown profile {:?}; must be one of: {}", name, names.join(", ")),
+            }
+        })?;
+
+    let mut profile: Table = toml::from_str(contents)
+        .unwrap_or_else(|e| panic!("Built-in profile {:?} is invalid: {}", name, e));
+
+    let profile_zones = match profile.remove("zones") {
+        Some(Value::Array(z)) => z,
+        _ => vec![],
+    };
+
+    if let Some(Value::Array(zones)) = config.get_mut("zones") {
+        merge_zones(path, &profile_zones, zones)?;
+    }
+
+    for (key, value) in profile {
+        config.entry(key).or_insert(value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use {
+    std::{
+        collections::HashMap,
+        io::{self, Write},
+        process::{Command, ExitStatus, Stdio},
+        result,
+    },
+    log::trace,
+    serde::{de::DeserializeOwned, Deserialize},
+    crate::{
+        config::SessionType,
+        sensor::{SensorReading, SensorType, SensorUnits, SensorValue},
+        tools,
+    },
+};
+
+/// Maximum time for a single request, including connecting.
+const REQUEST_TIMEOUT_SECS: &str = "10";
+
+/// curl exit codes for failing to resolve the host, failing to connect, and
+/// timing out.
+const CURL_CONNECTION_ERRORS: &[i32] = &[6, 7, 28];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("[redfish] Failed to run curl: {0}")]
+    Spawn(#[source] io::Error),
+    #[error("[redfish] Request failed: {url} (curl {status}): {stderr}")]
+    Failed {
+        url: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error("[redfish] Failed to parse response: {url}: {source}")]
+    BadResponse {
+        url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("[redfish] Only remote sessions are supported")]
+    LocalSession,
+    #[error("[redfish] Raw IPMI commands are not supported; the session can only be used to read sensors")]
+    RawCommand,
+}
+
+impl Error {
+    /// Whether curl could not reach the BMC.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Self::Failed { status, .. } => status.code().is_some_and(|c| CURL_CONNECTION_ERRORS.contains(&c)),
+            _ => false,
+        }
+    }
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+#[derive(Deserialize)]
+struct Link {
+    #[serde(rename = "@odata.id")]
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Collection {
+    members: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Chassis {
+    thermal: Option<Link>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Status {
+    state: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Temperature {
+    name: String,
+    reading_celsius: Option<f64>,
+    #[serde(default)]
+    status: Status,
+}
+
+/// Fan in a thermal resource. iLO 4 uses the older `FanName`,
+/// `CurrentReading`, and `Units` properties, sometimes alongside the standard
+/// ones.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Fan {
+    name: Option<String>,
+    fan_name: Option<String>,
+    reading: Option<f64>,
+    current_reading: Option<f64>,
+    reading_units: Option<String>,
+    units: Option<String>,
+    #[serde(default)]
+    status: Status,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Thermal {
+    #[serde(default)]
+    temperatures: Vec<Temperature>,
+    #[serde(default)]
+    fans: Vec<Fan>,
+}
+
+/// Session backed by a BMC's Redfish API, like HPE iLO's, through `curl`
+/// subprocesses. Redfish has no standard way to set fan duty cycles, so the
+/// session can only read the temperature and fan sensors of every chassis.
+pub struct Redfish {
+    /// `https://` URL of the BMC without a trailing slash
+    base_url: String,
+    /// curl config containing the credentials. This is passed via stdin so
+    /// that the password does not show up in the process list.
+    credentials: String,
+    /// CA certificate for verifying the BMC's certificate
+    ca_file: Option<String>,
+    /// Paths of the thermal resources of the chassis that have one
+    thermal_paths: Vec<String>,
+}
+
+/// Quote a value for a curl config file.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Redfish {
+    /// Connect to the BMC and find the thermal resources of its chassis. If `ca_file` is unset, the
+    /// BMC's certificate is verified against curl's default CA store.
+    pub fn new(st: &SessionType, ca_file: Option<&str>) -> Result<Self> {
+        let (hostname, username, password) = match st {
+            SessionType::Local { .. } => return Err(Error::LocalSession),
+            SessionType::Remote { hostname, username, password, .. } => (hostname, username, password),
+        };
+
+        let host = if hostname.contains(':') && !hostname.starts_with('[') {
+            format!("[{}]", hostname)
+        } else {
+            hostname.clone()
+        };
+        let password = password.as_ref().map_or("", |p| p.0.as_str());
+
+        let mut redfish = Self {
+            base_url: format!("https://{}", host),
+            credentials: format!("user = {}\n", quote(&format!("{}:{}", username, password))),
+            ca_file: ca_file.map(ToOwned::to_owned),
+            thermal_paths: vec![],
+        };
+
+        let collection: Collection = redfish.get("/redfish/v1/Chassis")?;
+
+        for member in collection.members {
+            let chassis: Chassis = redfish.get(&member.id)?;
+            redfish.thermal_paths.extend(chassis.thermal.map(|t| t.id));
+        }
+
+        Ok(redfish)
+    }
+
+    /// Send a GET request for a resource and parse the JSON response.
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        trace!("Sending Redfish request: {}", url);
+
+        let mut command = Command::new(&tools::get().curl);
+        command
+            .args(["--silent", "--show-error", "--fail"])
+            .args(["--max-time", REQUEST_TIMEOUT_SECS])
+            .args(["--header", "Accept: application/json"])
+            // Read the credentials from stdin
+            .args(["--config", "-"]);
+
+        if let Some(ca_file) = &self.ca_file {
+            command.args(["--cacert", ca_file]);
+        }
+
+        let mut child = command
+            .arg(&url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(Error::Spawn)?;
+
+        // curl reads the whole config before sending anything
+        child.stdin.take().unwrap()
+            .write_all(self.credentials.as_bytes())
+            .map_err(Error::Spawn)?;
+
+        let output = child.wait_with_output().map_err(Error::Spawn)?;
+
+        if !output.status.success() {
+            return Err(Error::Failed {
+                url,
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+
+        serde_json::from_slice(&output.stdout)
+            .map_err(|e| Error::BadResponse { url, source: e })
+    }
+
+    /// Get the readings of all sensors of the given type from the thermal
+    /// resource of every chassis. Only temperature and fan sensors are
+    /// reported. Fans are reported in RPM or, like on HPE iLO, as a
+    /// percentage. Sensors that are absent are skipped.
+    pub fn sensor_readings(&self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>>
+    {
+        let mut result = HashMap::new();
+
+        if !matches!(sensor_type, SensorType::Temperature | SensorType::Fan) {
+            return Ok(result);
+        }
+
+        for path in &self.thermal_paths {
+            let thermal: Thermal = self.get(path)?;
+
+            match sensor_type {
+                SensorType::Temperature => {
+                    for t in thermal.temperatures {
+                        if t.status.state.as_deref() == Some("Absent") {
+                            continue;
+                        }
+
+                        let reading = t.reading_celsius.map(|v| SensorReading {
+                            value: SensorValue::Double(v),
+                            units: SensorUnits::Celsius,
+                        });
+
+                        result.insert(t.name, reading);
+                    }
+                }
+                SensorType::Fan => {
+                    for f in thermal.fans {
+                        if f.status.state.as_deref() == Some("Absent") {
+                            continue;
+                        }
+
+                        let name = match f.name.or(f.fan_name) {
+                            Some(n) => n,
+                            None => continue,
+                        };
+                        let units = match f.reading_units.or(f.units).as_deref() {
+                            Some("RPM") => SensorUnits::Rpm,
+                            Some("Percent" | "Percentage") => SensorUnits::Percent,
+                            _ => continue,
+                        };
+                        let reading = f.reading.or(f.current_reading).map(|v| SensorReading {
+                            value: SensorValue::Double(v),
+                            units,
+                        });
+
+                        result.insert(name, reading);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,653 @@
+use {
+    std::{
+        collections::HashMap,
+        io,
+        net::{ToSocketAddrs, UdpSocket},
+        result,
+        thread,
+        time::{Duration, Instant},
+    },
+    aes::{
+        cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+        Aes128,
+    },
+    hmac::{Hmac, Mac},
+    log::{debug, trace, warn},
+    sha1::Sha1,
+    sha2::Sha256,
+    crate::{
+        config::{PrivilegeLevel, SessionType},
+        sdr::{self, SensorRecord},
+        sensor::{SensorReading, SensorType, SensorValue},
+    },
+};
+
+type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("[rmcp] Network error: {0}")]
+    Io(#[from] io::Error),
+    #[error("[rmcp] Failed to resolve hostname: {0}")]
+    HostNotFound(String),
+    #[error("[rmcp] Failed to generate random bytes: {0}")]
+    Random(getrandom::Error),
+    #[error("[rmcp] Unsupported cipher suite: {0}")]
+    UnsupportedCipherSuite(u8),
+    #[error("[rmcp] Username must not exceed 16 bytes")]
+    UsernameTooLong,
+    #[error("[rmcp] Only remote sessions are supported")]
+    LocalSession,
+    #[error("[rmcp] BMC rejected {step} with status code {status:#04x}")]
+    Rejected {
+        step: &'static str,
+        status: u8,
+    },
+    #[error("[rmcp] Authentication failed: {0}")]
+    AuthFailed(&'static str),
+    #[error("[rmcp] IPMI session timed out after {0:?}")]
+    SessionTimeout(Duration),
+    #[error("[rmcp] Malformed packet: {0}")]
+    BadPacket(&'static str),
+    #[error("{0}")]
+    Sdr(#[from] sdr::Error),
+    #[error("[rmcp] Command {net_fn:#04x}/{command:#04x} failed with completion code {code:#04x}")]
+    CompletionCode {
+        net_fn: u8,
+        command: u8,
+        code: u8,
+    },
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+const RMCP_PORT: u16 = 623;
+/// RMCP version 1.0, reserved, no RMCP ACK, IPMI message class
+const RMCP_HEADER: [u8; 4] = [0x06, 0x00, 0xff, 0x07];
+const AUTH_TYPE_RMCP_PLUS: u8 = 0x06;
+
+const PAYLOAD_IPMI: u8 = 0x00;
+const PAYLOAD_OPEN_SESSION_REQUEST: u8 = 0x10;
+const PAYLOAD_OPEN_SESSION_RESPONSE: u8 = 0x11;
+const PAYLOAD_RAKP_1: u8 = 0x12;
+const PAYLOAD_RAKP_2: u8 = 0x13;
+const PAYLOAD_RAKP_3: u8 = 0x14;
+const PAYLOAD_RAKP_4: u8 = 0x15;
+const PAYLOAD_ENCRYPTED: u8 = 0x80;
+const PAYLOAD_AUTHENTICATED: u8 = 0x40;
+
+const BMC_ADDR: u8 = 0x20;
+const REMOTE_CONSOLE_ADDR: u8 = 0x81;
+
+const NET_FN_SENSOR: u8 = 0x04;
+const NET_FN_APP: u8 = 0x06;
+const CMD_GET_SENSOR_READING: u8 = 0x2d;
+const CMD_SET_SESSION_PRIVILEGE_LEVEL: u8 = 0x3b;
+const CMD_CLOSE_SESSION: u8 = 0x3c;
+
+const COMP_CODE_SENSOR_NOT_PRESENT: u8 = 0xcb;
+
+
+/// Defaults used by freeipmi
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_millis(20000);
+const DEFAULT_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Hash function used for an authentication or integrity algorithm.
+#[derive(Clone, Copy, Debug)]
+enum Hash {
+    Sha1,
+    Sha256,
+}
+
+impl Hash {
+    fn hmac(self, key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key)
+                    .expect("HMAC accepts keys of any length");
+                parts.iter().for_each(|p| mac.update(p));
+                mac.finalize().into_bytes().to_vec()
+            }
+            Self::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key)
+                    .expect("HMAC accepts keys of any length");
+                parts.iter().for_each(|p| mac.update(p));
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Length of the truncated HMAC used for RAKP message 4 and for packet
+    /// integrity (HMAC-SHA1-96 or HMAC-SHA256-128).
+    fn icv_len(self) -> usize {
+        match self {
+            Self::Sha1 => 12,
+            Self::Sha256 => 16,
+        }
+    }
+}
+
+/// Algorithms negotiated for a cipher suite.
+#[derive(Clone, Copy, Debug)]
+struct CipherSuite {
+    auth: Hash,
+    integrity: Option<Hash>,
+    /// Whether payloads are encrypted with AES-CBC-128
+    aes: bool,
+}
+
+impl CipherSuite {
+    /// Get the algorithms for a cipher suite ID. Cipher suite 0 (no
+    /// authentication) and the MD5-based cipher suites are not supported.
+    fn from_id(id: u8) -> Result<Self> {
+        let (auth, integrity, aes) = match id {
+            1 => (Hash::Sha1, None, false),
+            2 => (Hash::Sha1, Some(Hash::Sha1), false),
+            3 => (Hash::Sha1, Some(Hash::Sha1), true),
+            17 => (Hash::Sha256, Some(Hash::Sha256), true),
+            _ => return Err(Error::UnsupportedCipherSuite(id)),
+        };
+
+        Ok(Self { auth, integrity, aes })
+    }
+
+    fn auth_id(&self) -> u8 {
+        match self.auth {
+            Hash::Sha1 => 0x01,
+            Hash::Sha256 => 0x03,
+        }
+    }
+
+    fn integrity_id(&self) -> u8 {
+        match self.integrity {
+            None => 0x00,
+            Some(Hash::Sha1) => 0x01,
+            Some(Hash::Sha256) => 0x04,
+        }
+    }
+}
+
+/// Compute the IPMI message checksum.
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg()
+}
+
+fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    getrandom::getrandom(&mut buf).map_err(Error::Random)?;
+    Ok(buf)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::BadPacket("truncated"))
+}
+
+/// Native IPMI 2.0 (RMCP+) session over UDP. This implements the same subset of
+/// IPMI as the freeipmi backend: raw commands and analog sensor readings.
+pub struct RmcpSession {
+    socket: UdpSocket,
+    suite: CipherSuite,
+    /// Session ID chosen by us
+    console_id: u32,
+    /// Session ID chosen by the BMC
+    bmc_id: u32,
+    /// Integrity key
+    k1: Vec<u8>,
+    /// Confidentiality key
+    k2: Vec<u8>,
+    /// Outbound session sequence number
+    seq: u32,
+    /// IPMI message sequence number
+    rq_seq: u8,
+    /// Whether the session is fully established
+    active: bool,
+    session_timeout: Duration,
+    retransmission_timeout: Duration,
+    /// Temperature and fan sensors from the SDR, read on first use
+    sensors: Option<Vec<SensorRecord>>,
+}
+
+impl RmcpSession {
+    /// Open a session with the BMC and raise it to the configured privilege
+    /// level.
+    pub fn new(st: &SessionType) -> Result<Self> {
+        let (hostname, username, password, privilege_level, cipher_suite_id, k_g,
+             session_timeout_ms, retransmission_timeout_ms) = match st {
+            SessionType::Local { .. } => return Err(Error::LocalSession),
+            SessionType::Remote {
+                hostname,
+                username,
+                password,
+                privilege_level,
+                cipher_suite_id,
+                k_g,
+                session_timeout_ms,
+                retransmission_timeout_ms,
+                ..
+            } => (hostname, username, password, privilege_level, cipher_suite_id, k_g,
+                  session_timeout_ms, retransmission_timeout_ms),
+        };
+
+        if username.len() > 16 {
+            return Err(Error::UsernameTooLong);
+        }
+
+        let addr = (hostname.as_str(), RMCP_PORT).to_socket_addrs()
+            .map_err(|_| Error::HostNotFound(hostname.clone()))?
+            .next()
+            .ok_or_else(|| Error::HostNotFound(hostname.clone()))?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(addr)?;
+
+        let mut session = Self {
+            socket,
+            suite: CipherSuite::from_id(cipher_suite_id.unwrap_or(3))?,
+            console_id: u32::from_le_bytes(random()?) | 1,
+            bmc_id: 0,
+            k1: vec![],
+            k2: vec![],
+            seq: 0,
+            rq_seq: 0,
+            active: false,
+            session_timeout: session_timeout_ms
+                .map_or(DEFAULT_SESSION_TIMEOUT, |t| Duration::from_millis(t.into())),
+            retransmission_timeout: retransmission_timeout_ms
+                .map_or(DEFAULT_RETRANSMISSION_TIMEOUT, |t| Duration::from_millis(t.into())),
+            sensors: None,
+        };
+
+        let privilege = match privilege_level {
+            PrivilegeLevel::User => 0x02,
+            PrivilegeLevel::Operator => 0x03,
+            PrivilegeLevel::Admin => 0x04,
+        };
+        let password = password.as_ref().map_or(&[][..], |p| p.0.as_bytes());
+        let k_g = k_g.as_ref().map(|k| k.0.as_slice());
+
+        session.open(username.as_bytes(), password, privilege, k_g)?;
+
+        debug!("[rmcp] Established session {:#010x} with {}", session.bmc_id, addr);
+
+        Ok(session)
+    }
+
+    /// Perform the open session request and the RAKP handshake.
+    fn open(&mut self, username: &[u8], password: &[u8], privilege: u8, k_g: Option<&[u8]>) -> Result<()> {
+        let suite = self.suite;
+        let tag = 0;
+
+        // Open Session Request
+        let mut request = vec![tag, privilege, 0, 0];
+        request.extend(self.console_id.to_le_bytes());
+        request.extend([0x00, 0, 0, 8, suite.auth_id(), 0, 0, 0]);
+        request.extend([0x01, 0, 0, 8, suite.integrity_id(), 0, 0, 0]);
+        request.extend([0x02, 0, 0, 8, u8::from(suite.aes), 0, 0, 0]);
+
+        let response = self.exchange_presession(
+            PAYLOAD_OPEN_SESSION_REQUEST, &request, PAYLOAD_OPEN_SESSION_RESPONSE)?;
+        if response.len() < 2 {
+            return Err(Error::BadPacket("open session response too short"));
+        } else if response[1] != 0 {
+            return Err(Error::Rejected { step: "open session request", status: response[1] });
+        } else if read_u32(&response, 4)? != self.console_id {
+            return Err(Error::BadPacket("open session response for another session"));
+        }
+        self.bmc_id = read_u32(&response, 8)?;
+
+        // RAKP Message 1. Use username/privilege lookup.
+        let console_random: [u8; 16] = random()?;
+        let mut rakp1 = vec![tag, 0, 0, 0];
+        rakp1.extend(self.bmc_id.to_le_bytes());
+        rakp1.extend(console_random);
+        rakp1.extend([privilege, 0, 0, username.len() as u8]);
+        rakp1.extend(username);
+
+        let rakp2 = self.exchange_presession(PAYLOAD_RAKP_1, &rakp1, PAYLOAD_RAKP_2)?;
+        if rakp2.len() < 2 {
+            return Err(Error::BadPacket("RAKP message 2 too short"));
+        } else if rakp2[1] != 0 {
+            return Err(Error::Rejected { step: "RAKP message 1", status: rakp2[1] });
+        }
+        let bmc_random = rakp2.get(8..24).ok_or(Error::BadPacket("RAKP message 2 too short"))?;
+        let bmc_guid = rakp2.get(24..40).ok_or(Error::BadPacket("RAKP message 2 too short"))?;
+        let auth_code = &rakp2[40..];
+
+        let console_id = self.console_id.to_le_bytes();
+        let bmc_id = self.bmc_id.to_le_bytes();
+        let user_info = [&[privilege, username.len() as u8][..], username].concat();
+
+        let expected = suite.auth.hmac(password, &[
+            &console_id, &bmc_id, &console_random, bmc_random, bmc_guid, &user_info]);
+        if auth_code != expected.as_slice() {
+            return Err(Error::AuthFailed("RAKP message 2 has an invalid key exchange code; check the username and password"));
+        }
+
+        let sik = suite.auth.hmac(k_g.unwrap_or(password), &[
+            &console_random, bmc_random, &user_info]);
+
+        // RAKP Message 3
+        let mut rakp3 = vec![tag, 0, 0, 0];
+        rakp3.extend(bmc_id);
+        rakp3.extend(suite.auth.hmac(password, &[bmc_random, &console_id, &user_info]));
+
+        let rakp4 = self.exchange_presession(PAYLOAD_RAKP_3, &rakp3, PAYLOAD_RAKP_4)?;
+        if rakp4.len() < 2 {
+            return Err(Error::BadPacket("RAKP message 4 too short"));
+        } else if rakp4[1] != 0 {
+            return Err(Error::Rejected { step: "RAKP message 3", status: rakp4[1] });
+        }
+
+        let icv = suite.auth.hmac(&sik, &[&console_random, &bmc_id, bmc_guid]);
+        if rakp4.get(8..) != Some(&icv[..suite.auth.icv_len()]) {
+            return Err(Error::AuthFailed("RAKP message 4 has an invalid integrity check value"));
+        }
+
+        let key_len = match suite.auth {
+            Hash::Sha1 => 20,
+            Hash::Sha256 => 32,
+        };
+        self.k1 = suite.auth.hmac(&sik, &[&vec![0x01; key_len]]);
+        self.k2 = suite.auth.hmac(&sik, &[&vec![0x02; key_len]]);
+        self.active = true;
+
+        // Sessions start at the user privilege level
+        self.raw_command(NET_FN_APP, CMD_SET_SESSION_PRIVILEGE_LEVEL, &[privilege])?;
+
+        Ok(())
+    }
+
+    /// Send a packet and wait for a response with the given payload type,
+    /// retransmitting until the session timeout expires. `build` is called for
+    /// every attempt so that each has a new sequence number.
+    fn exchange<B, P>(&mut self, mut build: B, mut parse: P) -> Result<Vec<u8>>
+    where
+        B: FnMut(&mut Self) -> Result<Vec<u8>>,
+        P: FnMut(&Self, &[u8]) -> Result<Option<Vec<u8>>>,
+    {
+        let start = Instant::now();
+        let mut buf = [0u8; 1024];
+
+        while start.elapsed() < self.session_timeout {
+            let packet = build(self)?;
+            match self.socket.send(&packet) {
+                Ok(_) => {}
+                // ICMP port unreachable from an earlier packet
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let deadline = Instant::now() + self.retransmission_timeout;
+
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    trace!("[rmcp] Retransmitting after {:?}", self.retransmission_timeout);
+                    break;
+                }
+                self.socket.set_read_timeout(Some(remaining))?;
+
+                let n = match self.socket.recv(&mut buf) {
+                    Ok(n) => n,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                    // The BMC may be rebooting, so treat ICMP port unreachable
+                    // like a lost packet
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        thread::sleep(remaining);
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                match parse(self, &buf[..n]) {
+                    Ok(Some(payload)) => return Ok(payload),
+                    // Response to an earlier attempt or unrelated packet
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("[rmcp] Ignoring invalid packet: {}", e);
+                        continue;
+                    }
+                }
+            }
+        }
+
+        Err(Error::SessionTimeout(self.session_timeout))
+    }
+
+    /// Exchange an unauthenticated message used to establish the session.
+    fn exchange_presession(&mut self, payload_type: u8, payload: &[u8], response_type: u8) -> Result<Vec<u8>> {
+        let mut packet = RMCP_HEADER.to_vec();
+        packet.extend([AUTH_TYPE_RMCP_PLUS, payload_type]);
+        // Session ID and sequence number are 0 outside of a session
+        packet.extend([0; 8]);
+        packet.extend((payload.len() as u16).to_le_bytes());
+        packet.extend(payload);
+
+        self.exchange(
+            |_| Ok(packet.clone()),
+            |_, data| {
+                let (ptype, _, payload) = Self::split_packet(data)?;
+                Ok((ptype & 0x3f == response_type).then(|| payload.to_vec()))
+            },
+        )
+    }
+
+    /// Split a packet into the payload type byte, session ID, and payload.
+    fn split_packet(data: &[u8]) -> Result<(u8, u32, &[u8])> {
+        if data.len() < 16 || data[..4] != RMCP_HEADER || data[4] != AUTH_TYPE_RMCP_PLUS {
+            return Err(Error::BadPacket("not an RMCP+ packet"));
+        }
+
+        let len = usize::from(u16::from_le_bytes([data[14], data[15]]));
+        let payload = data.get(16..16 + len).ok_or(Error::BadPacket("truncated payload"))?;
+
+        Ok((data[5], read_u32(data, 6)?, payload))
+    }
+
+    /// Build an authenticated and encrypted (depending on the cipher suite)
+    /// packet for an IPMI message.
+    fn seal(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        self.seq = self.seq.wrapping_add(1).max(1);
+
+        let mut payload_type = PAYLOAD_IPMI;
+        let payload = if self.suite.aes {
+            payload_type |= PAYLOAD_ENCRYPTED;
+
+            let iv: [u8; 16] = random()?;
+            let mut data = message.to_vec();
+            let pad_len = (16 - (data.len() + 1) % 16) % 16;
+            data.extend((1..=pad_len as u8).chain([pad_len as u8]));
+
+            let len = data.len();
+            Aes128CbcEnc::new(self.k2[..16].into(), &iv.into())
+                .encrypt_padded_mut::<NoPadding>(&mut data, len)
+                .expect("Data is block aligned");
+
+            [&iv[..], &data].concat()
+        } else {
+            message.to_vec()
+        };
+
+        if self.suite.integrity.is_some() {
+            payload_type |= PAYLOAD_AUTHENTICATED;
+        }
+
+        let mut packet = vec![AUTH_TYPE_RMCP_PLUS, payload_type];
+        packet.extend(self.bmc_id.to_le_bytes());
+        packet.extend(self.seq.to_le_bytes());
+        packet.extend((payload.len() as u16).to_le_bytes());
+        packet.extend(payload);
+
+        if let Some(hash) = self.suite.integrity {
+            let pad_len = (4 - (packet.len() + 2) % 4) % 4;
+            packet.extend(std::iter::repeat_n(0xff, pad_len));
+            packet.extend([pad_len as u8, RMCP_HEADER[3]]);
+
+            let auth_code = hash.hmac(&self.k1, &[&packet]);
+            packet.extend(&auth_code[..hash.icv_len()]);
+        }
+
+        Ok([&RMCP_HEADER[..], &packet].concat())
+    }
+
+    /// Verify and decrypt a packet from the BMC and return the IPMI message.
+    fn unseal(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (payload_type, session_id, payload) = Self::split_packet(data)?;
+
+        if payload_type & 0x3f != PAYLOAD_IPMI || session_id != self.console_id {
+            return Ok(None);
+        }
+
+        if let Some(hash) = self.suite.integrity {
+            if payload_type & PAYLOAD_AUTHENTICATED == 0 {
+                return Err(Error::BadPacket("unauthenticated packet"));
+            }
+
+            // The integrity pad aligns the session header, payload, pad
+            // length, and next header to 4 bytes
+            let icv_len = hash.icv_len();
+            let pad_len = (4 - (12 + payload.len() + 2) % 4) % 4;
+            let auth_start = 16 + payload.len() + pad_len + 2;
+            if data.len() < auth_start + icv_len {
+                return Err(Error::BadPacket("truncated integrity trailer"));
+            }
+
+            let covered = &data[4..auth_start];
+            let auth_code = &data[auth_start..auth_start + icv_len];
+            if hash.hmac(&self.k1, &[covered])[..icv_len] != *auth_code {
+                return Err(Error::BadPacket("integrity check failed"));
+            }
+        }
+
+        if !self.suite.aes {
+            return Ok(Some(payload.to_vec()));
+        } else if payload_type & PAYLOAD_ENCRYPTED == 0 {
+            return Err(Error::BadPacket("unencrypted packet"));
+        } else if payload.len() < 32 || payload.len() % 16 != 0 {
+            return Err(Error::BadPacket("bad encrypted payload size"));
+        }
+
+        let (iv, ciphertext) = payload.split_at(16);
+        let mut message = ciphertext.to_vec();
+        Aes128CbcDec::new(self.k2[..16].into(), iv.into())
+            .decrypt_padded_mut::<NoPadding>(&mut message)
+            .map_err(|_| Error::BadPacket("failed to decrypt payload"))?;
+
+        let pad_len = usize::from(*message.last().unwrap());
+        if pad_len + 1 > message.len() {
+            return Err(Error::BadPacket("bad confidentiality padding"));
+        }
+        message.truncate(message.len() - pad_len - 1);
+
+        Ok(Some(message))
+    }
+
+    /// Execute a raw IPMI command with the given LUN and return the response
+    /// data, excluding the completion code.
+    fn command(&mut self, lun: u8, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        self.rq_seq = (self.rq_seq + 1) % 64;
+        let rq_seq = self.rq_seq;
+
+        let header = [BMC_ADDR, net_fn << 2 | lun];
+        let mut message = header.to_vec();
+        message.push(checksum(&header));
+        let body_start = message.len();
+        message.extend([REMOTE_CONSOLE_ADDR, rq_seq << 2, command]);
+        message.extend(data);
+        message.push(checksum(&message[body_start..]));
+
+        trace!("[rmcp] Sending command: net_fn={:02x}, command={:02x}, data={:02x?}",
+               net_fn, command, data);
+
+        let response = self.exchange(
+            |s| s.seal(&message),
+            |s, packet| {
+                let response = match s.unseal(packet)? {
+                    Some(r) => r,
+                    None => return Ok(None),
+                };
+
+                // rqAddr, netFn/rqLUN, checksum, rsAddr, rqSeq/rsLUN, cmd,
+                // completion code, data, checksum
+                if response.len() < 8 {
+                    return Err(Error::BadPacket("IPMI message too short"));
+                }
+
+                let matches = response[1] >> 2 == net_fn | 1
+                    && response[4] >> 2 == rq_seq
+                    && response[5] == command;
+
+                Ok(matches.then(|| response[6..response.len() - 1].to_vec()))
+            },
+        )?;
+
+        if response[0] != 0 {
+            return Err(Error::CompletionCode { net_fn, command, code: response[0] });
+        }
+
+        Ok(response[1..].to_vec())
+    }
+
+    /// Execute a raw IPMI command and return the response data, excluding the
+    /// completion code.
+    pub fn raw_command(&mut self, net_fn: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        self.command(0, net_fn, command, data)
+    }
+
+    /// Discard the sensors read from the SDR so that the SDR is read again by
+    /// the next query.
+    pub fn clear_sdr_cache(&mut self) {
+        self.sensors = None;
+    }
+
+    /// Get readings for all analog sensors of the given type. The SDR is read
+    /// the first time this is called. Sensors without a reading, like empty
+    /// fan headers, have a value of [`None`].
+    pub fn sensor_readings(&mut self, sensor_type: SensorType)
+        -> Result<HashMap<String, Option<SensorReading>>>
+    {
+        if self.sensors.is_none() {
+            self.sensors = Some(sdr::read_sdr(|n, c, d| self.raw_command(n, c, d))?);
+        }
+
+        let sensors = self.sensors.clone().unwrap();
+        let mut result = HashMap::new();
+
+        for sensor in sensors.iter().filter(|s| s.sensor_type == sensor_type) {
+            let reading = match self.command(sensor.lun, NET_FN_SENSOR, CMD_GET_SENSOR_READING, &[sensor.number]) {
+                // Reading unavailable bit
+                Ok(r) if r.len() >= 2 && r[1] & 0x20 == 0 => {
+                    sensor.convert(r[0]).map(|v| SensorReading {
+                        value: SensorValue::Double(v),
+                        units: sensor.units,
+                    })
+                }
+                Ok(_) => None,
+                Err(Error::CompletionCode { code: COMP_CODE_SENSOR_NOT_PRESENT, .. }) => None,
+                Err(e) => return Err(e),
+            };
+
+            result.insert(sensor.name.clone(), reading);
+        }
+
+        Ok(result)
+    }
+}
+
+impl Drop for RmcpSession {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        // Don't wait long for a BMC that is already gone
+        self.session_timeout = self.retransmission_timeout;
+
+        let bmc_id = self.bmc_id.to_le_bytes();
+        if let Err(e) = self.raw_command(NET_FN_APP, CMD_CLOSE_SESSION, &bmc_id) {
+            debug!("[rmcp] Failed to close session: {}", e);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::config::{Schedule, Weekday, Zone};
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Sun,
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+];
+
+/// Get the current day of the week and minute since midnight in local time.
+#[cfg(unix)]
+pub fn local_now() -> (Weekday, u16) {
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut tm);
+    }
+
+    (WEEKDAYS[tm.tm_wday as usize % 7], (tm.tm_hour * 60 + tm.tm_min) as u16)
+}
+
+/// Get the current day of the week and minute since midnight in UTC since the
+/// local time zone isn't available.
+#[cfg(not(unix))]
+pub fn local_now() -> (Weekday, u16) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    // The Unix epoch was a Thursday
+    (WEEKDAYS[(secs / 86400 + 4) as usize % 7], (secs % 86400 / 60) as u16)
+}
+
+/// Get the index of the zone's first active schedule.
+pub fn active(zone_config: &Zone) -> Option<usize> {
+    let (day, minute) = local_now();
+
+    zone_config.schedules.iter().position(|s| s.is_active(day, minute))
+}
+
+/// Get a copy of the zone config with the schedule's steps and maximum duty
+/// cycle in place of the zone's own.
+pub fn apply(zone_config: &Zone, schedule: &Schedule) -> Zone {
+    let mut config = zone_config.clone();
+
+    if let Some(steps) = schedule.steps.as_ref().and_then(|n| zone_config.step_tables.get(n)) {
+        config.steps.clone_from(steps);
+    }
+    if let Some(max_dcycle) = schedule.max_dcycle {
+        config.max_dcycle.0 = config.max_dcycle.0.min(max_dcycle);
+    }
+
+    config
+}
+
+/// Describe what the schedule changes for log messages.
+pub fn describe(schedule: &Schedule) -> String {
+    let mut changes = vec![];
+
+    if let Some(name) = &schedule.steps {
+        changes.push(format!("steps {:?}", name));
+    }
+    if let Some(max_dcycle) = schedule.max_dcycle {
+        changes.push(format!("max_dcycle {}%", max_dcycle));
+    }
+
+    changes.join(", ")
+}
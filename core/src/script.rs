@@ -0,0 +1,132 @@
+use {
+    std::path::{Path, PathBuf},
+    crate::error::{Error, Result},
+};
+
+/// Maximum number of operations that a script may run per call, so that an
+/// infinite loop can't stall the zone loop.
+#[cfg(feature = "script")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Values that a zone's script can base the duty cycle on.
+#[derive(Clone, Debug)]
+pub struct ScriptInput<'a> {
+    /// Label of the zone
+    pub zone: &'a str,
+    /// Names of the zone's sources
+    pub sources: &'a [String],
+    /// Reading of each source in degrees Celsius or [`None`] if an optional
+    /// source was skipped
+    pub readings: &'a [Option<f32>],
+    /// Aggregated temperature of the zone
+    pub temp: f32,
+    /// Previously applied duty cycle
+    pub dcycle: Option<u8>,
+    /// Duty cycle from the zone's steps
+    pub steps_dcycle: u8,
+    /// Label, last temperature, and last duty cycle of the other zones
+    pub zones: &'a [(String, Option<f32>, Option<u8>)],
+}
+
+/// Compiled Rhai script of a zone. The script must define a function named
+/// `duty_cycle` that takes a map of the [`ScriptInput`] values and returns the
+/// duty cycle or `()` to use the zone's steps.
+pub struct Script {
+    path: PathBuf,
+    #[cfg(feature = "script")]
+    engine: rhai::Engine,
+    #[cfg(feature = "script")]
+    ast: rhai::AST,
+}
+
+impl Script {
+    /// Load and compile a script. `zone` is only used for log messages from
+    /// the script's `print()` and `debug()` calls.
+    #[cfg(feature = "script")]
+    pub fn load(path: &Path, zone: &str) -> Result<Self> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let label = zone.to_owned();
+        engine.on_print(move |s| log::info!(zone:% = label; "[{}] Script: {}", label, s));
+        let label = zone.to_owned();
+        engine.on_debug(move |s, _, _| log::debug!(zone:% = label; "[{}] Script: {}", label, s));
+
+        let ast = engine.compile_file(path.to_owned())
+            .map_err(|e| Error::Script { path: path.to_owned(), reason: e.to_string() })?;
+
+        if !ast.iter_functions().any(|f| f.name == "duty_cycle" && f.params.len() == 1) {
+            return Err(Error::Script {
+                path: path.to_owned(),
+                reason: "no duty_cycle(zone) function".to_owned(),
+            });
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            engine,
+            ast,
+        })
+    }
+
+    /// Scripts need the `script` feature.
+    #[cfg(not(feature = "script"))]
+    pub fn load(path: &Path, _zone: &str) -> Result<Self> {
+        Err(Error::ScriptDisabled(path.to_owned()))
+    }
+
+    /// Run the script's `duty_cycle` function. Returns [`None`] if the script
+    /// returned `()`. Numbers are rounded and capped to 0-100%.
+    #[cfg(feature = "script")]
+    pub fn duty_cycle(&self, input: &ScriptInput) -> Result<Option<u8>> {
+        use rhai::{Array, Dynamic, Map, FLOAT};
+
+        let float = |v: Option<f32>| v.map_or(Dynamic::UNIT, |v| Dynamic::from_float(FLOAT::from(v)));
+        let int = |v: Option<u8>| v.map_or(Dynamic::UNIT, |v| Dynamic::from_int(v.into()));
+
+        let zones = input.zones.iter()
+            .map(|(label, temp, dcycle)| {
+                let mut zone = Map::new();
+                zone.insert("temp".into(), float(*temp));
+                zone.insert("dcycle".into(), int(*dcycle));
+                (label.into(), Dynamic::from_map(zone))
+            })
+            .collect::<Map>();
+
+        let mut map = Map::new();
+        map.insert("name".into(), input.zone.into());
+        map.insert("sources".into(),
+                   Dynamic::from_array(input.sources.iter().map(|s| s.into()).collect()));
+        map.insert("readings".into(),
+                   Dynamic::from_array(input.readings.iter().map(|r| float(*r)).collect::<Array>()));
+        map.insert("temp".into(), float(Some(input.temp)));
+        map.insert("dcycle".into(), int(input.dcycle));
+        map.insert("steps_dcycle".into(), int(Some(input.steps_dcycle)));
+        map.insert("zones".into(), Dynamic::from_map(zones));
+
+        let result = self.engine
+            .call_fn::<Dynamic>(&mut rhai::Scope::new(), &self.ast, "duty_cycle", (Dynamic::from_map(map),))
+            .map_err(|e| Error::Script { path: self.path.clone(), reason: e.to_string() })?;
+
+        let dcycle = if result.is_unit() {
+            return Ok(None);
+        } else if let Ok(i) = result.as_int() {
+            i as f64
+        } else if let Ok(f) = result.as_float() {
+            f
+        } else {
+            return Err(Error::Script {
+                path: self.path.clone(),
+                reason: format!("duty_cycle() returned {}, not a number or ()", result.type_name()),
+            });
+        };
+
+        Ok(Some(dcycle.round().clamp(0.0, 100.0) as u8))
+    }
+
+    /// Scripts can't be loaded without the `script` feature.
+    #[cfg(not(feature = "script"))]
+    pub fn duty_cycle(&self, _input: &ScriptInput) -> Result<Option<u8>> {
+        Err(Error::ScriptDisabled(self.path.clone()))
+    }
+}
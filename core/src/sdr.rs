@@ -0,0 +1,200 @@
+use {
+    std::result,
+    log::trace,
+    crate::sensor::{SensorType, SensorUnits},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("[sdr] Malformed SDR: {0}")]
+    BadRecord(&'static str),
+}
+
+type Result<T, E = Error> = result::Result<T, E>;
+
+const BMC_ADDR: u8 = 0x20;
+
+const NET_FN_STORAGE: u8 = 0x0a;
+const CMD_RESERVE_SDR_REPOSITORY: u8 = 0x22;
+const CMD_GET_SDR: u8 = 0x23;
+
+const SDR_TYPE_FULL_SENSOR: u8 = 0x01;
+const SDR_HEADER_SIZE: u8 = 5;
+/// Many BMCs can't return more than this many bytes of an SDR at once
+const SDR_CHUNK_SIZE: u8 = 16;
+const SDR_LAST_RECORD: u16 = 0xffff;
+const EVENT_READING_TYPE_THRESHOLD: u8 = 0x01;
+
+/// Analog sensor from a full sensor record in the SDR.
+#[derive(Clone, Debug)]
+pub struct SensorRecord {
+    pub name: String,
+    pub sensor_type: SensorType,
+    pub number: u8,
+    pub lun: u8,
+    pub units: SensorUnits,
+    /// Analog data format: unsigned, 1's complement, or 2's complement
+    format: u8,
+    linearization: u8,
+    m: i16,
+    b: i16,
+    b_exp: i8,
+    r_exp: i8,
+}
+
+impl SensorRecord {
+    /// Parse a full sensor record, including the header. Returns [`None`] for
+    /// sensors that are not temperature, fan, or electrical threshold sensors
+    /// owned by the BMC.
+    pub fn parse(record: &[u8]) -> Option<Self> {
+        if record.len() < 48 || record[3] != SDR_TYPE_FULL_SENSOR {
+            return None;
+        }
+
+        let units = match record[21] {
+            1 => SensorUnits::Celsius,
+            2 => SensorUnits::Fahrenheit,
+            4 => SensorUnits::Volts,
+            5 => SensorUnits::Amps,
+            6 => SensorUnits::Watts,
+            18 => SensorUnits::Rpm,
+            u => SensorUnits::Unknown(u32::from(u)),
+        };
+
+        // Power sensors can be current, power supply, or other units-based
+        // sensors, so they are identified by their units
+        let sensor_type = match (record[12], units) {
+            (0x01, _) => SensorType::Temperature,
+            (0x04, _) => SensorType::Fan,
+            (0x02, SensorUnits::Volts) => SensorType::Voltage,
+            (0x03, SensorUnits::Amps) => SensorType::Current,
+            (0x03 | 0x08 | 0x0b, SensorUnits::Watts) => SensorType::Power,
+            _ => return None,
+        };
+
+        let format = record[20] >> 6;
+
+        // Sensors owned by other controllers would need bridging
+        if record[5] != BMC_ADDR || record[13] != EVENT_READING_TYPE_THRESHOLD || format == 0b11 {
+            return None;
+        }
+
+        // 10-bit two's complement values
+        let sign_extend_10 = |ls: u8, ms: u8| ((u16::from(ms >> 6) << 8 | u16::from(ls)) << 6) as i16 >> 6;
+        // 4-bit two's complement values
+        let sign_extend_4 = |n: u8| ((n << 4) as i8) >> 4;
+
+        let id_len = usize::from(record[47] & 0x1f);
+        let name = record.get(48..48 + id_len)?;
+        let name = String::from_utf8_lossy(name).trim_end_matches('\0').to_owned();
+
+        Some(Self {
+            name,
+            sensor_type,
+            number: record[7],
+            lun: record[6] & 0x03,
+            units,
+            format,
+            linearization: record[23] & 0x7f,
+            m: sign_extend_10(record[24], record[25]),
+            b: sign_extend_10(record[26], record[27]),
+            b_exp: sign_extend_4(record[29] & 0x0f),
+            r_exp: sign_extend_4(record[29] >> 4),
+        })
+    }
+
+    /// Convert a raw reading using the record's conversion factors. Returns
+    /// [`None`] for non-linear sensors, which need OEM conversion formulas.
+    pub fn convert(&self, raw: u8) -> Option<f64> {
+        let x = match self.format {
+            0b00 => f64::from(raw),
+            0b01 if raw & 0x80 != 0 => -f64::from(!raw),
+            0b01 => f64::from(raw),
+            _ => f64::from(raw as i8),
+        };
+
+        let y = (f64::from(self.m) * x + f64::from(self.b) * 10f64.powi(self.b_exp.into()))
+            * 10f64.powi(self.r_exp.into());
+
+        let value = match self.linearization {
+            0x00 => y,
+            0x01 => y.ln(),
+            0x02 => y.log10(),
+            0x03 => y.log2(),
+            0x04 => y.exp(),
+            0x05 => 10f64.powf(y),
+            0x06 => y.exp2(),
+            0x07 => y.recip(),
+            0x08 => y * y,
+            0x09 => y * y * y,
+            0x0a => y.sqrt(),
+            0x0b => y.cbrt(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Find the raw value whose converted value is closest to `value`. Returns
+    /// [`None`] for non-linear sensors.
+    pub fn to_raw(&self, value: f64) -> Option<u8> {
+        (0..=u8::MAX)
+            .filter_map(|raw| self.convert(raw).map(|v| (raw, (v - value).abs())))
+            .filter(|(_, d)| !d.is_nan())
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(raw, _)| raw)
+    }
+}
+
+/// Read every record from the SDR repository with `raw_command` and keep the
+/// analog temperature, fan, and electrical sensors.
+pub fn read_sdr<F, E>(mut raw_command: F) -> Result<Vec<SensorRecord>, E>
+where
+    F: FnMut(u8, u8, &[u8]) -> Result<Vec<u8>, E>,
+    E: From<Error>,
+{
+    let reservation = raw_command(NET_FN_STORAGE, CMD_RESERVE_SDR_REPOSITORY, &[])?;
+    let reservation = reservation.get(..2).ok_or(Error::BadRecord("bad reservation ID"))?.to_vec();
+
+    let mut sensors = vec![];
+    let mut record_id = 0u16;
+
+    while record_id != SDR_LAST_RECORD {
+        let mut read = |offset: u8, count: u8| -> Result<(u16, Vec<u8>), E> {
+            let mut request = reservation.clone();
+            request.extend(record_id.to_le_bytes());
+            request.extend([offset, count]);
+
+            let response = raw_command(NET_FN_STORAGE, CMD_GET_SDR, &request)?;
+            if response.len() < 2 {
+                return Err(Error::BadRecord("SDR response too short").into());
+            }
+
+            Ok((u16::from_le_bytes([response[0], response[1]]), response[2..].to_vec()))
+        };
+
+        let (next_id, mut record) = read(0, SDR_HEADER_SIZE)?;
+        if record.len() < usize::from(SDR_HEADER_SIZE) {
+            return Err(Error::BadRecord("SDR header too short").into());
+        }
+
+        let total = usize::from(SDR_HEADER_SIZE) + usize::from(record[4]);
+        while record.len() < total {
+            let count = (total - record.len()).min(SDR_CHUNK_SIZE.into()) as u8;
+            let (_, chunk) = read(record.len() as u8, count)?;
+            if chunk.is_empty() {
+                return Err(Error::BadRecord("empty SDR chunk").into());
+            }
+            record.extend(chunk);
+        }
+
+        if let Some(sensor) = SensorRecord::parse(&record) {
+            trace!("Found sensor: {:?}", sensor);
+            sensors.push(sensor);
+        }
+
+        record_id = next_id;
+    }
+
+    Ok(sensors)
+}
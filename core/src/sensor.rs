@@ -0,0 +1,150 @@
+use std::fmt;
+
+/// Sensor value. Only libipmimonitoring reports non-numeric values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(feature = "freeipmi"), allow(dead_code))]
+pub enum SensorValue {
+    Bool(bool),
+    Uint32(u32),
+    Double(f64),
+    Unknown,
+}
+
+impl fmt::Display for SensorValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(v) => write!(f, "{}", v),
+            Self::Uint32(v) => write!(f, "{}", v),
+            Self::Double(v) => write!(f, "{:.2}", v),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+impl SensorValue {
+    /// Get the value as a number if it is numeric.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Uint32(v) => Some(f64::from(*v)),
+            Self::Double(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SensorUnits {
+    Celsius,
+    Fahrenheit,
+    Rpm,
+    Volts,
+    Amps,
+    Watts,
+    /// Fan speed as a percentage of the maximum, as reported by Redfish
+    Percent,
+    /// Raw IPMI or libipmimonitoring units value
+    Unknown(u32),
+}
+
+impl fmt::Display for SensorUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Celsius => f.write_str("degrees C"),
+            Self::Fahrenheit => f.write_str("degrees F"),
+            Self::Rpm => f.write_str("RPM"),
+            Self::Volts => f.write_str("Volts"),
+            Self::Amps => f.write_str("Amps"),
+            Self::Watts => f.write_str("Watts"),
+            Self::Percent => f.write_str("percent"),
+            Self::Unknown(n) => write!(f, "unknown units ({})", n),
+        }
+    }
+}
+
+/// Type of sensor to query readings for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SensorType {
+    Temperature,
+    Fan,
+    Voltage,
+    Current,
+    /// Sensors reporting watts, which BMCs expose under several IPMI sensor
+    /// types
+    Power,
+}
+
+impl SensorType {
+    /// Whether a reading in the given units belongs to this sensor type. Only
+    /// the electrical types are checked because they are identified by their
+    /// units.
+    pub fn has_units(self, units: SensorUnits) -> bool {
+        match self {
+            Self::Temperature | Self::Fan => true,
+            Self::Voltage => units == SensorUnits::Volts,
+            Self::Current => units == SensorUnits::Amps,
+            Self::Power => units == SensorUnits::Watts,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SensorReading {
+    pub value: SensorValue,
+    pub units: SensorUnits,
+}
+
+/// Maximum number of suggestions for a sensor name that wasn't found.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Compute the case-insensitive Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.to_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_lowercase().chars().collect::<Vec<_>>();
+    // Distances between the prefix of `a` so far and every prefix of `b`
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Find the sensor names that are closest to a name that wasn't found, like
+/// `CPU1 Temp` for `CPU Temp`. Names that differ in more than a third of the
+/// characters are not considered similar.
+pub fn suggest_names<'a, I>(name: &str, names: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut candidates = names.into_iter()
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|(d, _)| *d <= max_distance)
+        .collect::<Vec<_>>();
+    candidates.sort();
+
+    candidates.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, n)| n.clone())
+        .collect()
+}
+
+/// Format suggested sensor names for an error message.
+pub fn format_suggestions(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+
+    let quoted = suggestions.iter().map(|s| format!("{:?}", s)).collect::<Vec<_>>();
+
+    format!(" (did you mean {}?)", quoted.join(", "))
+}
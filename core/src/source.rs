@@ -0,0 +1,918 @@
+use {
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+        fs,
+        io::{self, BufRead, BufReader, Read},
+        path::Path,
+        process::{Command, Stdio},
+        result,
+        sync::{Arc, Mutex, RwLock},
+        thread,
+        time::{Duration, Instant},
+    },
+    log::{debug, trace, warn},
+    once_cell::sync::Lazy,
+    retry::retry_with_index,
+    serde::de::DeserializeOwned,
+    toml::Value,
+    crate::{
+        config::{wildcard_match, CpuMetric, IpmiUnits, RetryPolicy, SmartBackend, Source, SourceType, StandbyPolicy, TempUnits, WmiProvider},
+        drive,
+        error::{Error, Result},
+        sensor::{suggest_names, SensorReading, SensorType, SensorUnits, SensorValue},
+        ipmi::IpmiWorker,
+        tools,
+    },
+};
+
+/// Maximum age of a reading for it to be reused by another zone. This is short
+/// enough that zones querying sources at the same time will share readings
+/// without any zone using readings from a previous interval.
+const SHARED_MAX_AGE: Duration = Duration::from_secs(1);
+
+/// A non-IPMI source's [`TempSource`] and latest reading.
+#[derive(Default)]
+struct SharedSource {
+    /// Source created on first use, along with the config it was created from
+    source: Option<(SourceType, Box<dyn TempSource>)>,
+    /// Latest reading and when it was read
+    reading: Option<(Instant, f32)>,
+}
+
+/// Each non-IPMI source, shared by all zones.
+static SHARED_SOURCES: Lazy<Mutex<HashMap<String, Arc<Mutex<SharedSource>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Previous /proc/stat sample: total and idle time in clock ticks, and the
+/// utilization that was computed from it.
+static CPU_SAMPLE: Lazy<Mutex<Option<(u64, u64, f32)>>> = Lazy::new(|| Mutex::new(None));
+
+/// Exit status that smartctl is told to use when a drive is in standby. The
+/// exit status is normally a bit mask, but bit 0 (command line error) is never
+/// combined with other bits.
+const SMARTCTL_STANDBY_STATUS: i32 = 3;
+
+/// Get the temperature of a hard drive via smartctl. This function fails with
+/// [`Error::DriveStandby`] if the drive is in standby, which is detected without
+/// waking the drive up. It also fails if smartctl does not return temperature
+/// data or if the reported temperature is not an integer.
+fn parse_smart_source<T: AsRef<Path>>(
+    block_dev: T,
+    device_type: Option<&str>,
+    args: &[String],
+) -> Result<f32> {
+    let block_dev = block_dev.as_ref();
+
+    let mut command = Command::new(&tools::get().smartctl);
+    command
+        .arg("-j")
+        .arg("-A")
+        .arg("-n")
+        .arg(format!("standby,{}", SMARTCTL_STANDBY_STATUS));
+    if let Some(t) = device_type {
+        command.arg("-d").arg(t);
+    }
+
+    let mut proc = command
+        .args(args)
+        .arg(block_dev)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Io { path: "(smartctl)".into(), source: e })?;
+
+    let result = serde_json::from_reader(proc.stdout.take().unwrap());
+    let status = proc.wait()
+        .map_err(|e| Error::Io { path: "(smartctl)".into(), source: e })?;
+
+    match status.code() {
+        Some(SMARTCTL_STANDBY_STATUS) => return Err(Error::DriveStandby(block_dev.to_owned())),
+        Some(0) | Some(2) => {},
+        _ => return Err(Error::Command { command: "smartctl".into(), status }),
+    }
+
+    let root: serde_json::Value = result
+        .map_err(|e| Error::SmartParse { block_dev: block_dev.to_owned(), source: e })?;
+
+    let temperature = root
+        .get("temperature")
+        .and_then(|v| v.get("current"))
+        .ok_or_else(|| Error::SmartNoReading(block_dev.to_owned()))?
+        .as_i64()
+        .ok_or(Error::ReadingExceedsBounds)?;
+
+    Ok(temperature as f32)
+}
+
+fn parse_hdparm_output(block_dev: &Path, stdout: &mut dyn Read) -> Result<f32> {
+    let mut reader = BufReader::new(stdout);
+    let mut line = String::new();
+
+    loop {
+        let n = reader.read_line(&mut line)
+            .map_err(|e| Error::Io { path: "(hdparm)".into(), source: e })?;
+        if n == 0 {
+            return Err(Error::HdparmNoData(block_dev.to_owned()));
+        } else if line.contains("bad/missing sense data") {
+            // hdparm exits with 0 when the drive responds with bad data
+            return Err(Error::HdparmBadData(block_dev.to_owned()));
+        } else if !line.contains("drive temperature (celsius) is:") {
+            continue;
+        }
+
+        // The drive temperature line always has the number as the last token
+        let last_token = line.trim_end().rsplit_once(' ')
+            .ok_or_else(|| Error::HdparmBadData(block_dev.to_owned()))?
+            .1;
+
+        let temperature = last_token
+            // Can be negative, but is within the bounds of 1 byte
+            .parse::<i8>()
+            .map_err(|e| Error::SensorValueParse { value: last_token.to_owned(), source: e })?;
+
+        return Ok(f32::from(temperature));
+    }
+}
+
+/// Check whether a drive is in standby or sleeping via `hdparm -C`, which does
+/// not wake the drive up.
+fn hdparm_in_standby(block_dev: &Path) -> Result<bool> {
+    let output = Command::new(&tools::get().hdparm)
+        .arg("-C")
+        .arg(block_dev)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::Io { path: "(hdparm)".into(), source: e })?;
+
+    if !output.status.success() {
+        return Err(Error::Command { command: "hdparm".into(), status: output.status });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("drive state is:"))
+        .any(|s| matches!(s.trim(), "standby" | "sleeping")))
+}
+
+/// Get the temperature of a Hitachi/HGST/WD drive via hdparm. This function
+/// fails if hdparm does not print the temperature line, hdparm prints the bad
+/// sense data line, or if the reported temperature does not fit in an [`i8`].
+/// If `check_standby` is true, it fails with [`Error::DriveStandby`] if the
+/// drive is in standby instead of querying the temperature.
+fn parse_hdparm_source<T: AsRef<Path>>(block_dev: T, check_standby: bool) -> Result<f32> {
+    let block_dev = block_dev.as_ref();
+
+    if check_standby && hdparm_in_standby(block_dev)? {
+        return Err(Error::DriveStandby(block_dev.to_owned()));
+    }
+
+    let mut proc = Command::new(&tools::get().hdparm)
+        .arg("-H")
+        .arg(block_dev)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Io { path: "(hdparm)".into(), source: e })?;
+
+    let result = parse_hdparm_output(block_dev, &mut proc.stdout.take().unwrap());
+    let status = proc.wait()
+        .map_err(|e| Error::Io { path: "(hdparm)".into(), source: e })?;
+
+    if status.code() != Some(0) {
+        return Err(Error::Command { command: "hdparm".into(), status });
+    }
+
+    result
+}
+
+/// Get the temperature of a WMI sensor via PowerShell. For the ACPI provider,
+/// `sensor` is the `InstanceName` of a thermal zone, which reports tenths of a
+/// Kelvin. For the hardware monitor providers, `sensor` is the `Identifier` of
+/// a temperature sensor (eg. `/intelcpu/0/temperature/0`), which reports
+/// degrees Celsius. LibreHardwareMonitor and OpenHardwareMonitor must be
+/// running for their namespaces to exist.
+fn parse_wmi_source(provider: WmiProvider, sensor: &str) -> Result<f32> {
+    if !cfg!(windows) {
+        return Err(Error::Io {
+            path: "(wmi)".into(),
+            source: io::Error::new(io::ErrorKind::Unsupported, "WMI is only available on Windows"),
+        });
+    }
+
+    // PowerShell single-quoted strings only need quotes escaped
+    let quoted = format!("'{}'", sensor.replace('\'', "''"));
+
+    let (namespace, class, filter, property) = match provider {
+        WmiProvider::Acpi => (
+            "root/WMI",
+            "MSAcpi_ThermalZoneTemperature",
+            format!("$_.InstanceName -eq {}", quoted),
+            "CurrentTemperature",
+        ),
+        WmiProvider::LibreHardwareMonitor | WmiProvider::OpenHardwareMonitor => (
+            if provider == WmiProvider::LibreHardwareMonitor {
+                "root/LibreHardwareMonitor"
+            } else {
+                "root/OpenHardwareMonitor"
+            },
+            "Sensor",
+            format!("$_.Identifier -eq {} -and $_.SensorType -eq 'Temperature'", quoted),
+            "Value",
+        ),
+    };
+
+    // Format with the invariant culture so that the decimal separator is
+    // always a period
+    let script = format!(
+        "Get-CimInstance -Namespace {} -ClassName {} | Where-Object {{ {} }} | \
+         Select-Object -First 1 | ForEach-Object {{ $_.{}.ToString([cultureinfo]::InvariantCulture) }}",
+        namespace, class, filter, property,
+    );
+
+    let output = Command::new(&tools::get().powershell)
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::Io { path: "(powershell)".into(), source: e })?;
+
+    if !output.status.success() {
+        return Err(Error::Command { command: "powershell".into(), status: output.status });
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() {
+        return Err(Error::SensorNotFound { sensor: sensor.to_owned(), suggestions: vec![] });
+    }
+
+    let value = trimmed.parse::<f32>()
+        .map_err(|_| Error::WmiParse(trimmed.to_owned()))?;
+
+    match provider {
+        WmiProvider::Acpi => Ok(value / 10.0 - 273.15),
+        _ => Ok(value),
+    }
+}
+
+/// Get the temperature from a plain-text file (typically a sysfs path). The
+/// contents of the file should be a decimal-formatted integer in units of
+/// thousandths of a degree after whitespace is trimmed. Negative values are
+/// allowed. The temperature is returned in degrees Celsius.
+fn parse_file_source<T: AsRef<Path>>(path: T, units: TempUnits) -> Result<f32> {
+    let contents = fs::read_to_string(path.as_ref())
+        .map_err(|e| Error::Io { path: path.as_ref().to_owned(), source: e })?;
+    let trimmed = contents.trim();
+
+    let millidegrees = trimmed
+        .parse::<i64>()
+        .map_err(|e| Error::SensorValueParse { value: trimmed.to_owned(), source: e })?;
+
+    Ok(units.to_celsius(millidegrees as f64 / 1000.0) as f32)
+}
+
+/// Get the utilization of all CPUs as a percentage since the previous call, or
+/// since boot for the first call. The idle time includes time spent waiting for
+/// I/O.
+fn parse_cpu_utilization() -> Result<f32> {
+    let path = Path::new("/proc/stat");
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+
+    // user, nice, system, idle, iowait, irq, softirq, steal. The guest times
+    // are already included in user and nice.
+    let times = contents.lines()
+        .next()
+        .and_then(|l| l.strip_prefix("cpu "))
+        .and_then(|l| {
+            l.split_whitespace()
+                .take(8)
+                .map(|t| t.parse::<u64>().ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|t| t.len() >= 5)
+        .ok_or_else(|| Error::ProcBadData(path.to_owned()))?;
+    let total = times.iter().sum::<u64>();
+    let idle = times[3] + times[4];
+
+    let mut sample = CPU_SAMPLE.lock().unwrap();
+    let (prev_total, prev_idle) = match *sample {
+        // Too soon for the counters to have changed
+        Some((t, _, utilization)) if t == total => return Ok(utilization),
+        Some((t, i, _)) => (t, i),
+        None => (0, 0),
+    };
+
+    let elapsed = total.saturating_sub(prev_total);
+    let busy = elapsed.saturating_sub(idle.saturating_sub(prev_idle));
+    let utilization = busy as f32 / elapsed as f32 * 100.0;
+    *sample = Some((total, idle, utilization));
+
+    Ok(utilization)
+}
+
+/// Get a load average divided by the number of CPUs as a percentage. This is
+/// above 100% when tasks are waiting for a CPU.
+fn parse_cpu_load(metric: CpuMetric) -> Result<f32> {
+    let path = Path::new("/proc/loadavg");
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+
+    let index = match metric {
+        CpuMetric::Load1 => 0,
+        CpuMetric::Load5 => 1,
+        CpuMetric::Load15 => 2,
+        CpuMetric::Utilization => unreachable!("Utilization is not a load average"),
+    };
+    let load = contents.split_whitespace()
+        .nth(index)
+        .and_then(|l| l.parse::<f32>().ok())
+        .ok_or_else(|| Error::ProcBadData(path.to_owned()))?;
+    let cpus = thread::available_parallelism()
+        .map_err(|e| Error::Io { path: "(available_parallelism)".into(), source: e })?;
+
+    Ok(load / cpus.get() as f32 * 100.0)
+}
+
+/// IPMI sensor readings by sensor type.
+type IpmiReadings = HashMap<SensorType, HashMap<String, Option<SensorReading>>>;
+
+/// Get the IPMI sensor readings of the given types. This must only be called
+/// if there are IPMI sources.
+fn query_ipmi_sensors(ipmi: Option<&IpmiWorker>, sensor_types: Vec<SensorType>)
+    -> Result<IpmiReadings>
+{
+    // Config validation guarantees that zones with IPMI sources have a session
+    let ipmi = ipmi.expect("IPMI session required for IPMI sources");
+
+    Ok(ipmi.run_blocking(move |i| {
+        sensor_types.into_iter()
+            .map(|t| i.get_shared_sensor_readings(t, SHARED_MAX_AGE).map(|r| (t, r)))
+            .collect::<result::Result<_, _>>()
+    })?)
+}
+
+/// Get the value of an IPMI sensor from the readings returned by
+/// [`query_ipmi_sensors`]. This function fails if the sensor is missing, has
+/// no reading, is not in the expected units, or does not have a numeric value.
+fn parse_ipmi_source(readings: &IpmiReadings, sensor: &str, units: IpmiUnits)
+    -> Result<f32>
+{
+    let type_readings = readings.get(&units.sensor_type());
+
+    let reading = match type_readings.and_then(|r| r.get(sensor)) {
+        Some(r) => r,
+        None => {
+            return Err(Error::SensorNotFound {
+                sensor: sensor.into(),
+                suggestions: suggest_names(sensor, type_readings.into_iter().flat_map(HashMap::keys)),
+            });
+        }
+    };
+
+    let reading = match reading {
+        Some(r) => r,
+        None => return Err(Error::SensorNoReading(sensor.into())),
+    };
+
+    let fahrenheit = match (units, reading.units) {
+        // Some BMCs report temperatures in Fahrenheit
+        (IpmiUnits::Celsius, SensorUnits::Fahrenheit) => true,
+        (u, r) if r == u.sensor_units() => false,
+        (_, r) => {
+            return Err(Error::SensorBadUnits {
+                sensor: sensor.into(),
+                units: r,
+            });
+        }
+    };
+
+    let value = match reading.value {
+        SensorValue::Uint32(t) => f64::from(t),
+        SensorValue::Double(t) => t,
+        v => {
+            return Err(Error::SensorBadValue {
+                sensor: sensor.into(),
+                value: v,
+            });
+        }
+    };
+
+    if fahrenheit {
+        Ok(TempUnits::Fahrenheit.to_celsius(value) as f32)
+    } else {
+        Ok(value as f32)
+    }
+}
+
+/// Get the highest value of the IPMI sensors whose names match a wildcard
+/// pattern. Sensors without a usable reading are ignored, but at least one
+/// must have one.
+fn parse_ipmi_pattern(readings: &IpmiReadings, pattern: &str, units: IpmiUnits)
+    -> Result<f32>
+{
+    readings.get(&units.sensor_type())
+        .into_iter()
+        .flat_map(HashMap::keys)
+        .filter(|name| wildcard_match(pattern, name))
+        .filter_map(|name| parse_ipmi_source(readings, name, units).ok())
+        .reduce(f32::max)
+        .ok_or_else(|| Error::SensorPatternNoMatch(pattern.to_owned()))
+}
+
+/// Get the value of an `ipmi` source's sensor or sensor pattern.
+fn parse_ipmi_sensor(readings: &IpmiReadings, sensor: &str, pattern: Option<&str>, units: IpmiUnits)
+    -> Result<f32>
+{
+    match pattern {
+        Some(p) => parse_ipmi_pattern(readings, p, units),
+        None => parse_ipmi_source(readings, sensor, units),
+    }
+}
+
+/// Discard the session's cached SDR after an IPMI sensor was not found so
+/// that the next attempt sees sensors that were added or renamed.
+fn invalidate_sdr_cache(zone: &str, ipmi: Option<&IpmiWorker>) {
+    let ipmi = match ipmi {
+        Some(i) => i,
+        None => return,
+    };
+
+    match ipmi.run_blocking(|i| i.invalidate_sdr_cache()) {
+        Ok(true) => warn!(zone = zone; "[{}] IPMI sensor not found; discarded SDR cache", zone),
+        Ok(false) => {}
+        Err(e) => warn!(zone = zone; "[{}] Failed to discard SDR cache: {}", zone, e),
+    }
+}
+
+/// Run a query, retrying according to the retry settings. If every attempt
+/// fails and there was more than one, the last error is wrapped in
+/// [`Error::RetriesFailed`]. `what` is only used for log messages.
+fn with_retries<T>(
+    zone: &str,
+    what: &dyn fmt::Display,
+    retry: &RetryPolicy,
+    mut query: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    retry_with_index(retry.delays(), |i| {
+        trace!(zone = zone; "[{}] Querying {} (attempt {}/{})", zone, what, i, retry.retries + 1);
+        query()
+    }).map_err(|e| if e.tries > 1 { e.into() } else { e.error })
+}
+
+/// Get temperature readings for the given sources. The returned values are in
+/// the same order as given. Each source is retried according to its retry
+/// settings, which default to the zone's `retry`. If an optional source fails,
+/// a warning is logged and its value is [`None`]. An IPMI session is only
+/// required if there are IPMI sources. `zone` is only used for log messages.
+pub fn get_source_readings(
+    zone: &str,
+    ipmi: Option<&IpmiWorker>,
+    sources: &[Source],
+    retry: &RetryPolicy,
+) -> Result<Vec<Option<f32>>> {
+    let is_ipmi = |s: &&Source| !s.source_type.ipmi_sensors().is_empty();
+
+    // Get IPMI sensor readings in one go for better performance. The query is
+    // retried with the zone's settings until every required IPMI sensor has a
+    // reading. If it still fails, the IPMI sources fail individually when
+    // looking up their sensors.
+    let any_ipmi = sources.iter().any(|s| is_ipmi(&s));
+    let mut sensor_types = vec![];
+    for (_, _, units) in sources.iter().flat_map(|s| s.source_type.ipmi_sensors()) {
+        if !sensor_types.contains(&units.sensor_type()) {
+            sensor_types.push(units.sensor_type());
+        }
+    }
+    let query = || {
+        let readings = query_ipmi_sensors(ipmi, sensor_types.clone())?;
+
+        for s in sources.iter().filter(|s| !s.options.optional) {
+            for (sensor, pattern, units) in s.source_type.ipmi_sensors() {
+                let result = parse_ipmi_sensor(&readings, sensor, pattern, units);
+                if matches!(result, Err(Error::SensorNotFound { .. } | Error::SensorPatternNoMatch(_))) {
+                    invalidate_sdr_cache(zone, ipmi);
+                }
+                result?;
+            }
+        }
+
+        Ok(readings)
+    };
+    let result = if any_ipmi {
+        with_retries(zone, &"IPMI sensors", retry, query)
+    } else {
+        Ok(HashMap::new())
+    };
+    let ipmi_readings = match result {
+        Ok(r) => r,
+        Err(e) if sources.iter().filter(is_ipmi).all(|s| s.options.optional) => {
+            warn!(zone = zone; "[{}] Failed to query IPMI sensors for optional sources: {}", zone, e);
+            HashMap::new()
+        }
+        Err(e) => return Err(e),
+    };
+
+    let ipmi_readings = &ipmi_readings;
+
+    // The remaining sources may spawn slow subprocesses, so query them in
+    // parallel to keep the total time close to that of the slowest source.
+    thread::scope(|scope| {
+        let handles = sources.iter()
+            .map(|s| {
+                match s.source_type {
+                    SourceType::Ipmi { .. } => None,
+                    _ => Some(scope.spawn(move || {
+                        with_retries(zone, s, &s.retry_policy(retry), || {
+                            parse_source(zone, &s.source_type, ipmi_readings)
+                        })
+                    })),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        sources.iter()
+            .zip(handles)
+            .map(|(s, h)| {
+                let result = match h {
+                    Some(h) => h.join().expect("Source thread panicked"),
+                    None => parse_source(zone, &s.source_type, ipmi_readings),
+                };
+
+                match result {
+                    Ok(t) => Ok(t),
+                    Err(e) if s.options.optional => {
+                        warn!(zone = zone, sensor:% = s; "[{}] Skipping optional source {}: {}", zone, s, e);
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .collect()
+    })
+}
+
+/// Get the temperature for a source. IPMI sensors are looked up in the
+/// readings from [`query_ipmi_sensors`]. A `delta` source is skipped if
+/// either of its parts is skipped.
+fn parse_source(
+    zone: &str,
+    source: &SourceType,
+    ipmi_readings: &IpmiReadings,
+) -> Result<Option<f32>> {
+    match source {
+        SourceType::Ipmi { sensor, sensor_pattern, units, divisor } => {
+            parse_ipmi_sensor(ipmi_readings, sensor, sensor_pattern.as_deref(), *units)
+                .map(|v| Some(v / divisor.0 as f32))
+        }
+        SourceType::Delta { source, reference } => {
+            let source = parse_source(zone, source, ipmi_readings)?;
+            let reference = parse_source(zone, reference, ipmi_readings)?;
+
+            Ok(source.zip(reference).map(|(s, r)| s - r))
+        }
+        SourceType::Cpu { range: Some([low, high]), .. } => {
+            // Map the usage onto the range, capping overloaded CPUs at 100%
+            let usage = parse_shared_local_source(zone, source)?;
+            let (low, high) = (f32::from(*low), f32::from(*high));
+
+            Ok(usage.map(|u| low + (high - low) * u.clamp(0.0, 100.0) / 100.0))
+        }
+        _ => parse_shared_local_source(zone, source),
+    }
+}
+
+/// Get the temperature for a non-IPMI source, reusing a reading from another
+/// zone if it is newer than [`SHARED_MAX_AGE`]. Concurrent queries for the same
+/// source are serialized so that the source is only queried once. If the
+/// source's drive is in standby, the source's standby policy decides the
+/// result and [`None`] means that the source is skipped.
+fn parse_shared_local_source(zone: &str, source: &SourceType) -> Result<Option<f32>> {
+    // The string representation does not include the source options
+    let entry = SHARED_SOURCES.lock().unwrap()
+        .entry(source.to_string())
+        .or_default()
+        .clone();
+    let mut entry = entry.lock().unwrap();
+
+    if let Some((time, reading)) = entry.reading {
+        if time.elapsed() < SHARED_MAX_AGE {
+            trace!("Using shared reading for source {}", source);
+            return Ok(Some(reading));
+        }
+    }
+
+    let reading = match (parse_local_source(&mut entry, source), source.standby()) {
+        (Err(Error::DriveStandby(_)), Some(policy)) => {
+            // The last reading is kept around for last_value, no matter its age
+            let reading = match policy {
+                StandbyPolicy::Skip => None,
+                StandbyPolicy::LastValue => entry.reading.map(|(_, r)| r),
+                StandbyPolicy::TreatAs(temp) => Some(temp.into()),
+            };
+
+            debug!(zone = zone; "[{}] Drive for source {} is in standby; using {:?}",
+                   zone, source, reading);
+
+            return Ok(reading);
+        }
+        (result, _) => result?,
+    };
+    entry.reading = Some((Instant::now(), reading));
+
+    Ok(Some(reading))
+}
+
+/// Get the temperature for a non-IPMI source. The [`TempSource`] is created
+/// the first time and reused afterwards, unless the source's options changed.
+fn parse_local_source(entry: &mut SharedSource, source: &SourceType) -> Result<f32> {
+    match source {
+        SourceType::Ipmi { .. } => unreachable!("IPMI sources are queried in bulk"),
+        SourceType::Delta { .. } => unreachable!("Delta sources are computed from their parts"),
+        SourceType::Disks { .. } | SourceType::Zpool { .. } => {
+            unreachable!("Disk patterns and pools are expanded when loading the config")
+        }
+        _ => {}
+    }
+
+    let (config, temp_source) = match entry.source.take() {
+        Some((config, s)) if config == *source => (config, s),
+        _ => (source.clone(), create_source(source)?),
+    };
+    let result = temp_source.read();
+    entry.source = Some((config, temp_source));
+
+    result
+}
+
+/// Drop the shared state of the sources that are no longer in `sources`, like
+/// after the config is reloaded. This closes their [`TempSource`]s.
+pub fn retain_shared_sources<'a>(sources: impl IntoIterator<Item = &'a SourceType>) {
+    fn add_keys(source: &SourceType, keys: &mut HashSet<String>) {
+        match source {
+            SourceType::Delta { source, reference } => {
+                add_keys(source, keys);
+                add_keys(reference, keys);
+            }
+            s => {
+                keys.insert(s.to_string());
+            }
+        }
+    }
+
+    let mut keys = HashSet::new();
+    for source in sources {
+        add_keys(source, &mut keys);
+    }
+
+    SHARED_SOURCES.lock().unwrap().retain(|k, _| keys.contains(k));
+}
+
+/// A temperature source that is queried on its own, as opposed to IPMI sensors,
+/// which are queried in bulk. Out-of-tree sources implement this and are added
+/// with [`register_source`].
+pub trait TempSource: Send + Sync {
+    /// Create the source from its config. This is only called with sources
+    /// whose `type` the implementation was registered for. Custom sources can
+    /// get their keys with [`custom_params`]. The source is created once and
+    /// then reused for every query until it is removed from the config.
+    fn from_config(source: &SourceType) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Get the temperature in degrees Celsius. If the source's drive is in
+    /// standby, this should fail with [`Error::DriveStandby`] so that the
+    /// source's standby policy is applied.
+    fn read(&self) -> Result<f32>;
+}
+
+/// Function that creates a [`TempSource`] from a source's config.
+pub type SourceFactory = fn(&SourceType) -> Result<Box<dyn TempSource>>;
+
+/// Create a boxed `T` from a source's config.
+fn new_source<T: TempSource + 'static>(source: &SourceType) -> Result<Box<dyn TempSource>> {
+    Ok(Box::new(T::from_config(source)?))
+}
+
+/// Factories for each source type that is read with a [`TempSource`], keyed by
+/// the config's `type`.
+static REGISTRY: Lazy<RwLock<HashMap<String, SourceFactory>>> = Lazy::new(|| {
+    let builtin: [(&str, SourceFactory); 5] = [
+        ("file", new_source::<FileSource>),
+        ("smart", new_source::<SmartSource>),
+        ("hdparm", new_source::<HdparmSource>),
+        ("cpu", new_source::<CpuSource>),
+        ("wmi", new_source::<WmiSource>),
+    ];
+
+    RwLock::new(builtin.into_iter().map(|(t, f)| (t.to_owned(), f)).collect())
+});
+
+/// Add a source type that can be used in the config as `type = "<type_name>"`.
+/// This must be called before the config is loaded. Fails with
+/// [`Error::SourceTypeRegistered`] if the type already exists, including the
+/// built-in types.
+pub fn register_source<T: TempSource + 'static>(type_name: &str) -> Result<()> {
+    let mut registry = REGISTRY.write().unwrap();
+
+    if registry.contains_key(type_name) || SourceType::BUILTIN_TYPES.contains(&type_name) {
+        return Err(Error::SourceTypeRegistered(type_name.to_owned()));
+    }
+
+    registry.insert(type_name.to_owned(), new_source::<T>);
+
+    Ok(())
+}
+
+/// Check if a source type has a registered [`TempSource`].
+pub fn is_registered(type_name: &str) -> bool {
+    REGISTRY.read().unwrap().contains_key(type_name)
+}
+
+/// Create the [`TempSource`] for a source with the factory registered for its
+/// type.
+pub fn create_source(source: &SourceType) -> Result<Box<dyn TempSource>> {
+    let factory = REGISTRY.read().unwrap()
+        .get(source.type_name())
+        .copied()
+        .ok_or_else(|| Error::SourceTypeUnknown(source.type_name().to_owned()))?;
+
+    factory(source)
+}
+
+/// Deserialize the keys of a [`SourceType::Custom`] source. This fails with
+/// [`Error::SourceConfig`] if the keys don't match `T`.
+pub fn custom_params<T: DeserializeOwned>(source: &SourceType) -> Result<T> {
+    let params = match source {
+        SourceType::Custom { params, .. } => params.0.clone(),
+        _ => return Err(Error::SourceConfig {
+            name: source.to_string(),
+            reason: "not a custom source".to_owned(),
+        }),
+    };
+
+    Value::Table(params).try_into()
+        .map_err(|e| Error::SourceConfig { name: source.to_string(), reason: e.to_string() })
+}
+
+/// `file` source.
+struct FileSource {
+    path: String,
+    units: TempUnits,
+}
+
+impl TempSource for FileSource {
+    fn from_config(source: &SourceType) -> Result<Self> {
+        match source {
+            SourceType::File { path, units } => Ok(Self { path: path.clone(), units: *units }),
+            _ => unreachable!("Not a file source: {}", source),
+        }
+    }
+
+    fn read(&self) -> Result<f32> {
+        parse_file_source(&self.path, self.units)
+    }
+}
+
+/// `smart` source.
+struct SmartSource {
+    block_dev: String,
+    device_type: Option<String>,
+    args: Vec<String>,
+    backend: SmartBackend,
+}
+
+impl TempSource for SmartSource {
+    fn from_config(source: &SourceType) -> Result<Self> {
+        match source {
+            SourceType::Smart { block_dev, device_type, args, backend, .. } => Ok(Self {
+                block_dev: block_dev.clone(),
+                device_type: device_type.clone(),
+                args: args.clone(),
+                backend: *backend,
+            }),
+            _ => unreachable!("Not a smart source: {}", source),
+        }
+    }
+
+    fn read(&self) -> Result<f32> {
+        match self.backend {
+            SmartBackend::Native => drive::read_temperature(Path::new(&self.block_dev)),
+            SmartBackend::Smartctl => {
+                parse_smart_source(&self.block_dev, self.device_type.as_deref(), &self.args)
+            }
+        }
+    }
+}
+
+/// `hdparm` source.
+struct HdparmSource {
+    block_dev: String,
+    check_standby: bool,
+}
+
+impl TempSource for HdparmSource {
+    fn from_config(source: &SourceType) -> Result<Self> {
+        match source {
+            SourceType::Hdparm { block_dev, standby } => Ok(Self {
+                block_dev: block_dev.clone(),
+                check_standby: standby.is_some(),
+            }),
+            _ => unreachable!("Not an hdparm source: {}", source),
+        }
+    }
+
+    fn read(&self) -> Result<f32> {
+        parse_hdparm_source(&self.block_dev, self.check_standby)
+    }
+}
+
+/// `cpu` source, without the mapping onto the temperature range.
+struct CpuSource {
+    metric: CpuMetric,
+}
+
+impl TempSource for CpuSource {
+    fn from_config(source: &SourceType) -> Result<Self> {
+        match source {
+            SourceType::Cpu { metric, .. } => Ok(Self { metric: *metric }),
+            _ => unreachable!("Not a cpu source: {}", source),
+        }
+    }
+
+    fn read(&self) -> Result<f32> {
+        match self.metric {
+            CpuMetric::Utilization => parse_cpu_utilization(),
+            metric => parse_cpu_load(metric),
+        }
+    }
+}
+
+/// `wmi` source.
+struct WmiSource {
+    sensor: String,
+    provider: WmiProvider,
+}
+
+impl TempSource for WmiSource {
+    fn from_config(source: &SourceType) -> Result<Self> {
+        match source {
+            SourceType::Wmi { sensor, provider } => Ok(Self { sensor: sensor.clone(), provider: *provider }),
+            _ => unreachable!("Not a wmi source: {}", source),
+        }
+    }
+
+    fn read(&self) -> Result<f32> {
+        parse_wmi_source(self.provider, &self.sensor)
+    }
+}
+
+/// Last successful reading of each source in a zone, along with when it was
+/// queried. Skipped optional sources are cached as [`None`].
+#[derive(Debug, Default)]
+pub struct SourceCache {
+    entries: Vec<Option<(Instant, Option<f32>)>>,
+}
+
+/// Get temperature readings for the given sources, like
+/// [`get_source_readings`], but reuse cached readings for sources whose poll
+/// interval has not elapsed yet. The cache is only updated if all due sources
+/// were queried successfully.
+pub fn get_cached_source_readings(
+    zone: &str,
+    ipmi: Option<&IpmiWorker>,
+    sources: &[Source],
+    retry: &RetryPolicy,
+    cache: &mut SourceCache,
+) -> Result<Vec<Option<f32>>> {
+    cache.entries.resize(sources.len(), None);
+
+    let now = Instant::now();
+    let due = sources.iter()
+        .zip(&cache.entries)
+        .enumerate()
+        .filter(|(_, (source, entry))| {
+            match (source.poll_interval(), entry) {
+                (Some(interval), Some((time, _))) => now.duration_since(*time) >= interval,
+                _ => true,
+            }
+        })
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    if due.len() != sources.len() {
+        trace!(zone = zone; "[{}] Using cached readings for {} of {} sources",
+               zone, sources.len() - due.len(), sources.len());
+    }
+
+    let due_sources = due.iter()
+        .map(|i| sources[*i].clone())
+        .collect::<Vec<_>>();
+    let readings = get_source_readings(zone, ipmi, &due_sources, retry)?;
+
+    for (i, reading) in due.into_iter().zip(readings) {
+        cache.entries[i] = Some((now, reading));
+    }
+
+    Ok(cache.entries.iter()
+        .map(|e| e.expect("All sources should have readings").1)
+        .collect())
+}
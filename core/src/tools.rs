@@ -0,0 +1,59 @@
+use {
+    std::{
+        env,
+        path::{Path, PathBuf},
+        sync::{Arc, RwLock},
+    },
+    once_cell::sync::Lazy,
+    crate::config::Tools,
+};
+
+/// Paths of the external programs from the currently loaded config.
+static TOOLS: Lazy<RwLock<Arc<Tools>>> = Lazy::new(Default::default);
+
+/// Get the configured paths of the external programs.
+pub fn get() -> Arc<Tools> {
+    TOOLS.read().unwrap().clone()
+}
+
+/// Set the paths of the external programs that are run from now on.
+pub fn set(tools: &Tools) {
+    *TOOLS.write().unwrap() = Arc::new(tools.clone());
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Find a program the same way that [`std::process::Command`] does. A name
+/// without a directory is searched for in `PATH`. Returns [`None`] if the
+/// program does not exist or is not executable.
+pub fn find(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_owned());
+    }
+
+    let candidates = |dir: PathBuf| {
+        let mut paths = vec![dir.join(path)];
+        if cfg!(windows) && path.extension().is_none() {
+            paths.push(dir.join(path).with_extension("exe"));
+        }
+        paths
+    };
+
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .flat_map(candidates)
+        .find(|p| is_executable(p))
+}
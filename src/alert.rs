@@ -0,0 +1,114 @@
+use {
+    std::sync::Mutex,
+    log::{debug, warn},
+    serde::Serialize,
+    tokio::{process::Command, task::JoinHandle},
+    crate::{
+        config::{AlertEvent, Alerts, Zone},
+        error::{Error, Result},
+        http,
+    },
+};
+
+/// Details of an event, passed to the alert hooks.
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    pub event: AlertEvent,
+    /// Name of the zone (see [`Zone::label`])
+    pub zone: String,
+    pub message: String,
+    /// Temperature in degrees Celsius that caused the event, if any
+    pub temp: Option<f32>,
+    /// Duty cycle that was applied because of the event, if any
+    pub dcycle: Option<u8>,
+}
+
+impl Alert {
+    pub fn new(event: AlertEvent, zone_config: &Zone, message: String) -> Self {
+        Self {
+            event,
+            zone: zone_config.label(),
+            message,
+            temp: None,
+            dcycle: None,
+        }
+    }
+
+    pub fn with_temp(mut self, temp: f32) -> Self {
+        self.temp = Some(temp);
+        self
+    }
+
+    pub fn with_dcycle(mut self, dcycle: u8) -> Self {
+        self.dcycle = Some(dcycle);
+        self
+    }
+}
+
+/// Dispatcher for alerts, shared by all zone loops. Alerts are discarded while
+/// no hooks are configured.
+#[derive(Default)]
+pub struct Notifier {
+    config: Mutex<Option<Alerts>>,
+}
+
+impl Notifier {
+    pub fn set_config(&self, config: Option<Alerts>) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Run the hooks for an alert in the background. Returns [`None`] if the
+    /// event does not trigger alerts.
+    pub fn notify(&self, alert: Alert) -> Option<JoinHandle<()>> {
+        let config = self.config.lock().unwrap().clone()
+            .filter(|c| c.enabled(alert.event))?;
+
+        Some(tokio::spawn(async move {
+            debug!("[{}] Sending alert: {:?}", alert.zone, alert);
+
+            if !config.command.is_empty() {
+                if let Err(e) = run_command(&config.command, &alert).await {
+                    warn!("[{}] Failed to run alert command for {} event: {}",
+                          alert.zone, alert.event, e);
+                }
+            }
+
+            if let Some(url) = &config.webhook {
+                let body = serde_json::to_string(&alert).expect("Alert is serializable");
+
+                if let Err(e) = http::post(url, "application/json", &[], &body).await {
+                    warn!("[{}] Failed to send alert webhook for {} event: {}",
+                          alert.zone, alert.event, e);
+                }
+            }
+        }))
+    }
+}
+
+/// Run the alert command with the alert details in environment variables
+/// prefixed with `IPMI_FAN_CONTROL_`.
+async fn run_command(command: &[String], alert: &Alert) -> Result<()> {
+    let prefix = env!("CARGO_PKG_NAME").replace('-', "_").to_uppercase();
+
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..])
+        .env(format!("{}_EVENT", prefix), alert.event.to_string())
+        .env(format!("{}_ZONE", prefix), &alert.zone)
+        .env(format!("{}_MESSAGE", prefix), &alert.message);
+
+    if let Some(temp) = alert.temp {
+        cmd.env(format!("{}_TEMP", prefix), format!("{:.1}", temp));
+    }
+    if let Some(dcycle) = alert.dcycle {
+        cmd.env(format!("{}_DCYCLE", prefix), dcycle.to_string());
+    }
+
+    let status = cmd.status().await
+        .map_err(|e| Error::Io { path: command[0].clone().into(), source: e })?;
+
+    if !status.success() {
+        return Err(Error::Command { command: command[0].clone().into(), status });
+    }
+
+    Ok(())
+}
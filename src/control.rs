@@ -0,0 +1,527 @@
+use {
+    std::{
+        fs,
+        io,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    log::{debug, info, warn},
+    serde::{Deserialize, Serialize},
+    tokio::sync::Notify,
+    crate::{
+        config::{self, Output, Zone},
+        error::{Error, Result},
+    },
+};
+
+/// Get the current Unix timestamp in seconds.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Read a zone's override file. The file contains a duty cycle percentage,
+/// optionally followed by a duration (eg. `80 30m`). The duration is counted
+/// from the file's modification time, so touching the file restarts it.
+/// Returns [`None`] if the file does not exist or the duration has elapsed.
+pub fn read_override_file(path: &Path) -> Result<Option<u8>> {
+    let invalid = |reason: String| Error::OverrideFile { path: path.to_owned(), reason };
+
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io { path: path.to_owned(), source: e }),
+    };
+
+    let mut tokens = contents.split_whitespace();
+
+    let dcycle = match tokens.next() {
+        Some(t) => t.trim_end_matches('%').parse::<u8>().ok()
+            .filter(|d| *d <= 100)
+            .ok_or_else(|| invalid(format!("invalid percentage: {:?}", t)))?,
+        None => return Err(invalid("file is empty".to_owned())),
+    };
+
+    if let Some(t) = tokens.next() {
+        let duration = config::parse_duration(t).map_err(invalid)?;
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+
+        if modified.elapsed().is_ok_and(|e| e >= duration) {
+            return Ok(None);
+        }
+    }
+
+    if let Some(t) = tokens.next() {
+        return Err(invalid(format!("unexpected data: {:?}", t)));
+    }
+
+    Ok(Some(dcycle))
+}
+
+/// Runtime status of a zone loop.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ZoneStatus {
+    /// Index of the zone in the config file
+    pub index: usize,
+    /// Name of the zone in the config file
+    #[serde(default)]
+    pub name: Option<String>,
+    pub session: String,
+    pub ipmi_zones: Vec<u8>,
+    /// hwmon PWM outputs controlled by the zone
+    #[serde(default)]
+    pub pwm: Vec<String>,
+    /// Last aggregated temperature reading in degrees Celsius
+    pub temp: Option<f32>,
+    /// Last duty cycle that was applied
+    pub dcycle: Option<u8>,
+    /// Unix timestamp (in seconds) of the last successful update
+    pub last_update: Option<u64>,
+    /// Message of the last error that occurred in the loop
+    pub last_error: Option<String>,
+    /// Whether the failsafe duty cycle is currently applied
+    #[serde(default)]
+    pub failsafe: bool,
+    /// Whether a source currently exceeds the emergency temperature
+    #[serde(default)]
+    pub emergency: bool,
+    /// Fan sensors that currently report 0 RPM
+    #[serde(default)]
+    pub stalled_fans: Vec<String>,
+    /// Duty cycle set via the control socket that replaces the computed duty
+    /// cycle
+    #[serde(default)]
+    pub override_dcycle: Option<u8>,
+    /// Unix timestamp (in seconds) when the override expires or [`None`] if it
+    /// does not expire
+    #[serde(default)]
+    pub override_until: Option<u64>,
+    /// Duty cycle from the zone's override file. This is only used if there is
+    /// no override set via the control socket.
+    #[serde(default)]
+    pub file_override_dcycle: Option<u8>,
+    /// Whether the zone loop is paused via the control socket. A paused zone
+    /// leaves the fans at their current duty cycle.
+    #[serde(default)]
+    pub paused: bool,
+    /// Whether the zone loop stopped after an error while the other zones kept
+    /// running
+    #[serde(default)]
+    pub stopped: bool,
+    /// When the zone loop last completed an iteration (successful or not)
+    #[serde(skip)]
+    heartbeat: Option<Instant>,
+    /// Zone's update interval
+    #[serde(skip)]
+    interval: Duration,
+    /// Zone's name for log messages (see [`Zone::label`])
+    #[serde(skip)]
+    label: String,
+    /// Config that the zone loop is running with
+    #[serde(skip)]
+    config: Option<Arc<Zone>>,
+}
+
+impl ZoneStatus {
+    pub fn new(index: usize, zone_config: &Arc<Zone>) -> Self {
+        Self {
+            index,
+            name: zone_config.name.clone(),
+            session: zone_config.session.0.clone(),
+            ipmi_zones: zone_config.ipmi_zones.clone(),
+            pwm: match &zone_config.output {
+                Output::Ipmi => vec![],
+                Output::Hwmon { pwm } => pwm.clone(),
+            },
+            temp: None,
+            dcycle: None,
+            last_update: None,
+            last_error: None,
+            failsafe: false,
+            emergency: false,
+            stalled_fans: vec![],
+            override_dcycle: None,
+            override_until: None,
+            file_override_dcycle: None,
+            paused: false,
+            stopped: false,
+            heartbeat: Some(Instant::now()),
+            interval: zone_config.interval.to_duration(),
+            label: zone_config.label(),
+            config: Some(zone_config.clone()),
+        }
+    }
+
+    /// Record the result of a fan update iteration.
+    pub fn update(&mut self, result: &Result<(f32, u8)>) {
+        self.heartbeat = Some(Instant::now());
+
+        match result {
+            Ok((temp, dcycle)) => {
+                self.temp = Some(*temp);
+                self.dcycle = Some(*dcycle);
+                self.failsafe = false;
+                self.last_update = Some(unix_time());
+            }
+            Err(e) => self.last_error = Some(e.to_string()),
+        }
+    }
+
+    pub fn config(&self) -> Option<&Zone> {
+        self.config.as_deref()
+    }
+
+    /// Get the duty cycle override set via the control socket, clearing it if
+    /// it has expired.
+    pub fn take_expired_override(&mut self) -> Option<u8> {
+        if self.override_until.is_some_and(|t| unix_time() >= t) {
+            info!("[{}] Duty cycle override expired", self.label);
+            self.override_dcycle = None;
+            self.override_until = None;
+        }
+
+        self.override_dcycle
+    }
+
+    /// Record an iteration that was skipped because the zone is paused.
+    pub fn record_paused(&mut self) {
+        self.heartbeat = Some(Instant::now());
+    }
+
+    /// Record that the zone loop stopped after an error. The zone's fans are no
+    /// longer controlled.
+    pub fn record_stopped(&mut self, error: &Error) {
+        self.last_error = Some(error.to_string());
+        self.temp = None;
+        self.dcycle = None;
+        self.failsafe = false;
+        self.stopped = true;
+    }
+
+    /// Record a failure to query the zone's sources. `failsafe_dcycle` is the
+    /// failsafe duty cycle if it was applied.
+    pub fn record_failure(&mut self, error: &Error, failsafe_dcycle: Option<u8>) {
+        self.heartbeat = Some(Instant::now());
+        self.last_error = Some(error.to_string());
+
+        if let Some(dcycle) = failsafe_dcycle {
+            self.temp = None;
+            self.dcycle = Some(dcycle);
+            self.failsafe = true;
+        }
+    }
+}
+
+/// State shared between the zone loops, the control socket, and the watchdog.
+#[derive(Default)]
+pub struct ControlState {
+    zones: Mutex<Vec<Arc<Mutex<ZoneStatus>>>>,
+    /// Notified when a config reload is requested via the control socket
+    pub reload: Notify,
+}
+
+impl ControlState {
+    /// Replace the list of zones being reported.
+    pub fn set_zones(&self, zones: Vec<Arc<Mutex<ZoneStatus>>>) {
+        *self.zones.lock().unwrap() = zones;
+    }
+
+    /// Check that every zone loop completed an iteration within the timeout
+    /// (plus the zone's own interval). A loop that is stuck, for example on a
+    /// hung subprocess, fails this check. Loops that stopped after an error are
+    /// not checked.
+    pub fn zones_alive(&self, timeout: Duration) -> bool {
+        self.zones.lock().unwrap()
+            .iter()
+            .all(|s| {
+                let s = s.lock().unwrap();
+                s.stopped || s.heartbeat.is_some_and(|h| h.elapsed() <= timeout + s.interval)
+            })
+    }
+
+    /// Get the label, last temperature, and last duty cycle of every zone except
+    /// the one at `index`.
+    pub fn other_zones(&self, index: usize) -> Vec<(String, Option<f32>, Option<u8>)> {
+        self.zones.lock().unwrap()
+            .iter()
+            .filter_map(|s| {
+                let s = s.lock().unwrap();
+                (s.index != index).then(|| (s.label.clone(), s.temp, s.dcycle))
+            })
+            .collect()
+    }
+
+    /// Get a snapshot of all zone statuses, sorted by zone index.
+    pub fn zone_statuses(&self) -> Vec<ZoneStatus> {
+        let mut statuses: Vec<_> = self.zones.lock().unwrap()
+            .iter()
+            .map(|s| s.lock().unwrap().clone())
+            .collect();
+        statuses.sort_by_key(|s| s.index);
+        statuses
+    }
+
+    /// Find a zone by its index or name.
+    pub fn find_zone(&self, zone: &ZoneRef) -> Option<Arc<Mutex<ZoneStatus>>> {
+        self.zones.lock().unwrap()
+            .iter()
+            .find(|s| {
+                let s = s.lock().unwrap();
+                match zone {
+                    ZoneRef::Index(i) => s.index == *i,
+                    ZoneRef::Name(n) => s.name.as_ref() == Some(n),
+                }
+            })
+            .cloned()
+    }
+}
+
+/// Zone referenced by a control socket request, either by its index in the
+/// config file or by its name.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ZoneRef {
+    Index(usize),
+    Name(String),
+}
+
+impl ZoneRef {
+    /// Parse a command line argument. Numbers refer to zone indexes.
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(i) => Self::Index(i),
+            Err(_) => Self::Name(value.to_owned()),
+        }
+    }
+}
+
+/// Request sent to the control socket. Each request is a single line of JSON.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "command")]
+pub enum Request {
+    Status,
+    /// Apply a fixed duty cycle to a zone instead of the computed one. The
+    /// emergency temperature and stalled fans still force 100%.
+    SetOverride {
+        zone: ZoneRef,
+        dcycle: u8,
+        /// Number of seconds after which the override is cleared
+        #[serde(default)]
+        duration_secs: Option<u64>,
+    },
+    ClearOverride {
+        zone: ZoneRef,
+    },
+    /// Stop querying sources and changing the duty cycle for a zone
+    Pause {
+        zone: ZoneRef,
+    },
+    Resume {
+        zone: ZoneRef,
+    },
+    /// Reload the config file, like sending `SIGHUP`
+    Reload,
+}
+
+/// Response sent by the control socket. Each response is a single line of
+/// JSON.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "result")]
+pub enum Response {
+    Status {
+        zones: Vec<ZoneStatus>,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+/// Apply a change to the status of the referenced zone.
+fn update_zone(state: &ControlState, zone: &ZoneRef, f: impl FnOnce(&mut ZoneStatus)) -> Response {
+    match state.find_zone(zone) {
+        Some(status) => {
+            f(&mut status.lock().unwrap());
+            Response::Ok
+        }
+        None => Response::Error {
+            message: format!("Zone not found: {:?}", zone),
+        },
+    }
+}
+
+/// Handle a request from the control socket or the HTTP API.
+pub fn handle_request(request: Request, state: &ControlState) -> Response {
+    match request {
+        Request::Status => Response::Status {
+            zones: state.zone_statuses(),
+        },
+        Request::SetOverride { dcycle, .. } if dcycle > 100 => Response::Error {
+            message: format!("Invalid percentage: {}", dcycle),
+        },
+        Request::SetOverride { zone, dcycle, duration_secs } => update_zone(state, &zone, |s| {
+            match duration_secs {
+                Some(d) => info!("[{}] Overriding duty cycle to {}% for {}s", s.label, dcycle, d),
+                None => info!("[{}] Overriding duty cycle to {}%", s.label, dcycle),
+            }
+            s.override_dcycle = Some(dcycle);
+            s.override_until = duration_secs.map(|d| unix_time().saturating_add(d));
+        }),
+        Request::ClearOverride { zone } => update_zone(state, &zone, |s| {
+            info!("[{}] Clearing duty cycle override", s.label);
+            s.override_dcycle = None;
+            s.override_until = None;
+        }),
+        Request::Pause { zone } => update_zone(state, &zone, |s| {
+            info!("[{}] Pausing zone", s.label);
+            s.paused = true;
+        }),
+        Request::Resume { zone } => update_zone(state, &zone, |s| {
+            info!("[{}] Resuming zone", s.label);
+            s.paused = false;
+        }),
+        Request::Reload => {
+            info!("Config reload requested");
+            state.reload.notify_one();
+            Response::Ok
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{bind, query, serve};
+
+#[cfg(unix)]
+mod unix {
+    use {
+        std::{
+            io::{BufRead, BufReader, Write},
+            os::unix::{
+                fs::{FileTypeExt, PermissionsExt},
+                net::UnixStream as StdUnixStream,
+            },
+        },
+        tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader},
+            net::{UnixListener, UnixStream},
+        },
+        super::*,
+    };
+
+    /// Create the control socket at the specified path. A stale socket left
+    /// behind by a previous instance is removed, but anything else at the path
+    /// is an error. The parent directory is created if it does not exist. The
+    /// socket is only accessible by the owner because it controls the fans.
+    pub fn bind(path: &Path) -> Result<UnixListener> {
+        let io_err = |e| Error::Io { path: path.to_owned(), source: e };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+
+        match fs::symlink_metadata(path) {
+            Ok(m) if m.file_type().is_socket() => fs::remove_file(path).map_err(io_err)?,
+            Ok(_) => {
+                return Err(io_err(io::Error::new(io::ErrorKind::AlreadyExists, "Not a socket")));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_err(e)),
+        }
+
+        let listener = UnixListener::bind(path).map_err(io_err)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(io_err)?;
+
+        Ok(listener)
+    }
+
+    /// Handle a single client connection until it is closed.
+    async fn handle_client(stream: UnixStream, state: Arc<ControlState>) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = AsyncBufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str(&line) {
+                Ok(r) => handle_request(r, &state),
+                Err(e) => Response::Error {
+                    message: format!("Invalid request: {}", e),
+                },
+            };
+            let mut data = serde_json::to_vec(&response)?;
+            data.push(b'\n');
+
+            writer.write_all(&data).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Accept and handle control socket connections forever.
+    pub async fn serve(listener: UnixListener, state: Arc<ControlState>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(stream, state).await {
+                            debug!("Control socket client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept control socket connection: {}", e),
+            }
+        }
+    }
+
+    /// Send a request to a running daemon and wait for the response.
+    pub fn query(path: &Path, request: &Request) -> Result<Response> {
+        let io_err = |e| Error::Io { path: path.to_owned(), source: e };
+
+        let mut stream = StdUnixStream::connect(path).map_err(io_err)?;
+
+        let mut data = serde_json::to_vec(request)
+            .map_err(|e| Error::ControlProtocol(e.to_string()))?;
+        data.push(b'\n');
+        stream.write_all(&data).map_err(io_err)?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).map_err(io_err)?;
+
+        serde_json::from_str(&line)
+            .map_err(|e| Error::ControlProtocol(e.to_string()))
+    }
+}
+
+#[cfg(windows)]
+pub use windows::{bind, query, serve};
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+
+    /// Placeholder listener type since Unix sockets are not available.
+    pub enum Listener {}
+
+    fn unsupported(path: &Path) -> Error {
+        Error::Io {
+            path: path.to_owned(),
+            source: io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported"),
+        }
+    }
+
+    pub fn bind(path: &Path) -> Result<Listener> {
+        Err(unsupported(path))
+    }
+
+    pub async fn serve(listener: Listener, _state: Arc<ControlState>) {
+        match listener {}
+    }
+
+    pub fn query(path: &Path, _request: &Request) -> Result<Response> {
+        Err(unsupported(path))
+    }
+}
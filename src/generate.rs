@@ -0,0 +1,309 @@
+use {
+    std::{
+        collections::HashMap,
+        fmt::Write,
+        fs,
+        path::{Path, PathBuf},
+    },
+    log::warn,
+    crate::{
+        config::{Session, SessionName},
+        error::Result,
+        sensor::SensorReading,
+        ipmi::{FanMode, Ipmi, MAX_PROBE_ZONES},
+    },
+};
+
+/// Local block device that might report a temperature.
+struct Disk {
+    /// Stable path under /dev/disk/by-id if there is one
+    path: PathBuf,
+    model: Option<String>,
+    rotational: bool,
+}
+
+/// Everything that was found while probing the system.
+struct Probe {
+    fan_mode: Option<FanMode>,
+    /// IPMI zones that responded to a duty cycle read and their duty cycles
+    ipmi_zones: Vec<(u8, u8)>,
+    temp_sensors: Vec<(String, Option<SensorReading>)>,
+    fan_sensors: Vec<(String, Option<SensorReading>)>,
+    disks: Vec<Disk>,
+}
+
+/// Get the sensor readings sorted by name.
+fn sorted(readings: HashMap<String, Option<SensorReading>>) -> Vec<(String, Option<SensorReading>)> {
+    let mut readings: Vec<_> = readings.into_iter().collect();
+    readings.sort_by(|a, b| a.0.cmp(&b.0));
+    readings
+}
+
+/// Find the stable /dev/disk/by-id path for each block device. Names based on
+/// the model and serial number are preferred over WWNs and EUIs.
+fn disk_ids() -> HashMap<String, PathBuf> {
+    let by_id = Path::new("/dev/disk/by-id");
+    let mut entries: Vec<_> = match fs::read_dir(by_id) {
+        Ok(d) => d.flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|n| !n.contains("-part"))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to list {:?}: {}", by_id, e);
+            return HashMap::new();
+        }
+    };
+    entries.sort_by_key(|n| (n.starts_with("wwn-") || n.starts_with("nvme-eui."), n.clone()));
+
+    let mut ids = HashMap::new();
+
+    for name in entries {
+        let path = by_id.join(&name);
+        let dev = match fs::canonicalize(&path) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+
+        if let Some(dev) = dev.file_name() {
+            ids.entry(dev.to_string_lossy().into_owned()).or_insert(path);
+        }
+    }
+
+    ids
+}
+
+/// Find the physical disks on the system. Virtual block devices, like loop
+/// devices and device mapper targets, have no `device` directory in sysfs and
+/// are skipped.
+fn find_disks() -> Vec<Disk> {
+    let sys_block = Path::new("/sys/block");
+    let mut names: Vec<_> = match fs::read_dir(sys_block) {
+        Ok(d) => d.flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|n| sys_block.join(n).join("device").exists())
+            // Optical drives
+            .filter(|n| !n.starts_with("sr"))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to list {:?}: {}", sys_block, e);
+            return vec![];
+        }
+    };
+    names.sort();
+
+    let ids = disk_ids();
+
+    names.into_iter()
+        .map(|name| {
+            let dir = sys_block.join(&name);
+            let model = fs::read_to_string(dir.join("device").join("model")).ok()
+                .map(|m| m.trim().to_owned())
+                .filter(|m| !m.is_empty());
+            let rotational = fs::read_to_string(dir.join("queue").join("rotational"))
+                .is_ok_and(|r| r.trim() == "1");
+            let path = ids.get(&name).cloned()
+                .unwrap_or_else(|| Path::new("/dev").join(&name));
+
+            Disk { path, model, rotational }
+        })
+        .collect()
+}
+
+/// Query the BMC for its fan mode, the zones that respond to duty cycle reads,
+/// and the available sensors. Failing to read the fan mode or a zone is not an
+/// error because not every board supports every command.
+fn probe(session: &Session) -> Result<Probe> {
+    let mut ipmi = Ipmi::new(session)?;
+
+    let fan_mode = match ipmi.get_fan_mode() {
+        Ok(m) => Some(m),
+        Err(e) => {
+            warn!("Failed to get fan mode: {}", e);
+            None
+        }
+    };
+
+    let ipmi_zones = ipmi.probe_zones(MAX_PROBE_ZONES);
+
+    Ok(Probe {
+        fan_mode,
+        ipmi_zones,
+        temp_sensors: sorted(ipmi.get_temperature_readings()?),
+        fan_sensors: sorted(ipmi.get_fan_readings()?),
+        disks: find_disks(),
+    })
+}
+
+/// Quote a string for TOML.
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_owned()).to_string()
+}
+
+/// Format a sensor reading for a comment.
+fn format_reading(reading: &Option<SensorReading>) -> String {
+    match reading {
+        Some(r) => format!("{} {}", r.value, r.units),
+        None => "no reading".to_owned(),
+    }
+}
+
+/// Write the body of a `sources` array. `sources` are written as entries and
+/// `others` as commented out entries. Each item is a source table and a comment.
+fn write_sources(out: &mut String, sources: &[(String, String)], others: &[(String, String)]) {
+    out.push_str("sources = [\n");
+    for (source, comment) in sources {
+        writeln!(out, "    {}, # {}", source, comment).unwrap();
+    }
+    for (source, comment) in others {
+        writeln!(out, "    #{}, # {}", source, comment).unwrap();
+    }
+    out.push_str("]\n");
+}
+
+/// Format the probe results as a starter config. IPMI zone 0 is given the CPU
+/// temperature sensors and the other zones are given the spinning disks, which
+/// matches the usual Supermicro layout of a CPU zone and a peripheral zone.
+/// Every other sensor and disk is listed as a commented out source.
+fn format_config(session_name: &str, probe: &Probe) -> String {
+    let mut out = String::new();
+
+    out.push_str("\
+# Starter config generated by `ipmi-fan-control generate-config`. The sources
+# and fan curves are only a guess based on the sensor names and disk types, so
+# review every zone before using this config. Run `ipmi-fan-control check` to
+# test the config without changing the fans. See config.sample.toml for all of
+# the available options.
+\n");
+
+    match probe.fan_mode {
+        Some(m) => writeln!(out, "# Fan mode when this config was generated: {:?}", m).unwrap(),
+        None => out.push_str("# The fan mode could not be read, so the BMC may not support the default\n\
+                              # `supermicro` protocol. See the `protocol` session option.\n"),
+    }
+
+    if session_name != SessionName::default().0 {
+        writeln!(out, "\n# Copy the {:?} session from the existing config here.", session_name).unwrap();
+        writeln!(out, "#[sessions.{}]", quote(session_name)).unwrap();
+    }
+
+    let ipmi_source = |(name, reading): &(String, Option<SensorReading>)| {
+        (format!("{{ type = \"ipmi\", sensor = {} }}", quote(name)), format_reading(reading))
+    };
+    let disk_source = |disk: &Disk| {
+        let path = quote(&disk.path.to_string_lossy());
+        let kind = if disk.rotational { "HDD" } else { "SSD" };
+        let comment = match &disk.model {
+            Some(m) => format!("{} {}", kind, m),
+            None => kind.to_owned(),
+        };
+        (format!("{{ type = \"smart\", block_dev = {}, optional = true }}", path), comment)
+    };
+
+    let is_cpu = |name: &str| name.to_ascii_uppercase().contains("CPU");
+    let (cpu_sensors, other_sensors): (Vec<_>, Vec<_>) = probe.temp_sensors.iter()
+        .partition(|(name, _)| is_cpu(name));
+    let (hdds, ssds): (Vec<_>, Vec<_>) = probe.disks.iter().partition(|d| d.rotational);
+
+    let fan_sensors = probe.fan_sensors.iter()
+        .filter(|(_, r)| r.is_some())
+        .map(|(name, _)| quote(name))
+        .collect::<Vec<_>>();
+
+    if probe.ipmi_zones.is_empty() {
+        out.push_str("\n# No IPMI zones responded to duty cycle reads. If the BMC can't control the\n\
+                      # fans, use `output = { type = \"hwmon\", ... }` instead of `ipmi_zones`.\n");
+    }
+
+    for (i, (zone, dcycle)) in probe.ipmi_zones.iter().enumerate() {
+        // CPU sensors go in the first zone and disks in the others. If there
+        // are no disks, the remaining IPMI sensors are used instead.
+        let (sources, others, steps) = if i == 0 {
+            let (sources, others) = if cpu_sensors.is_empty() {
+                (&other_sensors, &cpu_sensors)
+            } else {
+                (&cpu_sensors, &other_sensors)
+            };
+            let others = others.iter().copied().map(ipmi_source)
+                .chain(probe.disks.iter().map(disk_source))
+                .collect::<Vec<_>>();
+
+            (sources.iter().copied().map(ipmi_source).collect::<Vec<_>>(), others, CPU_STEPS)
+        } else if !hdds.is_empty() {
+            let others = ssds.iter().copied().map(disk_source)
+                .chain(probe.temp_sensors.iter().map(ipmi_source))
+                .collect::<Vec<_>>();
+
+            (hdds.iter().copied().map(disk_source).collect(), others, DISK_STEPS)
+        } else {
+            let others = cpu_sensors.iter().copied().map(ipmi_source)
+                .chain(probe.disks.iter().map(disk_source))
+                .collect::<Vec<_>>();
+
+            (other_sensors.iter().copied().map(ipmi_source).collect(), others, CPU_STEPS)
+        };
+
+        out.push('\n');
+        writeln!(out, "# IPMI zone {} was at {}% when this config was generated.", zone, dcycle).unwrap();
+
+        // Zones without sources are invalid, so leave them commented out
+        let prefix = if sources.is_empty() {
+            out.push_str("# No temperature sources were found for this zone.\n");
+            "#"
+        } else {
+            ""
+        };
+
+        let mut zone_out = String::new();
+        zone_out.push_str("[[zones]]\n");
+        writeln!(zone_out, "name = {}", quote(&format!("zone{}", zone))).unwrap();
+        if session_name != SessionName::default().0 {
+            writeln!(zone_out, "session = {}", quote(session_name)).unwrap();
+        }
+        writeln!(zone_out, "ipmi_zones = [{}]", zone).unwrap();
+        if !fan_sensors.is_empty() {
+            writeln!(zone_out, "# Fans to monitor for stalls. Only keep the fans in this zone.").unwrap();
+            writeln!(zone_out, "#fan_sensors = [{}]", fan_sensors.join(", ")).unwrap();
+        }
+        zone_out.push_str("interval = 5\n");
+        write_sources(&mut zone_out, &sources, &others);
+        zone_out.push_str(steps);
+
+        for line in zone_out.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                writeln!(out, "{}", line).unwrap();
+            } else {
+                writeln!(out, "{}{}", prefix, line).unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+/// Conservative curve for CPU and system temperature sensors.
+const CPU_STEPS: &str = "\
+steps = [
+    { temp = 40, dcycle = 20 },
+    { temp = 60, dcycle = 40 },
+    { temp = 75, dcycle = 80 },
+    { temp = 85, dcycle = 100 },
+]
+";
+
+/// Conservative curve for spinning disks, which should stay below 45C.
+const DISK_STEPS: &str = "\
+steps = [
+    { temp = 30, dcycle = 20 },
+    { temp = 40, dcycle = 40 },
+    { temp = 45, dcycle = 70 },
+    { temp = 50, dcycle = 100 },
+]
+";
+
+/// Probe the BMC and the local disks and generate a commented starter config.
+/// Only reads are performed: the fan mode and duty cycles are never changed.
+pub fn generate_config(session_name: &str, session: &Session) -> Result<String> {
+    let probe = probe(session)?;
+
+    Ok(format_config(session_name, &probe))
+}
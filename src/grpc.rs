@@ -0,0 +1,244 @@
+// Every RPC has to return tonic's Status as its error
+#![allow(clippy::result_large_err)]
+
+use {
+    std::sync::Arc,
+    tokio::task::JoinHandle,
+    crate::{control::ControlState, error::Result, influx::Sink},
+};
+#[cfg(feature = "grpc")]
+use {
+    std::time::UNIX_EPOCH,
+    log::{debug, error},
+    tokio::sync::{broadcast::error::RecvError, mpsc},
+    tokio_stream::wrappers::{ReceiverStream, TcpListenerStream},
+    tonic::{metadata::MetadataMap, transport::Server, Status},
+    crate::{
+        control::{self, Request, Response, ZoneRef},
+        influx::Tick,
+        web,
+    },
+    proto::{
+        fan_control_server::{FanControl, FanControlServer},
+        Empty, GetStatusRequest, GetStatusResponse, SetOverrideRequest, SourceReading,
+        StreamTicksRequest, ZoneRequest, ZoneStatus,
+    },
+};
+#[cfg(not(feature = "grpc"))]
+use crate::error::Error;
+
+#[cfg(feature = "grpc")]
+mod proto {
+    tonic::include_proto!("ipmi_fan_control.v1");
+}
+
+#[cfg(feature = "grpc")]
+struct Service {
+    state: Arc<ControlState>,
+    sink: Arc<Sink>,
+    token: Option<String>,
+}
+
+#[cfg(feature = "grpc")]
+impl Service {
+    /// Check the bearer token in the request metadata. If no token is
+    /// configured, only calls that don't change anything are allowed.
+    fn authorize(&self, metadata: &MetadataMap, changes: bool) -> Result<(), Status> {
+        let authorization = metadata.get("authorization").and_then(|v| v.to_str().ok());
+
+        match &self.token {
+            Some(t) if !web::authorized(authorization, t) => {
+                Err(Status::unauthenticated("Missing or invalid bearer token"))
+            }
+            None if changes => {
+                Err(Status::permission_denied("grpc_token must be set to change zones"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parse a zone reference and make sure that the zone exists.
+    fn zone(&self, zone: &str) -> Result<ZoneRef, Status> {
+        let zone = ZoneRef::parse(zone);
+
+        match self.state.find_zone(&zone) {
+            Some(_) => Ok(zone),
+            None => Err(Status::not_found(format!("Zone not found: {:?}", zone))),
+        }
+    }
+
+    /// Handle a request that changes a zone or reloads the config.
+    fn control(&self, metadata: &MetadataMap, request: impl FnOnce() -> Result<Request, Status>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        self.authorize(metadata, true)?;
+
+        match control::handle_request(request()?, &self.state) {
+            Response::Error { message } => Err(Status::invalid_argument(message)),
+            _ => Ok(tonic::Response::new(Empty {})),
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn zone_status(status: control::ZoneStatus) -> ZoneStatus {
+    ZoneStatus {
+        index: status.index as u32,
+        name: status.name,
+        session: status.session,
+        ipmi_zones: status.ipmi_zones.into_iter().map(u32::from).collect(),
+        pwm: status.pwm,
+        temp: status.temp,
+        dcycle: status.dcycle.map(u32::from),
+        last_update: status.last_update,
+        last_error: status.last_error,
+        failsafe: status.failsafe,
+        emergency: status.emergency,
+        stalled_fans: status.stalled_fans,
+        override_dcycle: status.override_dcycle.map(u32::from),
+        override_until: status.override_until,
+        file_override_dcycle: status.file_override_dcycle.map(u32::from),
+        paused: status.paused,
+        stopped: status.stopped,
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn tick_message(tick: Tick) -> proto::Tick {
+    proto::Tick {
+        zone: tick.zone,
+        time: tick.time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+        temp: tick.temp,
+        dcycle: tick.dcycle.map(u32::from),
+        failsafe: tick.failsafe,
+        emergency: tick.emergency,
+        error: tick.error,
+        sources: tick.sources
+            .into_iter()
+            .map(|(source, temp)| SourceReading { source, temp })
+            .collect(),
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[tonic::async_trait]
+impl FanControl for Service {
+    type StreamTicksStream = ReceiverStream<Result<proto::Tick, Status>>;
+
+    async fn get_status(&self, request: tonic::Request<GetStatusRequest>)
+        -> Result<tonic::Response<GetStatusResponse>, Status>
+    {
+        self.authorize(request.metadata(), false)?;
+
+        let zones = self.state.zone_statuses().into_iter().map(zone_status).collect();
+
+        Ok(tonic::Response::new(GetStatusResponse { zones }))
+    }
+
+    /// Forward every zone loop iteration to the client until it disconnects.
+    /// Ticks are skipped if the client can't keep up.
+    async fn stream_ticks(&self, request: tonic::Request<StreamTicksRequest>)
+        -> Result<tonic::Response<Self::StreamTicksStream>, Status>
+    {
+        self.authorize(request.metadata(), false)?;
+
+        let mut receiver = self.sink.subscribe();
+        let (sender, stream) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                let tick = match receiver.recv().await {
+                    Ok(t) => t,
+                    Err(RecvError::Lagged(n)) => {
+                        debug!("gRPC stream client skipped {} ticks", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if sender.send(Ok(tick_message(tick))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(stream)))
+    }
+
+    async fn set_override(&self, request: tonic::Request<SetOverrideRequest>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        let message = request.get_ref();
+
+        self.control(request.metadata(), || Ok(Request::SetOverride {
+            zone: self.zone(&message.zone)?,
+            dcycle: message.dcycle.try_into()
+                .map_err(|_| Status::invalid_argument(format!("Invalid percentage: {}", message.dcycle)))?,
+            duration_secs: message.duration_secs,
+        }))
+    }
+
+    async fn clear_override(&self, request: tonic::Request<ZoneRequest>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        self.control(request.metadata(), || Ok(Request::ClearOverride {
+            zone: self.zone(&request.get_ref().zone)?,
+        }))
+    }
+
+    async fn pause(&self, request: tonic::Request<ZoneRequest>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        self.control(request.metadata(), || Ok(Request::Pause {
+            zone: self.zone(&request.get_ref().zone)?,
+        }))
+    }
+
+    async fn resume(&self, request: tonic::Request<ZoneRequest>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        self.control(request.metadata(), || Ok(Request::Resume {
+            zone: self.zone(&request.get_ref().zone)?,
+        }))
+    }
+
+    async fn reload(&self, request: tonic::Request<Empty>)
+        -> Result<tonic::Response<Empty>, Status>
+    {
+        self.control(request.metadata(), || Ok(Request::Reload))
+    }
+}
+
+/// Start the gRPC server in proto/ipmi_fan_control.proto. The listener is
+/// created before returning so that errors are reported immediately.
+#[cfg(feature = "grpc")]
+pub fn start(
+    address: &str,
+    state: Arc<ControlState>,
+    sink: Arc<Sink>,
+    token: Option<String>,
+) -> Result<JoinHandle<()>> {
+    let listener = web::bind(address)?;
+    let service = Service { state, sink, token };
+
+    Ok(tokio::spawn(async move {
+        let result = Server::builder()
+            .add_service(FanControlServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await;
+
+        if let Err(e) = result {
+            error!("gRPC server failed: {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "grpc"))]
+pub fn start(
+    _address: &str,
+    _state: Arc<ControlState>,
+    _sink: Arc<Sink>,
+    _token: Option<String>,
+) -> Result<JoinHandle<()>> {
+    Err(Error::GrpcDisabled)
+}
@@ -0,0 +1,233 @@
+use {
+    std::{
+        fmt::Write as _,
+        fs::{self, OpenOptions},
+        io::{self, Write as _},
+        path::{Path, PathBuf},
+        process::Stdio,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    log::{trace, warn},
+    tokio::{io::AsyncWriteExt, process::Command, sync::mpsc::UnboundedReceiver},
+    crate::{
+        config::{History, HistoryFormat},
+        error::{Error, Result},
+        influx::Tick,
+        tools,
+    },
+};
+
+/// Header of the CSV file. Each tick is written as one row for the zone and
+/// one row for each source reading, which has the `source` column set.
+const CSV_HEADER: &str = "time,zone,source,temp,dcycle,failsafe,emergency,error\n";
+
+/// Schema of the SQLite database, which has the same columns as the CSV file.
+const SQLITE_SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS history (
+    time REAL NOT NULL,
+    zone TEXT NOT NULL,
+    source TEXT,
+    temp REAL,
+    dcycle INTEGER,
+    failsafe INTEGER,
+    emergency INTEGER,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS history_time ON history (time);
+";
+
+/// Get a tick's time as fractional seconds since the Unix epoch.
+fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or line break.
+fn quote_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Quote an SQL string literal.
+fn quote_sql(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Append the CSV rows for a tick to `out`.
+fn write_csv_tick(out: &mut String, tick: &Tick) {
+    let time = timestamp(tick.time);
+    let zone = quote_csv(&tick.zone);
+    let temp = tick.temp.filter(|t| t.is_finite()).map(|t| t.to_string()).unwrap_or_default();
+    let dcycle = tick.dcycle.map(|d| d.to_string()).unwrap_or_default();
+    let error = tick.error.as_deref().map(quote_csv).unwrap_or_default();
+
+    let _ = writeln!(out, "{:.3},{},,{},{},{},{},{}",
+                     time, zone, temp, dcycle, tick.failsafe, tick.emergency, error);
+
+    for (source, reading) in &tick.sources {
+        if let Some(temp) = reading.filter(|t| t.is_finite()) {
+            let _ = writeln!(out, "{:.3},{},{},{},,,,", time, zone, quote_csv(source), temp);
+        }
+    }
+}
+
+/// Append the SQL insert statements for a tick to `out`.
+fn write_sql_tick(out: &mut String, tick: &Tick) {
+    let time = timestamp(tick.time);
+    let zone = quote_sql(&tick.zone);
+    let temp = tick.temp.filter(|t| t.is_finite()).map_or_else(|| "NULL".to_owned(), |t| t.to_string());
+    let dcycle = tick.dcycle.map_or_else(|| "NULL".to_owned(), |d| d.to_string());
+    let error = tick.error.as_deref().map_or_else(|| "NULL".to_owned(), quote_sql);
+
+    let _ = writeln!(out, "INSERT INTO history VALUES ({:.3}, {}, NULL, {}, {}, {}, {}, {});",
+                     time, zone, temp, dcycle, u8::from(tick.failsafe), u8::from(tick.emergency), error);
+
+    for (source, reading) in &tick.sources {
+        if let Some(temp) = reading.filter(|t| t.is_finite()) {
+            let _ = writeln!(out, "INSERT INTO history (time, zone, source, temp) VALUES ({:.3}, {}, {}, {});",
+                             time, zone, quote_sql(source), temp);
+        }
+    }
+}
+
+/// Get the path of the `n`th rotated CSV file.
+fn rotated_path(path: &str, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path, n))
+}
+
+/// Rename the CSV file to `<path>.1`, shifting the previously rotated files
+/// up by one. The oldest file is overwritten once there are `max_files`.
+fn rotate_csv(config: &History) -> Result<()> {
+    for n in (1..=config.max_files.0).rev() {
+        let from = if n == 1 {
+            PathBuf::from(&config.path)
+        } else {
+            rotated_path(&config.path, n - 1)
+        };
+
+        match fs::rename(&from, rotated_path(&config.path, n)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io { path: from, source: e }),
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete rotated CSV files that were last written to before the retention
+/// period.
+fn delete_expired_csv(config: &History) -> Result<()> {
+    let retention = match config.retention {
+        Some(r) => r.to_duration(),
+        None => return Ok(()),
+    };
+
+    for n in 1..=config.max_files.0 {
+        let path = rotated_path(&config.path, n);
+
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::Io { path, source: e }),
+        };
+
+        if modified.elapsed().is_ok_and(|age| age > retention) {
+            trace!("Deleting expired history file: {:?}", path);
+            fs::remove_file(&path).map_err(|e| Error::Io { path, source: e })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Append rows to the CSV file, writing the header first if the file is new.
+/// The file is rotated once it reaches the maximum size.
+fn write_csv(config: &History, rows: &str) -> Result<()> {
+    let path = Path::new(&config.path);
+    let io_error = |e| Error::Io { path: path.to_owned(), source: e };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(io_error)?;
+
+    if file.metadata().map_err(io_error)?.len() == 0 {
+        file.write_all(CSV_HEADER.as_bytes()).map_err(io_error)?;
+    }
+    file.write_all(rows.as_bytes()).map_err(io_error)?;
+
+    if file.metadata().map_err(io_error)?.len() >= config.max_size.0 * 1024 * 1024 {
+        rotate_csv(config)?;
+    }
+
+    delete_expired_csv(config)
+}
+
+/// Run the statements with `sqlite3` in a single transaction that also deletes
+/// the rows that are older than the retention period.
+async fn write_sqlite(config: &History, statements: &str) -> Result<()> {
+    let sqlite3 = tools::get().sqlite3.clone();
+    let io_error = |e| Error::Io { path: "(sqlite3)".into(), source: e };
+
+    let mut script = format!("{}BEGIN;\n{}", SQLITE_SCHEMA, statements);
+    if let Some(retention) = config.retention {
+        let cutoff = timestamp(SystemTime::now()) - retention.to_duration().as_secs_f64();
+        let _ = writeln!(script, "DELETE FROM history WHERE time < {:.3};", cutoff);
+    }
+    script.push_str("COMMIT;\n");
+
+    let mut child = Command::new(&sqlite3)
+        .arg("-batch")
+        .arg("-bail")
+        .arg(&config.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(io_error)?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(script.as_bytes()).await.map_err(io_error)?;
+    drop(stdin);
+
+    let status = child.wait().await.map_err(io_error)?;
+    if !status.success() {
+        return Err(Error::Command { command: sqlite3.into(), status });
+    }
+
+    Ok(())
+}
+
+/// Record ticks as they arrive. Ticks that arrive while a write is in progress
+/// are batched into the next write. Failed writes are logged and their data is
+/// discarded.
+pub async fn serve(config: History, mut receiver: UnboundedReceiver<Tick>) {
+    let write_tick = match config.format {
+        HistoryFormat::Csv => write_csv_tick,
+        HistoryFormat::Sqlite => write_sql_tick,
+    };
+
+    while let Some(tick) = receiver.recv().await {
+        let mut batch = String::new();
+        write_tick(&mut batch, &tick);
+
+        while let Ok(tick) = receiver.try_recv() {
+            write_tick(&mut batch, &tick);
+        }
+
+        trace!("Recording history: {:?}", batch);
+
+        let result = match config.format {
+            HistoryFormat::Csv => write_csv(&config, &batch),
+            HistoryFormat::Sqlite => write_sqlite(&config, &batch).await,
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to record history: {}", e);
+        }
+    }
+}
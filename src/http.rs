@@ -0,0 +1,65 @@
+use {
+    std::{
+        fmt::Write as _,
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::timeout,
+    },
+    crate::{
+        config::HttpUrl,
+        error::{Error, Result},
+    },
+};
+
+/// Maximum time for connecting, sending the request, and receiving the
+/// response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send a POST request with a plain HTTP/1.1 connection. The request fails if
+/// the response status is not 2xx.
+pub async fn post(
+    url: &HttpUrl,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<()> {
+    let io_error = |e| Error::Io { path: "(http)".into(), source: e };
+
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let host_header = if url.port == 80 {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path, host_header, content_type, body.len());
+    for (name, value) in headers {
+        let _ = write!(request, "{}: {}\r\n", name, value);
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let response = timeout(REQUEST_TIMEOUT, async {
+        let mut stream = TcpStream::connect((host, url.port)).await.map_err(io_error)?;
+        stream.write_all(request.as_bytes()).await.map_err(io_error)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.map_err(io_error)?;
+
+        Ok::<_, Error>(response)
+    }).await.map_err(|_| Error::HttpRequest("Request timed out".to_owned()))??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, content) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status_line = head.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(Error::HttpRequest(format!("{}: {}", status_line, content.trim()))),
+    }
+}
@@ -0,0 +1,159 @@
+use {
+    std::{
+        collections::HashMap,
+        fmt::Write as _,
+        sync::Mutex,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    log::{trace, warn},
+    tokio::sync::{
+        broadcast,
+        mpsc::{UnboundedReceiver, UnboundedSender},
+    },
+    crate::{config::InfluxDb, http},
+};
+
+/// Measurements from one iteration of a zone loop.
+#[derive(Clone, Debug)]
+pub struct Tick {
+    pub zone: String,
+    pub time: SystemTime,
+    pub temp: Option<f32>,
+    pub dcycle: Option<u8>,
+    pub failsafe: bool,
+    pub emergency: bool,
+    pub error: Option<String>,
+    /// Source names paired with their readings
+    pub sources: Vec<(String, Option<f32>)>,
+}
+
+/// Consumer of the ticks sent to a [`Sink`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Exporter {
+    InfluxDb,
+    History,
+    Textfile,
+}
+
+/// Number of ticks that a slow stream subscriber can fall behind by before it
+/// misses ticks.
+const STREAM_CAPACITY: usize = 256;
+
+/// Destination for ticks, shared by all zone loops. Each tick is sent to every
+/// enabled exporter and every stream subscriber, and is discarded if there are
+/// none.
+pub struct Sink {
+    senders: Mutex<HashMap<Exporter, UnboundedSender<Tick>>>,
+    stream: broadcast::Sender<Tick>,
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Self {
+            senders: Mutex::default(),
+            stream: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+}
+
+impl Sink {
+    /// Replace the channel that an exporter receives ticks from.
+    pub fn set_sender(&self, exporter: Exporter, sender: Option<UnboundedSender<Tick>>) {
+        let mut senders = self.senders.lock().unwrap();
+
+        match sender {
+            Some(s) => senders.insert(exporter, s),
+            None => senders.remove(&exporter),
+        };
+    }
+
+    /// Receive every tick sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Tick> {
+        self.stream.subscribe()
+    }
+
+    pub fn send(&self, tick: Tick) {
+        for sender in self.senders.lock().unwrap().values() {
+            // The receiver only goes away while the exporter is being replaced
+            let _ = sender.send(tick.clone());
+        }
+
+        if self.stream.receiver_count() > 0 {
+            let _ = self.stream.send(tick);
+        }
+    }
+}
+
+/// Escape a measurement name.
+fn escape_measurement(value: &str) -> String {
+    value.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// Escape a tag key or value.
+fn escape_tag(value: &str) -> String {
+    escape_measurement(value).replace('=', "\\=")
+}
+
+/// Escape and quote a string field value.
+fn quote_field(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Append the line protocol representation of a tick to `out`. The zone's
+/// state is written to `measurement` and each source reading is written to
+/// `<measurement>_source`.
+fn write_tick(out: &mut String, measurement: &str, tick: &Tick) {
+    let timestamp = tick.time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let zone = escape_tag(&tick.zone);
+
+    let mut fields = vec![
+        format!("failsafe={}", tick.failsafe),
+        format!("emergency={}", tick.emergency),
+    ];
+    if let Some(temp) = tick.temp.filter(|t| t.is_finite()) {
+        fields.push(format!("temp={}", temp));
+    }
+    if let Some(dcycle) = tick.dcycle {
+        fields.push(format!("dcycle={}i", dcycle));
+    }
+    if let Some(error) = &tick.error {
+        fields.push(format!("error={}", quote_field(error)));
+    }
+
+    let _ = writeln!(out, "{},zone={} {} {}",
+                     escape_measurement(measurement), zone, fields.join(","), timestamp);
+
+    for (source, reading) in &tick.sources {
+        if let Some(temp) = reading.filter(|t| t.is_finite()) {
+            let _ = writeln!(out, "{}_source,zone={},source={} temp={} {}",
+                             escape_measurement(measurement), zone, escape_tag(source),
+                             temp, timestamp);
+        }
+    }
+}
+
+/// Write ticks to InfluxDB as they arrive. Ticks that arrive while a write is
+/// in progress are batched into the next write. Failed writes are logged and
+/// their data is discarded.
+pub async fn serve(config: InfluxDb, mut receiver: UnboundedReceiver<Tick>) {
+    while let Some(tick) = receiver.recv().await {
+        let mut body = String::new();
+        write_tick(&mut body, &config.measurement.0, &tick);
+
+        while let Ok(tick) = receiver.try_recv() {
+            write_tick(&mut body, &config.measurement.0, &tick);
+        }
+
+        trace!("Writing to InfluxDB: {:?}", body);
+
+        let authorization = config.token.as_ref().map(|t| format!("Token {}", t));
+        let headers = authorization.as_deref()
+            .map(|a| vec![("Authorization", a)])
+            .unwrap_or_default();
+
+        if let Err(e) = http::post(&config.url, "text/plain; charset=utf-8", &headers, &body).await {
+            warn!("Failed to write to InfluxDB: {}", e);
+        }
+    }
+}
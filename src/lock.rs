@@ -0,0 +1,56 @@
+use {
+    std::{
+        fs::{self, File, OpenOptions, TryLockError},
+        io::{Read, Write},
+        path::Path,
+        process,
+    },
+    log::debug,
+    crate::error::{Error, Result},
+};
+
+/// Exclusive lock that prevents multiple daemons from controlling the same
+/// fans. The lock is released by the OS when the process exits, so a lock file
+/// left behind by a crashed instance does not need to be cleaned up.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Lock the file at `path` and write the current PID to it. If another
+    /// instance holds the lock, the error includes the PID it wrote.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let io_err = |e| Error::Io { path: path.to_owned(), source: e };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_err)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut contents = String::new();
+                let pid = file.read_to_string(&mut contents).ok()
+                    .and_then(|_| contents.trim().parse().ok());
+
+                return Err(Error::AlreadyRunning { path: path.to_owned(), pid });
+            }
+            Err(TryLockError::Error(e)) => return Err(io_err(e)),
+        }
+
+        file.set_len(0).map_err(io_err)?;
+        writeln!(file, "{}", process::id()).map_err(io_err)?;
+
+        debug!("Acquired instance lock: {:?}", path);
+
+        Ok(Self { _file: file })
+    }
+}
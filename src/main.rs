@@ -1,41 +1,90 @@
-mod bindings;
-mod config;
-mod error;
-mod freeipmi;
-mod source;
-mod ipmi;
+mod alert;
+mod control;
+mod generate;
+mod grpc;
+mod http;
+mod history;
+mod influx;
+mod lock;
+mod privileges;
+mod snmp;
+mod syslog;
+mod systemd;
+mod textfile;
+mod web;
 
 use {
     std::{
-        cmp::Reverse,
-        collections::HashMap,
+        collections::{HashMap, HashSet, VecDeque},
         env,
-        io,
-        path::PathBuf,
+        fs::{self, File},
+        io::{self, BufRead, BufReader},
+        mem,
+        path::{Path, PathBuf},
         process,
+        slice,
         sync::{
             Arc,
             atomic::{AtomicBool, Ordering},
             Mutex,
         },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
         u8,
     },
-    clap::Parser,
-    log::{debug, error, info, trace},
-    retry::retry_with_index,
+    clap::{Parser, Subcommand, ValueEnum},
+    log::{debug, error, info, trace, warn},
     tokio::{
-        task::{self, JoinSet},
-        time::sleep,
+        sync::mpsc,
+        task::{self, AbortHandle, JoinHandle, JoinSet},
+        time::{sleep, timeout},
     },
+    ipmi_fan_control_core::{config, curve, error, hwmon, ipmi, schedule, script, sensor, source, tools},
 
-    config::{Aggregation, Config, load_config, SessionType, Step, Zone},
+    alert::{Alert, Notifier},
+    config::{
+        Aggregation, AlertEvent, Config, ControlSignal, expand_sources, History, InfluxDb, Interval, load_config,
+        Output, parse_duration, schema, Session, SessionFanMode, SessionName, Snmp, Source, SourceType, Textfile,
+        wildcard_match, Zone,
+    },
+    control::{ControlState, Request, Response, ZoneRef, ZoneStatus},
     error::{Error, Result},
-    ipmi::{FanMode, Ipmi},
-    source::get_source_readings,
+    sensor::{format_suggestions, suggest_names, SensorReading, SensorType, SensorUnits},
+    hwmon::PwmFan,
+    influx::{Exporter, Tick},
+    lock::InstanceLock,
+    script::{Script, ScriptInput},
+    ipmi::{FanMode, Ipmi, IpmiWorker},
+    source::{get_cached_source_readings, get_source_readings, SourceCache},
+    syslog::SyslogLogger,
 };
 
 static LOGGING_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// Logger that passes every record to two loggers.
+struct TeeLogger(Box<dyn log::Log>, Box<dyn log::Log>);
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata) || self.1.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.log(record);
+        self.1.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+        self.1.flush();
+    }
+}
+
+/// Maximum duty cycle change per iteration when targeting an RPM
+const RPM_MAX_DCYCLE_STEP: u8 = 10;
+
+/// How long a reload waits for a removed session to hand the fans back
+const SESSION_RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[cfg(unix)]
 async fn interrupted() -> io::Result<()> {
     use tokio::signal::unix::{signal, SignalKind};
@@ -63,313 +112,3335 @@ async fn interrupted() -> io::Result<()> {
     Ok(())
 }
 
+/// Receiver for config reload requests (SIGHUP).
+#[cfg(unix)]
+struct ReloadSignal(tokio::signal::unix::Signal);
+
+#[cfg(unix)]
+impl ReloadSignal {
+    fn new() -> io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(Self(signal(SignalKind::hangup())?))
+    }
+
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
+
+/// There is no reload signal on Windows, so this never receives anything.
+#[cfg(windows)]
+struct ReloadSignal;
+
+#[cfg(windows)]
+impl ReloadSignal {
+    fn new() -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Receiver for the signals that pause and resume every zone.
+#[cfg(unix)]
+struct PauseSignals {
+    pause: Option<tokio::signal::unix::Signal>,
+    resume: Option<tokio::signal::unix::Signal>,
+}
+
+#[cfg(unix)]
+impl PauseSignals {
+    fn new(pause: ControlSignal, resume: ControlSignal) -> io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let register = |s| match s {
+            ControlSignal::Sigusr1 => signal(SignalKind::user_defined1()).map(Some),
+            ControlSignal::Sigusr2 => signal(SignalKind::user_defined2()).map(Some),
+            ControlSignal::None => Ok(None),
+        };
+
+        Ok(Self {
+            pause: register(pause)?,
+            resume: register(resume)?,
+        })
+    }
+
+    /// Wait for a signal. Returns true if the zones should be paused and false
+    /// if they should be resumed.
+    async fn recv(&mut self) -> bool {
+        async fn next(signal: &mut Option<tokio::signal::unix::Signal>) {
+            match signal {
+                Some(s) => { s.recv().await; }
+                None => std::future::pending().await,
+            }
+        }
+
+        tokio::select! {
+            _ = next(&mut self.pause) => true,
+            _ = next(&mut self.resume) => false,
+        }
+    }
+}
+
+/// There are no user-defined signals on Windows, so this never receives
+/// anything.
+#[cfg(windows)]
+struct PauseSignals;
+
+#[cfg(windows)]
+impl PauseSignals {
+    fn new(_pause: ControlSignal, _resume: ControlSignal) -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> bool {
+        std::future::pending().await
+    }
+}
+
 struct IpmiSession {
     /// Session name (for logging only)
     name: String,
-    /// IPMI session
-    ipmi: Arc<Mutex<Ipmi>>,
-    /// Original fan mode
-    orig_fan_mode: FanMode,
-    /// Set these zones to dcycle 100% before restoring original fan mode
-    restore_zones: Vec<u8>,
+    /// Worker that owns the IPMI session
+    ipmi: IpmiWorker,
+    /// Fan mode to set while running or [`None`] to leave it untouched
+    fan_mode: Option<FanMode>,
+    /// Original fan mode or [`None`] if the fan mode was not changed
+    orig_fan_mode: Mutex<Option<FanMode>>,
+    /// Set these zones to the corresponding duty cycles before restoring the
+    /// original fan mode
+    restore_zones: Mutex<HashMap<u8, u8>>,
+    /// Duty cycles read when each zone was first controlled
+    snapshots: Mutex<HashMap<u8, u8>>,
+    /// Minimum time between fan mode checks or zero to disable them
+    fan_mode_check_interval: Duration,
+    /// When the fan mode was last checked
+    fan_mode_checked: Mutex<Instant>,
+    /// Consecutive failed commands before falling back to the BMC's fan
+    /// control or zero if failures are fatal
+    failure_limit: u32,
+    watchdog: Mutex<SessionWatchdog>,
+}
+
+/// Tracks whether the BMC is responding to fan control commands.
+#[derive(Default)]
+struct SessionWatchdog {
+    /// Number of consecutive failed commands
+    failures: u32,
+    /// Whether fan control was handed back to the BMC
+    fallen_back: bool,
 }
 
 impl IpmiSession {
-    pub fn new<N, R>(name: N, st: &SessionType, restore_zones: R) -> Result<Self>
+    /// Open an IPMI session. If there are zones to restore, then the session
+    /// controls the fans and the fan mode is changed to the session's
+    /// configured mode. Otherwise, the session is only used for reading sensors
+    /// and the fan mode is left untouched.
+    ///
+    /// Each zone to restore is paired with the duty cycle to restore or
+    /// [`None`] to restore the duty cycle read before the fan mode is changed.
+    pub fn new<N, R>(name: N, session: &Session, restore_zones: R) -> Result<Self>
     where
         N: AsRef<str>,
-        R: IntoIterator<Item = u8>,
+        R: IntoIterator<Item = (u8, Option<u8>)>,
     {
-        let mut ipmi = Ipmi::new(st)?;
-        let orig_fan_mode = ipmi.get_fan_mode()?;
+        let mut ipmi = Ipmi::new(session)?;
+
+        for threshold in &session.options.fan_thresholds {
+            let (old, new) = ipmi.set_fan_threshold(threshold)?;
+            info!("[{}] Fan sensor {:?} thresholds: {} -> {}",
+                  name.as_ref(), threshold.sensor, old, new);
+        }
 
-        info!("[{}] Original fan mode: {:?}", name.as_ref(), orig_fan_mode);
+        let mut snapshots = HashMap::new();
+        let restore_zones = Self::resolve_restore_zones(
+            name.as_ref(), &mut ipmi, &mut snapshots, restore_zones);
+        let fan_mode = Self::fan_mode(session);
+        let mut orig_fan_mode = None;
 
-        if orig_fan_mode != FanMode::Full {
-            info!("[{}] Setting fan mode to: {:?}", name.as_ref(), FanMode::Full);
-            ipmi.set_fan_mode(FanMode::Full)?;
+        if let Some(target) = fan_mode.filter(|_| !restore_zones.is_empty()) {
+            orig_fan_mode = Some(Self::set_target_fan_mode(name.as_ref(), &mut ipmi, target)?);
         }
 
         Ok(Self {
             name: name.as_ref().to_owned(),
-            ipmi: Arc::new(Mutex::new(ipmi)),
-            orig_fan_mode,
-            restore_zones: restore_zones.into_iter().collect(),
+            ipmi: IpmiWorker::new(name.as_ref(), ipmi),
+            fan_mode,
+            orig_fan_mode: Mutex::new(orig_fan_mode),
+            restore_zones: Mutex::new(restore_zones),
+            snapshots: Mutex::new(snapshots),
+            fan_mode_check_interval: session.options.fan_mode_check_interval.0.to_duration(),
+            fan_mode_checked: Mutex::new(Instant::now()),
+            failure_limit: session.options.ipmi_failure_limit.0,
+            watchdog: Mutex::new(SessionWatchdog::default()),
         })
     }
-}
 
-impl Drop for IpmiSession {
-    fn drop(&mut self) {
-        let mut ipmi_lock = self.ipmi.lock().unwrap();
+    /// Change the fan mode to the session's target mode, if it isn't already
+    /// in that mode, and return the original mode.
+    fn set_target_fan_mode(name: &str, ipmi: &mut Ipmi, target: FanMode) -> Result<FanMode> {
+        let mode = ipmi.get_fan_mode()?;
+
+        info!("[{}] Original fan mode: {:?}", name, mode);
+
+        if mode != target {
+            info!("[{}] Setting fan mode to: {:?}", name, target);
+            ipmi.set_fan_mode(target)?;
+        }
+
+        ipmi.set_reconnect_fan_mode(Some(target));
+
+        Ok(mode)
+    }
+
+    /// Start controlling the fans of a session that was opened without zones
+    /// to restore. This does the same as [`Self::new`] does for its zones.
+    async fn take_control(&self, restore_zones: Vec<(u8, Option<u8>)>) -> Result<()> {
+        if restore_zones.is_empty() {
+            return Ok(());
+        }
+
+        self.set_restore_zones(restore_zones).await;
+
+        if let Some(target) = self.fan_mode {
+            let name = self.name.clone();
+            let mode = self.ipmi.run(move |ipmi| Self::set_target_fan_mode(&name, ipmi, target)).await?;
+            *self.orig_fan_mode.lock().unwrap() = Some(mode);
+        }
+
+        Ok(())
+    }
+
+    /// Record a successful fan control command.
+    fn command_succeeded(&self) {
+        self.watchdog.lock().unwrap().failures = 0;
+    }
+
+    /// Record a failed fan control command. When the failure limit is
+    /// reached, the zones are set to their restore duty cycles and the
+    /// original fan mode is restored so that the BMC's own fan control takes
+    /// over. Returns whether control was handed back by this call.
+    async fn command_failed(&self) -> bool {
+        {
+            let mut watchdog = self.watchdog.lock().unwrap();
+            watchdog.failures += 1;
+
+            if watchdog.fallen_back || watchdog.failures < self.failure_limit {
+                return false;
+            }
+
+            watchdog.fallen_back = true;
+        }
+
+        warn!("[{}] {} consecutive IPMI failures; handing fan control back to the BMC",
+              self.name, self.failure_limit);
+
+        let name = self.name.clone();
+        let restore_zones = self.restore_zones.lock().unwrap().clone();
+        let orig_fan_mode = self.orig_fan_mode.lock().unwrap().filter(|m| Some(*m) != self.fan_mode);
+
+        // This will most likely fail too, but it's the best that can be done
+        self.ipmi.run(move |ipmi| Self::release(&name, ipmi, restore_zones, orig_fan_mode)).await;
+
+        true
+    }
+
+    /// Take back control of the fans if it was handed back to the BMC by
+    /// [`Self::command_failed`].
+    async fn reacquire(&self) -> Result<()> {
+        if !self.watchdog.lock().unwrap().fallen_back {
+            return Ok(());
+        }
+
+        let target = self.orig_fan_mode.lock().unwrap().and(self.fan_mode);
+        if let Some(target) = target {
+            self.ipmi.run(move |ipmi| ipmi.set_fan_mode(target)).await?;
+        }
+
+        info!("[{}] BMC is responding again; taking back fan control", self.name);
+
+        *self.watchdog.lock().unwrap() = SessionWatchdog::default();
+
+        Ok(())
+    }
+
+    /// Set the specified zones to their restore duty cycles and stop restoring
+    /// them when the session is dropped. This is used when a single zone loop
+    /// stops while the others keep running.
+    async fn release_zones(&self, zones: &[u8]) {
+        let name = self.name.clone();
+        let released = {
+            let mut restore_zones = self.restore_zones.lock().unwrap();
+            zones.iter().filter_map(|z| restore_zones.remove_entry(z)).collect()
+        };
+
+        self.ipmi.run(move |ipmi| Self::release(&name, ipmi, released, None)).await;
+    }
+
+    /// Set the zones to their restore duty cycles and restore the original fan
+    /// mode now instead of when the session is dropped. This is used when the
+    /// session is no longer needed, but something may still hold a reference.
+    async fn release_control(&self) {
+        let name = self.name.clone();
+        let restore_zones = mem::take(&mut *self.restore_zones.lock().unwrap());
+        let orig_fan_mode = self.orig_fan_mode.lock().unwrap().take().filter(|m| Some(*m) != self.fan_mode);
+
+        self.ipmi.run(move |ipmi| Self::release(&name, ipmi, restore_zones, orig_fan_mode)).await;
+    }
+
+    /// Set the zones to their restore duty cycles and then restore the
+    /// original fan mode, if there is one.
+    fn release(
+        name: &str,
+        ipmi: &mut Ipmi,
+        restore_zones: HashMap<u8, u8>,
+        orig_fan_mode: Option<FanMode>,
+    ) {
+        for (z, dcycle) in restore_zones {
+            info!("[{}] Setting zone {} duty cycle to {}%", name, z, dcycle);
+            if let Err(e) = ipmi.set_duty_cycle(z, dcycle) {
+                error!("[{}] Failed to set duty cycle: {}", name, e);
+            }
+        }
+
+        if let Some(mode) = orig_fan_mode {
+            info!("[{}] Restoring fan mode to: {:?}", name, mode);
+            if let Err(e) = ipmi.set_fan_mode(mode) {
+                error!("[{}] Failed to restore fan mode: {}", name, e);
+            }
+        }
+    }
+
+    /// Check that the BMC is still in the fan mode that was set at startup
+    /// and re-apply it if not. Supermicro BMCs revert to the optimal mode after
+    /// a cold reset, which would make them fight over the duty cycles. This is
+    /// done at most once per check interval, no matter how many zones use the
+    /// session.
+    async fn verify_fan_mode(&self) {
+        // Only sessions that changed the fan mode need to keep it
+        let target = *self.orig_fan_mode.lock().unwrap();
+        let target = match target.and(self.fan_mode) {
+            Some(m) if !self.fan_mode_check_interval.is_zero() => m,
+            _ => return,
+        };
 
-        for z in &self.restore_zones {
-            info!("[{}] Setting zone {} duty cycle to 100%", self.name, z);
-            if let Err(e) = ipmi_lock.set_duty_cycle(*z, 100) {
-                error!("[{}] Failed to set duty cycle: {}", self.name, e);
+        {
+            let mut checked = self.fan_mode_checked.lock().unwrap();
+            if checked.elapsed() < self.fan_mode_check_interval {
+                return;
             }
+            *checked = Instant::now();
         }
 
-        if self.orig_fan_mode != FanMode::Full {
-            info!("[{}] Restoring fan mode to: {:?}", self.name, self.orig_fan_mode);
-            if let Err(e) = ipmi_lock.set_fan_mode(self.orig_fan_mode) {
-                error!("[{}] Failed to restore fan mode: {}", self.name, e);
+        let name = self.name.clone();
+
+        self.ipmi.run(move |ipmi| {
+            match ipmi.get_fan_mode() {
+                Ok(mode) if mode == target => {
+                    trace!("[{}] Fan mode is still: {:?}", name, mode);
+                }
+                Ok(mode) => {
+                    warn!("[{}] Fan mode changed to {:?} outside of this program; setting it back to: {:?}",
+                          name, mode, target);
+                    if let Err(e) = ipmi.set_fan_mode(target) {
+                        error!("[{}] Failed to set fan mode: {}", name, e);
+                    }
+                }
+                Err(e) => warn!("[{}] Failed to check fan mode: {}", name, e),
             }
+        }).await;
+    }
+
+    /// Get the fan mode that should be set while running or [`None`] if the
+    /// session should not touch the fan mode.
+    fn fan_mode(session: &Session) -> Option<FanMode> {
+        match session.options.fan_mode {
+            SessionFanMode::Full => Some(FanMode::Full),
+            SessionFanMode::HeavyIo => Some(FanMode::HeavyIo),
+            SessionFanMode::None => None,
         }
     }
+
+    /// Compute the duty cycle to restore for each zone. Zones that restore a
+    /// snapshot have their current duty cycle read the first time they are
+    /// seen. If reading the duty cycle fails, 100% is restored instead.
+    fn resolve_restore_zones<R>(
+        name: &str,
+        ipmi: &mut Ipmi,
+        snapshots: &mut HashMap<u8, u8>,
+        restore_zones: R,
+    ) -> HashMap<u8, u8>
+    where
+        R: IntoIterator<Item = (u8, Option<u8>)>,
+    {
+        restore_zones
+            .into_iter()
+            .map(|(z, dcycle)| {
+                // Every zone is read, even if it doesn't restore the snapshot,
+                // to catch zones that don't exist on the board
+                let snapshot = *snapshots.entry(z).or_insert_with(|| {
+                    match ipmi.get_duty_cycle(z) {
+                        Ok(d) => {
+                            info!("[{}] Zone {} startup duty cycle: {}%", name, z, d);
+                            d
+                        }
+                        Err(ipmi::Error::DutyCycleUnknown(_)) => 100,
+                        Err(e) => {
+                            error!("[{}] Failed to read zone {} duty cycle: {}; the zone may not exist (run `ipmi-fan-control probe-zones` to list the zones)",
+                                   name, z, e);
+                            100
+                        }
+                    }
+                });
+
+                (z, dcycle.unwrap_or(snapshot))
+            })
+            .collect()
+    }
+
+    /// Replace the set of zones that are restored when the session is dropped.
+    /// Zones that are no longer in the set are released immediately by setting
+    /// them to their restore duty cycle.
+    async fn set_restore_zones(&self, restore_zones: Vec<(u8, Option<u8>)>) {
+        let name = self.name.clone();
+        let old_zones = mem::take(&mut *self.restore_zones.lock().unwrap());
+        let mut snapshots = mem::take(&mut *self.snapshots.lock().unwrap());
+
+        let (new_zones, snapshots) = self.ipmi.run(move |ipmi| {
+            let new_zones = Self::resolve_restore_zones(
+                &name, ipmi, &mut snapshots, restore_zones);
+
+            for (z, dcycle) in old_zones.iter().filter(|(z, _)| !new_zones.contains_key(z)) {
+                info!("[{}] Setting zone {} duty cycle to {}%", name, z, dcycle);
+                if let Err(e) = ipmi.set_duty_cycle(*z, *dcycle) {
+                    error!("[{}] Failed to set duty cycle: {}", name, e);
+                }
+            }
+
+            (new_zones, snapshots)
+        }).await;
+
+        *self.restore_zones.lock().unwrap() = new_zones;
+        *self.snapshots.lock().unwrap() = snapshots;
+    }
+}
+
+impl Drop for IpmiSession {
+    fn drop(&mut self) {
+        let name = self.name.clone();
+        let restore_zones = mem::take(self.restore_zones.get_mut().unwrap());
+        let orig_fan_mode = self.orig_fan_mode.get_mut().unwrap().filter(|m| Some(*m) != self.fan_mode);
+
+        self.ipmi.run_blocking(move |ipmi| {
+            Self::release(&name, ipmi, restore_zones, orig_fan_mode);
+        });
+    }
+}
+
+/// State carried between iterations of a zone loop.
+#[derive(Default)]
+struct ZoneState {
+    /// Number of consecutive iterations where reading the sources failed
+    failures: u32,
+    /// Whether the emergency temperature is currently exceeded
+    emergency: bool,
+    critical: CriticalState,
+    /// Fan sensors that were stalled during the last iteration
+    stalled_fans: Vec<String>,
+    /// RPM readings of the fan sensors from the last iteration or [`None`] if
+    /// they could not be read
+    fan_rpms: Option<Vec<f64>>,
+    /// Last duty cycle that was applied
+    dcycle: Option<u8>,
+    /// Cached source readings for sources with a poll interval
+    sources: SourceCache,
+    /// Alerts raised during the current iteration
+    alerts: Vec<Alert>,
+    /// Duty cycle override set via the control socket or the override file
+    override_dcycle: Option<u8>,
+    /// Duty cycle from the override file during the last iteration
+    file_override: Option<u8>,
+    /// Temperature (in tenths of a degree) and duty cycle from the last status
+    /// message and when it was logged
+    last_status: Option<(i32, u8, Instant)>,
+    /// Index of the active schedule and the zone config with the schedule
+    /// applied
+    schedule: Option<(usize, Arc<Zone>)>,
+    /// Aggregated temperatures within the trend boost window and when they
+    /// were read
+    temp_history: VecDeque<(Instant, f32)>,
+    /// Rate of temperature change in degrees Celsius per minute or [`None`]
+    /// if there aren't enough readings yet
+    temp_trend: Option<f32>,
+    /// Percentage points added to the steps' duty cycle
+    trend_boost: u8,
+    /// Changes of the steps' duty cycle within the oscillation window, as
+    /// when they happened and the previous and new duty cycles
+    dcycle_changes: VecDeque<(Instant, u8, u8)>,
+    /// Decreases smaller than this are ignored once oscillation is detected
+    damping_band: Option<u8>,
+    /// Duty cycle that oscillation damping kept the zone from decreasing to
+    /// during the current iteration
+    damped_dcycle: Option<u8>,
+    /// Compiled `script` of the zone
+    script: Option<Script>,
+    /// Label, last temperature, and last duty cycle of the other zones for the
+    /// script
+    other_zones: Vec<(String, Option<f32>, Option<u8>)>,
+    /// Duty cycle returned by the script during the current iteration
+    script_dcycle: Option<u8>,
+}
+
+/// Tracks how long a zone's sources have exceeded the critical temperature.
+#[derive(Default)]
+struct CriticalState {
+    /// When the critical temperature was first exceeded
+    since: Option<Instant>,
+    /// Whether the critical command already ran for the current excursion
+    triggered: bool,
+}
+
+/// Zone loop that is currently running.
+struct RunningZone {
+    /// Config that the loop was spawned with
+    config: Arc<Zone>,
+    /// Handle for stopping the loop
+    abort_handle: AbortHandle,
+    /// Status reported via the control socket
+    status: Arc<Mutex<ZoneStatus>>,
 }
 
 struct MainApp {
+    config_path: PathBuf,
     config: Config,
     sessions: HashMap<String, Arc<IpmiSession>>,
+    loops: JoinSet<Result<()>>,
+    running_zones: Vec<RunningZone>,
+    control_state: Arc<ControlState>,
+    /// Socket path and task for the control socket server, if enabled
+    control_server: Option<(PathBuf, JoinHandle<()>)>,
+    tick_sink: Arc<influx::Sink>,
+    /// Config and task for the InfluxDB exporter, if enabled
+    influx_exporter: Option<(InfluxDb, JoinHandle<()>)>,
+    /// Config and task for the history recorder, if enabled
+    history_recorder: Option<(History, JoinHandle<()>)>,
+    /// Config and task for the textfile collector exporter, if enabled
+    textfile_exporter: Option<(Textfile, JoinHandle<()>)>,
+    /// Config and task for the SNMP AgentX subagent, if enabled
+    snmp_agent: Option<(Snmp, JoinHandle<()>)>,
+    alert_notifier: Arc<Notifier>,
+    /// Whether a failed zone loop leaves the other loops running
+    isolate_failures: Arc<AtomicBool>,
+    /// Listen address, API token, and task for the HTTP server, if enabled
+    web_server: Option<(String, Option<String>, JoinHandle<()>)>,
+    /// Listen address, API token, and task for the gRPC server, if enabled
+    grpc_server: Option<(String, Option<String>, JoinHandle<()>)>,
+    /// Task that checks `disks` and `zpool` sources for added or removed
+    /// disks, if enabled
+    disk_rescanner: Option<JoinHandle<()>>,
 }
 
 impl MainApp {
-    fn new(config: Config) -> Result<Self> {
+    fn new(config_path: PathBuf, config: Config) -> Result<Self> {
         let mut sessions = HashMap::new();
 
-        for (name, st) in &config.sessions.0 {
-            let restore_zones: Vec<_> = config.zones
-                .iter()
-                .filter(|z| &z.session.0 == name)
-                .flat_map(|z| &z.ipmi_zones)
-                .copied()
-                .collect();
-
+        for (name, session) in &config.sessions.0 {
             // Don't waste resources if nothing would use the session
-            if restore_zones.is_empty() {
+            if !Self::session_used(&config, name) {
                 continue;
             }
 
-            sessions.insert(name.clone(), Arc::new(
-                IpmiSession::new(name, &st.0, restore_zones)?));
+            sessions.insert(name.clone(), Arc::new(IpmiSession::new(
+                name, session, Self::session_ipmi_zones(&config, name))?));
         }
 
+        let isolate_failures = Arc::new(AtomicBool::new(config.isolate_zone_failures));
+
         Ok(Self {
+            config_path,
             config,
             sessions,
+            loops: JoinSet::new(),
+            running_zones: vec![],
+            control_state: Arc::new(ControlState::default()),
+            control_server: None,
+            tick_sink: Arc::new(influx::Sink::default()),
+            influx_exporter: None,
+            history_recorder: None,
+            textfile_exporter: None,
+            snmp_agent: None,
+            alert_notifier: Arc::new(Notifier::default()),
+            isolate_failures,
+            web_server: None,
+            grpc_server: None,
+            disk_rescanner: None,
         })
     }
 
-    /// Run asynchronous loops for each zone. Returns when interrupted via
-    /// signal handlers (eg. ^C) or if a fatal error occurs.
-    async fn run(&mut self) -> Result<()> {
-        let mut loops = JoinSet::new();
+    /// Check if any zone uses the specified session for its output or sources.
+    fn session_used(config: &Config, name: &str) -> bool {
+        config.zones
+            .iter()
+            .any(|z| z.session.0 == name && z.uses_ipmi())
+    }
 
-        for zone_config in &self.config.zones {
-            loops.spawn(Self::zone_loop(
-                self.sessions.get_mut(&zone_config.session.0).unwrap().clone(),
-                // Cloned since there's no structured concurrency support yet
-                Arc::new(zone_config.clone()),
-            ));
-        }
+    /// Get the list of IPMI zones controlled by the specified session, paired
+    /// with the duty cycle to restore on exit (see [`Zone::restore_dcycle`]).
+    fn session_ipmi_zones(config: &Config, name: &str) -> Vec<(u8, Option<u8>)> {
+        config.zones
+            .iter()
+            .filter(|z| z.session.0 == name)
+            .flat_map(|z| z.ipmi_zones.iter().map(|i| (*i, z.restore_dcycle())))
+            .collect()
+    }
 
-        let mut first_result = None;
+    /// Spawn the loop for a zone.
+    fn spawn_zone(&mut self, index: usize, zone_config: Arc<Zone>) {
+        let status = Arc::new(Mutex::new(ZoneStatus::new(index, &zone_config)));
+        let session = if zone_config.uses_ipmi() {
+            Some(self.sessions[&zone_config.session.0].clone())
+        } else {
+            None
+        };
+        // Zones are staggered by their position in the config so that they
+        // don't all query the BMC at the same time
+        let start_delay = self.config.zone_start_stagger
+            .map_or(Duration::ZERO, |s| s.to_duration() * index as u32);
+        let abort_handle = self.loops.spawn(Self::zone_loop(
+            start_delay,
+            session,
+            zone_config.clone(),
+            status.clone(),
+            self.tick_sink.clone(),
+            self.alert_notifier.clone(),
+            self.isolate_failures.clone(),
+            self.control_state.clone(),
+        ));
 
-        loop {
-            let ret: Result<()> = tokio::select! {
-                // Explicitly interrupted by ^C or signal handler
-                c = interrupted() => {
-                    if c.is_ok() {
-                        info!("Interrupted");
-                    }
-                    c.map_err(|e| Error::Io { path: "(interrupt)".into(), source: e })
-                }
-                // Oh boy, this is an Option<Result<Result<()>, JoinError>>
-                r = loops.join_next() => {
-                    match r {
-                        // No tasks left
-                        None => break,
-                        // The task panicked or was aborted
-                        Some(Err(e)) => {
-                            if e.is_cancelled() {
-                                Ok(())
-                            } else {
-                                Err(e).map_err(Error::LoopPanicked)
-                            }
-                        },
-                        // zone_loop's actual error return value
-                        Some(Ok(r)) => r,
-                    }
-                }
-            };
+        self.running_zones.push(RunningZone {
+            config: zone_config,
+            abort_handle,
+            status,
+        });
+    }
 
-            if first_result.is_none() {
-                first_result = Some(ret);
-            }
+    /// Make the control socket report the currently running zones.
+    fn publish_zones(&self) {
+        self.control_state.set_zones(
+            self.running_zones.iter().map(|r| r.status.clone()).collect());
+    }
 
-            // If tokio::select returned, then a loop exited or the program was
-            // explicitly interrupted. Interrupt all remaining tasks and the
-            // loop will exit once the FuturesUnordered is empty. This mechanism
-            // is necessary because Tokio's JoinHandles do not cancel tasks when
-            // they are dropped. Without the explicit aborts and joins, the
-            // IpmiSession destructors might not run since the tasks would keep
-            // the Arcs alive.
-            loops.abort_all();
+    /// Start or restart the control socket server if the socket path in the
+    /// config changed.
+    fn update_control_server(&mut self) -> Result<()> {
+        let path = self.config.control_socket.as_ref().map(PathBuf::from);
+
+        if self.control_server.as_ref().map(|(p, _)| p) == path.as_ref() {
+            return Ok(());
         }
 
-        first_result.unwrap_or(Ok(()))
-    }
+        self.stop_control_server();
 
-    /// Main loop for a zone. The loop runs forever while the future is being
-    /// polled.
-    ///
-    /// All communication with the IPMI is behind a mutex to avoid needing
-    /// multiple IPMI sessions.
-    async fn zone_loop(
-        session: Arc<IpmiSession>,
-        zone_config: Arc<Zone>,
-    ) -> Result<()> {
-        info!("[{}] Starting loop for IPMI zones {:?}",
-              session.name, zone_config.ipmi_zones);
+        if let Some(path) = path {
+            info!("Listening on control socket: {:?}", path);
 
-        loop {
-            let s = session.clone();
-            let z = zone_config.clone();
+            let listener = control::bind(&path)?;
+            let task = tokio::spawn(control::serve(listener, self.control_state.clone()));
+
+            self.control_server = Some((path, task));
+        }
+
+        Ok(())
+    }
 
-            task::block_in_place(move || {
-                Self::update_duty_cycle(s, z.as_ref())
-            })?;
+    /// Stop the control socket server and remove the socket, if running.
+    fn stop_control_server(&mut self) {
+        if let Some((path, task)) = self.control_server.take() {
+            task.abort();
 
-            sleep(zone_config.interval.to_duration()).await;
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove control socket: {:?}: {}", path, e);
+            }
         }
     }
 
-    /// Update fan PWM duty cycle based on the CPU temperature
-    fn update_duty_cycle(session: Arc<IpmiSession>, zone_config: &Zone) -> Result<()> {
-        let temp = Self::get_temp(session.ipmi.clone(), zone_config)?;
+    /// Start or restart the HTTP server if the listen address or the API token
+    /// in the config changed.
+    fn update_web_server(&mut self) -> Result<()> {
+        let current = self.web_server.as_ref().map(|(a, t, _)| (a, t.as_ref()));
+        if current == self.config.http_listen.as_ref().map(|a| (a, self.config.http_token.as_ref())) {
+            return Ok(());
+        }
 
-        let result = zone_config.steps.binary_search_by(|s| s.temp.cmp(&temp));
-        // Index of first step >= the current temperature (if exists)
-        let above_index = match result {
-            Ok(i) => Some(i),
-            Err(i) if i == zone_config.steps.len() => None,
-            Err(i) => Some(i),
-        };
-        // Index of first step < the current temperature (if exists)
-        let below_index = match above_index {
-            Some(0) => None,
-            Some(i) => Some(i - 1),
-            None => None,
-        };
-        // If step above doesn't exist, use last step's dcycle or 100%
-        let above_step = match above_index {
-            Some(i) => zone_config.steps[i],
-            None => {
-                let dcycle = zone_config.steps.last()
-                    .map_or(100, |s| s.dcycle);
+        self.stop_web_server();
 
-                Step {
-                    temp,
-                    dcycle,
-                }
-            }
-        };
-        // If step below doesn't exist, use same step as step above
-        let below_step = match below_index {
-            Some(i) => zone_config.steps[i],
-            None => above_step,
-        };
+        if let Some(address) = &self.config.http_listen {
+            info!("Listening for HTTP connections: {}", address);
 
-        let dcycle_new = if below_step.temp == above_step.temp {
-            below_step.dcycle
-        } else {
-            // Linearly scale the dcycle
-            (u32::from(temp - below_step.temp)
-                * u32::from(above_step.dcycle - below_step.dcycle)
-                / u32::from(above_step.temp - below_step.temp)
-                + u32::from(below_step.dcycle)) as u8
-        };
+            let token = self.config.http_token.clone();
+            let listener = web::bind(address)?;
+            let task = tokio::spawn(web::serve(
+                listener, self.control_state.clone(), self.tick_sink.clone(), token.clone()));
 
-        let mut ipmi_lock = session.ipmi.lock().unwrap();
+            self.web_server = Some((address.clone(), token, task));
+        }
 
-        for z in &zone_config.ipmi_zones {
-            let dcycle_cur = ipmi_lock.get_duty_cycle(*z)?;
+        Ok(())
+    }
+
+    /// Stop the HTTP server, if running.
+    fn stop_web_server(&mut self) {
+        if let Some((_, _, task)) = self.web_server.take() {
+            task.abort();
+        }
+    }
+
+    /// Start or restart the gRPC server if the listen address or the API token
+    /// in the config changed.
+    fn update_grpc_server(&mut self) -> Result<()> {
+        let current = self.grpc_server.as_ref().map(|(a, t, _)| (a, t.as_ref()));
+        if current == self.config.grpc_listen.as_ref().map(|a| (a, self.config.grpc_token.as_ref())) {
+            return Ok(());
+        }
 
-            debug!("[{}] Zone {}: zone_temp={}C, dcycle_cur={}%, dcycle_new={}%",
-                   session.name, z, temp, dcycle_cur, dcycle_new);
+        self.stop_grpc_server();
 
-            if dcycle_new != dcycle_cur {
-                ipmi_lock.set_duty_cycle(*z, dcycle_new)?;
-            }
+        if let Some(address) = &self.config.grpc_listen {
+            info!("Listening for gRPC connections: {}", address);
+
+            let token = self.config.grpc_token.clone();
+            let task = grpc::start(
+                address, self.control_state.clone(), self.tick_sink.clone(), token.clone())?;
+
+            self.grpc_server = Some((address.clone(), token, task));
         }
 
         Ok(())
     }
 
-    /// Get temperature sensor value in degrees Celsius using the zone's
-    /// data aggregation method.
-    fn get_temp(ipmi: Arc<Mutex<Ipmi>>, zone_config: &Zone) -> Result<u8> {
-        let mut readings = retry_with_index(zone_config.retry_iter(), move |i| {
-            trace!("Querying sources for zones {:?} (attempt {}/{})",
-                   zone_config.ipmi_zones, i, zone_config.retries.0 + 1);
-            get_source_readings(ipmi.clone(), &zone_config.sources)
-        })?;
-        readings.sort_by_key(|r| Reverse(*r));
+    /// Stop the gRPC server, if running.
+    fn stop_grpc_server(&mut self) {
+        if let Some((_, _, task)) = self.grpc_server.take() {
+            task.abort();
+        }
+    }
+
+    /// Start or restart the InfluxDB exporter if its config changed.
+    fn update_influx_exporter(&mut self) {
+        if self.influx_exporter.as_ref().map(|(c, _)| c) == self.config.influxdb.as_ref() {
+            return;
+        }
 
-        // The source list is guaranteed to never be empty so if no error
-        // occurs, there will always be an equal number of readings
-        match zone_config.aggregation {
-            Aggregation::Maximum => {
-                Ok(readings.first().copied().unwrap())
-            }
-            Aggregation::Average { top } => {
-                let n = top.unwrap_or(readings.len());
+        self.stop_influx_exporter();
 
-                let sum = readings
-                    .into_iter()
-                    .take(n)
-                    .map(u32::from)
-                    .sum::<u32>();
+        if let Some(config) = &self.config.influxdb {
+            info!("Exporting measurements to InfluxDB: {}:{}", config.url.host, config.url.port);
 
-                Ok((sum as f32 / n as f32) as u8)
-            }
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = tokio::spawn(influx::serve(config.clone(), receiver));
+
+            self.tick_sink.set_sender(Exporter::InfluxDb, Some(sender));
+            self.influx_exporter = Some((config.clone(), task));
         }
     }
-}
 
-fn bool_env(name: &str, default: bool) -> bool {
-    let value = env::var(name);
+    /// Stop the InfluxDB exporter, if running.
+    fn stop_influx_exporter(&mut self) {
+        self.tick_sink.set_sender(Exporter::InfluxDb, None);
 
-    match value {
-        Ok(v) if v == "true" || v == "t" || v == "yes" || v == "y" || v == "1" => true,
-        Ok(v) if v == "false" || v == "f" || v == "no" || v == "n" || v == "0" => false,
-        _ => default,
+        if let Some((_, task)) = self.influx_exporter.take() {
+            task.abort();
+        }
     }
-}
 
-#[derive(Debug, Parser)]
-struct Opt {
-    /// Path to config file
-    #[clap(short, long)]
-    config: PathBuf,
-}
+    /// Start or restart the history recorder if its config changed.
+    fn update_history_recorder(&mut self) {
+        if self.history_recorder.as_ref().map(|(c, _)| c) == self.config.history.as_ref() {
+            return;
+        }
 
-async fn main_wrapper() -> Result<()> {
-    let opt = Opt::parse();
-    let config = load_config(&opt.config)?;
+        self.stop_history_recorder();
 
-    let pkg_name = env!("CARGO_PKG_NAME").replace('-', "_");
+        if let Some(config) = &self.config.history {
+            info!("Recording history to: {:?}", config.path);
 
-    // RUST_LOG has higher precedence than the config file option because it has
-    // more flexibility (eg. turning on logs for dependencies)
-    let mut builder = env_logger::Builder::from_env(
-        env_logger::Env::default()
-            .default_filter_or(format!("{}={}", pkg_name, config.log_level)));
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = tokio::spawn(history::serve(config.clone(), receiver));
 
-    // Don't include timestamps in the log if requested (eg. if logs are going
-    // to something like journald that already has timestamps)
-    if !bool_env(&format!("{}_LOG_TIMESTAMPS", pkg_name.to_uppercase()), true) {
-        builder.format_timestamp(None);
+            self.tick_sink.set_sender(Exporter::History, Some(sender));
+            self.history_recorder = Some((config.clone(), task));
+        }
     }
 
-    builder.init();
-    LOGGING_INITIALIZED.store(true, Ordering::SeqCst);
+    /// Stop the history recorder, if running.
+    fn stop_history_recorder(&mut self) {
+        self.tick_sink.set_sender(Exporter::History, None);
 
-    trace!("Loaded config: {:#?}", config);
+        if let Some((_, task)) = self.history_recorder.take() {
+            task.abort();
+        }
+    }
+
+    /// Start or restart the textfile collector exporter if its config changed.
+    fn update_textfile_exporter(&mut self) {
+        if self.textfile_exporter.as_ref().map(|(c, _)| c) == self.config.textfile.as_ref() {
+            return;
+        }
 
-    let mut app = MainApp::new(config)?;
-    app.run().await
+        self.stop_textfile_exporter();
+
+        if let Some(config) = &self.config.textfile {
+            info!("Writing textfile collector metrics to: {:?}", config.path);
+
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = tokio::spawn(textfile::serve(config.clone(), receiver));
+
+            self.tick_sink.set_sender(Exporter::Textfile, Some(sender));
+            self.textfile_exporter = Some((config.clone(), task));
+        }
+    }
+
+    /// Stop the textfile collector exporter, if running.
+    fn stop_textfile_exporter(&mut self) {
+        self.tick_sink.set_sender(Exporter::Textfile, None);
+
+        if let Some((_, task)) = self.textfile_exporter.take() {
+            task.abort();
+        }
+    }
+
+    /// Start or restart the SNMP subagent if its config changed.
+    fn update_snmp_agent(&mut self) {
+        if self.snmp_agent.as_ref().map(|(c, _)| c) == self.config.snmp.as_ref() {
+            return;
+        }
+
+        self.stop_snmp_agent();
+
+        if let Some(config) = &self.config.snmp {
+            let task = tokio::spawn(snmp::serve(config.clone(), self.control_state.clone()));
+            self.snmp_agent = Some((config.clone(), task));
+        }
+    }
+
+    /// Stop the SNMP subagent, if running.
+    fn stop_snmp_agent(&mut self) {
+        if let Some((_, task)) = self.snmp_agent.take() {
+            task.abort();
+        }
+    }
+
+    /// Start or restart the disk rescanner for the current config. It is
+    /// always restarted because the disks it compares against change with
+    /// every reload.
+    fn update_disk_rescanner(&mut self) {
+        self.stop_disk_rescanner();
+
+        let interval = match self.config.disk_rescan_interval {
+            Some(i) => i.to_duration(),
+            None => return,
+        };
+        let zones = self.config.zones.iter()
+            .filter(|z| !z.unexpanded_sources.is_empty())
+            .map(|z| (z.label(), z.unexpanded_sources.clone(), z.sources.clone()))
+            .collect::<Vec<_>>();
+
+        if !zones.is_empty() {
+            self.disk_rescanner = Some(task::spawn(
+                Self::disk_rescan_loop(self.control_state.clone(), zones, interval)));
+        }
+    }
+
+    /// Stop the disk rescanner, if running.
+    fn stop_disk_rescanner(&mut self) {
+        if let Some(task) = self.disk_rescanner.take() {
+            task.abort();
+        }
+    }
+
+    /// Periodically expand each zone's `disks` and `zpool` sources again and
+    /// reload the config if the matching disks changed. Each item in `zones`
+    /// is a zone's label, its unexpanded sources, and its current sources.
+    async fn disk_rescan_loop(
+        control_state: Arc<ControlState>,
+        zones: Vec<(String, Vec<Source>, Vec<Source>)>,
+        interval: Duration,
+    ) {
+        loop {
+            sleep(interval).await;
+
+            for (label, unexpanded, sources) in &zones {
+                match expand_sources(unexpanded, &tools::get()) {
+                    Ok(s) if s != *sources => {
+                        info!(zone:% = label; "[{}] Disks changed; reloading config", label);
+                        // A successful reload restarts this task
+                        control_state.reload.notify_one();
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!(zone:% = label; "[{}] Failed to rescan disks: {}", label, e),
+                }
+            }
+        }
+    }
+
+    /// Get the names of the session's sensors of the given type. The names are
+    /// cached so that each session is only queried once per type. [`None`] is
+    /// returned if the sensors couldn't be listed, which is reported the first
+    /// time.
+    async fn sensor_names<'a>(
+        &self,
+        cache: &'a mut HashMap<(String, SensorType), Option<HashSet<String>>>,
+        problems: &mut Vec<String>,
+        session: &str,
+        sensor_type: SensorType,
+    ) -> Option<&'a HashSet<String>> {
+        let key = (session.to_owned(), sensor_type);
+
+        if !cache.contains_key(&key) {
+            let names = match self.sessions.get(session) {
+                Some(s) => match s.ipmi.run(move |i| i.get_sensor_readings(sensor_type)).await {
+                    Ok(r) => Some(r.into_keys().collect()),
+                    Err(e) => {
+                        problems.push(format!("sessions[{:?}]: failed to list {:?} sensors: {}",
+                                              session, sensor_type, e));
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            cache.insert(key.clone(), names);
+        }
+
+        cache[&key].as_ref()
+    }
+
+    /// Check that every IPMI sensor, file, and block device used by the zones
+    /// exists so that typos are reported before any zone starts. Optional
+    /// sources are skipped because they are allowed to be missing.
+    async fn validate_sources(&self) -> Result<()> {
+        let mut problems = vec![];
+        let mut cache = HashMap::new();
+
+        let check_path = |problems: &mut Vec<String>, key: &str, path: &str| {
+            if let Err(e) = fs::metadata(path) {
+                problems.push(format!("{}: {:?}: {}", key, path, e));
+            }
+        };
+
+        for (i, zone) in self.config.zones.iter().enumerate() {
+            let session = zone.session.0.as_str();
+
+            for (j, source) in zone.sources.iter().enumerate() {
+                if source.options.optional {
+                    continue;
+                }
+
+                let key = format!("zones[{}].sources[{}]", i, j);
+
+                for leaf in source.source_type.leaves() {
+                    match leaf {
+                        SourceType::Ipmi { sensor, sensor_pattern, units, .. } => {
+                            let names = match self.sensor_names(
+                                &mut cache, &mut problems, session, units.sensor_type()).await
+                            {
+                                Some(n) => n,
+                                None => continue,
+                            };
+
+                            match sensor_pattern {
+                                Some(p) if !names.iter().any(|n| wildcard_match(p, n)) => {
+                                    problems.push(format!("{}: no IPMI sensors match pattern: {:?}", key, p));
+                                }
+                                None if !names.contains(sensor) => {
+                                    problems.push(format!("{}: IPMI sensor not found: {:?}{}", key, sensor,
+                                                          format_suggestions(&suggest_names(sensor, names))));
+                                }
+                                _ => {}
+                            }
+                        }
+                        SourceType::File { path, .. } => check_path(&mut problems, &key, path),
+                        SourceType::Smart { block_dev, .. } | SourceType::Hdparm { block_dev, .. } => {
+                            check_path(&mut problems, &key, block_dev);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if !zone.fan_sensors.is_empty() {
+                if let Some(names) = self.sensor_names(
+                    &mut cache, &mut problems, session, SensorType::Fan).await
+                {
+                    for (j, sensor) in zone.fan_sensors.iter().enumerate() {
+                        if !names.contains(sensor) {
+                            problems.push(format!("zones[{}].fan_sensors[{}]: IPMI sensor not found: {:?}{}",
+                                                  i, j, sensor, format_suggestions(&suggest_names(sensor, names))));
+                        }
+                    }
+                }
+            }
+
+            if let Output::Hwmon { pwm } = &zone.output {
+                for (j, path) in pwm.iter().enumerate() {
+                    check_path(&mut problems, &format!("zones[{}].output.pwm[{}]", i, j), path);
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::SourceValidation(problems))
+        }
+    }
+
+    /// Run asynchronous loops for each zone. Returns when interrupted via
+    /// signal handlers (eg. ^C) or if a fatal error occurs. The config is
+    /// reloaded when SIGHUP is received.
+    async fn run(&mut self) -> Result<()> {
+        if self.config.strict_sources {
+            self.validate_sources().await?;
+        }
+
+        // Cloned since there's no structured concurrency support yet
+        for (i, zone_config) in self.config.zones.clone().into_iter().enumerate() {
+            self.spawn_zone(i, Arc::new(zone_config));
+        }
+
+        self.publish_zones();
+        self.update_control_server()?;
+        self.update_web_server()?;
+        self.update_grpc_server()?;
+        self.update_influx_exporter();
+        self.update_history_recorder();
+        self.update_textfile_exporter();
+        self.update_snmp_agent();
+        self.update_disk_rescanner();
+        self.alert_notifier.set_config(self.config.alerts.clone());
+
+        // Everything that needs root has been opened by this point
+        if let Some(config) = &self.config.drop_privileges {
+            privileges::drop_privileges(config)?;
+        }
+
+        let watchdog = systemd::watchdog_timeout()
+            .map(|t| task::spawn(Self::watchdog_loop(self.control_state.clone(), t)));
+
+        // All sessions have been established by this point
+        if let Err(e) = systemd::notify("READY=1") {
+            warn!("Failed to notify systemd: {}", e);
+        }
+
+        let mut reload = ReloadSignal::new()
+            .map_err(|e| Error::Io { path: "(reload)".into(), source: e })?;
+        let mut pause_signals = self.pause_signals()?;
+        let control_state = self.control_state.clone();
+        let mut first_result = None;
+
+        loop {
+            let ret: Result<()> = tokio::select! {
+                // Explicitly interrupted by ^C or signal handler
+                c = interrupted() => {
+                    if c.is_ok() {
+                        info!("Interrupted");
+                    }
+                    c.map_err(|e| Error::Io { path: "(interrupt)".into(), source: e })
+                }
+                // Config reload requested. Reloading is not possible once the
+                // program is shutting down.
+                _ = reload.recv(), if first_result.is_none() => {
+                    match self.reload().await {
+                        Ok(_) => {
+                            pause_signals = self.pause_signals()?;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                // Pause or resume every zone
+                p = pause_signals.recv(), if first_result.is_none() => {
+                    self.set_all_paused(p);
+                    continue;
+                }
+                // Config reload requested via the control socket
+                _ = control_state.reload.notified(), if first_result.is_none() => {
+                    match self.reload().await {
+                        Ok(_) => {
+                            pause_signals = self.pause_signals()?;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                // Oh boy, this is an Option<Result<Result<()>, JoinError>>
+                r = self.loops.join_next() => {
+                    match r {
+                        // No tasks left
+                        None => break,
+                        // The task was aborted during a reload or shutdown
+                        Some(Err(e)) if e.is_cancelled() => continue,
+                        // The task panicked
+                        Some(Err(e)) => Err(Error::LoopPanicked(e)),
+                        // zone_loop's actual error return value
+                        Some(Ok(r)) => r,
+                    }
+                }
+            };
+
+            if first_result.is_none() {
+                first_result = Some(ret);
+
+                if let Err(e) = systemd::notify("STOPPING=1") {
+                    warn!("Failed to notify systemd: {}", e);
+                }
+            }
+
+            // If tokio::select returned, then a loop exited or the program was
+            // explicitly interrupted. Interrupt all remaining tasks and the
+            // loop will exit once the JoinSet is empty. This mechanism is
+            // necessary because Tokio's JoinHandles do not cancel tasks when
+            // they are dropped. Without the explicit aborts and joins, the
+            // IpmiSession destructors might not run since the tasks would keep
+            // the Arcs alive.
+            self.loops.abort_all();
+        }
+
+        self.stop_control_server();
+        self.stop_web_server();
+        self.stop_grpc_server();
+        self.stop_influx_exporter();
+        self.stop_history_recorder();
+        self.stop_textfile_exporter();
+        self.stop_snmp_agent();
+        self.stop_disk_rescanner();
+
+        if let Some(handle) = watchdog {
+            handle.abort();
+        }
+
+        first_result.unwrap_or(Ok(()))
+    }
+
+    /// Register the pause and resume signals from the config.
+    fn pause_signals(&self) -> Result<PauseSignals> {
+        PauseSignals::new(self.config.pause_signal.0, self.config.resume_signal.0)
+            .map_err(|e| Error::Io { path: "(pause signals)".into(), source: e })
+    }
+
+    /// Pause or resume every running zone. Paused zones stop querying their
+    /// sources and leave the fans at their current duty cycles.
+    fn set_all_paused(&self, paused: bool) {
+        if paused {
+            info!("Pausing all zones due to signal");
+        } else {
+            info!("Resuming all zones due to signal");
+        }
+
+        for r in &self.running_zones {
+            r.status.lock().unwrap().paused = paused;
+        }
+    }
+
+    /// Ping the systemd watchdog at half the timeout as long as every zone loop
+    /// is still making progress. If a loop gets stuck, the pings stop and
+    /// systemd restarts the service.
+    async fn watchdog_loop(control_state: Arc<ControlState>, timeout: Duration) {
+        info!("systemd watchdog enabled with timeout: {:?}", timeout);
+
+        loop {
+            sleep(timeout / 2).await;
+
+            if !control_state.zones_alive(timeout) {
+                warn!("Not pinging systemd watchdog because a zone loop is stuck");
+                continue;
+            }
+
+            if let Err(e) = systemd::notify("WATCHDOG=1") {
+                warn!("Failed to ping systemd watchdog: {}", e);
+            }
+        }
+    }
+
+    /// Reload the config file and apply the changes. Zones whose config did not
+    /// change keep running and sessions whose config did not change are kept
+    /// open, so the fan mode is not reset. If the new config fails to load,
+    /// the current config remains in effect, as it does if a new session
+    /// cannot be opened. An error is only returned if a zone loop fails while
+    /// the old loops are being stopped or if a new session cannot take control
+    /// of the fans.
+    async fn reload(&mut self) -> Result<()> {
+        info!("Reloading config: {:?}", self.config_path);
+
+        // Loading can run commands, load plugins, and compile scripts
+        let path = self.config_path.clone();
+        let loaded = task::spawn_blocking(move || load_config(&path)).await
+            .map_err(Error::LoopPanicked)?;
+
+        let config = match loaded {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Keeping current config: {}", e);
+                return Ok(());
+            }
+        };
+
+        trace!("Reloaded config: {:#?}", config);
+
+        for warning in &config.warnings {
+            warn!("{:?}: {}", self.config_path, warning);
+        }
+
+        if config.drop_privileges != self.config.drop_privileges {
+            warn!("Changes to drop_privileges require a restart");
+        }
+        if config.lock_file != self.config.lock_file {
+            warn!("Changes to lock_file require a restart");
+        }
+
+        // Sessions are only kept if their config is identical, something still
+        // uses them, and whether they control fans did not change
+        let kept_sessions: Vec<_> = self.sessions
+            .keys()
+            .filter(|name| {
+                config.sessions.0.get(*name) == self.config.sessions.0.get(*name)
+                    && Self::session_used(&config, name)
+                    && Self::session_ipmi_zones(&config, name).is_empty()
+                        == Self::session_ipmi_zones(&self.config, name).is_empty()
+            })
+            .cloned()
+            .collect();
+
+        // Open the new sessions before stopping anything so that an unreachable
+        // BMC doesn't leave the fans uncontrolled. They only take control of the
+        // fans once the old sessions have restored the original fan mode.
+        let mut new_sessions = vec![];
+
+        for (name, session) in &config.sessions.0 {
+            if !Self::session_used(&config, name) || kept_sessions.contains(name) {
+                continue;
+            }
+
+            match IpmiSession::new(name, session, []) {
+                Ok(s) => new_sessions.push((name.clone(), s)),
+                Err(e) => {
+                    error!("Keeping current config: failed to open session {:?}: {}", name, e);
+                    return Ok(());
+                }
+            }
+        }
+
+        // Each running zone can satisfy at most one identical new zone
+        let mut new_zones = vec![];
+        let mut kept_zones = vec![];
+        let mut stopped_zones = mem::take(&mut self.running_zones);
+
+        for (i, zone_config) in config.zones.iter().enumerate() {
+            // Loops that stopped after an error are restarted
+            let index = stopped_zones.iter().position(|r| {
+                r.config.as_ref() == zone_config
+                    && !r.status.lock().unwrap().stopped
+                    && (!zone_config.uses_ipmi()
+                        || kept_sessions.contains(&zone_config.session.0))
+            });
+
+            match index {
+                Some(j) => {
+                    let r = stopped_zones.remove(j);
+                    r.status.lock().unwrap().index = i;
+                    kept_zones.push(r);
+                }
+                None => new_zones.push((i, Arc::new(zone_config.clone()))),
+            }
+        }
+
+        self.running_zones = kept_zones;
+
+        // Wait for the stopped loops to exit so that they release their
+        // references to the sessions
+        for r in &stopped_zones {
+            info!(zone:% = r.config.label();
+                  "[{}] Stopping loop", r.config.label());
+            r.abort_handle.abort();
+        }
+
+        let mut remaining = stopped_zones.len();
+
+        while remaining > 0 {
+            match self.loops.join_next().await {
+                None => break,
+                Some(Err(e)) if e.is_cancelled() => remaining -= 1,
+                Some(Err(e)) => return Err(Error::LoopPanicked(e)),
+                Some(Ok(r)) => r?,
+            }
+        }
+
+        // The removed sessions restore their zones and the original fan mode
+        // before the new sessions take control of the fans. This is done
+        // explicitly instead of by dropping them because the stopped loops'
+        // source queries run on blocking threads that may still hold a
+        // reference.
+        let (kept, dropped): (HashMap<_, _>, HashMap<_, _>) = mem::take(&mut self.sessions)
+            .into_iter()
+            .partition(|(name, _)| kept_sessions.contains(name));
+        self.sessions = kept;
+
+        for (name, session) in dropped {
+            if timeout(SESSION_RELEASE_TIMEOUT, session.release_control()).await.is_err() {
+                warn!("[{}] Timed out restoring the fan mode; it will be restored once pending commands finish",
+                      name);
+            }
+        }
+
+        for (name, session) in &self.sessions {
+            session.set_restore_zones(Self::session_ipmi_zones(&config, name)).await;
+        }
+
+        for (name, session) in new_sessions {
+            session.take_control(Self::session_ipmi_zones(&config, &name)).await?;
+            self.sessions.insert(name, Arc::new(session));
+        }
+
+        tools::set(&config.tools);
+        self.config = config;
+
+        // Close the sources that are no longer used
+        source::retain_shared_sources(
+            self.config.zones.iter().flat_map(|z| &z.sources).map(|s| &s.source_type));
+
+        for (i, zone_config) in new_zones {
+            self.spawn_zone(i, zone_config);
+        }
+
+        self.publish_zones();
+
+        if let Err(e) = self.update_control_server() {
+            error!("Failed to start control socket: {}", e);
+        }
+
+        if let Err(e) = self.update_web_server() {
+            error!("Failed to start HTTP server: {}", e);
+        }
+
+        if let Err(e) = self.update_grpc_server() {
+            error!("Failed to start gRPC server: {}", e);
+        }
+
+        self.update_influx_exporter();
+        self.update_history_recorder();
+        self.update_textfile_exporter();
+        self.update_snmp_agent();
+        self.update_disk_rescanner();
+        self.alert_notifier.set_config(self.config.alerts.clone());
+        self.isolate_failures.store(self.config.isolate_zone_failures, Ordering::SeqCst);
+
+        info!("Config reloaded");
+
+        Ok(())
+    }
+
+    /// Main loop for a zone. The loop starts after `start_delay` and runs
+    /// forever while the future is being polled. Errors are tagged with the
+    /// zone's name.
+    ///
+    /// If failures are isolated, a failed loop restores its own IPMI zones and
+    /// then waits to be aborted instead of returning the error, so that the
+    /// other loops keep running. Reloading the config restarts the loop.
+    #[allow(clippy::too_many_arguments)]
+    async fn zone_loop(
+        start_delay: Duration,
+        session: Option<Arc<IpmiSession>>,
+        zone_config: Arc<Zone>,
+        status: Arc<Mutex<ZoneStatus>>,
+        sink: Arc<influx::Sink>,
+        notifier: Arc<Notifier>,
+        isolate_failures: Arc<AtomicBool>,
+        control_state: Arc<ControlState>,
+    ) -> Result<()> {
+        if !start_delay.is_zero() {
+            debug!(zone:% = zone_config.label();
+                   "[{}] Delaying start by {:?}", zone_config.label(), start_delay);
+            sleep(start_delay).await;
+        }
+
+        let result = Self::run_zone(
+            session.clone(), zone_config.clone(), status.clone(), sink, notifier.clone(), control_state).await;
+
+        if let Some(e) = result.as_ref().err().filter(|e| e.is_ipmi()) {
+            let alert = Alert::new(AlertEvent::IpmiError, &zone_config, e.to_string());
+
+            // Wait for the hooks to finish since the program exits when a
+            // zone loop fails
+            if let Some(handle) = notifier.notify(alert) {
+                let _ = handle.await;
+            }
+        }
+
+        match result {
+            Err(e) if isolate_failures.load(Ordering::SeqCst) => {
+                error!(zone:% = zone_config.label();
+                       "[{}] Loop stopped; other zones keep running: {}", zone_config.label(), e);
+
+                if let (Some(session), Output::Ipmi) = (&session, &zone_config.output) {
+                    session.release_zones(&zone_config.ipmi_zones).await;
+                }
+
+                status.lock().unwrap().record_stopped(&e);
+
+                std::future::pending().await
+            }
+            r => r.map_err(|e| Error::Zone { zone: zone_config.label(), source: Box::new(e) }),
+        }
+    }
+
+    /// Body of [`Self::zone_loop`].
+    ///
+    /// All communication with the IPMI goes through the session's worker
+    /// thread to avoid needing multiple IPMI sessions. The session is only
+    /// [`None`] if the zone uses neither IPMI output nor IPMI sources.
+    async fn run_zone(
+        session: Option<Arc<IpmiSession>>,
+        zone_config: Arc<Zone>,
+        status: Arc<Mutex<ZoneStatus>>,
+        sink: Arc<influx::Sink>,
+        notifier: Arc<Notifier>,
+        control_state: Arc<ControlState>,
+    ) -> Result<()> {
+        let mut pwm_fans = match &zone_config.output {
+            Output::Ipmi => {
+                info!(zone:% = zone_config.label();
+                      "[{}] Starting loop for IPMI zones {:?}",
+                      zone_config.label(), zone_config.ipmi_zones);
+                vec![]
+            }
+            Output::Hwmon { pwm } => {
+                info!(zone:% = zone_config.label();
+                      "[{}] Starting loop for PWM outputs {:?}", zone_config.label(), pwm);
+                pwm.iter()
+                    .map(|p| PwmFan::new(p, zone_config.restore_dcycle()))
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+
+        let mut state = ZoneState {
+            script: Self::load_script(&zone_config)?,
+            ..Default::default()
+        };
+        let started = Instant::now();
+
+        if let Some(dcycle) = zone_config.initial_dcycle {
+            match zone_config.startup_grace {
+                Some(grace) => info!(zone:% = zone_config.label(), dcycle = dcycle;
+                                     "[{}] Applying initial duty cycle {}% for {:?}",
+                                     zone_config.label(), dcycle, grace.to_duration()),
+                None => info!(zone:% = zone_config.label(), dcycle = dcycle;
+                              "[{}] Applying initial duty cycle {}%", zone_config.label(), dcycle),
+            }
+
+            let applied = Self::apply_duty_cycle(
+                session.as_deref(), &mut pwm_fans, &zone_config, None, dcycle, true).await;
+            if applied.is_ok() {
+                state.dcycle = Some(dcycle);
+            }
+            Self::handle_ipmi_failure(session.as_deref(), &zone_config, &notifier, applied).await?;
+        }
+
+        loop {
+            let (paused, override_dcycle) = {
+                let mut status_lock = status.lock().unwrap();
+                (status_lock.paused, status_lock.take_expired_override())
+            };
+
+            if paused {
+                Self::check_paused(session.clone(), &mut pwm_fans, &zone_config, &mut state, &notifier).await?;
+
+                for alert in state.alerts.drain(..) {
+                    notifier.notify(alert);
+                }
+
+                {
+                    let mut status_lock = status.lock().unwrap();
+                    status_lock.record_paused();
+                    status_lock.emergency = state.emergency;
+                }
+                sleep(zone_config.next_interval()).await;
+                continue;
+            }
+
+            let file_override = Self::read_override_file(&zone_config, &mut state);
+            status.lock().unwrap().file_override_dcycle = file_override;
+            state.override_dcycle = override_dcycle.or(file_override);
+
+            let (readings, cache) = Self::get_readings(
+                session.clone(), zone_config.clone(), mem::take(&mut state.sources)).await?;
+            state.sources = cache;
+
+            let mut source_readings = vec![];
+
+            let result = match readings {
+                Ok(readings) => {
+                    state.failures = 0;
+                    source_readings = zone_config.sources.iter()
+                        .map(|s| s.to_string())
+                        .zip(readings.iter().copied())
+                        .collect();
+
+                    let critical_readings = readings.clone();
+                    Self::check_fans(session.as_deref(), &zone_config, &mut state).await;
+
+                    if zone_config.log_decisions {
+                        Self::log_readings(&zone_config, &source_readings);
+                    }
+
+                    if state.script.is_some() {
+                        let index = status.lock().unwrap().index;
+                        state.other_zones = control_state.other_zones(index);
+                    }
+
+                    let curve_config = Self::scheduled_config(&zone_config, &mut state);
+                    let (temp, dcycle) = Self::target_duty_cycle(
+                        &curve_config, &mut state, readings, Instant::now());
+
+                    if zone_config.log_decisions {
+                        Self::log_decision(&curve_config, &state, temp, dcycle);
+                    }
+
+                    let dcycle = Self::startup_grace_dcycle(&zone_config, &state, started, temp, dcycle);
+
+                    let log_status = Self::should_log_status(&zone_config, &mut state, temp, dcycle);
+
+                    let result = match Self::spin_up(
+                        session.as_deref(), &mut pwm_fans, &zone_config, state.dcycle, temp, dcycle).await
+                    {
+                        Ok(()) => Self::apply_duty_cycle(
+                            session.as_deref(), &mut pwm_fans, &zone_config, Some(temp), dcycle, log_status)
+                            .await
+                            .map(|_| (temp, dcycle)),
+                        Err(e) => Err(e),
+                    };
+
+                    // After the duty cycle is applied so that the fans respond
+                    // to the temperature before anything else happens
+                    Self::check_critical(
+                        &zone_config, &critical_readings, &mut state.critical, &mut state.alerts);
+
+                    result
+                }
+                Err(e) => {
+                    state.failures += 1;
+
+                    if state.failures == 1 {
+                        notifier.notify(Alert::new(
+                            AlertEvent::SourceFailure, &zone_config, e.to_string()));
+                    }
+
+                    let failsafe = if state.failures >= zone_config.failsafe_cycles.0 {
+                        let dcycle = zone_config.failsafe_dcycle.0;
+                        warn!(zone:% = zone_config.label(), dcycle = dcycle;
+                              "[{}] Failed to query sources ({} consecutive failures), using failsafe duty cycle {}%: {}",
+                              zone_config.label(), state.failures, dcycle, e);
+
+                        let applied = Self::apply_duty_cycle(
+                            session.as_deref(), &mut pwm_fans, &zone_config, None, dcycle, true).await;
+                        if applied.is_ok() {
+                            state.dcycle = Some(dcycle);
+                        }
+                        Self::handle_ipmi_failure(
+                            session.as_deref(), &zone_config, &notifier, applied).await?;
+
+                        if state.failures == zone_config.failsafe_cycles.0 {
+                            notifier.notify(Alert::new(AlertEvent::Failsafe, &zone_config, format!(
+                                "Failed to query sources ({} consecutive failures): {}",
+                                state.failures, e)).with_dcycle(dcycle));
+                        }
+
+                        Some(dcycle)
+                    } else {
+                        warn!(zone:% = zone_config.label();
+                              "[{}] Failed to query sources ({}/{} failures before failsafe): {}",
+                              zone_config.label(), state.failures, zone_config.failsafe_cycles.0, e);
+                        None
+                    };
+
+                    status.lock().unwrap().record_failure(&e, failsafe);
+                    sink.send(Tick {
+                        zone: zone_config.label(),
+                        time: SystemTime::now(),
+                        temp: None,
+                        dcycle: failsafe,
+                        failsafe: failsafe.is_some(),
+                        emergency: state.emergency,
+                        error: Some(e.to_string()),
+                        sources: vec![],
+                    });
+                    sleep(zone_config.next_interval()).await;
+                    continue;
+                }
+            };
+
+            if let Ok((_, dcycle)) = result {
+                state.dcycle = Some(dcycle);
+            }
+
+            {
+                let mut status_lock = status.lock().unwrap();
+                status_lock.update(&result);
+                status_lock.emergency = state.emergency;
+                status_lock.stalled_fans = state.stalled_fans.clone();
+            }
+            sink.send(Tick {
+                zone: zone_config.label(),
+                time: SystemTime::now(),
+                temp: result.as_ref().ok().map(|(t, _)| *t),
+                dcycle: state.dcycle,
+                failsafe: false,
+                emergency: state.emergency,
+                error: result.as_ref().err().map(|e| e.to_string()),
+                sources: source_readings,
+            });
+
+            for alert in state.alerts.drain(..) {
+                notifier.notify(alert);
+            }
+
+            Self::handle_ipmi_failure(
+                session.as_deref(), &zone_config, &notifier, result.map(|_| ())).await?;
+
+            sleep(zone_config.next_interval()).await;
+        }
+    }
+
+    /// Handle the result of controlling a zone's fans. If an IPMI command
+    /// failed and the session tolerates failures, the error is logged and
+    /// counted towards the session's failure limit so that the zone loop can
+    /// keep retrying. Any other error is returned.
+    async fn handle_ipmi_failure(
+        session: Option<&IpmiSession>,
+        zone_config: &Zone,
+        notifier: &Notifier,
+        result: Result<()>,
+    ) -> Result<()> {
+        match (result, session.filter(|s| s.failure_limit > 0)) {
+            (Err(e), Some(session)) if e.is_ipmi() => {
+                error!(zone:% = zone_config.label();
+                       "[{}] Failed to control fans: {}", zone_config.label(), e);
+
+                if session.command_failed().await {
+                    notifier.notify(Alert::new(AlertEvent::IpmiFallback, zone_config, format!(
+                        "Handed fan control back to the BMC after {} consecutive IPMI failures: {}",
+                        session.failure_limit, e)));
+                }
+
+                Ok(())
+            }
+            (r, _) => r,
+        }
+    }
+
+    /// Read the zone's override file, if it has one. Errors are logged and
+    /// treated like a missing file so that a bad file can't stop fan control.
+    fn read_override_file(zone_config: &Zone, state: &mut ZoneState) -> Option<u8> {
+        let path = zone_config.override_file.as_ref()?;
+
+        let dcycle = match control::read_override_file(Path::new(path)) {
+            Ok(d) => d,
+            Err(e) => {
+                warn!(zone:% = zone_config.label(); "[{}] {}", zone_config.label(), e);
+                None
+            }
+        };
+
+        if dcycle != state.file_override {
+            match dcycle {
+                Some(d) => info!(zone:% = zone_config.label();
+                                 "[{}] Overriding duty cycle to {}% from {:?}",
+                                 zone_config.label(), d, path),
+                None => info!(zone:% = zone_config.label();
+                              "[{}] Override file {:?} no longer applies",
+                              zone_config.label(), path),
+            }
+        }
+        state.file_override = dcycle;
+
+        dcycle
+    }
+
+    /// Compute the zone temperature and the duty cycle to apply. The duty cycle
+    /// is forced to 100% if a source exceeds the emergency temperature or if
+    /// any fans are stalled. Otherwise, the override duty cycle is used if one
+    /// is set and the duty cycle is 0% at or below the stop temperature.
+    /// Otherwise, the duty cycle from the steps is limited to the zone's
+    /// minimum and maximum duty cycles. `now` is the time of the iteration,
+    /// which `simulate` advances by the zone's interval for each line.
+    fn target_duty_cycle(
+        zone_config: &Zone,
+        state: &mut ZoneState,
+        readings: Vec<Option<f32>>,
+        now: Instant,
+    ) -> (f32, u8) {
+        Self::update_emergency(zone_config, state, &readings);
+
+        // The aggregation consumes the readings
+        let script_readings = state.script.as_ref().map(|_| readings.clone());
+        let temp = curve::aggregate_readings(zone_config, readings);
+
+        Self::update_trend(zone_config, state, temp, now);
+
+        let dcycle = if state.emergency || !state.stalled_fans.is_empty() {
+            100
+        } else if let Some(dcycle) = state.override_dcycle {
+            dcycle
+        } else if zone_config.stop_temp.is_some_and(|t| temp <= f32::from(t)) {
+            0
+        } else if !zone_config.rpm_steps.is_empty() {
+            Self::calc_rpm_duty_cycle(zone_config, state, temp)
+                .clamp(zone_config.min_dcycle.0, zone_config.max_dcycle.0)
+        } else {
+            let steps_dcycle = curve::calc_step_duty_cycle(zone_config, state.dcycle, temp)
+                .saturating_add(state.trend_boost);
+            let dcycle = match &script_readings {
+                Some(r) => Self::script_duty_cycle(zone_config, state, r, temp, steps_dcycle),
+                None => None,
+            };
+            state.script_dcycle = dcycle;
+
+            let dcycle = dcycle.unwrap_or(steps_dcycle)
+                .clamp(zone_config.min_dcycle.0, zone_config.max_dcycle.0);
+
+            Self::damp_oscillation(zone_config, state, dcycle, now)
+        };
+
+        (temp, dcycle)
+    }
+
+    /// Enter or leave the emergency state depending on whether any source
+    /// exceeds `emergency_temp`.
+    fn update_emergency(zone_config: &Zone, state: &mut ZoneState, readings: &[Option<f32>]) {
+        let hottest = curve::emergency_reading(zone_config, readings);
+
+        match (hottest, state.emergency) {
+            (Some(t), false) => {
+                error!(zone:% = zone_config.label(), temp = t;
+                       "[{}] EMERGENCY: Source temperature {:.1}C exceeds emergency_temp {}C; forcing 100% duty cycle",
+                       zone_config.label(), t, zone_config.emergency_temp.unwrap());
+                state.alerts.push(Alert::new(AlertEvent::Emergency, zone_config, format!(
+                    "Source temperature {:.1}C exceeds emergency_temp {}C",
+                    t, zone_config.emergency_temp.unwrap())).with_temp(t).with_dcycle(100));
+            }
+            (None, true) => {
+                warn!(zone:% = zone_config.label();
+                      "[{}] Source temperatures are below emergency_temp again; resuming normal fan control",
+                      zone_config.label());
+                state.alerts.push(Alert::new(AlertEvent::EmergencyCleared, zone_config,
+                    "Source temperatures are below emergency_temp again".to_owned()));
+            }
+            _ => {}
+        }
+        state.emergency = hottest.is_some();
+    }
+
+    /// Watch the sources of a paused zone. Nothing is sent to the fans unless
+    /// a source exceeds `emergency_temp`, in which case they are forced to 100%
+    /// until the zone is resumed. The critical command also still runs. The
+    /// sources are not read at all if neither is configured, and failures are
+    /// ignored because the BMC may be unavailable while the zone is paused.
+    async fn check_paused(
+        session: Option<Arc<IpmiSession>>,
+        pwm_fans: &mut [PwmFan],
+        zone_config: &Arc<Zone>,
+        state: &mut ZoneState,
+        notifier: &Notifier,
+    ) -> Result<()> {
+        if zone_config.emergency_temp.is_none() && zone_config.critical.is_none() {
+            return Ok(());
+        }
+
+        let (readings, cache) = Self::get_readings(
+            session.clone(), zone_config.clone(), mem::take(&mut state.sources)).await?;
+        state.sources = cache;
+
+        let readings = match readings {
+            Ok(r) => r,
+            Err(e) => {
+                debug!(zone:% = zone_config.label();
+                       "[{}] Failed to query sources while paused: {}", zone_config.label(), e);
+                return Ok(());
+            }
+        };
+
+        Self::update_emergency(zone_config, state, &readings);
+
+        if state.emergency && state.dcycle != Some(100) {
+            let applied = Self::apply_duty_cycle(
+                session.as_deref(), pwm_fans, zone_config, None, 100, true).await;
+            if applied.is_ok() {
+                state.dcycle = Some(100);
+            }
+            Self::handle_ipmi_failure(session.as_deref(), zone_config, notifier, applied).await?;
+        }
+
+        Self::check_critical(zone_config, &readings, &mut state.critical, &mut state.alerts);
+
+        Ok(())
+    }
+
+    /// Load the zone's script, if it has one.
+    fn load_script(zone_config: &Zone) -> Result<Option<Script>> {
+        zone_config.script.as_ref()
+            .map(|p| Script::load(Path::new(p), &zone_config.label()))
+            .transpose()
+    }
+
+    /// Run the zone's script to compute the duty cycle. Returns [`None`] if the
+    /// script returned `()` or failed, in which case the steps are used.
+    fn script_duty_cycle(
+        zone_config: &Zone,
+        state: &ZoneState,
+        readings: &[Option<f32>],
+        temp: f32,
+        steps_dcycle: u8,
+    ) -> Option<u8> {
+        let script = state.script.as_ref()?;
+        let label = zone_config.label();
+        let sources = zone_config.sources.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let input = ScriptInput {
+            zone: &label,
+            sources: &sources,
+            readings,
+            temp,
+            dcycle: state.dcycle,
+            steps_dcycle,
+            zones: &state.other_zones,
+        };
+
+        match script.duty_cycle(&input) {
+            Ok(dcycle) => dcycle,
+            Err(e) => {
+                warn!(zone:% = label; "[{}] Using steps: {}", label, e);
+                None
+            }
+        }
+    }
+
+    /// Detect the duty cycle repeatedly rising and falling within the
+    /// oscillation window. Once detected, decreases that are smaller than the
+    /// largest swing seen are ignored so that the fans stay at the higher duty
+    /// cycle instead of hunting. The band only ever widens until the zone is
+    /// restarted.
+    fn damp_oscillation(zone_config: &Zone, state: &mut ZoneState, dcycle: u8, now: Instant) -> u8 {
+        let config = &zone_config.oscillation_damping;
+        state.damped_dcycle = None;
+
+        let prev = match state.dcycle {
+            Some(d) if config.enabled => d,
+            _ => return dcycle,
+        };
+
+        let dcycle = match state.damping_band {
+            Some(band) if dcycle < prev && prev - dcycle < band => {
+                state.damped_dcycle = Some(dcycle);
+                prev
+            }
+            _ => dcycle,
+        };
+
+        if dcycle == prev {
+            return dcycle;
+        }
+
+        let window = config.window.to_duration();
+
+        state.dcycle_changes.retain(|(t, _, _)| now.duration_since(*t) <= window);
+        state.dcycle_changes.push_back((now, prev, dcycle));
+
+        let flips = state.dcycle_changes.iter()
+            .zip(state.dcycle_changes.iter().skip(1))
+            .filter(|(a, b)| (a.2 > a.1) != (b.2 > b.1))
+            .count();
+
+        if flips >= config.flips as usize {
+            let swing = state.dcycle_changes.iter()
+                .map(|(_, from, to)| from.abs_diff(*to))
+                .max()
+                .unwrap_or_default();
+            let band = swing.saturating_add(1);
+
+            if state.damping_band.is_none_or(|b| band > b) {
+                warn!(zone:% = zone_config.label(), dcycle = dcycle;
+                      "[{}] Duty cycle changed direction {} times in the last {:?}; ignoring decreases smaller than {}%. \
+                       Consider adding cooldown_steps, increasing the interval, or using average aggregation",
+                      zone_config.label(), flips, window, band);
+
+                state.damping_band = Some(band);
+            }
+
+            state.dcycle_changes.clear();
+        }
+
+        dcycle
+    }
+
+    /// Record the temperature and compute the trend boost from the rate at
+    /// which the temperature rose over the window. The rate is only computed
+    /// once the readings span at least half of the window to avoid reacting to
+    /// noise.
+    fn update_trend(zone_config: &Zone, state: &mut ZoneState, temp: f32, now: Instant) {
+        let config = match &zone_config.trend_boost {
+            Some(c) => c,
+            None => return,
+        };
+
+        let window = config.window.0.to_duration();
+
+        state.temp_history.retain(|(t, _)| now.duration_since(*t) <= window);
+        state.temp_history.push_back((now, temp));
+
+        let (oldest_time, oldest_temp) = state.temp_history[0];
+        let elapsed = now.duration_since(oldest_time);
+
+        state.temp_trend = if elapsed >= window / 2 && temp.is_finite() && oldest_temp.is_finite() {
+            Some((temp - oldest_temp) / elapsed.as_secs_f32() * 60.0)
+        } else {
+            None
+        };
+
+        state.trend_boost = match state.temp_trend {
+            Some(rate) if rate > 0.0 => {
+                (rate * f32::from(config.gain)).round().min(f32::from(config.max_boost.0)) as u8
+            }
+            _ => 0,
+        };
+    }
+
+    /// Get the duty cycle to apply while the startup grace period is active,
+    /// which is the initial duty cycle unless the emergency, stall, or override
+    /// duty cycle applies.
+    fn startup_grace_dcycle(zone_config: &Zone, state: &ZoneState, started: Instant, temp: f32, dcycle: u8) -> u8 {
+        let initial = match (zone_config.initial_dcycle, zone_config.startup_grace) {
+            (Some(d), Some(grace)) if started.elapsed() < grace.to_duration() => d,
+            _ => return dcycle,
+        };
+
+        if state.emergency || !state.stalled_fans.is_empty() || state.override_dcycle.is_some() {
+            return dcycle;
+        }
+
+        if zone_config.log_decisions {
+            info!(zone:% = zone_config.label(), temp = temp, dcycle = initial;
+                  "[{}] Decision: startup_grace holds duty cycle at {}% instead of {}%",
+                  zone_config.label(), initial, dcycle);
+        }
+
+        initial
+    }
+
+    /// Get the zone config that the duty cycle is computed from, which has the
+    /// active schedule's changes applied. Schedule changes are logged.
+    fn scheduled_config(zone_config: &Arc<Zone>, state: &mut ZoneState) -> Arc<Zone> {
+        let active = schedule::active(zone_config);
+
+        if state.schedule.as_ref().map(|(i, _)| *i) != active {
+            state.schedule = match active {
+                Some(i) => {
+                    let config = &zone_config.schedules[i];
+                    info!(zone:% = zone_config.label();
+                          "[{}] Schedule {} is active: {}",
+                          zone_config.label(), i, schedule::describe(config));
+                    Some((i, Arc::new(schedule::apply(zone_config, config))))
+                }
+                None => {
+                    info!(zone:% = zone_config.label();
+                          "[{}] No schedule is active; using the zone's steps and max_dcycle",
+                          zone_config.label());
+                    None
+                }
+            };
+        }
+
+        match &state.schedule {
+            Some((_, config)) => config.clone(),
+            None => zone_config.clone(),
+        }
+    }
+
+    /// Log each source reading for `log_decisions`.
+    fn log_readings(zone_config: &Zone, source_readings: &[(String, Option<f32>)]) {
+        for (source, reading) in source_readings {
+            let reading = match reading {
+                Some(r) => format!("{:.1}C", r),
+                None => "skipped".to_owned(),
+            };
+
+            info!(zone:% = zone_config.label(), sensor:% = source;
+                  "[{}] Decision: source {}: {}", zone_config.label(), source, reading);
+        }
+    }
+
+    /// Log how [`Self::target_duty_cycle`] arrived at the duty cycle for
+    /// `log_decisions`. This must be called before the new duty cycle is
+    /// stored in `state` so that the cool-down steps can be explained.
+    fn log_decision(zone_config: &Zone, state: &ZoneState, temp: f32, dcycle: u8) {
+        let label = zone_config.label();
+        let aggregation = match zone_config.aggregation {
+            Aggregation::Maximum => "maximum".to_owned(),
+            Aggregation::Average { top: Some(n) } => format!("average of top {}", n),
+            Aggregation::Average { top: None } => "average".to_owned(),
+            Aggregation::Weighted => "weighted average".to_owned(),
+        };
+
+        info!(zone:% = label, temp = temp;
+              "[{}] Decision: {} of sources: {:.1}C", label, aggregation, temp);
+
+        let reason = if state.emergency {
+            "emergency_temp exceeded".to_owned()
+        } else if !state.stalled_fans.is_empty() {
+            format!("stalled fans {:?}", state.stalled_fans)
+        } else if let Some(d) = state.override_dcycle {
+            format!("override of {}%", d)
+        } else if let Some(t) = zone_config.stop_temp.filter(|t| temp <= f32::from(*t)) {
+            format!("at or below stop_temp {}C", t)
+        } else {
+            let (curve, computed) = if !zone_config.rpm_steps.is_empty() {
+                let target = curve::calc_target_rpm(zone_config, temp);
+                let rpm_range = Self::describe_bracket(
+                    &zone_config.rpm_steps.iter().map(|s| (s.temp, s.rpm.to_string())).collect::<Vec<_>>(),
+                    temp,
+                );
+
+                info!(zone:% = label, temp = temp;
+                      "[{}] Decision: rpm_steps {}: target {} RPM", label, rpm_range, target);
+
+                // The duty cycle before the limits is logged by calc_rpm_duty_cycle()
+                ("rpm_steps".to_owned(), dcycle)
+            } else {
+                let computed = curve::calc_duty_cycle(&zone_config.steps, temp);
+                let step_range = Self::describe_bracket(
+                    &zone_config.steps.iter().map(|s| (s.temp, format!("{}%", s.dcycle))).collect::<Vec<_>>(),
+                    temp,
+                );
+
+                info!(zone:% = label, temp = temp, dcycle = computed;
+                      "[{}] Decision: steps {}: {}%", label, step_range, computed);
+
+                let cooldown = curve::calc_step_duty_cycle(zone_config, state.dcycle, temp);
+                let (curve, computed) = if cooldown == computed {
+                    ("steps".to_owned(), computed)
+                } else {
+                    info!(zone:% = label, temp = temp, dcycle = cooldown;
+                          "[{}] Decision: cooldown_steps hold duty cycle at {}% (previously {}%)",
+                          label, cooldown, state.dcycle.unwrap_or_default());
+
+                    ("cooldown_steps".to_owned(), cooldown)
+                };
+
+                let (curve, computed) = if state.trend_boost > 0 {
+                    info!(zone:% = label, temp = temp, dcycle = computed.saturating_add(state.trend_boost);
+                          "[{}] Decision: trend_boost: rising {:.1}C/min: +{}%",
+                          label, state.temp_trend.unwrap_or_default(), state.trend_boost);
+
+                    (format!("{} + trend_boost", curve), computed.saturating_add(state.trend_boost))
+                } else {
+                    (curve, computed)
+                };
+
+                match state.script_dcycle {
+                    Some(d) => {
+                        info!(zone:% = label, temp = temp, dcycle = d;
+                              "[{}] Decision: script returned {}% instead of {}% from {}",
+                              label, d, computed, curve);
+
+                        ("script".to_owned(), d)
+                    }
+                    None => (curve, computed),
+                }
+            };
+
+            if computed < zone_config.min_dcycle.0 {
+                format!("{} raised from {}% to min_dcycle", curve, computed)
+            } else if computed > zone_config.max_dcycle.0 {
+                format!("{} lowered from {}% to max_dcycle", curve, computed)
+            } else {
+                curve
+            }
+        };
+
+        if let Some(damped) = state.damped_dcycle {
+            info!(zone:% = label, temp = temp, dcycle = dcycle;
+                  "[{}] Decision: oscillation_damping holds duty cycle at {}% instead of {}%",
+                  label, dcycle, damped);
+        }
+
+        info!(zone:% = label, temp = temp, dcycle = dcycle;
+              "[{}] Decision: {}% ({})", label, dcycle, reason);
+    }
+
+    /// Describe which of the `(temp, value)` steps bracket the temperature.
+    fn describe_bracket(steps: &[(i8, String)], temp: f32) -> String {
+        let i = steps.partition_point(|(t, _)| f32::from(*t) < temp);
+
+        if steps.is_empty() {
+            "empty".to_owned()
+        } else if i == 0 {
+            format!("at or below first step {}C={}", steps[0].0, steps[0].1)
+        } else if i == steps.len() {
+            format!("above last step {}C={}", steps[i - 1].0, steps[i - 1].1)
+        } else {
+            format!("between {}C={} and {}C={}", steps[i - 1].0, steps[i - 1].1, steps[i].0, steps[i].1)
+        }
+    }
+
+    /// Read the zone's fan sensors and check for fans that report 0 RPM even
+    /// though the previously applied duty cycle is non-zero. Failing to read
+    /// the sensors is logged and clears the RPM readings, but otherwise leaves
+    /// the stall state unchanged.
+    async fn check_fans(session: Option<&IpmiSession>, zone_config: &Zone, state: &mut ZoneState) {
+        let session = match session {
+            Some(s) if !zone_config.fan_sensors.is_empty() => s,
+            _ => return,
+        };
+
+        let rpms = session.ipmi.run(|ipmi| ipmi.get_fan_readings()).await
+            .map_err(Error::from)
+            .and_then(|r| Self::get_fan_rpms(&r, zone_config));
+
+        let rpms = match rpms {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(zone:% = zone_config.label();
+                      "[{}] Failed to read fan sensors: {}", zone_config.label(), e);
+                state.fan_rpms = None;
+                return;
+            }
+        };
+
+        let stalled: Vec<_> = if state.dcycle.unwrap_or(0) == 0 {
+            vec![]
+        } else {
+            zone_config.fan_sensors
+                .iter()
+                .zip(&rpms)
+                .filter(|(_, rpm)| **rpm < 1.0)
+                .map(|(sensor, _)| sensor.clone())
+                .collect()
+        };
+
+        if stalled != state.stalled_fans {
+            if stalled.is_empty() {
+                info!(zone:% = zone_config.label();
+                      "[{}] No stalled fans detected anymore", zone_config.label());
+                state.alerts.push(Alert::new(AlertEvent::FanStallCleared, zone_config,
+                    "No stalled fans detected anymore".to_owned()));
+            } else {
+                error!(zone:% = zone_config.label(), sensor:% = stalled.join(",");
+                       "[{}] Stalled fans detected: {:?}; boosting remaining fans to 100%",
+                       zone_config.label(), stalled);
+                state.alerts.push(Alert::new(AlertEvent::FanStall, zone_config, format!(
+                    "Stalled fans detected: {}", stalled.join(", "))).with_dcycle(100));
+            }
+        }
+
+        state.stalled_fans = stalled;
+        state.fan_rpms = Some(rpms);
+    }
+
+    /// Get the RPM readings of the zone's fan sensors in the same order as
+    /// `fan_sensors`.
+    fn get_fan_rpms(
+        readings: &HashMap<String, Option<SensorReading>>,
+        zone_config: &Zone,
+    ) -> Result<Vec<f64>> {
+        zone_config.fan_sensors
+            .iter()
+            .map(|sensor| {
+                let reading = match readings.get(sensor) {
+                    Some(Some(r)) => r,
+                    Some(None) => return Err(Error::SensorNoReading(sensor.clone())),
+                    None => {
+                        return Err(Error::SensorNotFound {
+                            sensor: sensor.clone(),
+                            suggestions: suggest_names(sensor, readings.keys()),
+                        });
+                    }
+                };
+
+                if reading.units != SensorUnits::Rpm {
+                    return Err(Error::SensorBadUnits {
+                        sensor: sensor.clone(),
+                        units: reading.units,
+                    });
+                }
+
+                reading.value.as_f64().ok_or_else(|| Error::SensorBadValue {
+                    sensor: sensor.clone(),
+                    value: reading.value,
+                })
+            })
+            .collect()
+    }
+
+    /// Adjust the previously applied duty cycle so that the average fan speed
+    /// approaches the target RPM. The duty cycle is scaled by the ratio of the
+    /// target and measured speeds, but changes by at most
+    /// [`RPM_MAX_DCYCLE_STEP`] per iteration. If the fan speeds are unknown,
+    /// the previous duty cycle is kept.
+    fn calc_rpm_duty_cycle(zone_config: &Zone, state: &ZoneState, temp: f32) -> u8 {
+        let target = curve::calc_target_rpm(zone_config, temp);
+        let dcycle_cur = state.dcycle.unwrap_or(100);
+
+        let rpms = match &state.fan_rpms {
+            Some(r) if !r.is_empty() => r,
+            _ => return dcycle_cur,
+        };
+        let rpm = rpms.iter().sum::<f64>() / rpms.len() as f64;
+
+        let dcycle_ideal = if rpm < 1.0 || dcycle_cur == 0 {
+            if target == 0 { 0.0 } else { 100.0 }
+        } else {
+            f64::from(dcycle_cur) * f64::from(target) / rpm
+        };
+
+        let min = dcycle_cur.saturating_sub(RPM_MAX_DCYCLE_STEP);
+        let max = dcycle_cur.saturating_add(RPM_MAX_DCYCLE_STEP).min(100);
+        let dcycle_new = (dcycle_ideal.round().clamp(0.0, 100.0) as u8).clamp(min, max);
+
+        debug!(zone:% = zone_config.label(), temp = temp, dcycle = dcycle_new;
+               "[{}] target_rpm={}, avg_rpm={:.0}, dcycle_cur={}%, dcycle_new={}%",
+               zone_config.label(), target, rpm, dcycle_cur, dcycle_new);
+
+        dcycle_new
+    }
+
+    /// If the fans are starting from a stop, briefly apply the zone's spin-up
+    /// duty cycle (or the new duty cycle if it is higher) so that the fans
+    /// start reliably.
+    async fn spin_up(
+        session: Option<&IpmiSession>,
+        pwm_fans: &mut [PwmFan],
+        zone_config: &Zone,
+        dcycle_prev: Option<u8>,
+        temp: f32,
+        dcycle_new: u8,
+    ) -> Result<()> {
+        let spinup = match &zone_config.spinup {
+            Some(s) if dcycle_prev == Some(0) && dcycle_new > 0 => s,
+            _ => return Ok(()),
+        };
+        let dcycle = spinup.dcycle.max(dcycle_new);
+
+        info!(zone:% = zone_config.label(), temp = temp, dcycle = dcycle;
+              "[{}] Spinning up fans at {}% for {:?}",
+              zone_config.label(), dcycle, spinup.duration.to_duration());
+
+        Self::apply_duty_cycle(session, pwm_fans, zone_config, Some(temp), dcycle, true).await?;
+        sleep(spinup.duration.to_duration()).await;
+
+        Ok(())
+    }
+
+    /// Set the zone's fans to the specified duty cycle. `temp` is the
+    /// temperature the duty cycle was computed from or [`None`] if the failsafe
+    /// duty cycle is being applied.
+    async fn apply_duty_cycle(
+        session: Option<&IpmiSession>,
+        pwm_fans: &mut [PwmFan],
+        zone_config: &Zone,
+        temp: Option<f32>,
+        dcycle_new: u8,
+        log_status: bool,
+    ) -> Result<()> {
+        // The journal field is left empty for the failsafe duty cycle
+        let temp_field = temp.map(|t| t.to_string()).unwrap_or_default();
+        let temp = temp.map_or_else(|| "failsafe".to_owned(), |t| format!("{:.1}C", t));
+
+        match &zone_config.output {
+            Output::Ipmi => {
+                // Config validation guarantees that IPMI output has a session
+                let session = session.expect("IPMI session required for IPMI output");
+                let label = zone_config.label();
+                let ipmi_zones = zone_config.ipmi_zones.clone();
+
+                session.reacquire().await?;
+                // The duty cycles below are re-applied if they were reset
+                // along with the fan mode
+                session.verify_fan_mode().await;
+
+                let mut delays = zone_config.retry_policy().delays();
+                let mut attempt = 1;
+
+                loop {
+                    let (label, ipmi_zones, temp, temp_field) =
+                        (label.clone(), ipmi_zones.clone(), temp.clone(), temp_field.clone());
+
+                    let result = session.ipmi.run(move |ipmi| -> Result<()> {
+                        for z in ipmi_zones {
+                            let dcycle_cur = ipmi.update_duty_cycle(z, dcycle_new)?;
+
+                            if log_status {
+                                debug!(zone:% = label, temp:% = temp_field, dcycle = dcycle_new;
+                                       "[{}] Zone {}: zone_temp={}, dcycle_cur={}%, dcycle_new={}%",
+                                       label, z, temp,
+                                       dcycle_cur.map_or_else(|| "?".to_owned(), |d| d.to_string()),
+                                       dcycle_new);
+                            }
+                        }
+
+                        Ok(())
+                    }).await;
+
+                    match (result, delays.next()) {
+                        (Ok(()), _) => break,
+                        (Err(e), Some(delay)) => {
+                            warn!(zone:% = zone_config.label();
+                                  "[{}] Failed to set duty cycle (attempt {}); retrying in {:?}: {}",
+                                  zone_config.label(), attempt, delay, e);
+                            sleep(delay).await;
+                            attempt += 1;
+                        }
+                        (Err(e), None) if attempt > 1 => {
+                            return Err(Error::RetriesFailed { attempts: attempt, source: Box::new(e) });
+                        }
+                        (Err(e), None) => return Err(e),
+                    }
+                }
+
+                session.command_succeeded();
+            }
+            Output::Hwmon { .. } => {
+                for fan in pwm_fans {
+                    let dcycle_cur = fan.get_duty_cycle()?;
+
+                    if log_status {
+                        debug!(zone:% = zone_config.label(), temp:% = temp_field, dcycle = dcycle_new;
+                               "[{}] PWM {}: zone_temp={}, dcycle_cur={}%, dcycle_new={}%",
+                               zone_config.label(), fan.path().display(), temp, dcycle_cur, dcycle_new);
+                    }
+
+                    if dcycle_new != dcycle_cur {
+                        fan.set_duty_cycle(dcycle_new)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the per-iteration status message should be logged. With
+    /// `log_on_change_only`, it is only logged when the temperature (rounded
+    /// to a tenth of a degree) or the duty cycle changed, or when the
+    /// `log_heartbeat` interval has elapsed since it was last logged.
+    fn should_log_status(zone_config: &Zone, state: &mut ZoneState, temp: f32, dcycle: u8) -> bool {
+        if !zone_config.log_on_change_only {
+            return true;
+        }
+
+        let temp = (temp * 10.0).round() as i32;
+        let now = Instant::now();
+
+        let log = match state.last_status {
+            Some((t, d, _)) if t != temp || d != dcycle => true,
+            Some((_, _, logged)) => zone_config.log_heartbeat
+                .is_some_and(|h| now.duration_since(logged) >= h.to_duration()),
+            None => true,
+        };
+
+        if log {
+            state.last_status = Some((temp, dcycle, now));
+        }
+
+        log
+    }
+
+    /// Get the temperature readings in degrees Celsius of all of the zone's
+    /// sources, retrying each source according to its retry settings. The
+    /// sources are queried on a blocking thread, which takes ownership of the
+    /// cache and hands it back alongside the result. The outer error is only
+    /// returned if the thread panicked.
+    async fn get_readings(
+        session: Option<Arc<IpmiSession>>,
+        zone_config: Arc<Zone>,
+        mut cache: SourceCache,
+    ) -> Result<(Result<Vec<Option<f32>>>, SourceCache)> {
+        task::spawn_blocking(move || {
+            let ipmi = session.as_ref().map(|s| &s.ipmi);
+            let readings = get_cached_source_readings(
+                &zone_config.label(), ipmi, &zone_config.sources, &zone_config.retry_policy(), &mut cache,
+            ).and_then(|readings| {
+                if readings.iter().all(Option::is_none) {
+                    return Err(Error::NoReadings);
+                }
+
+                Ok(readings)
+            });
+
+            (readings, cache)
+        }).await.map_err(Error::LoopPanicked)
+    }
+
+    /// Run the zone's critical command if any source has exceeded the critical
+    /// temperature for the configured duration. The command runs in the
+    /// background at most once until all sources drop to or below the critical
+    /// temperature again.
+    fn check_critical(
+        zone_config: &Zone,
+        readings: &[Option<f32>],
+        state: &mut CriticalState,
+        alerts: &mut Vec<Alert>,
+    ) {
+        let critical = match &zone_config.critical {
+            Some(c) => c,
+            None => return,
+        };
+
+        let hottest = readings.iter().flatten().copied().reduce(f32::max)
+            .filter(|t| *t > f32::from(critical.temp));
+
+        let hottest = match hottest {
+            Some(t) => t,
+            None => {
+                if state.since.take().is_some() {
+                    info!(zone:% = zone_config.label();
+                          "[{}] Source temperatures are below critical temp {}C again",
+                          zone_config.label(), critical.temp);
+                    alerts.push(Alert::new(AlertEvent::CriticalCleared, zone_config, format!(
+                        "Source temperatures are below critical temp {}C again", critical.temp)));
+                }
+                state.triggered = false;
+                return;
+            }
+        };
+
+        let since = *state.since.get_or_insert_with(|| {
+            error!(zone:% = zone_config.label(), temp = hottest;
+                   "[{}] CRITICAL: Source temperature {:.1}C exceeds critical temp {}C",
+                   zone_config.label(), hottest, critical.temp);
+            alerts.push(Alert::new(AlertEvent::Critical, zone_config, format!(
+                "Source temperature {:.1}C exceeds critical temp {}C",
+                hottest, critical.temp)).with_temp(hottest));
+            Instant::now()
+        });
+
+        let duration = critical.duration.map_or(Duration::ZERO, Interval::to_duration);
+        if state.triggered || since.elapsed() < duration {
+            return;
+        }
+
+        state.triggered = true;
+
+        error!(zone:% = zone_config.label();
+               "[{}] CRITICAL: Temperature exceeded {}C for {:?}; running: {:?}",
+               zone_config.label(), critical.temp, duration, critical.command);
+
+        let child = tokio::process::Command::new(&critical.command[0])
+            .args(&critical.command[1..])
+            .spawn();
+        let mut child = match child {
+            Ok(c) => c,
+            Err(e) => {
+                error!(zone:% = zone_config.label();
+                       "[{}] Failed to run critical command: {}", zone_config.label(), e);
+                return;
+            }
+        };
+
+        // The command may take a while, like `shutdown -h +5`, so wait for it
+        // in the background to keep controlling the fans
+        let label = zone_config.label();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => error!(zone:% = label;
+                                     "[{}] Critical command failed: {}", label, status),
+                Err(e) => error!(zone:% = label;
+                                 "[{}] Failed to wait for critical command: {}", label, e),
+            }
+        });
+    }
+
+}
+
+fn bool_env(name: &str, default: bool) -> bool {
+    let value = env::var(name);
+
+    match value {
+        Ok(v) if v == "true" || v == "t" || v == "yes" || v == "y" || v == "1" => true,
+        Ok(v) if v == "false" || v == "f" || v == "no" || v == "n" || v == "0" => false,
+        _ => default,
+    }
+}
+
+/// Fan mode that can be set with the `set-mode` subcommand.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FanModeArg {
+    Standard,
+    Full,
+    Optimal,
+    HeavyIo,
+}
+
+impl From<FanModeArg> for FanMode {
+    fn from(mode: FanModeArg) -> Self {
+        match mode {
+            FanModeArg::Standard => Self::Standard,
+            FanModeArg::Full => Self::Full,
+            FanModeArg::Optimal => Self::Optimal,
+            FanModeArg::HeavyIo => Self::HeavyIo,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the available IPMI temperature, fan, and electrical sensors and exit
+    Sensors {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+        /// Discard the SDR cache and rebuild it from the BMC's SDR. This is
+        /// needed if sensors are missing after a BMC firmware update.
+        #[clap(long)]
+        refresh_sdr: bool,
+    },
+    /// List the lower thresholds (in RPM) of the BMC's fan sensors and exit
+    FanThresholds {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+    },
+    /// Set the fan mode once and exit. The mode is not restored afterwards.
+    SetMode {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+        /// Fan mode to set
+        #[clap(value_enum)]
+        mode: FanModeArg,
+    },
+    /// Set the duty cycle of an IPMI zone once and exit. The fan mode usually
+    /// needs to be `full` for the duty cycle to stick.
+    SetDuty {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+        /// IPMI zone
+        zone: u8,
+        /// Duty cycle percentage
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        dcycle: u8,
+    },
+    /// Read the duty cycle of each candidate IPMI zone and list the zones that
+    /// respond. Configured zones that don't respond are reported.
+    ProbeZones {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+    },
+    /// Probe the BMC and local disks and print a commented starter config. The
+    /// config file does not need to exist unless a non-default session is used.
+    GenerateConfig {
+        /// Name of the session from the config file to use
+        #[clap(short, long, default_value = "default")]
+        session: String,
+    },
+    /// Print the duty cycle that a zone would apply at each temperature
+    Curve {
+        /// Index or name of the zone
+        zone: String,
+        /// Lowest temperature to show (defaults to 5C below the first step)
+        #[clap(long, allow_hyphen_values = true)]
+        from: Option<i8>,
+        /// Highest temperature to show (defaults to 5C above the last step)
+        #[clap(long, allow_hyphen_values = true)]
+        to: Option<i8>,
+        /// Draw an ASCII plot instead of printing a table
+        #[clap(long)]
+        plot: bool,
+    },
+    /// Replay temperatures through a zone's duty cycle logic without touching
+    /// any fans. Each input line is one iteration with either a single
+    /// temperature or one comma-separated temperature per source.
+    Simulate {
+        /// Index or name of the zone
+        zone: String,
+        /// File containing the temperatures (defaults to stdin)
+        input: Option<PathBuf>,
+    },
+    /// Query the status of the running daemon via the control socket
+    Status,
+    /// Set a zone's duty cycle to a fixed value via the control socket
+    SetOverride {
+        /// Index or name of the zone
+        zone: String,
+        /// Duty cycle percentage
+        dcycle: u8,
+        /// Clear the override after this duration (eg. `30m`)
+        #[clap(long = "for", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Clear a zone's duty cycle override via the control socket
+    ClearOverride {
+        /// Index or name of the zone
+        zone: String,
+    },
+    /// Pause a zone via the control socket, leaving its fans at the current
+    /// duty cycle
+    Pause {
+        /// Index or name of the zone
+        zone: String,
+    },
+    /// Resume a paused zone via the control socket
+    Resume {
+        /// Index or name of the zone
+        zone: String,
+    },
+    /// Make the running daemon reload its config via the control socket
+    Reload,
+    /// Validate the config and show what each zone would do without changing
+    /// the fan mode or duty cycles
+    Check,
+    /// Print a JSON Schema for the config file that editors and CI can use to
+    /// validate configs and exit
+    PrintSchema,
+}
+
+#[derive(Debug, Parser)]
+struct Opt {
+    /// Path to config file
+    #[clap(short, long, global = true, default_value = "/etc/ipmi-fan-control.toml")]
+    config: PathBuf,
+
+    /// Command to run instead of the fan control daemon
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Print the name, value, and units of every temperature, fan, and electrical
+/// sensor reported by the IPMI session. This does not change the fan mode or
+/// any duty cycles. If `refresh_sdr` is true, the SDR cache is rebuilt first.
+fn list_sensors(config: &Config, name: &str, refresh_sdr: bool) -> Result<()> {
+    let session = config.sessions.0.get(name)
+        .ok_or_else(|| Error::SessionNotFound(name.to_owned()))?;
+
+    let mut ipmi = Ipmi::new(session)?;
+    if refresh_sdr {
+        ipmi.clear_sdr_cache()?;
+    }
+    let mut readings = vec![];
+    for sensor_type in [
+        SensorType::Temperature,
+        SensorType::Fan,
+        SensorType::Voltage,
+        SensorType::Current,
+        SensorType::Power,
+    ] {
+        readings.extend(ipmi.get_sensor_readings(sensor_type)?);
+    }
+    readings.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let width = readings.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+    for (name, reading) in readings {
+        match reading {
+            Some(r) => println!("{:width$}  {} {}", name, r.value, r.units, width = width),
+            None => println!("{:width$}  (no reading)", name, width = width),
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the lower thresholds of every fan sensor in the BMC's SDR.
+fn list_fan_thresholds(config: &Config, name: &str) -> Result<()> {
+    let session = config.sessions.0.get(name)
+        .ok_or_else(|| Error::SessionNotFound(name.to_owned()))?;
+
+    let mut ipmi = Ipmi::new(session)?;
+    let thresholds = ipmi.get_fan_thresholds()?;
+
+    let width = thresholds.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+    for (name, t) in thresholds {
+        println!("{:width$}  {}", name, t, width = width);
+    }
+
+    Ok(())
+}
+
+/// Set the fan mode of a session once.
+fn set_fan_mode(config: &Config, name: &str, mode: FanMode) -> Result<()> {
+    let session = config.sessions.0.get(name)
+        .ok_or_else(|| Error::SessionNotFound(name.to_owned()))?;
+
+    let mut ipmi = Ipmi::new(session)?;
+    let orig_mode = ipmi.get_fan_mode()?;
+    ipmi.set_fan_mode(mode)?;
+
+    println!("Fan mode: {:?} -> {:?}", orig_mode, mode);
+
+    Ok(())
+}
+
+/// Set the duty cycle of an IPMI zone once.
+fn set_duty_cycle(config: &Config, name: &str, zone: u8, dcycle: u8) -> Result<()> {
+    let session = config.sessions.0.get(name)
+        .ok_or_else(|| Error::SessionNotFound(name.to_owned()))?;
+
+    let mut ipmi = Ipmi::new(session)?;
+    let orig_dcycle = match ipmi.get_duty_cycle(zone) {
+        Ok(d) => format!("{}%", d),
+        Err(ipmi::Error::DutyCycleUnknown(_)) => "?".to_owned(),
+        Err(e) => return Err(e.into()),
+    };
+    ipmi.set_duty_cycle(zone, dcycle)?;
+
+    println!("Zone {} duty cycle: {} -> {}%", zone, orig_dcycle, dcycle);
+
+    Ok(())
+}
+
+/// List the IPMI zones that respond to duty cycle reads and report the zones
+/// in the config that use the session, but don't respond.
+fn probe_zones(config: &Config, name: &str) -> Result<()> {
+    let session = config.sessions.0.get(name)
+        .ok_or_else(|| Error::SessionNotFound(name.to_owned()))?;
+
+    let mut ipmi = Ipmi::new(session)?;
+    let found = ipmi.probe_zones(ipmi::MAX_PROBE_ZONES);
+
+    if found.is_empty() {
+        println!("No IPMI zones responded to duty cycle reads");
+    }
+    for (z, dcycle) in &found {
+        println!("IPMI zone {}: dcycle_cur={}%", z, dcycle);
+    }
+
+    let mut missing = 0;
+
+    for (i, zone_config) in config.zones.iter().enumerate() {
+        if zone_config.session.0 != name || !matches!(zone_config.output, Output::Ipmi) {
+            continue;
+        }
+
+        for z in &zone_config.ipmi_zones {
+            if !found.iter().any(|(f, _)| f == z) {
+                println!("Warning: zones[{}] ({}) uses IPMI zone {}, which did not respond",
+                         i, zone_config.label(), z);
+                missing += 1;
+            }
+        }
+    }
+
+    if missing > 0 {
+        return Err(Error::ZonesMissing(missing));
+    }
+
+    Ok(())
+}
+
+/// Look up a zone in the config by index or name.
+fn find_zone<'a>(config: &'a Config, zone: &str) -> Result<(usize, &'a Zone)> {
+    match ZoneRef::parse(zone) {
+        ZoneRef::Index(i) => config.zones.get(i).map(|z| (i, z)),
+        ZoneRef::Name(n) => config.zones.iter()
+            .enumerate()
+            .find(|(_, z)| z.name.as_ref() == Some(&n)),
+    }.ok_or_else(|| Error::ZoneNotFound(zone.to_owned()))
+}
+
+/// Compute a zone's duty cycle for a temperature with the same code as the zone
+/// loop. `prev_dcycle` is the previously applied duty cycle, which determines
+/// whether the cool-down steps are used. Returns [`None`] if the emergency
+/// temperature is exceeded.
+fn curve_duty_cycle(zone_config: &Zone, temp: i8, prev_dcycle: Option<u8>) -> Option<u8> {
+    if zone_config.emergency_temp.is_some_and(|t| temp > t) {
+        return None;
+    }
+
+    let mut state = ZoneState {
+        dcycle: prev_dcycle,
+        ..Default::default()
+    };
+    let readings = vec![Some(f32::from(temp)); zone_config.sources.len()];
+
+    Some(MainApp::target_duty_cycle(zone_config, &mut state, readings, Instant::now()).1)
+}
+
+/// Print the duty cycle (or target RPM) that a zone would use at each
+/// temperature in the range, either as a table or as an ASCII plot. If the zone
+/// has cool-down steps, the duty cycle used while cooling down from 100% is
+/// shown as well.
+fn print_curve(config: &Config, zone: &str, from: Option<i8>, to: Option<i8>, plot: bool) -> Result<()> {
+    let (index, zone_config) = find_zone(config, zone)?;
+
+    let step_temps = zone_config.steps.iter().map(|s| s.temp)
+        .chain(zone_config.cooldown_steps.iter().map(|s| s.temp))
+        .chain(zone_config.rpm_steps.iter().map(|s| s.temp));
+    let from = from.unwrap_or_else(|| step_temps.clone().min().unwrap_or(30).saturating_sub(5));
+    let to = to.unwrap_or_else(|| step_temps.max().unwrap_or(80).saturating_add(5));
+
+    println!("Zone {} ({}):", index, zone_config.label());
+
+    if !zone_config.rpm_steps.is_empty() {
+        // The duty cycle depends on the measured fan speed, so only the target
+        // speed can be shown
+        println!("{:>6}  {:>10}", "Temp", "Target RPM");
+
+        for temp in from..=to {
+            println!("{:>5}C  {:>10}", temp, curve::calc_target_rpm(zone_config, f32::from(temp)));
+        }
+
+        return Ok(());
+    }
+
+    let cooldown = !zone_config.cooldown_steps.is_empty();
+    let rows: Vec<_> = (from..=to)
+        .map(|t| (t, curve_duty_cycle(zone_config, t, None), curve_duty_cycle(zone_config, t, Some(100))))
+        .collect();
+    let format_dcycle = |d: Option<u8>| d.map_or_else(|| "100% (emergency)".to_owned(), |d| format!("{}%", d));
+
+    if !plot {
+        if cooldown {
+            println!("{:>6}  {:>8}  {:>9}", "Temp", "Dcycle", "Cool-down");
+        } else {
+            println!("{:>6}  {:>8}", "Temp", "Dcycle");
+        }
+
+        for (temp, dcycle, cooldown_dcycle) in rows {
+            if cooldown {
+                println!("{:>5}C  {:>8}  {:>9}", temp, format_dcycle(dcycle), format_dcycle(cooldown_dcycle));
+            } else {
+                println!("{:>5}C  {:>8}", temp, format_dcycle(dcycle));
+            }
+        }
+
+        return Ok(());
+    }
+
+    // One row per 5% and one column per degree
+    for level in (0..=20).rev().map(|l| l * 5) {
+        let line: String = rows.iter()
+            .map(|(_, d, c)| {
+                let row = |d: &Option<u8>| (d.unwrap_or(100) + 2) / 5 * 5 == level;
+                if row(d) {
+                    '*'
+                } else if cooldown && row(c) {
+                    '.'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+
+        println!("{:>4}% |{}", level, line.trim_end());
+    }
+
+    println!("      +{}", "-".repeat(rows.len()));
+    println!("       {:<width$}{}C", format!("{}C", from), to, width = rows.len().saturating_sub(to.to_string().len() + 1));
+
+    if cooldown {
+        println!("       (* = heating up, . = cooling down from 100%)");
+    }
+
+    Ok(())
+}
+
+/// Parse a line of simulation input into one reading per source. A single value
+/// is used for all of the zone's sources. Otherwise, there must be one
+/// comma-separated value per source, where an empty value is treated like a
+/// skipped optional source.
+fn parse_simulation_line(zone_config: &Zone, line_num: usize, line: &str) -> Result<Vec<Option<f32>>> {
+    let invalid = |reason: String| Error::SimulationInput { line: line_num, reason };
+    let fields: Vec<_> = line.split(',').map(str::trim).collect();
+    let num_sources = zone_config.sources.len();
+
+    if fields.len() != 1 && fields.len() != num_sources {
+        return Err(invalid(format!(
+            "Expected a single value or one value per source ({}), but found {}",
+            num_sources, fields.len())));
+    }
+
+    let readings = fields.iter()
+        .map(|f| {
+            if f.is_empty() {
+                return Ok(None);
+            }
+
+            match f.parse::<f32>() {
+                Ok(t) if t.is_finite() => Ok(Some(t)),
+                Ok(_) => Err(invalid(format!("Temperature is not finite: {:?}", f))),
+                Err(e) => Err(invalid(format!("{:?}: {}", f, e))),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if readings.iter().all(Option::is_none) {
+        return Err(invalid("No temperature readings".to_owned()));
+    } else if readings.len() == 1 {
+        return Ok(vec![readings[0]; num_sources]);
+    }
+
+    Ok(readings)
+}
+
+/// Replay temperatures through a zone's duty cycle logic and print the duty
+/// cycle that would be applied at each iteration. Each non-empty line of the
+/// input (or stdin if [`None`] or `-`) is one iteration. Lines starting with
+/// `#` are ignored. Nothing is queried and no fans are changed.
+///
+/// Zones that target an RPM adjust the duty cycle based on the measured fan
+/// speed, so only the target speed is shown for them.
+fn simulate_zone(config: &Config, zone: &str, input: Option<&Path>) -> Result<()> {
+    let (index, zone_config) = find_zone(config, zone)?;
+
+    let reader: Box<dyn BufRead> = match input {
+        Some(path) if path != Path::new("-") => {
+            let file = File::open(path)
+                .map_err(|e| Error::Io { path: path.to_owned(), source: e })?;
+            Box::new(BufReader::new(file))
+        }
+        _ => Box::new(io::stdin().lock()),
+    };
+    let input_path = input.unwrap_or_else(|| Path::new("-"));
+
+    let rpm = !zone_config.rpm_steps.is_empty();
+    let mut state = ZoneState {
+        script: MainApp::load_script(zone_config)?,
+        ..Default::default()
+    };
+
+    println!("Zone {} ({}):", index, zone_config.label());
+    if rpm {
+        println!("{:>5}  {:>7}  {:>10}  Notes", "Iter", "Temp", "Target RPM");
+    } else {
+        println!("{:>5}  {:>7}  {:>6}  Notes", "Iter", "Temp", "Dcycle");
+    }
+
+    // Each line is one interval after the previous one
+    let start = Instant::now();
+    let mut iteration = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::Io { path: input_path.to_owned(), source: e })?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let readings = parse_simulation_line(zone_config, i + 1, line)?;
+        iteration += 1;
+
+        let now = start + zone_config.interval.to_duration() * iteration;
+        let (temp, dcycle) = MainApp::target_duty_cycle(zone_config, &mut state, readings, now);
+        // Alerts are only relevant to the zone loop
+        state.alerts.clear();
+
+        let mut notes = vec![];
+        if state.emergency {
+            notes.push("emergency".to_owned());
+        } else if zone_config.stop_temp.is_some_and(|t| temp <= f32::from(t)) {
+            notes.push("stopped".to_owned());
+        } else if state.script_dcycle.is_some() {
+            notes.push("script".to_owned());
+        } else if !rpm && state.trend_boost > 0 {
+            notes.push(format!("trend +{}%", state.trend_boost));
+        } else if let Some(damped) = state.damped_dcycle {
+            notes.push(format!("damped from {}%", damped));
+        } else if !rpm && dcycle > curve::calc_duty_cycle(&zone_config.steps, temp)
+            .clamp(zone_config.min_dcycle.0, zone_config.max_dcycle.0)
+        {
+            notes.push("cool-down".to_owned());
+        }
+        if let Some(spinup) = &zone_config.spinup {
+            if state.dcycle == Some(0) && dcycle > 0 {
+                notes.push(format!("spin-up at {}%", spinup.dcycle.max(dcycle)));
+            }
+        }
+
+        if rpm {
+            let target = if state.emergency {
+                "100%".to_owned()
+            } else {
+                curve::calc_target_rpm(zone_config, temp).to_string()
+            };
+            let row = format!("{:>5}  {:>6.1}C  {:>10}  {}", iteration, temp, target, notes.join(", "));
+            println!("{}", row.trim_end());
+        } else {
+            let row = format!("{:>5}  {:>6.1}C  {:>5}%  {}", iteration, temp, dcycle, notes.join(", "));
+            println!("{}", row.trim_end());
+        }
+
+        state.dcycle = Some(dcycle);
+    }
+
+    Ok(())
+}
+
+/// Connect to every session used by the config, query each zone's sources, and
+/// report the duty cycle that would be applied. Everything is read-only: the fan
+/// mode and duty cycles are never changed. Problems are reported and counted
+/// instead of aborting the check.
+fn check_config(config: &Config) -> Result<()> {
+    let mut problems = 0;
+    let mut sessions = HashMap::new();
+
+    if let Some(profile) = &config.profile {
+        println!("Board profile: {}", profile);
+    }
+    for warning in &config.warnings {
+        println!("Warning: {}", warning);
+    }
+
+    for (name, session) in &config.sessions.0 {
+        if !MainApp::session_used(config, name) {
+            continue;
+        }
+
+        println!("Session {:?}:", name);
+
+        let controls_fans = !MainApp::session_ipmi_zones(config, name).is_empty();
+        let result = Ipmi::new(session).map_err(Error::from).and_then(|mut ipmi| {
+            let fan_mode = if controls_fans {
+                Some(ipmi.get_fan_mode()?)
+            } else {
+                None
+            };
+            Ok((ipmi, fan_mode))
+        });
+
+        match result {
+            Ok((ipmi, None)) => {
+                println!("  Only used for sensors");
+                sessions.insert(name.as_str(), IpmiWorker::new(name, ipmi));
+            }
+            Ok((ipmi, Some(fan_mode))) => {
+                match IpmiSession::fan_mode(session) {
+                    Some(target) if target != fan_mode => {
+                        println!("  Fan mode: {:?} (would be set to {:?})", fan_mode, target);
+                    }
+                    Some(_) => println!("  Fan mode: {:?}", fan_mode),
+                    None => println!("  Fan mode: {:?} (left untouched)", fan_mode),
+                }
+
+                sessions.insert(name.as_str(), IpmiWorker::new(name, ipmi));
+            }
+            Err(e) => {
+                println!("  Error: {}", e);
+                problems += 1;
+            }
+        }
+    }
+
+    for (i, zone_config) in config.zones.iter().enumerate() {
+        let name = zone_config.name.as_ref().map_or_else(String::new, |n| format!(" {:?}", n));
+
+        match &zone_config.output {
+            Output::Ipmi => println!("Zone {}{} (session {:?}, IPMI zones {:?}):",
+                                     i, name, zone_config.session.0, zone_config.ipmi_zones),
+            Output::Hwmon { pwm } => println!("Zone {}{} (PWM outputs {:?}):", i, name, pwm),
+        }
+
+        let ipmi = if zone_config.uses_ipmi() {
+            match sessions.get(zone_config.session.0.as_str()) {
+                Some(ipmi) => Some(ipmi),
+                None => {
+                    println!("  Skipped because session is unavailable");
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut readings = vec![];
+
+        for source in &zone_config.sources {
+            match get_source_readings(
+                &zone_config.label(), ipmi, slice::from_ref(source), &zone_config.retry_policy())
+            {
+                Ok(r) => {
+                    match r[0] {
+                        Some(t) => println!("  Source {}: {:.1}C", source, t),
+                        None => println!("  Source {}: Skipped (optional)", source),
+                    }
+                    readings.extend(r);
+                }
+                Err(e) => {
+                    println!("  Source {}: Error: {}", source, e);
+                    problems += 1;
+                }
+            }
+        }
+
+        if readings.len() == zone_config.sources.len() && readings.iter().any(Option::is_some) {
+            let hottest = curve::emergency_reading(zone_config, &readings);
+            let temp = curve::aggregate_readings(zone_config, readings);
+
+            match hottest {
+                Some(t) => println!("  zone_temp={:.1}C, dcycle_new=100% (emergency: {:.1}C exceeds {}C)",
+                                    temp, t, zone_config.emergency_temp.unwrap()),
+                None if zone_config.stop_temp.is_some_and(|t| temp <= f32::from(t)) => {
+                    println!("  zone_temp={:.1}C, dcycle_new=0% (stopped)", temp);
+                }
+                None if !zone_config.rpm_steps.is_empty() => {
+                    println!("  zone_temp={:.1}C, target_rpm={}",
+                             temp, curve::calc_target_rpm(zone_config, temp));
+                }
+                None => println!("  zone_temp={:.1}C, dcycle_new={}%",
+                                 temp, curve::calc_duty_cycle(&zone_config.steps, temp)
+                                     .clamp(zone_config.min_dcycle.0, zone_config.max_dcycle.0)),
+            }
+        }
+
+        match &zone_config.output {
+            Output::Ipmi => {
+                let ipmi = ipmi.unwrap();
+
+                for z in &zone_config.ipmi_zones {
+                    let z = *z;
+
+                    match ipmi.run_blocking(move |i| i.get_duty_cycle(z)) {
+                        Ok(dcycle_cur) => println!("  IPMI zone {}: dcycle_cur={}%", z, dcycle_cur),
+                        Err(ipmi::Error::DutyCycleUnknown(_)) => {
+                            println!("  IPMI zone {}: dcycle_cur=unknown (protocol can't read it)", z);
+                        }
+                        Err(e) => {
+                            println!("  IPMI zone {}: Error: {}", z, e);
+                            problems += 1;
+                        }
+                    }
+                }
+            }
+            Output::Hwmon { pwm } => {
+                for p in pwm {
+                    match hwmon::read_duty_cycle(Path::new(p)) {
+                        Ok(dcycle_cur) => println!("  PWM {}: dcycle_cur={}%", p, dcycle_cur),
+                        Err(e) => {
+                            println!("  PWM {}: Error: {}", p, e);
+                            problems += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(Error::CheckFailed(problems));
+    }
+
+    println!("No problems found");
+
+    Ok(())
+}
+
+/// Print the status of each zone reported by the running daemon.
+fn print_status(config: &Config) -> Result<()> {
+    let path = config.control_socket.as_ref()
+        .ok_or(Error::ControlSocketDisabled)?;
+
+    let zones = match control::query(path.as_ref(), &Request::Status)? {
+        Response::Status { zones } => zones,
+        Response::Error { message } => return Err(Error::ControlProtocol(message)),
+        r => return Err(Error::ControlProtocol(format!("Unexpected response: {:?}", r))),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    for zone in zones {
+        let name = zone.name.as_ref().map_or_else(String::new, |n| format!(" {:?}", n));
+
+        if zone.pwm.is_empty() {
+            println!("Zone {}{} (session {:?}, IPMI zones {:?}):",
+                     zone.index, name, zone.session, zone.ipmi_zones);
+        } else {
+            println!("Zone {}{} (PWM outputs {:?}):", zone.index, name, zone.pwm);
+        }
+
+        match (zone.temp, zone.dcycle) {
+            (_, Some(dcycle)) if zone.failsafe => {
+                println!("  Failsafe: dcycle={}%", dcycle);
+            }
+            (Some(temp), Some(dcycle)) if zone.emergency => {
+                println!("  Emergency: zone_temp={:.1}C, dcycle={}%", temp, dcycle);
+            }
+            (Some(temp), Some(dcycle)) => {
+                println!("  zone_temp={:.1}C, dcycle={}%", temp, dcycle);
+            }
+            _ => println!("  No readings yet"),
+        }
+
+        if let Some(t) = zone.last_update {
+            println!("  Last update: {}s ago", now.saturating_sub(t));
+        }
+        if !zone.stalled_fans.is_empty() {
+            println!("  Stalled fans: {:?}", zone.stalled_fans);
+        }
+        match (zone.override_dcycle, zone.override_until) {
+            (Some(d), Some(t)) => {
+                println!("  Override: dcycle={}%, expires in {}s", d, t.saturating_sub(now));
+            }
+            (Some(d), None) => println!("  Override: dcycle={}%", d),
+            _ => {}
+        }
+        if let Some(d) = zone.file_override_dcycle {
+            println!("  Override file: dcycle={}%", d);
+        }
+        if zone.paused {
+            println!("  Paused");
+        }
+        if zone.stopped {
+            println!("  Stopped after an error; reload the config to restart it");
+        }
+        if let Some(e) = zone.last_error {
+            println!("  Last error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a command that does not return any data to the running daemon.
+fn send_control_request(config: &Config, request: &Request) -> Result<()> {
+    let path = config.control_socket.as_ref()
+        .ok_or(Error::ControlSocketDisabled)?;
+
+    match control::query(path.as_ref(), request)? {
+        Response::Ok => Ok(()),
+        Response::Error { message } => Err(Error::ControlProtocol(message)),
+        r => Err(Error::ControlProtocol(format!("Unexpected response: {:?}", r))),
+    }
+}
+
+async fn main_wrapper() -> Result<()> {
+    let opt = Opt::parse();
+
+    // The schema doesn't depend on the config
+    if let Some(Command::PrintSchema) = opt.command {
+        println!("{:#}", schema().as_value());
+        return Ok(());
+    }
+
+    let config = match load_config(&opt.config) {
+        // A starter config can be generated before there is a config file
+        Err(Error::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound
+            && matches!(opt.command, Some(Command::GenerateConfig { .. })) =>
+        {
+            let mut config = Config::default();
+            config.sessions.0.insert(SessionName::default().0, Session::default());
+            config
+        }
+        result => result?,
+    };
+
+    tools::set(&config.tools);
+
+    let pkg_name = env!("CARGO_PKG_NAME").replace('-', "_");
+
+    // RUST_LOG has higher precedence than the config file option because it has
+    // more flexibility (eg. turning on logs for dependencies)
+    let filters = env::var("RUST_LOG")
+        .unwrap_or_else(|_| format!("{}={}", pkg_name, config.log_level));
+
+    // Log to the journal directly when running under systemd so that records
+    // include structured fields, like the zone name, for filtering
+    let journal = systemd::journal_logger(&filters).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to journal: {}", e);
+        None
+    });
+
+    let logger = match journal {
+        Some(l) => l,
+        None => {
+            let mut builder = env_logger::Builder::new();
+            builder.parse_filters(&filters);
+
+            // Don't include timestamps in the log if requested (eg. if logs are
+            // going to something like journald that already has timestamps)
+            if !bool_env(&format!("{}_LOG_TIMESTAMPS", pkg_name.to_uppercase()), true) {
+                builder.format_timestamp(None);
+            }
+
+            Box::new(builder.build())
+        }
+    };
+
+    let logger = match config.syslog.as_ref().map(|s| SyslogLogger::new(s, &filters)) {
+        Some(Ok(syslog)) => Box::new(TeeLogger(logger, Box::new(syslog))),
+        Some(Err(e)) => {
+            eprintln!("Failed to connect to syslog: {}", e);
+            logger
+        }
+        None => logger,
+    };
+
+    log::set_max_level(env_logger::filter::Builder::new().parse(&filters).build().filter());
+    // Only fails if a logger was already installed
+    let _ = log::set_boxed_logger(logger);
+
+    LOGGING_INITIALIZED.store(true, Ordering::SeqCst);
+
+    trace!("Loaded config: {:#?}", config);
+
+    // Shown by the check command instead
+    if !matches!(opt.command, Some(Command::Check)) {
+        for warning in &config.warnings {
+            warn!("{:?}: {}", opt.config, warning);
+        }
+    }
+
+    match opt.command {
+        Some(Command::Sensors { session, refresh_sdr }) => {
+            list_sensors(&config, &session, refresh_sdr)
+        }
+        Some(Command::FanThresholds { session }) => list_fan_thresholds(&config, &session),
+        Some(Command::SetMode { session, mode }) => set_fan_mode(&config, &session, mode.into()),
+        Some(Command::SetDuty { session, zone, dcycle }) => {
+            set_duty_cycle(&config, &session, zone, dcycle)
+        }
+        Some(Command::ProbeZones { session }) => probe_zones(&config, &session),
+        Some(Command::GenerateConfig { session }) => {
+            let session_config = config.sessions.0.get(&session)
+                .ok_or_else(|| Error::SessionNotFound(session.clone()))?;
+            print!("{}", generate::generate_config(&session, session_config)?);
+            Ok(())
+        }
+        Some(Command::Curve { zone, from, to, plot }) => print_curve(&config, &zone, from, to, plot),
+        Some(Command::Simulate { zone, input }) => simulate_zone(&config, &zone, input.as_deref()),
+        Some(Command::Status) => print_status(&config),
+        Some(Command::SetOverride { zone, dcycle, duration }) => send_control_request(
+            &config, &Request::SetOverride {
+                zone: ZoneRef::parse(&zone),
+                dcycle,
+                // Round up so that short durations don't expire immediately
+                duration_secs: duration.map(|d| d.as_secs() + u64::from(d.subsec_nanos() > 0)),
+            }),
+        Some(Command::ClearOverride { zone }) => send_control_request(
+            &config, &Request::ClearOverride { zone: ZoneRef::parse(&zone) }),
+        Some(Command::Pause { zone }) => send_control_request(
+            &config, &Request::Pause { zone: ZoneRef::parse(&zone) }),
+        Some(Command::Resume { zone }) => send_control_request(
+            &config, &Request::Resume { zone: ZoneRef::parse(&zone) }),
+        Some(Command::Reload) => send_control_request(&config, &Request::Reload),
+        Some(Command::Check) => check_config(&config),
+        Some(Command::PrintSchema) => unreachable!(),
+        None => {
+            // Held until the sessions are dropped and the fans are restored
+            let _lock = InstanceLock::acquire(Path::new(&config.lock_file.0))?;
+            let mut app = MainApp::new(opt.config, config)?;
+            let result = app.run().await;
+
+            source::retain_shared_sources([]);
+
+            result
+        }
+    }
 }
 
 #[tokio::main]
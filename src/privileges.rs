@@ -0,0 +1,153 @@
+use {
+    std::io,
+    crate::{
+        config::DropPrivileges,
+        error::{Error, Result},
+    },
+};
+
+#[cfg(unix)]
+pub use unix::drop_privileges;
+
+#[cfg(unix)]
+mod unix {
+    use {
+        std::{
+            ffi::{CStr, CString},
+            mem::MaybeUninit,
+            ptr,
+        },
+        log::info,
+        super::*,
+    };
+
+    /// Create an error for a failed system call.
+    fn os_error(call: &str) -> Error {
+        Error::Io {
+            path: format!("({})", call).into(),
+            source: io::Error::last_os_error(),
+        }
+    }
+
+    /// Size of the buffer for getpwnam_r() and getgrnam_r() when sysconf()
+    /// has no suggestion.
+    const DEFAULT_BUF_SIZE: usize = 16384;
+
+    fn buf_size(name: libc::c_int) -> usize {
+        match unsafe { libc::sysconf(name) } {
+            n if n > 0 => n as usize,
+            _ => DEFAULT_BUF_SIZE,
+        }
+    }
+
+    /// Look up a user's UID and primary GID.
+    fn lookup_user(name: &CStr) -> Result<(libc::uid_t, libc::gid_t)> {
+        let mut buf = vec![0 as libc::c_char; buf_size(libc::_SC_GETPW_R_SIZE_MAX)];
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getpwnam_r(name.as_ptr(), pwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+        if ret != 0 {
+            return Err(Error::Io {
+                path: "(getpwnam_r)".into(),
+                source: io::Error::from_raw_os_error(ret),
+            });
+        } else if result.is_null() {
+            return Err(Error::UserNotFound(name.to_string_lossy().into_owned()));
+        }
+
+        let pwd = unsafe { pwd.assume_init() };
+
+        Ok((pwd.pw_uid, pwd.pw_gid))
+    }
+
+    /// Look up a group's GID.
+    fn lookup_group(name: &CStr) -> Result<libc::gid_t> {
+        let mut buf = vec![0 as libc::c_char; buf_size(libc::_SC_GETGR_R_SIZE_MAX)];
+        let mut grp = MaybeUninit::<libc::group>::uninit();
+        let mut result = ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getgrnam_r(name.as_ptr(), grp.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+        if ret != 0 {
+            return Err(Error::Io {
+                path: "(getgrnam_r)".into(),
+                source: io::Error::from_raw_os_error(ret),
+            });
+        } else if result.is_null() {
+            return Err(Error::GroupNotFound(name.to_string_lossy().into_owned()));
+        }
+
+        Ok(unsafe { grp.assume_init() }.gr_gid)
+    }
+
+    /// Switch to the configured user and group. If only the user is set, the
+    /// user's primary group is used. The supplementary groups are replaced by
+    /// the user's groups or, if there is no user, cleared. This applies to
+    /// every thread in the process.
+    pub fn drop_privileges(config: &DropPrivileges) -> Result<()> {
+        // Names with NUL bytes can't exist
+        let user = config.user.as_ref()
+            .map(|u| CString::new(u.as_str()).map_err(|_| Error::UserNotFound(u.clone())))
+            .transpose()?;
+        let user_ids = user.as_deref().map(lookup_user).transpose()?;
+        let gid = match &config.group {
+            Some(g) => {
+                let name = CString::new(g.as_str()).map_err(|_| Error::GroupNotFound(g.clone()))?;
+                Some(lookup_group(&name)?)
+            }
+            None => user_ids.map(|(_, gid)| gid),
+        };
+
+        if let Some(gid) = gid {
+            let ret = match &user {
+                Some(u) => unsafe { libc::initgroups(u.as_ptr(), gid as _) },
+                None => unsafe { libc::setgroups(1, &gid) },
+            };
+            if ret != 0 {
+                return Err(os_error("setgroups"));
+            }
+
+            if unsafe { libc::setgid(gid) } != 0 {
+                return Err(os_error("setgid"));
+            }
+        }
+
+        if let Some((uid, _)) = user_ids {
+            if unsafe { libc::setuid(uid) } != 0 {
+                return Err(os_error("setuid"));
+            }
+
+            // Make sure that root can't be regained
+            if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                return Err(Error::Io {
+                    path: "(setuid)".into(),
+                    source: io::Error::other("Regained root privileges after dropping them"),
+                });
+            }
+        }
+
+        info!("Dropped privileges: uid={}, gid={}",
+              unsafe { libc::geteuid() }, unsafe { libc::getegid() });
+
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+pub use windows::drop_privileges;
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+
+    pub fn drop_privileges(_config: &DropPrivileges) -> Result<()> {
+        Err(Error::Io {
+            path: "(drop_privileges)".into(),
+            source: io::Error::new(io::ErrorKind::Unsupported, "Dropping privileges is not supported"),
+        })
+    }
+}
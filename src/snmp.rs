@@ -0,0 +1,477 @@
+use {
+    std::{
+        io,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+    log::{debug, info, warn},
+    tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::TcpStream,
+        time::sleep,
+    },
+    crate::{
+        config::Snmp,
+        control::{ControlState, ZoneStatus},
+    },
+};
+
+/// Time to wait before reconnecting to the master agent.
+const RECONNECT_DELAY: Duration = Duration::from_secs(15);
+/// Timeout in seconds for the master agent's requests to this subagent.
+const TIMEOUT_SECS: u8 = 5;
+
+// AgentX PDU types (RFC 2741)
+const PDU_OPEN: u8 = 1;
+const PDU_CLOSE: u8 = 2;
+const PDU_REGISTER: u8 = 3;
+const PDU_GET: u8 = 5;
+const PDU_GET_NEXT: u8 = 6;
+const PDU_GET_BULK: u8 = 7;
+const PDU_TEST_SET: u8 = 8;
+const PDU_COMMIT_SET: u8 = 9;
+const PDU_UNDO_SET: u8 = 10;
+const PDU_CLEANUP_SET: u8 = 11;
+const PDU_RESPONSE: u8 = 18;
+
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+
+const ERROR_NOT_WRITABLE: u16 = 17;
+
+// Column numbers of the zone table, which is at `<oid>.1.1.<column>.<row>`
+const COLUMN_INDEX: u32 = 1;
+const COLUMN_NAME: u32 = 2;
+/// Temperature in tenths of a degree Celsius
+const COLUMN_TEMP: u32 = 3;
+const COLUMN_DCYCLE: u32 = 4;
+const COLUMN_FAILSAFE: u32 = 5;
+const COLUMN_EMERGENCY: u32 = 6;
+const COLUMN_PAUSED: u32 = 7;
+const COLUMN_OVERRIDE_DCYCLE: u32 = 8;
+
+/// SNMP TruthValue
+fn truth_value(value: bool) -> Value {
+    Value::Integer(if value { 1 } else { 2 })
+}
+
+/// Value of a variable binding.
+#[derive(Clone, Debug)]
+enum Value {
+    Integer(i32),
+    OctetString(String),
+    Gauge32(u32),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn encode(&self, buf: &mut Vec<u8>, oid: &[u32]) {
+        let value_type: u16 = match self {
+            Self::Integer(_) => 2,
+            Self::OctetString(_) => 4,
+            Self::Gauge32(_) => 66,
+            Self::NoSuchObject => 128,
+            Self::NoSuchInstance => 129,
+            Self::EndOfMibView => 130,
+        };
+
+        buf.extend_from_slice(&value_type.to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        encode_oid(buf, oid, false);
+
+        match self {
+            Self::Integer(n) => buf.extend_from_slice(&n.to_be_bytes()),
+            Self::OctetString(s) => encode_octet_string(buf, s.as_bytes()),
+            Self::Gauge32(n) => buf.extend_from_slice(&n.to_be_bytes()),
+            Self::NoSuchObject | Self::NoSuchInstance | Self::EndOfMibView => {}
+        }
+    }
+}
+
+fn encode_oid(buf: &mut Vec<u8>, oid: &[u32], include: bool) {
+    buf.extend_from_slice(&[oid.len() as u8, 0, u8::from(include), 0]);
+    for subid in oid {
+        buf.extend_from_slice(&subid.to_be_bytes());
+    }
+}
+
+fn encode_octet_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf.resize(buf.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+/// AgentX PDU header.
+#[derive(Clone, Copy, Debug, Default)]
+struct Header {
+    pdu_type: u8,
+    flags: u8,
+    session_id: u32,
+    transaction_id: u32,
+    packet_id: u32,
+}
+
+impl Header {
+    const SIZE: usize = 20;
+
+    /// Encode a PDU with this header. Payloads are always sent in network byte
+    /// order.
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![1, self.pdu_type, self.flags | FLAG_NETWORK_BYTE_ORDER, 0];
+        buf.extend_from_slice(&self.session_id.to_be_bytes());
+        buf.extend_from_slice(&self.transaction_id.to_be_bytes());
+        buf.extend_from_slice(&self.packet_id.to_be_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Reader for PDU payloads in the byte order indicated by the PDU's header.
+struct Decoder<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Decoder<'_> {
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.data.len() < n {
+            return Err(invalid_data("Truncated AgentX PDU"));
+        }
+
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?.try_into().unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    /// Read an OID and its `include` field.
+    fn oid(&mut self) -> io::Result<(Vec<u32>, bool)> {
+        let header = self.bytes(4)?;
+        let (n_subid, prefix, include) = (header[0], header[1], header[2] != 0);
+
+        let mut oid = vec![];
+        if prefix != 0 {
+            oid.extend_from_slice(&[1, 3, 6, 1, u32::from(prefix)]);
+        }
+        for _ in 0..n_subid {
+            oid.push(self.u32()?);
+        }
+
+        Ok((oid, include))
+    }
+
+    fn octet_string(&mut self) -> io::Result<()> {
+        let len = self.u32()? as usize;
+        self.bytes(len + (4 - len % 4) % 4)?;
+        Ok(())
+    }
+}
+
+/// Range of OIDs to search, from a Get, GetNext, or GetBulk PDU.
+#[derive(Clone, Debug)]
+struct SearchRange {
+    start: Vec<u32>,
+    include: bool,
+    /// Exclusive upper bound or empty if unbounded
+    end: Vec<u32>,
+}
+
+impl SearchRange {
+    fn decode_list(decoder: &mut Decoder) -> io::Result<Vec<Self>> {
+        let mut ranges = vec![];
+
+        while !decoder.data.is_empty() {
+            let (start, include) = decoder.oid()?;
+            let (end, _) = decoder.oid()?;
+            ranges.push(Self { start, include, end });
+        }
+
+        Ok(ranges)
+    }
+}
+
+/// Column number and the function for getting a row's value, if it has one.
+type Column = (u32, fn(&ZoneStatus) -> Option<Value>);
+
+/// Snapshot of the zone table as a sorted list of variables.
+struct ZoneTable {
+    entries: Vec<(Vec<u32>, Value)>,
+}
+
+impl ZoneTable {
+    fn new(base: &[u32], statuses: &[ZoneStatus]) -> Self {
+        let columns: [Column; 8] = [
+            (COLUMN_INDEX, |s| Some(Value::Integer(s.index as i32 + 1))),
+            (COLUMN_NAME, |s| Some(Value::OctetString(s.name.clone().unwrap_or_default()))),
+            (COLUMN_TEMP, |s| s.temp.map(|t| Value::Integer((t * 10.0).round() as i32))),
+            (COLUMN_DCYCLE, |s| s.dcycle.map(|d| Value::Gauge32(d.into()))),
+            (COLUMN_FAILSAFE, |s| Some(truth_value(s.failsafe))),
+            (COLUMN_EMERGENCY, |s| Some(truth_value(s.emergency))),
+            (COLUMN_PAUSED, |s| Some(truth_value(s.paused))),
+            (COLUMN_OVERRIDE_DCYCLE, |s| s.override_dcycle.map(|d| Value::Gauge32(d.into()))),
+        ];
+
+        // Rows are numbered from 1 since SNMP table indexes can't be 0
+        let entries = columns.iter()
+            .flat_map(|(column, get)| statuses.iter().filter_map(move |s| {
+                let mut oid = base.to_vec();
+                oid.extend_from_slice(&[1, 1, *column, s.index as u32 + 1]);
+                get(s).map(|v| (oid, v))
+            }))
+            .collect();
+
+        Self { entries }
+    }
+
+    fn get(&self, base: &[u32], oid: &[u32]) -> Value {
+        match self.entries.iter().find(|(o, _)| o == oid) {
+            Some((_, v)) => v.clone(),
+            None if oid.starts_with(base) => Value::NoSuchInstance,
+            None => Value::NoSuchObject,
+        }
+    }
+
+    /// Find the first variable after the start of the range (or at the start
+    /// if it is included) and before the end of the range.
+    fn get_next(&self, range: &SearchRange) -> (Vec<u32>, Value) {
+        let found = self.entries.iter()
+            .find(|(o, _)| if range.include { *o >= range.start } else { *o > range.start })
+            .filter(|(o, _)| range.end.is_empty() || *o < range.end);
+
+        match found {
+            Some((o, v)) => (o.clone(), v.clone()),
+            None => (range.start.clone(), Value::EndOfMibView),
+        }
+    }
+}
+
+/// Connection to the AgentX master agent.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+async fn connect(address: &str) -> io::Result<Box<dyn Stream>> {
+    if let Some(address) = address.strip_prefix("tcp:") {
+        return Ok(Box::new(TcpStream::connect(address).await?));
+    }
+
+    #[cfg(unix)]
+    {
+        Ok(Box::new(tokio::net::UnixStream::connect(address).await?))
+    }
+
+    #[cfg(not(unix))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported"))
+    }
+}
+
+/// AgentX session with the master agent.
+struct Session {
+    stream: Box<dyn Stream>,
+    session_id: u32,
+    packet_id: u32,
+    started: Instant,
+}
+
+impl Session {
+    async fn read_pdu(&mut self) -> io::Result<(Header, Vec<u8>, bool)> {
+        let mut header = [0u8; Header::SIZE];
+        self.stream.read_exact(&mut header).await?;
+
+        if header[0] != 1 {
+            return Err(invalid_data("Unsupported AgentX version"));
+        }
+
+        let big_endian = header[2] & FLAG_NETWORK_BYTE_ORDER != 0;
+        let mut decoder = Decoder { data: &header[4..], big_endian };
+        let parsed = Header {
+            pdu_type: header[1],
+            flags: header[2],
+            session_id: decoder.u32()?,
+            transaction_id: decoder.u32()?,
+            packet_id: decoder.u32()?,
+        };
+        let len = decoder.u32()? as usize;
+
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload).await?;
+
+        Ok((parsed, payload, big_endian))
+    }
+
+    /// Send a request to the master agent and wait for its response.
+    async fn request(&mut self, pdu_type: u8, payload: &[u8]) -> io::Result<()> {
+        self.packet_id += 1;
+
+        let header = Header {
+            pdu_type,
+            session_id: self.session_id,
+            packet_id: self.packet_id,
+            ..Default::default()
+        };
+        self.stream.write_all(&header.encode(payload)).await?;
+
+        let (header, payload, big_endian) = self.read_pdu().await?;
+        if header.pdu_type != PDU_RESPONSE || header.packet_id != self.packet_id {
+            return Err(invalid_data("Unexpected AgentX PDU in response to request"));
+        }
+
+        let mut decoder = Decoder { data: &payload, big_endian };
+        decoder.u32()?;
+        let error = decoder.u16()?;
+        if error != 0 {
+            return Err(io::Error::other(format!("AgentX master agent returned error {}", error)));
+        }
+
+        self.session_id = header.session_id;
+        Ok(())
+    }
+
+    /// Open a session and register the subtree.
+    async fn open(address: &str, base: &[u32]) -> io::Result<Self> {
+        let mut session = Self {
+            stream: connect(address).await?,
+            session_id: 0,
+            packet_id: 0,
+            started: Instant::now(),
+        };
+
+        let mut payload = vec![TIMEOUT_SECS, 0, 0, 0];
+        encode_oid(&mut payload, &[], false);
+        encode_octet_string(&mut payload, env!("CARGO_PKG_NAME").as_bytes());
+        session.request(PDU_OPEN, &payload).await?;
+
+        let mut payload = vec![TIMEOUT_SECS, 127, 0, 0];
+        encode_oid(&mut payload, base, false);
+        session.request(PDU_REGISTER, &payload).await?;
+
+        Ok(session)
+    }
+
+    async fn respond(&mut self, request: &Header, error: u16, index: u16, varbinds: &[u8]) -> io::Result<()> {
+        let uptime = (self.started.elapsed().as_millis() / 10) as u32;
+
+        let mut payload = uptime.to_be_bytes().to_vec();
+        payload.extend_from_slice(&error.to_be_bytes());
+        payload.extend_from_slice(&index.to_be_bytes());
+        payload.extend_from_slice(varbinds);
+
+        let header = Header {
+            pdu_type: PDU_RESPONSE,
+            flags: 0,
+            ..*request
+        };
+        self.stream.write_all(&header.encode(&payload)).await
+    }
+
+    /// Answer requests from the master agent until the connection is closed.
+    async fn serve(&mut self, base: &[u32], state: &ControlState) -> io::Result<()> {
+        loop {
+            let (header, payload, big_endian) = self.read_pdu().await?;
+            let mut decoder = Decoder { data: &payload, big_endian };
+
+            if header.flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+                decoder.octet_string()?;
+            }
+
+            let mut varbinds = vec![];
+
+            match header.pdu_type {
+                PDU_GET | PDU_GET_NEXT => {
+                    let table = ZoneTable::new(base, &state.zone_statuses());
+
+                    for range in SearchRange::decode_list(&mut decoder)? {
+                        if header.pdu_type == PDU_GET {
+                            table.get(base, &range.start).encode(&mut varbinds, &range.start);
+                        } else {
+                            let (oid, value) = table.get_next(&range);
+                            value.encode(&mut varbinds, &oid);
+                        }
+                    }
+                }
+                PDU_GET_BULK => {
+                    let non_repeaters = usize::from(decoder.u16()?);
+                    let max_repetitions = decoder.u16()?;
+                    let table = ZoneTable::new(base, &state.zone_statuses());
+                    let mut ranges = SearchRange::decode_list(&mut decoder)?;
+                    let repeaters = ranges.split_off(non_repeaters.min(ranges.len()));
+
+                    for range in &ranges {
+                        let (oid, value) = table.get_next(range);
+                        value.encode(&mut varbinds, &oid);
+                    }
+
+                    let mut repeaters = repeaters;
+                    for _ in 0..max_repetitions {
+                        let mut done = true;
+
+                        for range in &mut repeaters {
+                            let (oid, value) = table.get_next(range);
+                            done &= matches!(value, Value::EndOfMibView);
+                            value.encode(&mut varbinds, &oid);
+
+                            range.start = oid;
+                            range.include = false;
+                        }
+
+                        if done {
+                            break;
+                        }
+                    }
+                }
+                PDU_TEST_SET => {
+                    self.respond(&header, ERROR_NOT_WRITABLE, 1, &[]).await?;
+                    continue;
+                }
+                PDU_COMMIT_SET | PDU_UNDO_SET | PDU_CLEANUP_SET => {}
+                PDU_CLOSE => return Ok(()),
+                t => {
+                    debug!("Ignoring AgentX PDU type {}", t);
+                    continue;
+                }
+            }
+
+            self.respond(&header, 0, 0, &varbinds).await?;
+        }
+    }
+
+}
+
+/// Serve the zone table to the master agent forever, reconnecting whenever the
+/// connection fails.
+pub async fn serve(config: Snmp, state: Arc<ControlState>) {
+    let base = config.oid.0;
+
+    loop {
+        match Session::open(&config.agentx.0, &base).await {
+            Ok(mut session) => {
+                info!("Registered with AgentX master agent: {}", config.agentx.0);
+
+                match session.serve(&base, &state).await {
+                    Ok(()) => warn!("AgentX master agent closed the session"),
+                    Err(e) => warn!("AgentX session failed: {}", e),
+                }
+            }
+            Err(e) => warn!("Failed to connect to AgentX master agent: {}: {}", config.agentx.0, e),
+        }
+
+        sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,227 @@
+use {
+    std::{
+        io::{self, Write},
+        net::{TcpStream, ToSocketAddrs, UdpSocket},
+        process,
+        sync::Mutex,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    crate::config::{Syslog, SyslogTransport},
+};
+
+/// Default socket of the local syslog daemon.
+#[cfg(unix)]
+const LOCAL_SOCKET: &str = "/dev/log";
+/// Timeout for connecting to a TCP syslog server.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time to wait before reconnecting after a TCP connection failed. Messages
+/// logged in the meantime are dropped.
+const TCP_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Map a log level to a syslog severity.
+pub fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC with millisecond precision.
+fn format_timestamp(time: SystemTime) -> String {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = duration.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year, month, day, rem / 3600, rem / 60 % 60, rem % 60, duration.subsec_millis())
+}
+
+/// Get the system's hostname or [`None`] if it can't be determined.
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return None;
+    }
+
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Connection to the syslog daemon or server.
+enum Connection {
+    #[cfg(unix)]
+    Local(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    Tcp {
+        address: String,
+        stream: Option<TcpStream>,
+        /// When connecting last failed
+        failed: Option<Instant>,
+    },
+}
+
+impl Connection {
+    /// Connect to the TCP server, unless the last attempt failed too recently.
+    fn connect_tcp(address: &str, failed: &mut Option<Instant>) -> Option<TcpStream> {
+        if failed.is_some_and(|t| t.elapsed() < TCP_RECONNECT_DELAY) {
+            return None;
+        }
+
+        let stream = address.to_socket_addrs()
+            .and_then(|mut addrs| addrs.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found")))
+            .and_then(|addr| TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT));
+
+        match stream {
+            Ok(s) => {
+                *failed = None;
+                Some(s)
+            }
+            Err(e) => {
+                // The logger can't log its own errors
+                eprintln!("Failed to connect to syslog server: {}: {}", address, e);
+                *failed = Some(Instant::now());
+                None
+            }
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        // There's nowhere to report errors for the datagram transports, but the
+        // message was also sent to the other logger
+        match self {
+            #[cfg(unix)]
+            Self::Local(socket) => {
+                let _ = socket.send(message);
+            }
+            Self::Udp(socket) => {
+                let _ = socket.send(message);
+            }
+            Self::Tcp { address, stream, failed } => {
+                if stream.is_none() {
+                    *stream = Self::connect_tcp(address, failed);
+                }
+
+                if let Some(s) = stream {
+                    // Octet-counting framing (RFC 6587)
+                    let mut frame = format!("{} ", message.len()).into_bytes();
+                    frame.extend_from_slice(message);
+
+                    if let Err(e) = s.write_all(&frame) {
+                        eprintln!("Failed to send to syslog server: {}: {}", address, e);
+                        *stream = None;
+                        *failed = Some(Instant::now());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Logger that sends records to syslog. Local messages use the traditional BSD
+/// format, leaving the timestamp to the daemon, while remote messages use RFC
+/// 5424.
+pub struct SyslogLogger {
+    connection: Mutex<Connection>,
+    transport: SyslogTransport,
+    facility: u8,
+    hostname: String,
+    filter: env_logger::filter::Filter,
+}
+
+impl SyslogLogger {
+    /// Create a logger that only logs records matching `filters`, which uses
+    /// the same syntax as `RUST_LOG`.
+    pub fn new(config: &Syslog, filters: &str) -> io::Result<Self> {
+        let connection = match config.transport {
+            #[cfg(unix)]
+            SyslogTransport::Local => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(config.address.as_deref().unwrap_or(LOCAL_SOCKET))?;
+                Connection::Local(socket)
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Local => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "local syslog is not supported"));
+            }
+            SyslogTransport::Udp => {
+                // Config validation guarantees that remote transports have an address
+                let address = config.address.as_deref().unwrap().to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+                let socket = UdpSocket::bind(if address.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+                socket.connect(address)?;
+                Connection::Udp(socket)
+            }
+            SyslogTransport::Tcp => {
+                let address = config.address.clone().unwrap();
+                let mut failed = None;
+                let stream = Connection::connect_tcp(&address, &mut failed);
+                Connection::Tcp { address, stream, failed }
+            }
+        };
+
+        let filter = env_logger::filter::Builder::new()
+            .parse(filters)
+            .build();
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            transport: config.transport,
+            facility: config.facility.code(),
+            hostname: hostname().filter(|h| !h.is_empty()).unwrap_or_else(|| "-".to_owned()),
+            filter,
+        })
+    }
+
+    /// Format a record as a syslog message.
+    fn format(&self, record: &log::Record) -> String {
+        let priority = self.facility * 8 + severity(record.level());
+        let app = env!("CARGO_PKG_NAME");
+
+        if self.transport == SyslogTransport::Local {
+            format!("<{}>{}[{}]: {}", priority, app, process::id(), record.args())
+        } else {
+            format!("<{}>1 {} {} {} {} - - {}", priority, format_timestamp(SystemTime::now()),
+                    self.hostname, app, process::id(), record.args())
+        }
+    }
+}
+
+impl log::Log for SyslogLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let message = self.format(record);
+        self.connection.lock().unwrap().send(message.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,197 @@
+use {
+    std::{
+        env,
+        io,
+        time::Duration,
+    },
+    log::debug,
+    crate::syslog,
+};
+
+/// Send a state update to the service manager via the sd_notify protocol.
+/// Returns `false` if the program was not started by a service manager that
+/// expects notifications.
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<bool> {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+
+    debug!("Sending systemd notification: {}", state);
+
+    let socket = UnixDatagram::unbound()?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::{
+            linux::net::SocketAddrExt,
+            unix::{ffi::OsStrExt, net::SocketAddr},
+        };
+
+        // Abstract namespace sockets are specified with a leading '@'
+        if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(true);
+        }
+    }
+
+    socket.send_to(state.as_bytes(), path)?;
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Check if stderr is connected to the journal. systemd sets `JOURNAL_STREAM`
+/// to the device and inode numbers of stderr's stream socket.
+#[cfg(unix)]
+fn journal_connected() -> bool {
+    use std::{
+        fs::File,
+        os::{fd::AsFd, unix::fs::MetadataExt},
+    };
+
+    let stream = match env::var("JOURNAL_STREAM") {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let metadata = io::stderr().as_fd().try_clone_to_owned()
+        .and_then(|fd| File::from(fd).metadata());
+
+    match metadata {
+        Ok(m) => stream == format!("{}:{}", m.dev(), m.ino()),
+        Err(_) => false,
+    }
+}
+
+/// Create a [`JournalLogger`] if stderr is connected to the journal. Returns
+/// [`None`] if the journal should not be logged to directly.
+#[cfg(unix)]
+pub fn journal_logger(filters: &str) -> io::Result<Option<Box<dyn log::Log>>> {
+    if !journal_connected() {
+        return Ok(None);
+    }
+
+    Ok(Some(Box::new(JournalLogger::new(filters)?)))
+}
+
+#[cfg(not(unix))]
+pub fn journal_logger(_filters: &str) -> io::Result<Option<Box<dyn log::Log>>> {
+    Ok(None)
+}
+
+/// Logger that writes directly to the journal using the native protocol.
+/// Key-value pairs attached to log records become journal fields with
+/// uppercase names (eg. `zone = "hdd"` becomes `ZONE=hdd`).
+#[cfg(unix)]
+struct JournalLogger {
+    socket: std::os::unix::net::UnixDatagram,
+    filter: env_logger::filter::Filter,
+}
+
+#[cfg(unix)]
+impl JournalLogger {
+    const SOCKET: &'static str = "/run/systemd/journal/socket";
+
+    /// Create a logger that only logs records matching `filters`, which uses
+    /// the same syntax as `RUST_LOG`.
+    fn new(filters: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(Self::SOCKET)?;
+
+        let filter = env_logger::filter::Builder::new()
+            .parse(filters)
+            .build();
+
+        Ok(Self { socket, filter })
+    }
+
+    /// Append a field to a message. Values containing newlines must be
+    /// length-prefixed instead of using the `KEY=value` form.
+    fn append_field(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+        buf.extend_from_slice(key.as_bytes());
+
+        if value.contains(&b'\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+
+        buf.extend_from_slice(value);
+        buf.push(b'\n');
+    }
+}
+
+#[cfg(unix)]
+impl log::Log for JournalLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        struct Fields<'a>(&'a mut Vec<u8>);
+
+        impl<'kvs> log::kv::VisitSource<'kvs> for Fields<'_> {
+            fn visit_pair(&mut self, key: log::kv::Key<'kvs>, value: log::kv::Value<'kvs>)
+                -> Result<(), log::kv::Error>
+            {
+                // Skip fields that don't apply to this record
+                let value = value.to_string();
+                if !value.is_empty() {
+                    let key = key.as_str().to_uppercase();
+                    JournalLogger::append_field(self.0, &key, value.as_bytes());
+                }
+
+                Ok(())
+            }
+        }
+
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let mut buf = vec![];
+        Self::append_field(&mut buf, "PRIORITY", syslog::severity(record.level()).to_string().as_bytes());
+        Self::append_field(&mut buf, "MESSAGE", record.args().to_string().as_bytes());
+        Self::append_field(&mut buf, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME").as_bytes());
+        Self::append_field(&mut buf, "TARGET", record.target().as_bytes());
+        if let Some(file) = record.file() {
+            Self::append_field(&mut buf, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = record.line() {
+            Self::append_field(&mut buf, "CODE_LINE", line.to_string().as_bytes());
+        }
+        let _ = record.key_values().visit(&mut Fields(&mut buf));
+
+        // There's nowhere else to report the error if the journal is gone
+        if self.socket.send(&buf).is_err() {
+            eprintln!("<{}>{}", syslog::severity(record.level()), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Get the watchdog timeout configured by the service manager or [`None`] if
+/// the watchdog is not enabled for this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC").ok()
+        .and_then(|u| u.parse::<u64>().ok())
+        .filter(|u| *u > 0)
+        .map(Duration::from_micros)
+}
@@ -0,0 +1,122 @@
+use {
+    std::{
+        collections::BTreeMap,
+        fmt::Write as _,
+        fs,
+        path::PathBuf,
+        time::UNIX_EPOCH,
+    },
+    log::{trace, warn},
+    tokio::{
+        sync::mpsc::UnboundedReceiver,
+        time::{interval, MissedTickBehavior},
+    },
+    crate::{
+        config::Textfile,
+        error::{Error, Result},
+        influx::Tick,
+    },
+};
+
+/// Prefix of every metric name.
+const PREFIX: &str = "ipmi_fan_control";
+
+/// Name, type, help text, and the function for getting a tick's value, if it
+/// has one.
+type ZoneMetric = (&'static str, &'static str, &'static str, fn(&Tick) -> Option<f64>);
+
+/// Metrics that are written for every zone.
+const ZONE_METRICS: [ZoneMetric; 6] = [
+    ("zone_temperature_celsius", "gauge", "Aggregated temperature of the zone's sources.",
+     |t| t.temp.filter(|t| t.is_finite()).map(f64::from)),
+    ("zone_duty_cycle_percent", "gauge", "Duty cycle applied to the zone's fans.",
+     |t| t.dcycle.map(f64::from)),
+    ("zone_failsafe", "gauge", "Whether the failsafe duty cycle is applied.",
+     |t| Some(f64::from(u8::from(t.failsafe)))),
+    ("zone_emergency", "gauge", "Whether a source exceeds the emergency temperature.",
+     |t| Some(f64::from(u8::from(t.emergency)))),
+    ("zone_error", "gauge", "Whether the last zone loop iteration failed.",
+     |t| Some(f64::from(u8::from(t.error.is_some())))),
+    ("zone_last_update_timestamp_seconds", "gauge", "Unix time of the last zone loop iteration.",
+     |t| t.time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs_f64())),
+];
+
+/// Escape a label value.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Format the latest tick of every zone in the Prometheus text format.
+fn format_metrics(ticks: &BTreeMap<String, Tick>) -> String {
+    let mut out = String::new();
+
+    for (name, metric_type, help, get) in ZONE_METRICS {
+        let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+        let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, metric_type);
+
+        for (zone, tick) in ticks {
+            if let Some(value) = get(tick) {
+                let _ = writeln!(out, "{}_{}{{zone=\"{}\"}} {}", PREFIX, name, escape_label(zone), value);
+            }
+        }
+    }
+
+    let _ = writeln!(out, "# HELP {}_source_temperature_celsius Temperature reported by a source.", PREFIX);
+    let _ = writeln!(out, "# TYPE {}_source_temperature_celsius gauge", PREFIX);
+
+    for (zone, tick) in ticks {
+        for (source, reading) in &tick.sources {
+            if let Some(temp) = reading.filter(|t| t.is_finite()) {
+                let _ = writeln!(out, "{}_source_temperature_celsius{{zone=\"{}\",source=\"{}\"}} {}",
+                                 PREFIX, escape_label(zone), escape_label(source), temp);
+            }
+        }
+    }
+
+    out
+}
+
+/// Write the metrics to a temporary file and rename it over the target so that
+/// the collector never reads a partially written file.
+fn write_metrics(config: &Textfile, metrics: &str) -> Result<()> {
+    // The collector only reads files ending in .prom
+    let temp_path = PathBuf::from(format!("{}.tmp", config.path));
+
+    fs::write(&temp_path, metrics)
+        .map_err(|e| Error::Io { path: temp_path.clone(), source: e })?;
+    fs::rename(&temp_path, &config.path)
+        .map_err(|e| Error::Io { path: config.path.clone().into(), source: e })
+}
+
+/// Keep track of the latest tick of every zone and rewrite the metrics file
+/// every interval. The file is only rewritten if a tick arrived since the last
+/// write, so its modification time shows when the daemon stopped updating.
+pub async fn serve(config: Textfile, mut receiver: UnboundedReceiver<Tick>) {
+    let mut ticks = BTreeMap::new();
+    let mut changed = false;
+
+    let mut timer = interval(config.interval.0.to_duration());
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            tick = receiver.recv() => match tick {
+                Some(t) => {
+                    ticks.insert(t.zone.clone(), t);
+                    changed = true;
+                }
+                None => return,
+            },
+            _ = timer.tick(), if changed => {
+                let metrics = format_metrics(&ticks);
+                trace!("Writing textfile metrics: {:?}", metrics);
+
+                if let Err(e) = write_metrics(&config, &metrics) {
+                    warn!("Failed to write textfile metrics: {}", e);
+                }
+
+                changed = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use {
+    std::{
+        io,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    },
+    log::{debug, warn},
+    serde::Deserialize,
+    serde_json::json,
+    tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Take},
+        net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpListener, TcpStream},
+        sync::broadcast::{self, error::RecvError},
+        time::timeout,
+    },
+    crate::{
+        control::{self, ControlState, Request, Response, ZoneRef},
+        error::{Error, Result},
+        influx::{Sink, Tick},
+    },
+};
+
+#[cfg(feature = "web-ui")]
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+/// Maximum size of a request body.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Maximum size of the request line and headers.
+const MAX_HEAD_SIZE: u64 = 8 * 1024;
+/// How long a client has to send the request line and headers, and then the
+/// body.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Status line, content type, and body of a response.
+type HttpResponse = (&'static str, &'static str, String);
+
+/// Reader for a client connection that stops at the size limit of the current
+/// part of the request.
+type RequestReader = BufReader<Take<OwnedReadHalf>>;
+
+/// Request line and the headers that are used by the server.
+#[derive(Default)]
+struct RequestHead {
+    request_line: String,
+    content_length: usize,
+    authorization: Option<String>,
+}
+
+/// Body of a request that sets a zone's duty cycle override.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideBody {
+    dcycle: u8,
+    /// Number of seconds after which the override is cleared
+    #[serde(default)]
+    duration_secs: Option<u64>,
+}
+
+/// Create the HTTP listener for the status API and the web UI.
+pub fn bind(address: &str) -> Result<TcpListener> {
+    let io_err = |e| Error::Io { path: address.into(), source: e };
+
+    let listener = std::net::TcpListener::bind(address).map_err(io_err)?;
+    listener.set_nonblocking(true).map_err(io_err)?;
+
+    TcpListener::from_std(listener).map_err(io_err)
+}
+
+/// Get the status of every zone along with its curve for the web UI.
+fn status_json(state: &ControlState) -> serde_json::Value {
+    let zones = state.zone_statuses()
+        .into_iter()
+        .map(|s| {
+            let config = s.config();
+
+            json!({
+                "index": s.index,
+                "name": config.map(|c| c.label()),
+                "temp": s.temp,
+                "dcycle": s.dcycle,
+                "failsafe": s.failsafe,
+                "emergency": s.emergency,
+                "paused": s.paused,
+                "stopped": s.stopped,
+                "override_dcycle": s.override_dcycle,
+                "override_until": s.override_until,
+                "file_override_dcycle": s.file_override_dcycle,
+                "last_error": s.last_error,
+                "steps": config.map(|c| &c.steps),
+                "cooldown_steps": config.map(|c| &c.cooldown_steps),
+                "rpm_steps": config.map(|c| &c.rpm_steps),
+                "min_dcycle": config.map(|c| c.min_dcycle.0),
+                "max_dcycle": config.map(|c| c.max_dcycle.0),
+                "stop_temp": config.and_then(|c| c.stop_temp),
+                "emergency_temp": config.and_then(|c| c.emergency_temp),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "zones": zones })
+}
+
+fn json_response(status: &'static str, value: impl serde::Serialize) -> HttpResponse {
+    let body = serde_json::to_string(&value).unwrap_or_default();
+    (status, "application/json", body)
+}
+
+fn error_response(status: &'static str, message: impl Into<String>) -> HttpResponse {
+    json_response(status, Response::Error { message: message.into() })
+}
+
+/// Check the `Authorization` header against the bearer token. The comparison
+/// takes the same amount of time regardless of where the first mismatch is.
+pub fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    let provided = authorization
+        .and_then(|a| a.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    provided.len() == token.len()
+        && provided.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Handle a request to the zone and reload endpoints. `segments` is the path
+/// after `/api/`. If `token` is set, every request must include it. Otherwise,
+/// only `GET` requests are allowed.
+fn handle_api(
+    method: &str,
+    segments: &[&str],
+    authorization: Option<&str>,
+    body: &[u8],
+    state: &ControlState,
+    token: Option<&str>,
+) -> HttpResponse {
+    match token {
+        Some(t) if !authorized(authorization, t) => {
+            return error_response("401 Unauthorized", "Missing or invalid bearer token");
+        }
+        None if method != "GET" => {
+            return error_response("403 Forbidden", "http_token must be set to change zones");
+        }
+        _ => {}
+    }
+
+    let zone = match segments {
+        ["zones", zone, ..] => {
+            let zone = ZoneRef::parse(zone);
+            match state.find_zone(&zone) {
+                Some(s) => Some((zone, s)),
+                None => return error_response("404 Not Found", format!("Zone not found: {:?}", zone)),
+            }
+        }
+        _ => None,
+    };
+
+    let request = match (method, segments, zone) {
+        ("GET", ["zones"], _) => return json_response("200 OK", json!({ "zones": state.zone_statuses() })),
+        ("GET", ["zones", _], Some((_, status))) => {
+            return json_response("200 OK", &*status.lock().unwrap());
+        }
+        ("POST", ["zones", _, "override"], Some((zone, _))) => {
+            match serde_json::from_slice::<OverrideBody>(body) {
+                Ok(b) => Request::SetOverride { zone, dcycle: b.dcycle, duration_secs: b.duration_secs },
+                Err(e) => return error_response("400 Bad Request", format!("Invalid request: {}", e)),
+            }
+        }
+        ("DELETE", ["zones", _, "override"], Some((zone, _))) => Request::ClearOverride { zone },
+        ("POST", ["zones", _, "pause"], Some((zone, _))) => Request::Pause { zone },
+        ("POST", ["zones", _, "resume"], Some((zone, _))) => Request::Resume { zone },
+        ("POST", ["reload"], _) => Request::Reload,
+        (_, ["zones"] | ["zones", _] | ["zones", _, "override" | "pause" | "resume"] | ["reload"], _) => {
+            return error_response("405 Method Not Allowed", "Method not allowed");
+        }
+        _ => return error_response("404 Not Found", "Not found"),
+    };
+
+    match control::handle_request(request, state) {
+        r @ Response::Error { .. } => json_response("400 Bad Request", r),
+        r => json_response("200 OK", r),
+    }
+}
+
+/// Convert a tick to JSON for the stream endpoint.
+fn tick_json(tick: &Tick) -> serde_json::Value {
+    let sources = tick.sources.iter()
+        .map(|(source, temp)| json!({ "source": source, "temp": temp }))
+        .collect::<Vec<_>>();
+
+    json!({
+        "zone": tick.zone,
+        "time": tick.time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+        "temp": tick.temp,
+        "dcycle": tick.dcycle,
+        "failsafe": tick.failsafe,
+        "emergency": tick.emergency,
+        "error": tick.error,
+        "sources": sources,
+    })
+}
+
+/// Send every zone loop iteration to the client as a line of JSON until the
+/// client disconnects. Ticks are skipped if the client can't keep up.
+async fn stream_ticks(mut writer: OwnedWriteHalf, mut receiver: broadcast::Receiver<Tick>) -> io::Result<()> {
+    writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n").await?;
+
+    loop {
+        let tick = match receiver.recv().await {
+            Ok(t) => t,
+            Err(RecvError::Lagged(n)) => {
+                debug!("HTTP stream client skipped {} ticks", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let mut line = tick_json(&tick).to_string();
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+
+    writer.shutdown().await
+}
+
+/// Read a line, failing if it was cut off by the reader's size limit.
+async fn read_line(reader: &mut RequestReader, line: &mut String) -> io::Result<usize> {
+    let n = reader.read_line(line).await?;
+    if reader.get_ref().limit() == 0 && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Request headers are too large"));
+    }
+
+    Ok(n)
+}
+
+/// Read the request line and headers.
+async fn read_head(reader: &mut RequestReader) -> io::Result<RequestHead> {
+    let mut head = RequestHead::default();
+    read_line(reader, &mut head.request_line).await?;
+
+    loop {
+        let mut line = String::new();
+        if read_line(reader, &mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                head.content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("authorization") {
+                head.authorization = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    Ok(head)
+}
+
+/// Read part of the request, failing if the client doesn't send it within
+/// [`REQUEST_TIMEOUT`].
+async fn with_timeout<T>(future: impl std::future::Future<Output = io::Result<T>>) -> io::Result<T> {
+    timeout(REQUEST_TIMEOUT, future).await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out reading request")))
+}
+
+/// Handle a single request. The connection is closed after the response.
+async fn handle_client(
+    stream: TcpStream,
+    state: Arc<ControlState>,
+    sink: Arc<Sink>,
+    token: Arc<Option<String>>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader.take(MAX_HEAD_SIZE));
+
+    let RequestHead { request_line, content_length, authorization } =
+        with_timeout(read_head(&mut reader)).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split_once('?').map_or(target, |(p, _)| p);
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+
+    debug!("HTTP request: {} {}", method, path);
+
+    let unauthorized = token.as_deref().is_some_and(|t| !authorized(authorization.as_deref(), t));
+
+    let (status, content_type, body) = if content_length > MAX_BODY_SIZE {
+        error_response("413 Payload Too Large", "Request body is too large")
+    } else {
+        let mut body = vec![0; content_length];
+        reader.get_mut().set_limit(content_length as u64);
+        with_timeout(reader.read_exact(&mut body)).await?;
+
+        match (method, segments.as_slice()) {
+            ("GET", ["api", "status" | "stream"]) if unauthorized => {
+                error_response("401 Unauthorized", "Missing or invalid bearer token")
+            }
+            ("GET", ["api", "status"]) => {
+                ("200 OK", "application/json", status_json(&state).to_string())
+            }
+            ("GET", ["api", "stream"]) => return stream_ticks(writer, sink.subscribe()).await,
+            (_, ["api", rest @ ..]) if matches!(rest.first(), Some(&"zones" | &"reload")) => {
+                handle_api(method, rest, authorization.as_deref(), &body, &state, token.as_deref())
+            }
+            #[cfg(feature = "web-ui")]
+            ("GET", [""]) => ("200 OK", "text/html; charset=utf-8", INDEX_HTML.to_owned()),
+            ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_owned()),
+            _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_owned()),
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body);
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+/// Accept and handle HTTP connections forever.
+pub async fn serve(listener: TcpListener, state: Arc<ControlState>, sink: Arc<Sink>, token: Option<String>) {
+    let token = Arc::new(token);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let (state, sink, token) = (state.clone(), sink.clone(), token.clone());
+
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(stream, state, sink, token).await {
+                        debug!("HTTP client error: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept HTTP connection: {}", e),
+        }
+    }
+}